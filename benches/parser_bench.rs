@@ -0,0 +1,9 @@
+use bazel_lsp::parser::BazelParser;
+use criterion::{criterion_group, criterion_main, Criterion};
+
+fn bench_parser_new(c: &mut Criterion) {
+    c.bench_function("BazelParser::new", |b| b.iter(BazelParser::new));
+}
+
+criterion_group!(benches, bench_parser_new);
+criterion_main!(benches);
@@ -0,0 +1,55 @@
+use bazel_lsp::target_trie::{RuleInfo, TargetTrie};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+const PACKAGES: usize = 1_000;
+const TARGETS_PER_PACKAGE: usize = 10;
+
+fn target_path(package: usize, target: usize) -> String {
+    format!(
+        "//pkg_{:04}:target_{}",
+        package,
+        (b'a' + target as u8) as char
+    )
+}
+
+fn build_trie(packages: usize) -> TargetTrie {
+    let mut trie = TargetTrie::new();
+    for package in 0..packages {
+        for target in 0..TARGETS_PER_PACKAGE {
+            let path = target_path(package, target);
+            let name = path.rsplit(':').next().unwrap().to_string();
+            trie.insert_target(&path, RuleInfo::new(name, path.clone()));
+        }
+    }
+    trie
+}
+
+fn bench_starts_with(c: &mut Criterion) {
+    // 1 000 packages x 10 targets = 10 000 targets
+    let trie = build_trie(PACKAGES);
+
+    c.bench_function("starts_with package prefix (10k targets)", |b| {
+        b.iter(|| trie.starts_with(black_box("//pkg_0050")))
+    });
+
+    c.bench_function("starts_with empty prefix (10k targets)", |b| {
+        b.iter(|| trie.starts_with(black_box("")))
+    });
+}
+
+fn bench_insert_target(c: &mut Criterion) {
+    // 100 packages x 10 targets = 1 000 targets
+    c.bench_function("insert_target 1k targets", |b| {
+        b.iter(|| build_trie(black_box(PACKAGES / 10)))
+    });
+}
+
+// Informational budget: changes within 5% are treated as noise, anything
+// beyond that is reported as a regression when compared against a saved
+// baseline (`cargo bench -- --save-baseline main` / `--baseline main`).
+criterion_group! {
+    name = benches;
+    config = Criterion::default().noise_threshold(0.05);
+    targets = bench_starts_with, bench_insert_target
+}
+criterion_main!(benches);
@@ -0,0 +1,11 @@
+#![no_main]
+
+use bazel_lsp::parser::BazelParser;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(source) = std::str::from_utf8(data) {
+        let _ = BazelParser::default().extract_targets(source);
+        let _ = BazelParser::default().sort_deps_in_text(source);
+    }
+});
@@ -1,13 +1,16 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::env;
 use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
 
 /// Checks if a directory is a Bazel workspace
 ///
 /// A directory is considered a Bazel workspace if it contains a WORKSPACE or WORKSPACE.bazel file
-/// at the root level.
+/// at the root level. Directories the user may not look into aren't workspaces to us.
 pub fn is_workspace_dir(path: &Path) -> Result<bool> {
-    if !path.is_dir() {
+    if !is_dir(path)? {
         return Ok(false);
     }
 
@@ -15,23 +18,80 @@ pub fn is_workspace_dir(path: &Path) -> Result<bool> {
     let workspace_file = path.join("WORKSPACE");
     let workspace_bazel_file = path.join("WORKSPACE.bazel");
 
-    Ok(workspace_file.exists() || workspace_bazel_file.exists())
+    Ok(file_exists(&workspace_file)? || file_exists(&workspace_bazel_file)?)
+}
+
+/// Whether `path` exists, treating paths the user may not access as missing.
+fn file_exists(path: &Path) -> Result<bool> {
+    Ok(metadata(path)?.is_some())
+}
+
+fn is_dir(path: &Path) -> Result<bool> {
+    Ok(metadata(path)?.is_some_and(|metadata| metadata.is_dir()))
+}
+
+fn is_file(path: &Path) -> Result<bool> {
+    Ok(metadata(path)?.is_some_and(|metadata| metadata.is_file()))
+}
+
+/// Whether an error means the path isn't there for us, because it doesn't exist or
+/// the user may not access it.
+fn is_missing(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied
+    )
+}
+
+/// The metadata of `path`, or None for paths that are missing, see [`is_missing`].
+fn metadata(path: &Path) -> Result<Option<fs::Metadata>> {
+    match fs::metadata(path) {
+        Ok(metadata) => Ok(Some(metadata)),
+        Err(e) if is_missing(&e) => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Failed to access {}", path.display())),
+    }
+}
+
+/// The paths of the entries of `dir`, or none if it is missing, see [`is_missing`].
+fn read_dir(dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if is_missing(&e) => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", dir.display())),
+    };
+    entries
+        .map(|entry| {
+            entry
+                .map(|entry| entry.path())
+                .with_context(|| format!("Failed to read {}", dir.display()))
+        })
+        .collect()
 }
 
 /// Finds the root of a Bazel workspace from a given path
 ///
 /// This function traverses up the directory tree from the given path
 /// until it finds a directory containing a WORKSPACE or WORKSPACE.bazel file.
-/// Returns None if no workspace root is found.
+/// Returns None if no workspace root is found within
+/// [`DEFAULT_WORKSPACE_SEARCH_DEPTH`] parent directories.
 pub fn find_workspace_root(path: &Path) -> Result<Option<&Path>> {
-    let mut current = Some(path);
+    find_workspace_root_with_depth_limit(path, DEFAULT_WORKSPACE_SEARCH_DEPTH)
+}
+
+/// How many parent directories `find_workspace_root` checks before giving up.
+/// Each check is a filesystem round-trip, which is slow on network mounts.
+pub const DEFAULT_WORKSPACE_SEARCH_DEPTH: usize = 20;
 
-    while let Some(dir) = current {
+/// Finds the root of a Bazel workspace, checking `path` and at most `max_depth`
+/// of its parent directories.
+pub fn find_workspace_root_with_depth_limit(
+    path: &Path,
+    max_depth: usize,
+) -> Result<Option<&Path>> {
+    for dir in path.ancestors().take(max_depth + 1) {
         if is_workspace_dir(dir)? {
             return Ok(Some(dir));
         }
-
-        current = dir.parent();
     }
 
     Ok(None)
@@ -51,35 +111,349 @@ pub fn get_package_path(path: &Path) -> Result<Option<String>> {
     Ok(None)
 }
 
+/// Finds the BUILD file of the package a file belongs to, i.e. the nearest one in its
+/// directory or a parent directory within its workspace. A directory has BUILD.bazel
+/// take precedence over BUILD, like Bazel does.
+///
+/// Returns None for files outside of a Bazel workspace or of any package.
+pub fn find_package_build_file(path: &Path) -> Result<Option<PathBuf>> {
+    let Some(workspace_root) = find_workspace_root(path)? else {
+        return Ok(None);
+    };
+
+    for dir in path.ancestors().skip(if is_dir(path)? { 0 } else { 1 }) {
+        for name in ["BUILD.bazel", "BUILD"] {
+            let build_file = dir.join(name);
+            if is_file(&build_file)? {
+                return Ok(Some(build_file));
+            }
+        }
+        if dir == workspace_root {
+            break;
+        }
+    }
+
+    Ok(None)
+}
+
 /// Finds all BUILD files in a directory recursively
 ///
 /// This function searches for files named "BUILD" or "BUILD.bazel" in the given directory
-/// and all its subdirectories, excluding hidden directories and bazel-out.
-pub fn find_build_files(dir: &Path) -> Vec<PathBuf> {
-    let mut build_files = Vec::new();
-
-    if let Ok(entries) = fs::read_dir(dir) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if path.is_dir() {
-                if !path
-                    .file_name()
-                    .and_then(|name| name.to_str())
-                    .map(|name| name.starts_with('.') || name == "bazel-out")
-                    .unwrap_or(false)
-                {
-                    build_files.extend(find_build_files(&path));
-                }
-            } else if path
+/// and all its subdirectories, excluding hidden directories, bazel-out and directories
+/// named in `excluded_dirs`.
+pub fn find_build_files(dir: &Path, excluded_dirs: &[String]) -> Result<Vec<PathBuf>> {
+    find_files(dir, excluded_dirs, is_build_file)
+}
+
+/// Finds all Starlark extension files in a directory recursively
+///
+/// Like [`find_build_files`], but for files ending in ".bzl".
+pub fn find_bzl_files(dir: &Path, excluded_dirs: &[String]) -> Result<Vec<PathBuf>> {
+    find_files(dir, excluded_dirs, is_bzl_file)
+}
+
+fn find_files(
+    dir: &Path,
+    excluded_dirs: &[String],
+    matches: fn(&Path) -> bool,
+) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+
+    for path in read_dir(dir)? {
+        if is_dir(&path)? {
+            if !path
                 .file_name()
                 .and_then(|name| name.to_str())
-                .map(|name| name == "BUILD" || name == "BUILD.bazel")
+                .map(|name| {
+                    name.starts_with('.')
+                        || name == "bazel-out"
+                        || excluded_dirs.iter().any(|excluded| excluded == name)
+                })
                 .unwrap_or(false)
             {
-                build_files.push(path);
+                files.extend(find_files(&path, excluded_dirs, matches)?);
+            }
+        } else if matches(&path) {
+            files.push(path);
+        }
+    }
+
+    Ok(files)
+}
+
+/// Finds the files of the package rooted at `dir`, relative to it and sorted
+///
+/// Like [`find_build_files`] it skips hidden directories and bazel-out. Subdirectories
+/// with a BUILD file of their own are skipped too, as they are separate packages.
+pub fn find_package_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    fn collect(root: &Path, dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+        for path in read_dir(dir)? {
+            if is_dir(&path)? {
+                let skipped = path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with('.') || name == "bazel-out");
+                let is_package =
+                    file_exists(&path.join("BUILD"))? || file_exists(&path.join("BUILD.bazel"))?;
+                if !skipped && !is_package {
+                    collect(root, &path, files)?;
+                }
+            } else if let Ok(relative_path) = path.strip_prefix(root) {
+                files.push(relative_path.to_path_buf());
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+    collect(dir, dir, &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+const CC_SOURCE_EXTENSIONS: &[&str] = &["c", "cc", "cpp", "cxx"];
+const CC_HEADER_EXTENSIONS: &[&str] = &["h", "hh", "hpp", "hxx", "inc"];
+
+/// A BUILD file with a library for the sources among `files`, named `name`: a
+/// `cc_library` for C and C++ files and a `py_library` for Python files. When a
+/// package has both, the `py_library` gets a `_py` suffix. Returns None when none
+/// of the files is a source file.
+pub fn build_stub(name: &str, files: &[PathBuf]) -> Option<String> {
+    let with_extension = |extensions: &[&str]| -> Vec<String> {
+        files
+            .iter()
+            .filter(|file| {
+                file.extension()
+                    .and_then(|extension| extension.to_str())
+                    .is_some_and(|extension| extensions.contains(&extension))
+            })
+            .map(|file| file.to_string_lossy().replace('\\', "/"))
+            .collect()
+    };
+    let cc_srcs = with_extension(CC_SOURCE_EXTENSIONS);
+    let cc_hdrs = with_extension(CC_HEADER_EXTENSIONS);
+    let py_srcs = with_extension(&["py"]);
+
+    let mut rules = Vec::new();
+    if !cc_srcs.is_empty() || !cc_hdrs.is_empty() {
+        rules.push(rule_stub(
+            "cc_library",
+            name,
+            &[("srcs", &cc_srcs), ("hdrs", &cc_hdrs)],
+        ));
+    }
+    if !py_srcs.is_empty() {
+        let py_name = if rules.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}_py", name)
+        };
+        rules.push(rule_stub("py_library", &py_name, &[("srcs", &py_srcs)]));
+    }
+
+    if rules.is_empty() {
+        None
+    } else {
+        Some(rules.join("\n"))
+    }
+}
+
+/// A call of `rule` with its name, the non-empty `attributes` and empty `deps`.
+fn rule_stub(rule: &str, name: &str, attributes: &[(&str, &Vec<String>)]) -> String {
+    let mut stub = format!("{}(\n    name = \"{}\",\n", rule, name);
+    for (attribute, values) in attributes {
+        match values.as_slice() {
+            [] => {}
+            [value] => stub.push_str(&format!("    {} = [\"{}\"],\n", attribute, value)),
+            values => {
+                stub.push_str(&format!("    {} = [\n", attribute));
+                for value in values {
+                    stub.push_str(&format!("        \"{}\",\n", value));
+                }
+                stub.push_str("    ],\n");
+            }
+        }
+    }
+    stub.push_str("    deps = [],\n)\n");
+    stub
+}
+
+/// Whether a path names a BUILD file, i.e. "BUILD" or "BUILD.bazel"
+pub fn is_build_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| name == "BUILD" || name == "BUILD.bazel")
+        .unwrap_or(false)
+}
+
+/// Whether a path names a Starlark extension file, i.e. ends in ".bzl"
+pub fn is_bzl_file(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "bzl")
+}
+
+/// Finds an executable on `PATH`
+///
+/// Returns the full path of the first `PATH` entry containing a file with the given name,
+/// or None if the program is not installed.
+pub fn find_executable(name: &str) -> Option<PathBuf> {
+    let paths = env::var_os("PATH")?;
+    env::split_paths(&paths)
+        .map(|dir| dir.join(name))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Parses the output of `bazel query --output=graph --nograph:factored` into a map
+/// from each label to the labels it depends on.
+pub fn parse_query_graph(output: &str) -> HashMap<String, Vec<String>> {
+    let mut graph: HashMap<String, Vec<String>> = HashMap::new();
+
+    for line in output.lines() {
+        let line = line.trim();
+        if !line.starts_with('"') {
+            continue;
+        }
+
+        match line.split_once(" -> ") {
+            Some((from, to)) => {
+                let to = to.trim_matches('"').to_string();
+                graph.entry(to.clone()).or_default();
+                graph
+                    .entry(from.trim_matches('"').to_string())
+                    .or_default()
+                    .push(to);
+            }
+            None => {
+                graph.entry(line.trim_matches('"').to_string()).or_default();
+            }
+        }
+    }
+
+    graph
+}
+
+/// Parses the output of `bazel query --output=label_kind` into a map from label to
+/// kind. Rules are reported by their rule class (`cc_library rule //a:b` becomes
+/// `cc_library`), files by their kind (`source file`).
+pub fn parse_label_kinds(output: &str) -> HashMap<String, String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (kind, label) = line.trim().rsplit_once(' ')?;
+            let kind = kind.strip_suffix(" rule").unwrap_or(kind);
+            Some((label.to_string(), kind.to_string()))
+        })
+        .collect()
+}
+
+/// Parses the output of `bazel query --output=location`, e.g.
+/// `/ws/pkg/BUILD:3:1: cc_library rule //pkg:lib`, into the file and the zero-based
+/// line and column the first result is defined at.
+pub fn parse_query_location(output: &str) -> Option<(PathBuf, u32, u32)> {
+    let (location, _) = output.lines().next()?.split_once(": ")?;
+    let mut parts = location.rsplitn(3, ':');
+    let column: u32 = parts.next()?.parse().ok()?;
+    let line: u32 = parts.next()?.parse().ok()?;
+    let path = parts.next()?;
+    Some((
+        PathBuf::from(path),
+        line.saturating_sub(1),
+        column.saturating_sub(1),
+    ))
+}
+
+/// Builds a `{"label", "kind", "deps"}` tree rooted at `label` from a dependency
+/// graph, expanding at most `depth` levels. A dependency that is already being
+/// expanded higher up in the tree is listed without its own deps.
+pub fn deps_tree(
+    label: &str,
+    graph: &HashMap<String, Vec<String>>,
+    kinds: &HashMap<String, String>,
+    depth: usize,
+) -> serde_json::Value {
+    fn node(
+        label: &str,
+        graph: &HashMap<String, Vec<String>>,
+        kinds: &HashMap<String, String>,
+        depth: usize,
+        path: &mut Vec<String>,
+    ) -> serde_json::Value {
+        let mut deps = Vec::new();
+        if depth > 0 && !path.iter().any(|ancestor| ancestor == label) {
+            path.push(label.to_string());
+            for dep in graph.get(label).into_iter().flatten() {
+                deps.push(node(dep, graph, kinds, depth - 1, path));
+            }
+            path.pop();
+        }
+
+        serde_json::json!({
+            "label": label,
+            "kind": kinds.get(label),
+            "deps": deps,
+        })
+    }
+
+    node(label, graph, kinds, depth, &mut Vec::new())
+}
+
+/// Names of the configs a `.bazelrc` defines, i.e. the values `--config` accepts, from
+/// lines like `build:opt --compilation_mode=opt`. Configs are usually defined for
+/// `build`, but any command prefix counts. Sorted and without duplicates.
+pub fn parse_bazelrc_configs(content: &str) -> Vec<String> {
+    let mut configs: Vec<String> = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let (_, name) = line.split_whitespace().next()?.split_once(':')?;
+            (!name.is_empty()).then(|| name.to_string())
+        })
+        .collect();
+    configs.sort();
+    configs.dedup();
+    configs
+}
+
+/// The configs defined by the `.bazelrc` at the root of a workspace and the files it
+/// imports with `import` or `try-import`, see [`parse_bazelrc_configs`]. Files that
+/// don't exist or the user may not read define none.
+pub fn find_bazelrc_configs(workspace_root: &Path) -> Result<Vec<String>> {
+    fn collect(
+        path: &Path,
+        workspace_root: &Path,
+        visited: &mut Vec<PathBuf>,
+    ) -> Result<Vec<String>> {
+        if visited.iter().any(|seen| seen == path) {
+            return Ok(Vec::new());
+        }
+        visited.push(path.to_path_buf());
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if is_missing(&e) => return Ok(Vec::new()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+
+        let mut configs = parse_bazelrc_configs(&content);
+        for line in content.lines() {
+            let imported = line
+                .trim()
+                .strip_prefix("try-import ")
+                .or_else(|| line.trim().strip_prefix("import "));
+            if let Some(imported) = imported {
+                let workspace = workspace_root.to_string_lossy();
+                let imported = imported.trim().replace("%workspace%", &workspace);
+                configs.extend(collect(Path::new(&imported), workspace_root, visited)?);
             }
         }
+        Ok(configs)
     }
 
-    build_files
+    let mut configs = collect(
+        &workspace_root.join(".bazelrc"),
+        workspace_root,
+        &mut Vec::new(),
+    )?;
+    configs.sort();
+    configs.dedup();
+    Ok(configs)
 }
@@ -0,0 +1,248 @@
+use crate::documents::DEFAULT_MAX_OPEN_DOCUMENTS;
+use crate::parser::TargetSortKey;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tower_lsp::lsp_types::{DiagnosticSeverity, TraceValue};
+
+/// Default for `max_completion_items`.
+pub const DEFAULT_MAX_COMPLETION_ITEMS: usize = 200;
+
+/// Default for `excluded_dirs`: directories of package managers and build tools,
+/// whose BUILD files are not part of the workspace.
+pub const DEFAULT_EXCLUDED_DIRS: &[&str] = &["node_modules", "vendor", ".cache", "target"];
+
+/// Server settings, read from the `initializationOptions` sent by the client.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Format documents with `buildifier` instead of the built-in deps sorter
+    /// when it is available on `PATH`.
+    pub prefer_buildifier: bool,
+    /// Extra environment variables for bazel invocations, e.g.
+    /// `BAZEL_DO_NOT_DETECT_CPP_TOOLCHAIN=1`. These override the defaults.
+    pub bazel_env: HashMap<String, String>,
+    /// Trace level; internal events are logged only when this is `verbose`.
+    pub trace: TraceValue,
+    /// Flags added to every bazel command to keep its output readable in the log.
+    pub bazel_output_flags: Vec<String>,
+    /// Severity overrides keyed by diagnostic code, e.g. `parse_error` or `undefined_target`.
+    pub diagnostic_levels: HashMap<String, DiagnosticSeverity>,
+    /// How many documents to keep in memory. Documents open in the client are
+    /// always kept; closed ones are evicted least recently used first.
+    pub max_open_documents: usize,
+    /// Show a "Debug" code lens on `_binary` targets, which builds them with
+    /// `--compilation_mode=dbg` and asks the client to start a debugger.
+    pub enable_debug_lens: bool,
+    /// Most label completions returned at once. Longer lists are cut and marked
+    /// incomplete, so the client asks again as the user keeps typing.
+    pub max_completion_items: usize,
+    /// Ask `bazel query --output=location` for labels that no BUILD file declares,
+    /// e.g. targets generated by macros, when going to their definition.
+    pub use_bazel_query_fallback: bool,
+    /// A JSON file describing the attributes of custom rules, see
+    /// [`RuleSchema`](crate::rule_schema::RuleSchema). Relative paths are resolved
+    /// against the first workspace folder.
+    pub rule_schema_path: Option<PathBuf>,
+    /// Names of directories skipped when looking for BUILD and `.bzl` files to index,
+    /// on top of hidden directories and `bazel-out`.
+    pub excluded_dirs: Vec<String>,
+    /// Reorder the targets of a BUILD file when formatting it: `"name"` (or `true`)
+    /// sorts them by name, `"kind"` by rule kind and then name. Off by default.
+    pub sort_targets: Option<TargetSortKey>,
+    /// Whether the client accepts snippet completions. Taken from its capabilities
+    /// rather than the initialization options.
+    pub snippet_support: bool,
+    /// Whether the client resolves the documentation of completion items, which is then
+    /// left out of completion lists. Taken from its capabilities too.
+    pub completion_resolve_support: bool,
+    /// Pop up the result of `bazel build` and `bazel test` commands with
+    /// `window/showMessage`, on top of logging it.
+    pub show_command_result_popup: bool,
+    /// Complete labels from the target index in files outside every workspace folder
+    /// too, as long as it isn't empty, e.g. when it was filled by an embedder.
+    pub index_completion_outside_workspace: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            prefer_buildifier: false,
+            bazel_env: HashMap::new(),
+            trace: TraceValue::Off,
+            bazel_output_flags: vec![
+                "--noshow_progress".to_string(),
+                "--noshow_loading_progress".to_string(),
+                "--color=no".to_string(),
+            ],
+            diagnostic_levels: HashMap::new(),
+            max_open_documents: DEFAULT_MAX_OPEN_DOCUMENTS,
+            enable_debug_lens: false,
+            max_completion_items: DEFAULT_MAX_COMPLETION_ITEMS,
+            use_bazel_query_fallback: false,
+            rule_schema_path: None,
+            excluded_dirs: DEFAULT_EXCLUDED_DIRS
+                .iter()
+                .map(|dir| dir.to_string())
+                .collect(),
+            sort_targets: None,
+            snippet_support: false,
+            completion_resolve_support: false,
+            show_command_result_popup: true,
+            index_completion_outside_workspace: false,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Builds a config from the client's initialization options.
+    ///
+    /// Unknown keys are ignored and missing keys keep their default value. Keys may be
+    /// spelled in camelCase as well, see [`option`].
+    pub fn from_initialization_options(options: Option<&Value>) -> Self {
+        let mut config = Self::default();
+
+        let Some(options) = options.and_then(Value::as_object) else {
+            return config;
+        };
+
+        if let Some(prefer_buildifier) =
+            option(options, "prefer_buildifier").and_then(Value::as_bool)
+        {
+            config.prefer_buildifier = prefer_buildifier;
+        }
+
+        if let Some(bazel_env) = option(options, "bazel_env").and_then(Value::as_object) {
+            config.bazel_env = bazel_env
+                .iter()
+                .filter_map(|(key, value)| Some((key.clone(), value.as_str()?.to_string())))
+                .collect();
+        }
+
+        if let Some(flags) = option(options, "bazel_output_flags").and_then(Value::as_array) {
+            config.bazel_output_flags = flags
+                .iter()
+                .filter_map(|flag| Some(flag.as_str()?.to_string()))
+                .collect();
+        }
+
+        if let Some(levels) = option(options, "diagnostic_levels").and_then(Value::as_object) {
+            config.diagnostic_levels = levels
+                .iter()
+                .filter_map(|(code, level)| Some((code.clone(), parse_severity(level)?)))
+                .collect();
+        }
+
+        if let Some(max_open_documents) =
+            option(options, "max_open_documents").and_then(Value::as_u64)
+        {
+            config.max_open_documents = max_open_documents as usize;
+        }
+
+        if let Some(enable_debug_lens) =
+            option(options, "enable_debug_lens").and_then(Value::as_bool)
+        {
+            config.enable_debug_lens = enable_debug_lens;
+        }
+
+        if let Some(max_completion_items) =
+            option(options, "max_completion_items").and_then(Value::as_u64)
+        {
+            config.max_completion_items = max_completion_items as usize;
+        }
+
+        if let Some(use_bazel_query_fallback) =
+            option(options, "use_bazel_query_fallback").and_then(Value::as_bool)
+        {
+            config.use_bazel_query_fallback = use_bazel_query_fallback;
+        }
+
+        if let Some(rule_schema_path) = option(options, "rule_schema_path").and_then(Value::as_str)
+        {
+            config.rule_schema_path = Some(PathBuf::from(rule_schema_path));
+        }
+
+        if let Some(excluded_dirs) = option(options, "excluded_dirs").and_then(Value::as_array) {
+            config.excluded_dirs = excluded_dirs
+                .iter()
+                .filter_map(|dir| Some(dir.as_str()?.to_string()))
+                .collect();
+        }
+
+        if let Some(sort_targets) = option(options, "sort_targets") {
+            config.sort_targets = match sort_targets {
+                Value::Bool(true) => Some(TargetSortKey::Name),
+                Value::String(key) if key == "name" => Some(TargetSortKey::Name),
+                Value::String(key) if key == "kind" => Some(TargetSortKey::Kind),
+                _ => None,
+            };
+        }
+
+        if let Some(show_command_result_popup) =
+            option(options, "show_command_result_popup").and_then(Value::as_bool)
+        {
+            config.show_command_result_popup = show_command_result_popup;
+        }
+
+        if let Some(index_completion_outside_workspace) =
+            option(options, "index_completion_outside_workspace").and_then(Value::as_bool)
+        {
+            config.index_completion_outside_workspace = index_completion_outside_workspace;
+        }
+
+        if let Some(trace) =
+            option(options, "trace").and_then(|trace| serde_json::from_value(trace.clone()).ok())
+        {
+            config.trace = trace;
+        }
+
+        config
+    }
+
+    /// The severity to publish a diagnostic with, honoring `diagnostic_levels`.
+    pub fn diagnostic_severity(
+        &self,
+        code: &str,
+        default: DiagnosticSeverity,
+    ) -> DiagnosticSeverity {
+        self.diagnostic_levels.get(code).copied().unwrap_or(default)
+    }
+}
+
+/// Looks up an option by its snake_case `key` or, as clients such as VS Code name their
+/// settings, its camelCase spelling, e.g. `ruleSchemaPath` for `rule_schema_path`.
+fn option<'a>(options: &'a Map<String, Value>, key: &str) -> Option<&'a Value> {
+    if let Some(value) = options.get(key) {
+        return Some(value);
+    }
+
+    let mut camel_case = String::with_capacity(key.len());
+    let mut words = key.split('_');
+    camel_case.extend(words.next());
+    for word in words {
+        let mut chars = word.chars();
+        camel_case.extend(chars.next().map(|c| c.to_ascii_uppercase()));
+        camel_case.push_str(chars.as_str());
+    }
+    options.get(&camel_case)
+}
+
+/// Accepts either an LSP severity number (1-4) or its name.
+fn parse_severity(value: &Value) -> Option<DiagnosticSeverity> {
+    if let Some(level) = value.as_str() {
+        return match level.to_ascii_lowercase().as_str() {
+            "error" => Some(DiagnosticSeverity::ERROR),
+            "warning" => Some(DiagnosticSeverity::WARNING),
+            "information" | "info" => Some(DiagnosticSeverity::INFORMATION),
+            "hint" => Some(DiagnosticSeverity::HINT),
+            _ => None,
+        };
+    }
+
+    match value.as_i64()? {
+        1 => Some(DiagnosticSeverity::ERROR),
+        2 => Some(DiagnosticSeverity::WARNING),
+        3 => Some(DiagnosticSeverity::INFORMATION),
+        4 => Some(DiagnosticSeverity::HINT),
+        _ => None,
+    }
+}
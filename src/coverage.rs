@@ -0,0 +1,93 @@
+use crate::label::parse_label;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Line coverage summed over every file in an LCOV report, as a percentage.
+///
+/// Uses the `LF` (lines found) and `LH` (lines hit) totals of each file record and
+/// falls back to counting `DA` lines for records that don't have them. Returns None
+/// when the report doesn't cover any lines.
+pub fn parse_line_coverage(report: &str) -> Option<f64> {
+    let mut found = 0u64;
+    let mut hit = 0u64;
+
+    // Per-record counters, reset at each `end_of_record`
+    let mut record_found = None;
+    let mut record_hit = None;
+    let mut da_found = 0u64;
+    let mut da_hit = 0u64;
+
+    for line in report.lines().map(str::trim) {
+        if let Some(value) = line.strip_prefix("LF:") {
+            record_found = value.parse::<u64>().ok();
+        } else if let Some(value) = line.strip_prefix("LH:") {
+            record_hit = value.parse::<u64>().ok();
+        } else if let Some(value) = line.strip_prefix("DA:") {
+            da_found += 1;
+            let count = value.split(',').nth(1).and_then(|c| c.parse::<u64>().ok());
+            if count.is_some_and(|count| count > 0) {
+                da_hit += 1;
+            }
+        } else if line == "end_of_record" {
+            match (record_found, record_hit) {
+                (Some(record_found), Some(record_hit)) => {
+                    found += record_found;
+                    hit += record_hit;
+                }
+                _ => {
+                    found += da_found;
+                    hit += da_hit;
+                }
+            }
+            record_found = None;
+            record_hit = None;
+            da_found = 0;
+            da_hit = 0;
+        }
+    }
+
+    if found == 0 {
+        return None;
+    }
+    Some(hit as f64 * 100.0 / found as f64)
+}
+
+/// Where `bazel coverage` leaves the LCOV report of a test target:
+/// `bazel-testlogs/<package>/<name>/coverage.dat` under the workspace root.
+pub fn coverage_report_path(workspace_root: &Path, label: &str) -> Option<PathBuf> {
+    let label = parse_label(label).ok()?;
+    Some(
+        workspace_root
+            .join("bazel-testlogs")
+            .join(label.package)
+            .join(label.name)
+            .join("coverage.dat"),
+    )
+}
+
+/// Coverage percentages of test targets, read from their reports on first use.
+#[derive(Debug, Default)]
+pub struct CoverageCache {
+    /// None records that the target has no usable report
+    coverage: HashMap<String, Option<f64>>,
+}
+
+impl CoverageCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the line coverage of `label`, reading its report if it isn't cached.
+    pub fn get(&mut self, workspace_root: &Path, label: &str) -> Option<f64> {
+        *self.coverage.entry(label.to_string()).or_insert_with(|| {
+            let path = coverage_report_path(workspace_root, label)?;
+            parse_line_coverage(&fs::read_to_string(path).ok()?)
+        })
+    }
+
+    /// Forgets the cached coverage of `label`, so the report is read again.
+    pub fn invalidate(&mut self, label: &str) {
+        self.coverage.remove(label);
+    }
+}
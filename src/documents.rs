@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+
+/// Default for `max_open_documents`.
+pub const DEFAULT_MAX_OPEN_DOCUMENTS: usize = 500;
+
+#[derive(Debug)]
+struct Document {
+    text: String,
+    last_used: u64,
+    open: bool,
+}
+
+/// Document contents keyed by URI, bounded by a least-recently-used policy.
+///
+/// Documents the client has open are never evicted. Closed documents stay cached
+/// until the store grows past its capacity, at which point the least recently used
+/// ones are dropped first.
+#[derive(Debug)]
+pub struct DocumentStore {
+    documents: HashMap<String, Document>,
+    capacity: usize,
+    clock: u64,
+}
+
+impl DocumentStore {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            documents: HashMap::new(),
+            capacity,
+            clock: 0,
+        }
+    }
+
+    pub fn get(&self, uri: &str) -> Option<&String> {
+        self.documents.get(uri).map(|document| &document.text)
+    }
+
+    pub fn contains_key(&self, uri: &str) -> bool {
+        self.documents.contains_key(uri)
+    }
+
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+
+    pub fn is_open(&self, uri: &str) -> bool {
+        self.documents
+            .get(uri)
+            .is_some_and(|document| document.open)
+    }
+
+    /// The URIs and contents of the documents the client has open.
+    pub fn open_documents(&self) -> Vec<(String, String)> {
+        self.documents
+            .iter()
+            .filter(|(_, document)| document.open)
+            .map(|(uri, document)| (uri.clone(), document.text.clone()))
+            .collect()
+    }
+
+    /// Stores a document the client has opened.
+    pub fn open(&mut self, uri: String, text: String) {
+        self.store(uri, text).open = true;
+        self.evict();
+    }
+
+    /// Marks a document as closed by the client, making it eligible for eviction.
+    pub fn close(&mut self, uri: &str) {
+        if let Some(document) = self.documents.get_mut(uri) {
+            document.open = false;
+        }
+        self.evict();
+    }
+
+    /// Stores or replaces the contents of a document and marks it as recently used.
+    pub fn insert(&mut self, uri: String, text: String) {
+        self.store(uri, text);
+        self.evict();
+    }
+
+    /// Marks a document as recently used.
+    pub fn touch(&mut self, uri: &str) {
+        let last_used = self.tick();
+        if let Some(document) = self.documents.get_mut(uri) {
+            document.last_used = last_used;
+        }
+    }
+
+    pub fn remove(&mut self, uri: &str) -> Option<String> {
+        self.documents.remove(uri).map(|document| document.text)
+    }
+
+    pub fn clear(&mut self) {
+        self.documents.clear();
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict();
+    }
+
+    fn store(&mut self, uri: String, text: String) -> &mut Document {
+        let last_used = self.tick();
+        let document = self.documents.entry(uri).or_insert(Document {
+            text: String::new(),
+            last_used,
+            open: false,
+        });
+        document.text = text;
+        document.last_used = last_used;
+        document
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    /// Drops the least recently used closed documents until the store fits its capacity.
+    fn evict(&mut self) {
+        while self.documents.len() > self.capacity {
+            let oldest = self
+                .documents
+                .iter()
+                .filter(|(_, document)| !document.open)
+                .min_by_key(|(_, document)| document.last_used)
+                .map(|(uri, _)| uri.clone());
+
+            match oldest {
+                Some(uri) => {
+                    self.documents.remove(&uri);
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+impl Default for DocumentStore {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_OPEN_DOCUMENTS)
+    }
+}
@@ -0,0 +1,189 @@
+use anyhow::{bail, Result};
+use std::fmt;
+
+/// A Bazel label split into its parts.
+///
+/// Relative labels like `:name` have an empty `package`, meaning the package of the
+/// BUILD file they are written in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    /// The repository of `@repo//pkg:name`, None for labels in the main repository
+    pub repo: Option<String>,
+    pub package: String,
+    pub name: String,
+}
+
+impl Label {
+    /// The label of the target `name` in the main repository's package `package`.
+    /// Path separators are normalized to `/` so the label doesn't depend on the platform.
+    pub fn new(package: &str, name: &str) -> Self {
+        Self {
+            repo: None,
+            package: package.replace('\\', "/"),
+            name: name.to_string(),
+        }
+    }
+}
+
+/// Formats the label in its explicit form, `//pkg:name` or `@repo//pkg:name`.
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(repo) = &self.repo {
+            write!(f, "@{}", repo)?;
+        }
+        write!(f, "//{}:{}", self.package, self.name)
+    }
+}
+
+/// How a label that is still being typed starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LabelStart {
+    /// `//pkg:name`, in the main repository
+    Absolute,
+    /// `:name`, in the package of the BUILD file
+    Relative,
+    /// `@repo`, in an external repository
+    Repository,
+}
+
+/// Splits the start off a label that may still be incomplete, returning the text after
+/// it: `//pkg:na` is `(LabelStart::Absolute, "pkg:na")`. None if `s` can't start a label.
+pub fn split_label_start(s: &str) -> Option<(LabelStart, &str)> {
+    if let Some(rest) = s.strip_prefix("//") {
+        Some((LabelStart::Absolute, rest))
+    } else if let Some(rest) = s.strip_prefix(':') {
+        Some((LabelStart::Relative, rest))
+    } else {
+        s.strip_prefix('@')
+            .map(|rest| (LabelStart::Repository, rest))
+    }
+}
+
+/// Parses a label of the form `@repo//pkg:name`, `//pkg:name` or `:name`.
+///
+/// The target name may be left out: `//pkg` is `//pkg:pkg` and `@repo` is
+/// `@repo//:repo`. Only the syntax is checked; whether the target exists isn't.
+pub fn parse_label(s: &str) -> Result<Label> {
+    parse_label_in(s, "")
+}
+
+/// Parses a label written in a BUILD file of the package `package`, which relative
+/// labels like `:name` refer to.
+pub fn parse_label_in(s: &str, package: &str) -> Result<Label> {
+    let (repo, rest) = match s.strip_prefix('@') {
+        Some(rest) => {
+            let (repo, rest) = match rest.find("//") {
+                Some(index) => (&rest[..index], Some(&rest[index..])),
+                None => (rest, None),
+            };
+            check_repo(repo)?;
+            match rest {
+                Some(rest) => (Some(repo.to_string()), rest),
+                None => {
+                    return Ok(Label {
+                        repo: Some(repo.to_string()),
+                        package: String::new(),
+                        name: repo.to_string(),
+                    })
+                }
+            }
+        }
+        None => (None, s),
+    };
+
+    if let Some(name) = rest.strip_prefix(':') {
+        if repo.is_some() {
+            bail!("Expected `//` after the repository in {:?}", s);
+        }
+        check_name(name)?;
+        return Ok(Label::new(package, name));
+    }
+
+    let Some(rest) = rest.strip_prefix("//") else {
+        bail!("{:?} doesn't start with `//`, `:` or `@`", s);
+    };
+    let (package, name) = match rest.split_once(':') {
+        Some((package, name)) => (package, name),
+        None => {
+            let Some(name) = rest.rsplit('/').next().filter(|name| !name.is_empty()) else {
+                bail!("{:?} has no target name", s);
+            };
+            (rest, name)
+        }
+    };
+    check_package(package)?;
+    check_name(name)?;
+
+    Ok(Label {
+        repo,
+        package: package.to_string(),
+        name: name.to_string(),
+    })
+}
+
+/// Expands a label to its explicit `//package:name` form.
+///
+/// Bazel treats `//foo/bar` as shorthand for `//foo/bar:bar`, so a label without
+/// a colon gets the last package segment as its target name. Strings that aren't
+/// labels are returned unchanged.
+pub fn expand_label(label: &str) -> String {
+    parse_label(label)
+        .map(|label| label.to_string())
+        .unwrap_or_else(|_| label.to_string())
+}
+
+fn check_repo(repo: &str) -> Result<()> {
+    if !repo.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        bail!("Repository name {:?} must start with a letter", repo);
+    }
+    if let Some(c) = repo
+        .chars()
+        .find(|c| !c.is_ascii_alphanumeric() && !"-._+~".contains(*c))
+    {
+        bail!("Invalid character {:?} in repository name {:?}", c, repo);
+    }
+    Ok(())
+}
+
+/// Punctuation bazel allows in package names besides `/`, i.e. printable ASCII other
+/// than spaces, `:` and `\`.
+const PACKAGE_PUNCTUATION: &str = "!\"#$%&'()*+,-.;<=>?@[]^_`{|}~";
+
+fn check_package(package: &str) -> Result<()> {
+    if let Some(c) = package
+        .chars()
+        .find(|c| !c.is_ascii_alphanumeric() && *c != '/' && !PACKAGE_PUNCTUATION.contains(*c))
+    {
+        bail!("Invalid character {:?} in package {:?}", c, package);
+    }
+    check_segments(package, "Package")
+}
+
+fn check_name(name: &str) -> Result<()> {
+    if name.is_empty() {
+        bail!("Target name is empty");
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| c.is_whitespace() || c.is_control() || matches!(c, ':' | '\\'))
+    {
+        bail!("Invalid character {:?} in target name {:?}", c, name);
+    }
+    check_segments(name, "Target name")
+}
+
+/// Checks the `/`-separated segments of a package or target name: none may be
+/// empty, `.` or `..`. An empty package is the root package.
+fn check_segments(path: &str, what: &str) -> Result<()> {
+    if path.is_empty() {
+        return Ok(());
+    }
+    for segment in path.split('/') {
+        match segment {
+            "" => bail!("{} {:?} has an empty segment", what, path),
+            "." | ".." => bail!("{} {:?} may not contain {:?}", what, path, segment),
+            _ => {}
+        }
+    }
+    Ok(())
+}
@@ -1,4 +1,9 @@
 pub mod bazel;
+pub mod config;
+pub mod coverage;
+pub mod documents;
+pub mod label;
 pub mod parser;
+pub mod rule_schema;
 pub mod server;
 pub mod target_trie;
@@ -1,14 +1,154 @@
-use bazel_lsp::server::Backend;
+use bazel_lsp::server::{build_service, Backend};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
 use tokio::runtime::Runtime;
-use tower_lsp::{LspService, Server};
+use tower_lsp::{ClientSocket, LspService, Server};
+
+/// How the client talks to the server.
+enum Transport {
+    Stdio,
+    /// `--tcp <port>`: listen on a local TCP port
+    Tcp(u16),
+    /// `--pipe <path>`: a Unix domain socket, or a named pipe on Windows
+    Pipe(String),
+}
+
+/// The transport selected by the command line. Flags take their value as the next
+/// argument or after `=`. Other arguments, such as the `--stdio` some clients pass,
+/// are ignored.
+fn transport(mut args: impl Iterator<Item = String>) -> Result<Transport, String> {
+    while let Some(arg) = args.next() {
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        match flag.as_str() {
+            "--tcp" => {
+                let port = value
+                    .or_else(|| args.next())
+                    .ok_or("--tcp expects a port")?;
+                return port
+                    .parse()
+                    .map(Transport::Tcp)
+                    .map_err(|_| format!("Invalid port for --tcp: {}", port));
+            }
+            "--pipe" => {
+                let path = value
+                    .or_else(|| args.next())
+                    .ok_or("--pipe expects a path")?;
+                return Ok(Transport::Pipe(path));
+            }
+            _ => {}
+        }
+    }
+    Ok(Transport::Stdio)
+}
+
+/// Reports a transport that can't be set up and exits, like bad arguments do.
+fn fail(message: String) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(2);
+}
+
+async fn serve(
+    stream: impl AsyncRead + AsyncWrite,
+    service: LspService<Backend>,
+    socket: ClientSocket,
+) {
+    let (read, write) = tokio::io::split(stream);
+    Server::new(read, write, socket).serve(service).await;
+}
+
+/// Serves a client over the Unix domain socket at `path`.
+///
+/// When something already listens there the server connects to it. That is how VS Code
+/// sets up the pipe transport: with `transport: TransportKind.pipe` in the server
+/// options of `vscode-languageclient`, the extension creates the socket, starts
+/// `bazel-lsp --pipe=<path>` and waits for it to connect. Otherwise the server creates
+/// the socket and serves the first client to connect, e.g. Helix or `socat`.
+#[cfg(unix)]
+async fn serve_pipe(path: &str, service: LspService<Backend>, socket: ClientSocket) {
+    use std::os::unix::fs::FileTypeExt;
+    use tokio::net::{UnixListener, UnixStream};
+
+    if let Ok(stream) = UnixStream::connect(path).await {
+        return serve(stream, service, socket).await;
+    }
+
+    // Nobody listens on a socket left behind by an earlier run
+    if std::fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        let _ = std::fs::remove_file(path);
+    }
+    let listener = UnixListener::bind(path)
+        .unwrap_or_else(|err| fail(format!("Failed to listen on {}: {}", path, err)));
+    eprintln!("Listening on {}", path);
+    let (stream, _) = listener
+        .accept()
+        .await
+        .unwrap_or_else(|err| fail(format!("Failed to accept a client on {}: {}", path, err)));
+    serve(stream, service, socket).await;
+}
+
+/// Serves a client over the named pipe `path`, e.g. `\\.\pipe\bazel-lsp`.
+///
+/// Like on Unix, the server connects to a pipe VS Code already created and creates the
+/// pipe itself otherwise.
+#[cfg(windows)]
+async fn serve_pipe(path: &str, service: LspService<Backend>, socket: ClientSocket) {
+    use tokio::net::windows::named_pipe::{ClientOptions, ServerOptions};
+
+    if let Ok(client) = ClientOptions::new().open(path) {
+        return serve(client, service, socket).await;
+    }
+
+    let server = ServerOptions::new()
+        .first_pipe_instance(true)
+        .create(path)
+        .unwrap_or_else(|err| fail(format!("Failed to listen on {}: {}", path, err)));
+    eprintln!("Listening on {}", path);
+    server
+        .connect()
+        .await
+        .unwrap_or_else(|err| fail(format!("Failed to accept a client on {}: {}", path, err)));
+    serve(server, service, socket).await;
+}
 
 fn main() {
+    let transport = transport(std::env::args().skip(1)).unwrap_or_else(|message| {
+        eprintln!("{}", message);
+        std::process::exit(2);
+    });
+
     let runtime = Runtime::new().unwrap();
     runtime.block_on(async {
-        let stdin = tokio::io::stdin();
-        let stdout = tokio::io::stdout();
+        let (service, socket) = build_service(Backend::new);
 
-        let (service, socket) = LspService::new(|client| Backend::new(client));
-        Server::new(stdin, stdout, socket).serve(service).await;
+        match transport {
+            Transport::Stdio => {
+                let stdin = tokio::io::stdin();
+                let stdout = tokio::io::stdout();
+                Server::new(stdin, stdout, socket).serve(service).await;
+            }
+            // Serves the first client to connect, e.g. for debugging with netcat
+            Transport::Tcp(port) => {
+                let listener = TcpListener::bind(("127.0.0.1", port))
+                    .await
+                    .unwrap_or_else(|err| {
+                        fail(format!("Failed to listen on port {}: {}", port, err))
+                    });
+                let address = listener.local_addr().unwrap_or_else(|err| {
+                    fail(format!("Failed to listen on port {}: {}", port, err))
+                });
+                eprintln!("Listening on {}", address);
+                let (stream, _) = listener.accept().await.unwrap_or_else(|err| {
+                    fail(format!(
+                        "Failed to accept a client on port {}: {}",
+                        port, err
+                    ))
+                });
+                serve(stream, service, socket).await;
+            }
+            Transport::Pipe(path) => serve_pipe(&path, service, socket).await,
+        }
     });
 }
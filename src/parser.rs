@@ -1,8 +1,12 @@
 use anyhow::Result;
-use std::sync::Mutex;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
 use tower_lsp::lsp_types::{Position, Range};
 use tree_sitter::StreamingIterator;
-use tree_sitter::{Parser, Query, QueryCursor};
+use tree_sitter::{Parser, Query, QueryCursor, Tree};
+
+/// Marks a file as UTF-8 when it starts with it, as some editors on Windows save them.
+const BYTE_ORDER_MARK: char = '\u{feff}';
 
 #[derive(Clone)]
 pub struct BazelTarget {
@@ -13,6 +17,75 @@ pub struct BazelTarget {
     pub rule_call_range: Range,
 }
 
+/// What a rule produces, told apart by the suffix of its name the way Bazel's own
+/// rules are named, e.g. `cc_test`, `py_binary` or `java_library`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleCategory {
+    Test,
+    Binary,
+    Library,
+    Other,
+}
+
+impl RuleCategory {
+    pub fn from_rule_type(rule_type: &str) -> Self {
+        if rule_type.ends_with("_test") {
+            Self::Test
+        } else if rule_type.ends_with("_binary") {
+            Self::Binary
+        } else if rule_type.ends_with("_library") {
+            Self::Library
+        } else {
+            Self::Other
+        }
+    }
+}
+
+impl BazelTarget {
+    pub fn rule_category(&self) -> RuleCategory {
+        RuleCategory::from_rule_type(&self.rule_type)
+    }
+
+    /// Whether the target can be run with `bazel test`.
+    pub fn is_test_rule(&self) -> bool {
+        self.rule_category() == RuleCategory::Test
+    }
+
+    /// Whether the target can be run with `bazel run`.
+    pub fn is_binary_rule(&self) -> bool {
+        self.rule_category() == RuleCategory::Binary
+    }
+
+    pub fn is_library_rule(&self) -> bool {
+        self.rule_category() == RuleCategory::Library
+    }
+}
+
+/// How [`BazelParser::sort_targets_in_text`] orders the targets of a BUILD file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetSortKey {
+    /// Alphabetically by name
+    Name,
+    /// By rule kind, then by name, which e.g. keeps `cc_library` targets before
+    /// `cc_test` ones
+    Kind,
+}
+
+/// A syntax error found by [`BazelParser::check_syntax`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    pub range: Range,
+    pub message: String,
+}
+
+impl std::fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for SyntaxError {}
+
 #[derive(Debug, Clone)]
 pub struct BazelAttribute {
     pub range: Range,
@@ -23,21 +96,374 @@ pub struct BazelString {
     pub range: Range,
 }
 
-pub struct BazelParser {
-    parser: Mutex<Parser>,
+/// A target declaration with its attributes parsed into typed values.
+#[derive(Debug, Clone)]
+pub struct ParsedTarget {
+    pub name: String,
+    pub rule_type: String,
+    pub attributes: HashMap<String, ParsedAttribute>,
+    pub range: Range,
+}
+
+#[derive(Debug, Clone)]
+pub struct ParsedAttribute {
+    pub value: AttributeValue,
+    /// Range of the attribute value
+    pub range: Range,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeValue {
+    String(String),
+    /// A list made up only of string literals
+    List(Vec<String>),
+    /// `select({...})`: each condition label with the value selected for it
+    Select(Vec<(String, AttributeValue)>),
+    /// Any other expression, as source text
+    Other(String),
+}
+
+/// A string literal together with the keyword argument it is passed to.
+#[derive(Debug, Clone)]
+pub struct BazelStringValue {
+    pub value: String,
+    /// Name of the enclosing keyword argument, e.g. `name` or `deps`
+    pub attribute: Option<String>,
+    pub range: Range,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LiteralKind {
+    Boolean,
+    Number,
+}
+
+#[derive(Debug, Clone)]
+pub struct BazelLiteral {
+    pub kind: LiteralKind,
+    pub range: Range,
+}
+
+/// A symbol imported by a `load` statement.
+///
+/// `name` is the local name and `original` the name exported by the `.bzl` file;
+/// they differ for aliased loads such as `load(":defs.bzl", my_alias = "my_rule")`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadedSymbol {
+    pub name: String,
+    pub original: String,
+}
+
+/// A `load(...)` statement.
+#[derive(Debug, Clone)]
+pub struct BazelLoad {
+    pub module: String,
+    pub symbols: Vec<LoadedSymbol>,
+    pub range: Range,
+}
+
+/// A file target declared by `exports_files([...])`, named after the file.
+#[derive(Debug, Clone)]
+pub struct BazelExportedFile {
+    pub name: String,
+    pub range: Range,
+}
+
+/// A top-level `def` in a `.bzl` file.
+#[derive(Debug, Clone)]
+pub struct BazelMacroDef {
+    pub name: String,
+    /// Range of the function name in the `def` line
+    pub name_range: Range,
+    pub range: Range,
+}
+
+/// A call of a plain function name, e.g. `my_macro(name = "x")` or `glob(["*.cc"])`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionCall {
+    pub name: String,
+    /// Range of the whole call, from the function name to the closing parenthesis
+    pub range: Range,
+    /// Source text of each argument, e.g. `name = "x"` or `["*.cc"]`
+    pub args: Vec<String>,
+}
+
+/// A file a `genrule` of the package generates, which other targets can use as a
+/// source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratedFile {
+    /// Path of the file relative to the package, as listed in `outs`
+    pub path: String,
+    /// Name of the genrule generating it
+    pub generator: String,
+}
+
+/// A `glob(...)` call found in a BUILD file.
+///
+/// `include` holds the positional (or `include =`) patterns and `exclude` the
+/// patterns passed through the `exclude` keyword argument.
+#[derive(Debug, Clone)]
+pub struct BazelGlob {
+    pub attribute: Option<String>,
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub range: Range,
+}
+
+impl BazelGlob {
+    /// Returns true if the path is matched by an include pattern and by no exclude pattern.
+    pub fn matches(&self, path: &str) -> bool {
+        self.include
+            .iter()
+            .any(|pattern| glob_pattern_matches(pattern, path))
+            && !self
+                .exclude
+                .iter()
+                .any(|pattern| glob_pattern_matches(pattern, path))
+    }
+
+    /// Filters the given files down to the ones this glob would expand to.
+    pub fn sources<'a>(&self, files: &[&'a str]) -> Vec<&'a str> {
+        files
+            .iter()
+            .copied()
+            .filter(|file| self.matches(file))
+            .collect()
+    }
+}
+
+/// Matches a package-relative path against a Bazel glob pattern.
+///
+/// Supports `*` and `?` within a path segment and `**` for any number of segments.
+pub fn glob_pattern_matches(pattern: &str, path: &str) -> bool {
+    let pattern_segments: Vec<&str> = pattern.split('/').collect();
+    let path_segments: Vec<&str> = path.split('/').collect();
+    match_segments(&pattern_segments, &path_segments)
+}
+
+fn match_segments(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| match_segments(rest, &path[skip..])),
+        Some((segment, rest)) => match path.split_first() {
+            Some((name, path_rest)) => {
+                match_segment(segment.as_bytes(), name.as_bytes())
+                    && match_segments(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+fn match_segment(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skip| match_segment(rest, &name[skip..])),
+        Some((b'?', rest)) => !name.is_empty() && match_segment(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && match_segment(rest, &name[1..]),
+    }
+}
+
+fn unquote(text: &str) -> Option<&str> {
+    if text.len() >= 2
+        && ((text.starts_with('"') && text.ends_with('"'))
+            || (text.starts_with('\'') && text.ends_with('\'')))
+    {
+        Some(&text[1..text.len() - 1])
+    } else {
+        None
+    }
+}
+
+/// The rule kind and name of a statement declaring a target, e.g. `cc_library(name = "x")`.
+fn target_statement(statement: tree_sitter::Node, source: &str) -> Option<(String, String)> {
+    if statement.kind() != "expression_statement" {
+        return None;
+    }
+    let call = statement
+        .named_child(0)
+        .filter(|call| call.kind() == "call")?;
+    let function = call
+        .child_by_field_name("function")
+        .filter(|function| function.kind() == "identifier")?;
+    let args = call.child_by_field_name("arguments")?;
+
+    let mut cursor = args.walk();
+    let name = args.named_children(&mut cursor).find_map(|arg| {
+        let name = arg.child_by_field_name("name")?;
+        let value = arg.child_by_field_name("value")?;
+        (arg.kind() == "keyword_argument"
+            && &source[name.byte_range()] == "name"
+            && value.kind() == "string")
+            .then(|| unquote(&source[value.byte_range()]))
+            .flatten()
+    })?;
+    Some((source[function.byte_range()].to_string(), name.to_string()))
+}
+
+fn string_list_items(list: tree_sitter::Node, source: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut cursor = list.walk();
+    for child in list.named_children(&mut cursor) {
+        if child.kind() == "string" {
+            if let Some(item) = unquote(&source[child.start_byte()..child.end_byte()]) {
+                items.push(item.to_string());
+            }
+        }
+    }
+    items
+}
+
+fn node_range(node: tree_sitter::Node) -> Range {
+    Range {
+        start: Position {
+            line: node.start_position().row as u32,
+            character: node.start_position().column as u32,
+        },
+        end: Position {
+            line: node.end_position().row as u32,
+            character: node.end_position().column as u32,
+        },
+    }
+}
+
+/// Whether a deps value is built only from lists, `select()`s of lists and `+`.
+fn is_list_or_select(node: tree_sitter::Node, source: &str) -> bool {
+    match node.kind() {
+        "list" => true,
+        "binary_operator" => {
+            let is_plus = node
+                .child_by_field_name("operator")
+                .is_some_and(|operator| &source[operator.byte_range()] == "+");
+            let mut cursor = node.walk();
+            is_plus
+                && node
+                    .named_children(&mut cursor)
+                    .all(|operand| is_list_or_select(operand, source))
+        }
+        "call" => {
+            let is_select = node
+                .child_by_field_name("function")
+                .is_some_and(|function| &source[function.byte_range()] == "select");
+            let dictionary = node
+                .child_by_field_name("arguments")
+                .and_then(|args| args.named_child(0))
+                .filter(|arg| arg.kind() == "dictionary");
+            let Some(dictionary) = dictionary.filter(|_| is_select) else {
+                return false;
+            };
+            let mut cursor = dictionary.walk();
+            let all_lists = dictionary
+                .named_children(&mut cursor)
+                .filter_map(|pair| pair.child_by_field_name("value"))
+                .all(|value| value.kind() == "list");
+            all_lists
+        }
+        _ => false,
+    }
+}
+
+fn attribute_value(node: tree_sitter::Node, source: &str) -> AttributeValue {
+    let text = &source[node.start_byte()..node.end_byte()];
+
+    match node.kind() {
+        "string" => {
+            if let Some(value) = unquote(text) {
+                return AttributeValue::String(value.to_string());
+            }
+        }
+        "list" => {
+            let mut cursor = node.walk();
+            let only_strings = node
+                .named_children(&mut cursor)
+                .all(|child| matches!(child.kind(), "string" | "comment"));
+            if only_strings {
+                return AttributeValue::List(string_list_items(node, source));
+            }
+        }
+        "call" => {
+            let is_select = node
+                .child_by_field_name("function")
+                .is_some_and(|function| &source[function.byte_range()] == "select");
+            let dictionary = node
+                .child_by_field_name("arguments")
+                .and_then(|args| args.named_child(0))
+                .filter(|arg| arg.kind() == "dictionary");
+
+            if let (true, Some(dictionary)) = (is_select, dictionary) {
+                let mut branches = Vec::new();
+                let mut cursor = dictionary.walk();
+                for pair in dictionary.named_children(&mut cursor) {
+                    let key = pair.child_by_field_name("key");
+                    let value = pair.child_by_field_name("value");
+                    if let (Some(key), Some(value)) = (key, value) {
+                        if let Some(condition) = unquote(&source[key.byte_range()]) {
+                            branches.push((condition.to_string(), attribute_value(value, source)));
+                        }
+                    }
+                }
+                return AttributeValue::Select(branches);
+            }
+        }
+        _ => {}
+    }
+
+    AttributeValue::Other(text.to_string())
+}
+
+/// The Starlark dialect a file is written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BazelParserMode {
+    /// `BUILD` and `BUILD.bazel` files: rule calls only, no `def` statements
+    #[default]
+    Build,
+    /// `.bzl` extension files, which may define functions and macros
+    Starlark,
+}
+
+impl BazelParserMode {
+    /// Picks the mode from a file name or URI: `.bzl` files are `Starlark`,
+    /// everything else is treated as a BUILD file.
+    pub fn from_file_name(name: &str) -> Self {
+        if name.ends_with(".bzl") {
+            Self::Starlark
+        } else {
+            Self::Build
+        }
+    }
+}
+
+/// Queries shared by every parser instance. They only depend on the grammar, and
+/// compiling them is far more expensive than creating a tree-sitter parser.
+struct Queries {
     target_query: Query,
+    top_level_target_query: Query,
     attribute_query: Query,
     string_query: Query,
     deps_query: Query,
+    glob_query: Query,
+    literal_query: Query,
+    load_query: Query,
+    conditional_deps_query: Query,
+    unsortable_deps_query: Query,
+    exports_files_query: Query,
+    call_query: Query,
+    def_query: Query,
 }
 
-impl BazelParser {
-    pub fn new() -> Result<Self> {
-        let mut parser = Parser::new();
+static QUERIES: OnceLock<Queries> = OnceLock::new();
+
+impl Queries {
+    fn get() -> Result<&'static Self> {
+        if let Some(queries) = QUERIES.get() {
+            return Ok(queries);
+        }
+        let queries = Self::compile()?;
+        Ok(QUERIES.get_or_init(|| queries))
+    }
+
+    fn compile() -> Result<Self> {
         let language = tree_sitter_starlark::LANGUAGE;
-        parser
-            .set_language(&language.into())
-            .expect("Error loading Starlark parser");
 
         let target_query = Query::new(
             &language.into(),
@@ -47,6 +473,7 @@ impl BazelParser {
                 arguments: (argument_list
                     (keyword_argument
                         name: (identifier) @arg_name
+                        (#eq? @arg_name "name")
                         value: (string) @target_name
                     ) @first_name
                 )
@@ -54,6 +481,28 @@ impl BazelParser {
             "#,
         )?;
 
+        // Rule calls inside a `def` body are macro templates, not targets, so in
+        // `.bzl` files only module-level calls declare targets
+        let top_level_target_query = Query::new(
+            &language.into(),
+            r#"
+            (module
+                (expression_statement
+                    (call
+                        function: (identifier) @rule_type
+                        arguments: (argument_list
+                            (keyword_argument
+                                name: (identifier) @arg_name
+                                (#eq? @arg_name "name")
+                                value: (string) @target_name
+                            ) @first_name
+                        )
+                    )
+                )
+            )
+            "#,
+        )?;
+
         let attribute_query = Query::new(
             &language.into(),
             r#"
@@ -81,35 +530,264 @@ impl BazelParser {
             "#,
         )?;
 
+        let glob_query = Query::new(
+            &language.into(),
+            r#"
+            (call
+                function: (identifier) @function
+                (#eq? @function "glob")
+                arguments: (argument_list) @args
+            ) @glob
+            "#,
+        )?;
+
+        let literal_query = Query::new(
+            &language.into(),
+            r#"
+            [(true) (false)] @boolean
+            [(integer) (float)] @number
+            "#,
+        )?;
+
+        let load_query = Query::new(
+            &language.into(),
+            r#"
+            (call
+                function: (identifier) @function
+                (#eq? @function "load")
+                arguments: (argument_list) @args
+            ) @load
+            "#,
+        )?;
+
+        // deps built from `select({...})` or `[...] + select({...})`
+        let conditional_deps_query = Query::new(
+            &language.into(),
+            r#"
+            (keyword_argument
+                name: (identifier) @attr_name
+                (#eq? @attr_name "deps")
+                value: [(call) (binary_operator)] @deps_value
+            )
+            "#,
+        )?;
+
+        // Any deps value; the ones that aren't a plain list are filtered in code
+        let unsortable_deps_query = Query::new(
+            &language.into(),
+            r#"
+            (keyword_argument
+                name: (identifier) @attr_name
+                (#eq? @attr_name "deps")
+                value: (_) @deps_value
+            )
+            "#,
+        )?;
+
+        let exports_files_query = Query::new(
+            &language.into(),
+            r#"
+            (call
+                function: (identifier) @function
+                (#eq? @function "exports_files")
+                arguments: (argument_list) @args
+            )
+            "#,
+        )?;
+
+        let call_query = Query::new(
+            &language.into(),
+            r#"
+            (call
+                function: (identifier) @name
+                arguments: (argument_list) @args
+            ) @call
+            "#,
+        )?;
+
+        // Functions defined at the top level of a file
+        let def_query = Query::new(
+            &language.into(),
+            r#"
+            (module
+                (function_definition
+                    name: (identifier) @name
+                ) @def
+            )
+            "#,
+        )?;
+
+        Ok(Self {
+            exports_files_query,
+            call_query,
+            def_query,
+            target_query,
+            top_level_target_query,
+            attribute_query,
+            string_query,
+            deps_query,
+            glob_query,
+            literal_query,
+            load_query,
+            conditional_deps_query,
+            unsortable_deps_query,
+        })
+    }
+}
+
+pub struct BazelParser {
+    parser: Mutex<Parser>,
+    mode: BazelParserMode,
+    target_query: &'static Query,
+    attribute_query: &'static Query,
+    string_query: &'static Query,
+    deps_query: &'static Query,
+    glob_query: &'static Query,
+    literal_query: &'static Query,
+    load_query: &'static Query,
+    conditional_deps_query: &'static Query,
+    unsortable_deps_query: &'static Query,
+    exports_files_query: &'static Query,
+    call_query: &'static Query,
+    def_query: &'static Query,
+}
+
+impl BazelParser {
+    pub fn new() -> Result<Self> {
+        Self::new_with_mode(BazelParserMode::Build)
+    }
+
+    pub fn new_with_mode(mode: BazelParserMode) -> Result<Self> {
+        let mut parser = Parser::new();
+        let language = tree_sitter_starlark::LANGUAGE;
+        parser
+            .set_language(&language.into())
+            .expect("Error loading Starlark parser");
+
+        let queries = Queries::get()?;
+
         Ok(Self {
             parser: Mutex::new(parser),
-            target_query: target_query,
-            attribute_query: attribute_query,
-            string_query: string_query,
-            deps_query: deps_query,
+            mode,
+            target_query: match mode {
+                BazelParserMode::Build => &queries.target_query,
+                BazelParserMode::Starlark => &queries.top_level_target_query,
+            },
+            attribute_query: &queries.attribute_query,
+            string_query: &queries.string_query,
+            deps_query: &queries.deps_query,
+            glob_query: &queries.glob_query,
+            literal_query: &queries.literal_query,
+            load_query: &queries.load_query,
+            conditional_deps_query: &queries.conditional_deps_query,
+            unsortable_deps_query: &queries.unsortable_deps_query,
+            exports_files_query: &queries.exports_files_query,
+            call_query: &queries.call_query,
+            def_query: &queries.def_query,
         })
     }
 
-    pub fn parse(&self, source: &str) -> Result<String> {
-        self.parser
-            .lock()
-            .unwrap()
+    /// Parses `source` into a tree-sitter syntax tree, for callers that need more than
+    /// the extractors below offer. Syntax errors are kept in the tree as error nodes.
+    ///
+    /// A leading byte order mark is left out of the tree. Tree-sitter columns count
+    /// bytes, so the tree starts at column 1 after it: the mark is a single character
+    /// of the first line to the client, as it is to `position_to_byte_index`.
+    pub fn parse_to_tree(&self, source: &str) -> Result<Tree> {
+        let mut parser = self.parser.lock().unwrap();
+        let included_ranges = match source.strip_prefix(BYTE_ORDER_MARK) {
+            Some(_) => vec![tree_sitter::Range {
+                start_byte: BYTE_ORDER_MARK.len_utf8(),
+                end_byte: u32::MAX as usize,
+                start_point: tree_sitter::Point::new(0, 1),
+                end_point: tree_sitter::Point::new(u32::MAX as usize, u32::MAX as usize),
+            }],
+            None => Vec::new(),
+        };
+        parser.set_included_ranges(&included_ranges)?;
+        parser
             .parse(source, None)
-            .ok_or_else(|| anyhow::anyhow!("Failed to parse BUILD file"))?;
-        Ok(source.to_string())
+            .ok_or_else(|| anyhow::anyhow!("Failed to parse BUILD file"))
+    }
+
+    /// Finds the syntax errors in `source`: error and missing nodes of the tree, and in
+    /// BUILD files function definitions, which only `.bzl` files may contain.
+    pub fn check_syntax(&self, source: &str) -> Result<Vec<SyntaxError>> {
+        let tree = self.parse_to_tree(source)?;
+
+        let mut errors = Vec::new();
+        let mut pending = vec![tree.root_node()];
+        while let Some(node) = pending.pop() {
+            if node.is_missing() {
+                errors.push(SyntaxError {
+                    range: node_range(node),
+                    message: format!(
+                        "Syntax error at line {}, column {}: missing `{}`",
+                        node.start_position().row + 1,
+                        node.start_position().column + 1,
+                        node.kind()
+                    ),
+                });
+            } else if node.is_error() {
+                // One error per error node, not one for each of its children
+                errors.push(SyntaxError {
+                    range: node_range(node),
+                    message: format!(
+                        "Syntax error at line {}, column {}",
+                        node.start_position().row + 1,
+                        node.start_position().column + 1
+                    ),
+                });
+            } else if node.has_error() {
+                let mut cursor = node.walk();
+                let children: Vec<_> = node.children(&mut cursor).collect();
+                pending.extend(children.into_iter().rev());
+            }
+        }
+
+        if self.mode == BazelParserMode::Build {
+            let mut cursor = tree.walk();
+            for statement in tree.root_node().named_children(&mut cursor) {
+                if statement.kind() == "function_definition" {
+                    errors.push(SyntaxError {
+                        range: node_range(statement),
+                        message: format!(
+                            "Syntax error at line {}, column {}: functions can only be defined in .bzl files",
+                            statement.start_position().row + 1,
+                            statement.start_position().column + 1
+                        ),
+                    });
+                }
+            }
+        }
+
+        Ok(errors)
     }
 
+    pub fn mode(&self) -> BazelParserMode {
+        self.mode
+    }
+
+    /// Like [`extract_targets`](Self::extract_targets), for a BUILD file read as bytes.
+    /// Fails when `source` isn't valid UTF-8.
+    pub fn extract_targets_bytes(&self, source: &[u8]) -> Result<Vec<BazelTarget>> {
+        let source = std::str::from_utf8(source)
+            .map_err(|err| anyhow::anyhow!("BUILD file is not valid UTF-8: {}", err))?;
+        self.extract_targets(source)
+    }
+
+    /// Finds the targets declared in a BUILD file, i.e. the calls with a string literal
+    /// `name` argument.
+    ///
+    /// Starlark isn't evaluated, so a call whose name is computed or forwarded, like
+    /// `cc_library(**kwargs)` in a macro, is skipped rather than reported under a wrong
+    /// name. Attributes forwarded through `**kwargs` are unknown too.
     pub fn extract_targets(&self, source: &str) -> Result<Vec<BazelTarget>> {
-        let tree = self
-            .parser
-            .lock()
-            .unwrap()
-            .parse(source, None)
-            .ok_or_else(|| anyhow::anyhow!("Failed to parse BUILD file"))?;
+        let tree = self.parse_to_tree(source)?;
 
         let mut targets = Vec::new();
         let mut cursor = QueryCursor::new();
-        let mut matches = cursor.matches(&self.target_query, tree.root_node(), source.as_bytes());
+        let mut matches = cursor.matches(self.target_query, tree.root_node(), source.as_bytes());
 
         let mut processed_rule_calls = std::collections::HashSet::new();
 
@@ -127,108 +805,193 @@ impl BazelParser {
                     0 => {
                         rule_type = text.to_string();
                         rule_type_node = Some(node);
-
-                        // Find the parent call node which represents the entire target
-                        let mut current = node.parent();
-                        while let Some(parent) = current {
-                            if parent.kind() == "call" {
-                                rule_call_node = Some(parent);
-                                break;
-                            }
-                            current = parent.parent();
-                        }
                     }
                     2 => {
-                        if text.starts_with('"') && text.ends_with('"') {
-                            target_name = text[1..text.len() - 1].to_string();
+                        // The node spans exactly the literal, quotes included, however
+                        // tightly the call is written
+                        if let Some(name) = unquote(text) {
+                            target_name = name.to_string();
                         }
                     }
+                    3 => {
+                        // The call that directly owns the `name` argument represents the
+                        // target, even when it is itself nested in another call such as
+                        // `my_wrapper(cc_library(name = "x"))`.
+                        rule_call_node = node
+                            .parent()
+                            .filter(|args| args.kind() == "argument_list")
+                            .and_then(|args| args.parent())
+                            .filter(|call| call.kind() == "call");
+                    }
                     _ => {}
                 }
             }
 
             if let Some(rule_call) = rule_call_node {
-                let rule_call_id = rule_call.id();
-                if !processed_rule_calls.contains(&rule_call_id) {
-                    processed_rule_calls.insert(rule_call_id);
-
-                    if !rule_type.is_empty() && !target_name.is_empty() {
-                        // Create the rule type range
-                        let rule_type_range = if let Some(rule_type_node) = rule_type_node {
-                            Range {
-                                start: Position {
-                                    line: rule_type_node.start_position().row as u32,
-                                    character: rule_type_node.start_position().column as u32,
-                                },
-                                end: Position {
-                                    line: rule_type_node.end_position().row as u32,
-                                    character: rule_type_node.end_position().column as u32,
-                                },
-                            }
-                        } else {
-                            // Fallback to the start of the rule call if rule type node is not available
-                            Range {
-                                start: Position {
-                                    line: rule_call.start_position().row as u32,
-                                    character: rule_call.start_position().column as u32,
-                                },
-                                end: Position {
-                                    line: rule_call.start_position().row as u32,
-                                    character: rule_call.start_position().column as u32
-                                        + rule_type.len() as u32,
-                                },
-                            }
-                        };
+                // Unlike node ids, byte ranges identify a call in any tree of the source
+                if processed_rule_calls.insert(rule_call.byte_range())
+                    && !rule_type.is_empty()
+                    && !target_name.is_empty()
+                {
+                    // Create the rule type range
+                    let rule_type_range = if let Some(rule_type_node) = rule_type_node {
+                        Range {
+                            start: Position {
+                                line: rule_type_node.start_position().row as u32,
+                                character: rule_type_node.start_position().column as u32,
+                            },
+                            end: Position {
+                                line: rule_type_node.end_position().row as u32,
+                                character: rule_type_node.end_position().column as u32,
+                            },
+                        }
+                    } else {
+                        // Fallback to the start of the rule call if rule type node is not available
+                        Range {
+                            start: Position {
+                                line: rule_call.start_position().row as u32,
+                                character: rule_call.start_position().column as u32,
+                            },
+                            end: Position {
+                                line: rule_call.start_position().row as u32,
+                                character: rule_call.start_position().column as u32
+                                    + rule_type.len() as u32,
+                            },
+                        }
+                    };
+
+                    // Create the rule call range (from rule type to closing parenthesis)
+                    let rule_call_range = Range {
+                        start: Position {
+                            line: rule_type_range.start.line,
+                            character: rule_type_range.start.character,
+                        },
+                        end: Position {
+                            line: rule_call.end_position().row as u32,
+                            character: rule_call.end_position().column as u32,
+                        },
+                    };
 
-                        // Create the rule call range (from rule type to closing parenthesis)
-                        let rule_call_range = Range {
+                    // Use the range of the entire call node instead of just the rule type
+                    targets.push(BazelTarget {
+                        name: target_name,
+                        rule_type,
+                        range: Range {
                             start: Position {
-                                line: rule_type_range.start.line,
-                                character: rule_type_range.start.character,
+                                line: rule_call.start_position().row as u32,
+                                character: rule_call.start_position().column as u32,
                             },
                             end: Position {
                                 line: rule_call.end_position().row as u32,
                                 character: rule_call.end_position().column as u32,
                             },
-                        };
+                        },
+                        rule_type_range,
+                        rule_call_range,
+                    });
+                }
+            }
+        }
 
-                        // Use the range of the entire call node instead of just the rule type
-                        targets.push(BazelTarget {
-                            name: target_name,
-                            rule_type,
-                            range: Range {
-                                start: Position {
-                                    line: rule_call.start_position().row as u32,
-                                    character: rule_call.start_position().column as u32,
-                                },
-                                end: Position {
-                                    line: rule_call.end_position().row as u32,
-                                    character: rule_call.end_position().column as u32,
-                                },
-                            },
-                            rule_type_range,
-                            rule_call_range,
-                        });
-                    }
+        Ok(targets)
+    }
+
+    /// Parses every target in a BUILD file together with its typed attribute values.
+    /// Like [`extract_targets`](Self::extract_targets), calls without a literal name are
+    /// skipped, and only keyword arguments become attributes.
+    pub fn parse_build_file(&self, source: &str) -> Result<Vec<ParsedTarget>> {
+        let tree = self.parse_to_tree(source)?;
+
+        let mut targets = Vec::new();
+        let mut cursor = QueryCursor::new();
+        let mut matches = cursor.matches(self.target_query, tree.root_node(), source.as_bytes());
+
+        let mut processed_rule_calls = std::collections::HashSet::new();
+
+        while let Some(m) = matches.next() {
+            let Some(rule_call) = m
+                .captures
+                .iter()
+                .find(|c| c.index == 3)
+                .and_then(|c| c.node.parent())
+                .and_then(|args| args.parent())
+                .filter(|call| call.kind() == "call")
+            else {
+                continue;
+            };
+            if !processed_rule_calls.insert(rule_call.byte_range()) {
+                continue;
+            }
+
+            let (Some(function), Some(args)) = (
+                rule_call.child_by_field_name("function"),
+                rule_call.child_by_field_name("arguments"),
+            ) else {
+                continue;
+            };
+
+            let mut attributes = HashMap::new();
+            let mut args_cursor = args.walk();
+            for arg in args.named_children(&mut args_cursor) {
+                if arg.kind() != "keyword_argument" {
+                    continue;
+                }
+                if let (Some(name), Some(value)) = (
+                    arg.child_by_field_name("name"),
+                    arg.child_by_field_name("value"),
+                ) {
+                    attributes.insert(
+                        source[name.byte_range()].to_string(),
+                        ParsedAttribute {
+                            value: attribute_value(value, source),
+                            range: node_range(value),
+                        },
+                    );
                 }
             }
+
+            let name = match attributes.get("name").map(|attr| &attr.value) {
+                Some(AttributeValue::String(name)) if !name.is_empty() => name.clone(),
+                _ => continue,
+            };
+
+            targets.push(ParsedTarget {
+                name,
+                rule_type: source[function.byte_range()].to_string(),
+                attributes,
+                range: node_range(rule_call),
+            });
         }
 
         Ok(targets)
     }
 
+    /// The files the genrules of a BUILD file list in their `outs`. Outputs that aren't
+    /// string literals, e.g. built by a list comprehension, are unknown.
+    pub fn generated_files(&self, source: &str) -> Result<Vec<GeneratedFile>> {
+        let mut files = Vec::new();
+        for target in self.parse_build_file(source)? {
+            if target.rule_type != "genrule" {
+                continue;
+            }
+            if let Some(AttributeValue::List(outs)) =
+                target.attributes.get("outs").map(|outs| &outs.value)
+            {
+                files.extend(outs.iter().map(|path| GeneratedFile {
+                    path: path.clone(),
+                    generator: target.name.clone(),
+                }));
+            }
+        }
+        Ok(files)
+    }
+
     pub fn extract_attributes(&self, source: &str) -> Result<Vec<BazelAttribute>> {
-        let tree = self
-            .parser
-            .lock()
-            .unwrap()
-            .parse(source, None)
-            .ok_or_else(|| anyhow::anyhow!("Failed to parse BUILD file"))?;
+        let tree = self.parse_to_tree(source)?;
 
         let mut attributes = Vec::new();
         let mut cursor = QueryCursor::new();
-        let mut matches =
-            cursor.matches(&self.attribute_query, tree.root_node(), source.as_bytes());
+        let mut matches = cursor.matches(self.attribute_query, tree.root_node(), source.as_bytes());
 
         while let Some(m) = matches.next() {
             for capture in m.captures {
@@ -253,22 +1016,51 @@ impl BazelParser {
     }
 
     pub fn extract_strings(&self, source: &str) -> Result<Vec<BazelString>> {
-        let tree = self
-            .parser
-            .lock()
-            .unwrap()
-            .parse(source, None)
-            .ok_or_else(|| anyhow::anyhow!("Failed to parse BUILD file"))?;
+        let tree = self.parse_to_tree(source)?;
 
         let mut strings = Vec::new();
         let mut cursor = QueryCursor::new();
-        let mut matches = cursor.matches(&self.string_query, tree.root_node(), source.as_bytes());
+        let mut matches = cursor.matches(self.string_query, tree.root_node(), source.as_bytes());
+
+        while let Some(m) = matches.next() {
+            for capture in m.captures {
+                let node = capture.node;
+
+                strings.push(BazelString {
+                    range: Range {
+                        start: Position {
+                            line: node.start_position().row as u32,
+                            character: node.start_position().column as u32,
+                        },
+                        end: Position {
+                            line: node.end_position().row as u32,
+                            character: node.end_position().column as u32,
+                        },
+                    },
+                });
+            }
+        }
+
+        Ok(strings)
+    }
+
+    pub fn extract_literals(&self, source: &str) -> Result<Vec<BazelLiteral>> {
+        let tree = self.parse_to_tree(source)?;
+
+        let mut literals = Vec::new();
+        let mut cursor = QueryCursor::new();
+        let mut matches = cursor.matches(self.literal_query, tree.root_node(), source.as_bytes());
 
         while let Some(m) = matches.next() {
             for capture in m.captures {
                 let node = capture.node;
+                let kind = match capture.index {
+                    0 => LiteralKind::Boolean,
+                    _ => LiteralKind::Number,
+                };
 
-                strings.push(BazelString {
+                literals.push(BazelLiteral {
+                    kind,
                     range: Range {
                         start: Position {
                             line: node.start_position().row as u32,
@@ -283,26 +1075,152 @@ impl BazelParser {
             }
         }
 
-        Ok(strings)
+        Ok(literals)
+    }
+
+    pub fn extract_globs(&self, source: &str) -> Result<Vec<BazelGlob>> {
+        let tree = self.parse_to_tree(source)?;
+
+        let mut globs = Vec::new();
+        let mut cursor = QueryCursor::new();
+        let mut matches = cursor.matches(self.glob_query, tree.root_node(), source.as_bytes());
+
+        while let Some(m) = matches.next() {
+            let mut args_node = None;
+            let mut glob_node = None;
+
+            for capture in m.captures {
+                match capture.index {
+                    1 => args_node = Some(capture.node),
+                    2 => glob_node = Some(capture.node),
+                    _ => {}
+                }
+            }
+
+            let (Some(args), Some(glob)) = (args_node, glob_node) else {
+                continue;
+            };
+
+            let mut include = Vec::new();
+            let mut exclude = Vec::new();
+            let mut args_cursor = args.walk();
+            for arg in args.named_children(&mut args_cursor) {
+                match arg.kind() {
+                    "list" => include.extend(string_list_items(arg, source)),
+                    "keyword_argument" => {
+                        let name = arg
+                            .child_by_field_name("name")
+                            .map(|n| &source[n.start_byte()..n.end_byte()]);
+                        let value = arg.child_by_field_name("value");
+                        match (name, value) {
+                            (Some("include"), Some(list)) if list.kind() == "list" => {
+                                include.extend(string_list_items(list, source))
+                            }
+                            (Some("exclude"), Some(list)) if list.kind() == "list" => {
+                                exclude.extend(string_list_items(list, source))
+                            }
+                            _ => {}
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            // The attribute the glob is assigned to, e.g. `srcs = glob(...)` or
+            // `srcs = glob(...) + [...]`
+            let mut value = glob;
+            while let Some(parent) = value
+                .parent()
+                .filter(|parent| parent.kind() == "binary_operator")
+            {
+                value = parent;
+            }
+            let attribute = value
+                .parent()
+                .filter(|parent| parent.kind() == "keyword_argument")
+                .and_then(|parent| parent.child_by_field_name("name"))
+                .map(|n| source[n.start_byte()..n.end_byte()].to_string());
+
+            globs.push(BazelGlob {
+                attribute,
+                include,
+                exclude,
+                range: Range {
+                    start: Position {
+                        line: glob.start_position().row as u32,
+                        character: glob.start_position().column as u32,
+                    },
+                    end: Position {
+                        line: glob.end_position().row as u32,
+                        character: glob.end_position().column as u32,
+                    },
+                },
+            });
+        }
+
+        Ok(globs)
+    }
+
+    /// Counts the labels in the `deps` of the target named `target_name`, without
+    /// extracting every target. A label listed twice counts once, as
+    /// [`sort_deps_in_text`](Self::sort_deps_in_text) removes duplicates. 0 when there
+    /// is no such target or it has no `deps` list.
+    pub fn count_deps(&self, source: &str, target_name: &str) -> Result<usize> {
+        let tree = self.parse_to_tree(source)?;
+
+        let mut cursor = QueryCursor::new();
+        let mut matches = cursor.matches(self.deps_query, tree.root_node(), source.as_bytes());
+        while let Some(m) = matches.next() {
+            let Some(deps_list) = m.captures.iter().find(|c| c.index == 1).map(|c| c.node) else {
+                continue;
+            };
+
+            // keyword_argument -> argument_list -> call -> expression_statement
+            let statement = deps_list
+                .parent()
+                .and_then(|arg| arg.parent())
+                .and_then(|args| args.parent())
+                .and_then(|call| call.parent());
+            let is_target = statement
+                .and_then(|statement| target_statement(statement, source))
+                .is_some_and(|(_, name)| name == target_name);
+            if is_target {
+                let mut deps = string_list_items(deps_list, source);
+                deps.sort();
+                deps.dedup();
+                return Ok(deps.len());
+            }
+        }
+
+        Ok(0)
     }
 
+    /// Sorts the labels of every `deps` list. Fails with the first [`SyntaxError`] of
+    /// `source`, as moving lines around a syntax error could break the file further.
     pub fn sort_deps_in_text(&self, source: &str) -> Result<String> {
-        let tree = self
-            .parser
-            .lock()
-            .unwrap()
-            .parse(source, None)
-            .ok_or_else(|| anyhow::anyhow!("Failed to parse BUILD file"))?;
+        let edits = self.compute_deps_sort_edits(source)?;
+        Ok(self.apply_edits(source, &edits))
+    }
+
+    /// The edits [`sort_deps_in_text`](Self::sort_deps_in_text) makes, as the range of a
+    /// `deps` argument and its sorted replacement. One per list whose text changes, in
+    /// document order.
+    pub fn compute_deps_sort_edits(&self, source: &str) -> Result<Vec<(Range, String)>> {
+        if let Some(error) = self.check_syntax(source)?.into_iter().next() {
+            return Err(error.into());
+        }
+
+        let tree = self.parse_to_tree(source)?;
 
         let mut cursor = QueryCursor::new();
-        let mut matches = cursor.matches(&self.deps_query, tree.root_node(), source.as_bytes());
+        let mut matches = cursor.matches(self.deps_query, tree.root_node(), source.as_bytes());
 
-        let mut result = source.to_string();
-        let mut changes = Vec::new();
+        let mut edits = Vec::new();
 
         while let Some(m) = matches.next() {
             let mut deps: Vec<(String, String)> = Vec::new();
             let mut deps_range = None;
+            let mut has_multi_line_string = false;
 
             for capture in m.captures {
                 let node = capture.node;
@@ -315,6 +1233,17 @@ impl BazelParser {
                     }
                     1 => {
                         // This is the deps_list capture
+                        // Deps are moved line by line, which would split a string spanning
+                        // lines, e.g. a triple-quoted one or a label being edited
+                        let mut list_cursor = node.walk();
+                        has_multi_line_string = node.named_children(&mut list_cursor).any(|item| {
+                            item.kind() == "string"
+                                && item.start_position().row != item.end_position().row
+                        });
+                        if has_multi_line_string {
+                            continue;
+                        }
+
                         let list_text = text.trim();
                         if list_text.starts_with('[') && list_text.ends_with(']') {
                             let content = &list_text[1..list_text.len() - 1];
@@ -330,7 +1259,8 @@ impl BazelParser {
                                     if let Some(comment_start) = dep_line.find('#') {
                                         dep = dep_line[..comment_start].trim().to_string();
                                     }
-                                    if dep.starts_with('"') && dep.ends_with('"') {
+                                    if dep.len() >= 2 && dep.starts_with('"') && dep.ends_with('"')
+                                    {
                                         let dep_name = dep[1..dep.len() - 1].to_string();
                                         // Keep the first occurrence of each dependency with its comment
                                         if !deps.iter().any(|(name, _)| name == &dep_name) {
@@ -358,6 +1288,10 @@ impl BazelParser {
                 }
             }
 
+            if has_multi_line_string {
+                continue;
+            }
+
             if let Some(range) = deps_range {
                 // Sort dependencies
                 deps.sort_by(|a, b| a.0.cmp(&b.0));
@@ -373,16 +1307,210 @@ impl BazelParser {
                     )
                 };
 
-                let start = self.position_to_byte_index(&result, &range.start);
-                let end = self.position_to_byte_index(&result, &range.end);
-                changes.push((start, end, formatted_deps));
+                let start = self.position_to_byte_index(source, &range.start);
+                let end = self.position_to_byte_index(source, &range.end);
+                if source[start..end] != formatted_deps {
+                    edits.push((range, formatted_deps));
+                }
             }
         }
 
+        edits.sort_by_key(|(range, _)| range.start);
+        Ok(edits)
+    }
+
+    /// Applies non-overlapping `edits` of `source`, each replacing a range with a text.
+    pub fn apply_edits(&self, source: &str, edits: &[(Range, String)]) -> String {
+        let mut changes: Vec<(usize, usize, &str)> = edits
+            .iter()
+            .map(|(range, new_text)| {
+                (
+                    self.position_to_byte_index(source, &range.start),
+                    self.position_to_byte_index(source, &range.end),
+                    new_text.as_str(),
+                )
+            })
+            .collect();
+
         // Apply changes in reverse order to maintain correct indices
         changes.sort_by(|a, b| b.0.cmp(&a.0));
-        for (start, end, formatted_deps) in changes {
-            result.replace_range(start..end, &formatted_deps);
+        let mut result = source.to_string();
+        for (start, end, new_text) in changes {
+            result.replace_range(start..end, new_text);
+        }
+        result
+    }
+
+    /// Formats a BUILD file: sorts its deps, collapses runs of blank lines and strips
+    /// trailing whitespace, see [`normalize_blank_lines`](Self::normalize_blank_lines)
+    /// and [`strip_trailing_whitespace`](Self::strip_trailing_whitespace). Fails with the
+    /// first [`SyntaxError`] of `source`, like [`sort_deps_in_text`](Self::sort_deps_in_text).
+    pub fn format_document(&self, source: &str) -> Result<String> {
+        let sorted = self.sort_deps_in_text(source)?;
+        let normalized = self.normalize_blank_lines(&sorted)?;
+        self.strip_trailing_whitespace(&normalized)
+    }
+
+    /// Collapses consecutive blank lines into one and ends the file with a single
+    /// newline. Blank lines inside multi-line strings are kept.
+    pub fn normalize_blank_lines(&self, source: &str) -> Result<String> {
+        let in_string = self.multi_line_string_rows(source)?;
+        let newline = if source.contains("\r\n") {
+            "\r\n"
+        } else {
+            "\n"
+        };
+
+        let mut lines: Vec<&str> = Vec::new();
+        for (row, line) in source.lines().enumerate() {
+            let is_blank = |line: &str| line.trim().is_empty();
+            let protected = in_string.iter().any(|rows| rows.contains(&row));
+            if !protected && is_blank(line) && lines.last().is_some_and(|last| is_blank(last)) {
+                continue;
+            }
+            lines.push(line);
+        }
+        while lines.last().is_some_and(|line| line.trim().is_empty()) {
+            lines.pop();
+        }
+
+        if lines.is_empty() {
+            return Ok(String::new());
+        }
+        Ok(lines.join(newline) + newline)
+    }
+
+    /// Strips the whitespace at the end of every line, except lines continued by a
+    /// multi-line string, where it is part of the string.
+    pub fn strip_trailing_whitespace(&self, source: &str) -> Result<String> {
+        let in_string = self.multi_line_string_rows(source)?;
+
+        let mut result = String::with_capacity(source.len());
+        for (row, line) in source.split_inclusive('\n').enumerate() {
+            let content = line.trim_end_matches(['\n', '\r']);
+            if in_string.iter().any(|rows| rows.contains(&row)) {
+                result.push_str(content);
+            } else {
+                result.push_str(content.trim_end());
+            }
+            result.push_str(&line[content.len()..]);
+        }
+        Ok(result)
+    }
+
+    /// The rows a multi-line string continues past, i.e. from its first row up to but
+    /// not including its last.
+    fn multi_line_string_rows(&self, source: &str) -> Result<Vec<std::ops::Range<usize>>> {
+        Ok(self
+            .extract_strings(source)?
+            .into_iter()
+            .filter(|string| string.range.start.line != string.range.end.line)
+            .map(|string| string.range.start.line as usize..string.range.end.line as usize)
+            .collect())
+    }
+
+    /// Orders the targets of a BUILD file by `key`.
+    ///
+    /// A target moves together with the comment lines directly above it and a comment
+    /// at the end of its last line. Only runs of consecutive targets are sorted, so no
+    /// target moves past a `load`, another statement or a comment set apart by blank
+    /// lines, such as a section header. The blank lines between targets stay where
+    /// they are. Fails with the first [`SyntaxError`] of `source`, like
+    /// [`sort_deps_in_text`](Self::sort_deps_in_text).
+    pub fn sort_targets_in_text(&self, source: &str, key: TargetSortKey) -> Result<String> {
+        struct Block {
+            start: usize,
+            end: usize,
+            end_row: usize,
+            key: (String, String),
+        }
+
+        if let Some(error) = self.check_syntax(source)?.into_iter().next() {
+            return Err(error.into());
+        }
+
+        let tree = self.parse_to_tree(source)?;
+        let root = tree.root_node();
+
+        let mut runs: Vec<Vec<Block>> = vec![Vec::new()];
+        let end_run = |runs: &mut Vec<Vec<Block>>| {
+            if runs.last().is_some_and(|run| !run.is_empty()) {
+                runs.push(Vec::new());
+            }
+        };
+        // The comment lines since the last statement: where they start and the row of
+        // the last one
+        let mut comments: Option<(usize, usize)> = None;
+        let mut last_statement_row = None;
+
+        let mut cursor = root.walk();
+        for node in root.named_children(&mut cursor) {
+            let start_row = node.start_position().row;
+
+            if node.kind() == "comment" {
+                if comments.is_none() && last_statement_row == Some(start_row) {
+                    // A comment ending a target's line moves with it
+                    if let Some(block) = runs.last_mut().and_then(|run| run.last_mut()) {
+                        if block.end_row == start_row {
+                            block.end = node.end_byte();
+                        }
+                    }
+                    continue;
+                }
+                comments = match comments {
+                    Some((start, last_row)) if last_row + 1 == start_row => {
+                        Some((start, node.end_position().row))
+                    }
+                    Some(_) => {
+                        end_run(&mut runs);
+                        Some((node.start_byte(), node.end_position().row))
+                    }
+                    None => Some((node.start_byte(), node.end_position().row)),
+                };
+                continue;
+            }
+
+            let start = match comments.take() {
+                Some((start, last_row)) if last_row + 1 == start_row => start,
+                Some(_) => {
+                    end_run(&mut runs);
+                    node.start_byte()
+                }
+                None => node.start_byte(),
+            };
+            last_statement_row = Some(node.end_position().row);
+
+            let Some((rule_type, name)) = target_statement(node, source) else {
+                end_run(&mut runs);
+                continue;
+            };
+            let run = runs.last_mut().expect("there is always a run");
+            run.push(Block {
+                start,
+                end: node.end_byte(),
+                end_row: node.end_position().row,
+                key: match key {
+                    TargetSortKey::Name => (name, String::new()),
+                    TargetSortKey::Kind => (rule_type, name),
+                },
+            });
+        }
+
+        let mut result = source.to_string();
+        // Replace the last runs first so the byte offsets of the others stay valid
+        for run in runs.iter().rev().filter(|run| run.len() > 1) {
+            let mut sorted: Vec<&Block> = run.iter().collect();
+            sorted.sort_by(|a, b| a.key.cmp(&b.key));
+
+            let mut text = String::new();
+            for (i, block) in sorted.iter().enumerate() {
+                text.push_str(&source[block.start..block.end]);
+                // The text between targets, i.e. their blank lines, stays in place
+                if let Some(next) = run.get(i + 1) {
+                    text.push_str(&source[run[i].end..next.start]);
+                }
+            }
+            result.replace_range(run[0].start..run[run.len() - 1].end, &text);
         }
 
         Ok(result)
@@ -415,19 +1543,532 @@ impl BazelParser {
             byte_index += bytes;
         }
 
-        byte_index
+        // Positions past the last line, e.g. in an empty document, map to its end
+        byte_index.min(text.len())
+    }
+
+    /// Ranges of the `deps` values that aren't built from lists and `select()`s of
+    /// lists, e.g. `COMMON_DEPS + ["//extra"]`, so their labels can't be sorted.
+    pub fn unsortable_deps(&self, source: &str) -> Result<Vec<Range>> {
+        let tree = self.parse_to_tree(source)?;
+
+        let mut ranges = Vec::new();
+        let mut cursor = QueryCursor::new();
+        let mut matches = cursor.matches(
+            self.unsortable_deps_query,
+            tree.root_node(),
+            source.as_bytes(),
+        );
+        while let Some(m) = matches.next() {
+            ranges.extend(
+                m.captures
+                    .iter()
+                    .filter(|c| c.index == 1 && !is_list_or_select(c.node, source))
+                    .map(|c| node_range(c.node)),
+            );
+        }
+
+        Ok(ranges)
+    }
+
+    /// The labels listed in `deps` attributes, in document order. Lists combined with
+    /// `+` or selected by `select()` are included, the conditions of a `select()` aren't.
+    pub fn extract_dep_strings(&self, source: &str) -> Result<Vec<BazelStringValue>> {
+        let tree = self.parse_to_tree(source)?;
+
+        let mut strings = Vec::new();
+        let mut cursor = QueryCursor::new();
+        let mut matches = cursor.matches(
+            self.unsortable_deps_query,
+            tree.root_node(),
+            source.as_bytes(),
+        );
+        while let Some(m) = matches.next() {
+            let mut pending: Vec<_> = m
+                .captures
+                .iter()
+                .filter(|c| c.index == 1)
+                .map(|c| c.node)
+                .collect();
+            while let Some(node) = pending.pop() {
+                if node.kind() != "string" {
+                    let mut cursor = node.walk();
+                    pending.extend(node.named_children(&mut cursor));
+                    continue;
+                }
+                // Only list items are labels; other strings are conditions or parts of
+                // a concatenation
+                if node.parent().is_some_and(|parent| parent.kind() == "list") {
+                    if let Some(value) = unquote(&source[node.byte_range()]) {
+                        strings.push(BazelStringValue {
+                            value: value.to_string(),
+                            attribute: Some("deps".to_string()),
+                            range: node_range(node),
+                        });
+                    }
+                }
+            }
+        }
+
+        strings.sort_by_key(|string| string.range.start);
+        Ok(strings)
+    }
+
+    /// Extracts the files listed in `exports_files(...)` calls, either positionally or
+    /// through `srcs =`.
+    pub fn extract_exported_files(&self, source: &str) -> Result<Vec<BazelExportedFile>> {
+        let tree = self.parse_to_tree(source)?;
+
+        let mut files = Vec::new();
+        let mut cursor = QueryCursor::new();
+        let mut matches = cursor.matches(
+            self.exports_files_query,
+            tree.root_node(),
+            source.as_bytes(),
+        );
+
+        while let Some(m) = matches.next() {
+            let Some(args) = m.captures.iter().find(|c| c.index == 1).map(|c| c.node) else {
+                continue;
+            };
+
+            let mut args_cursor = args.walk();
+            let list = args
+                .named_children(&mut args_cursor)
+                .find_map(|arg| match arg.kind() {
+                    "list" => Some(arg),
+                    "keyword_argument" => {
+                        let name = arg.child_by_field_name("name")?;
+                        let value = arg.child_by_field_name("value")?;
+                        (&source[name.byte_range()] == "srcs" && value.kind() == "list")
+                            .then_some(value)
+                    }
+                    _ => None,
+                });
+            let Some(list) = list else {
+                continue;
+            };
+
+            let mut list_cursor = list.walk();
+            for item in list.named_children(&mut list_cursor) {
+                if item.kind() != "string" {
+                    continue;
+                }
+                if let Some(name) = unquote(&source[item.byte_range()]) {
+                    files.push(BazelExportedFile {
+                        name: name.to_string(),
+                        range: node_range(item),
+                    });
+                }
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Extracts every call of a plain function name, in source order.
+    ///
+    /// Unlike [`Self::extract_targets`] this includes calls without a `name` argument and
+    /// calls nested in other calls or in function bodies.
+    pub fn extract_function_calls(&self, source: &str) -> Result<Vec<FunctionCall>> {
+        let tree = self.parse_to_tree(source)?;
+
+        let mut calls = Vec::new();
+        let mut cursor = QueryCursor::new();
+        let mut matches = cursor.matches(self.call_query, tree.root_node(), source.as_bytes());
+
+        while let Some(m) = matches.next() {
+            let capture = |index| m.captures.iter().find(|c| c.index == index).map(|c| c.node);
+            let (Some(name), Some(args), Some(call)) = (capture(0), capture(1), capture(2)) else {
+                continue;
+            };
+
+            let mut args_cursor = args.walk();
+            let args = args
+                .named_children(&mut args_cursor)
+                .filter(|arg| arg.kind() != "comment")
+                .map(|arg| source[arg.byte_range()].to_string())
+                .collect();
+
+            calls.push(FunctionCall {
+                name: source[name.byte_range()].to_string(),
+                range: node_range(call),
+                args,
+            });
+        }
+
+        Ok(calls)
+    }
+
+    /// Extracts the functions defined at the top level of a `.bzl` file, with the
+    /// range of their names. Functions nested in them are left out.
+    pub fn extract_defs(&self, source: &str) -> Result<Vec<BazelMacroDef>> {
+        let tree = self.parse_to_tree(source)?;
+
+        let mut defs = Vec::new();
+        let mut cursor = QueryCursor::new();
+        let mut matches = cursor.matches(self.def_query, tree.root_node(), source.as_bytes());
+
+        while let Some(m) = matches.next() {
+            let name = m.captures.iter().find(|c| c.index == 0).map(|c| c.node);
+            let def = m.captures.iter().find(|c| c.index == 1).map(|c| c.node);
+            let (Some(name), Some(def)) = (name, def) else {
+                continue;
+            };
+
+            defs.push(BazelMacroDef {
+                name: source[name.byte_range()].to_string(),
+                name_range: node_range(name),
+                range: node_range(def),
+            });
+        }
+
+        Ok(defs)
+    }
+
+    /// Returns the name of the function called at the position, if the position is on
+    /// the function name of a call such as `my_macro(...)`.
+    pub fn get_called_function(&self, source: &str, position: &Position) -> Result<Option<String>> {
+        let tree = self.parse_to_tree(source)?;
+
+        let byte = self.position_to_byte_index(source, position);
+        let Some(node) = tree.root_node().descendant_for_byte_range(byte, byte) else {
+            return Ok(None);
+        };
+        // The cursor may sit right after the last character of the name
+        let node = match node.kind() {
+            "identifier" => node,
+            _ => match tree
+                .root_node()
+                .descendant_for_byte_range(byte.saturating_sub(1), byte.saturating_sub(1))
+            {
+                Some(previous) if previous.kind() == "identifier" => previous,
+                _ => return Ok(None),
+            },
+        };
+
+        let is_callee = node.parent().is_some_and(|call| {
+            call.kind() == "call" && call.child_by_field_name("function") == Some(node)
+        });
+
+        Ok(is_callee.then(|| source[node.byte_range()].to_string()))
+    }
+
+    pub fn extract_loads(&self, source: &str) -> Result<Vec<BazelLoad>> {
+        let tree = self.parse_to_tree(source)?;
+
+        let mut loads = Vec::new();
+        let mut cursor = QueryCursor::new();
+        let mut matches = cursor.matches(self.load_query, tree.root_node(), source.as_bytes());
+
+        while let Some(m) = matches.next() {
+            let mut args_node = None;
+            let mut load_node = None;
+
+            for capture in m.captures {
+                match capture.index {
+                    1 => args_node = Some(capture.node),
+                    2 => load_node = Some(capture.node),
+                    _ => {}
+                }
+            }
+
+            let (Some(args), Some(load)) = (args_node, load_node) else {
+                continue;
+            };
+
+            let mut module = None;
+            let mut symbols = Vec::new();
+            let mut args_cursor = args.walk();
+            for arg in args.named_children(&mut args_cursor) {
+                match arg.kind() {
+                    "string" => {
+                        let Some(text) = unquote(&source[arg.start_byte()..arg.end_byte()]) else {
+                            continue;
+                        };
+                        if module.is_none() {
+                            module = Some(text.to_string());
+                        } else {
+                            symbols.push(LoadedSymbol {
+                                name: text.to_string(),
+                                original: text.to_string(),
+                            });
+                        }
+                    }
+                    "keyword_argument" => {
+                        let name = arg
+                            .child_by_field_name("name")
+                            .map(|n| &source[n.start_byte()..n.end_byte()]);
+                        let original = arg
+                            .child_by_field_name("value")
+                            .and_then(|v| unquote(&source[v.start_byte()..v.end_byte()]));
+                        if let (Some(name), Some(original)) = (name, original) {
+                            symbols.push(LoadedSymbol {
+                                name: name.to_string(),
+                                original: original.to_string(),
+                            });
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            let Some(module) = module else {
+                continue;
+            };
+
+            loads.push(BazelLoad {
+                module,
+                symbols,
+                range: Range {
+                    start: Position {
+                        line: load.start_position().row as u32,
+                        character: load.start_position().column as u32,
+                    },
+                    end: Position {
+                        line: load.end_position().row as u32,
+                        character: load.end_position().column as u32,
+                    },
+                },
+            });
+        }
+
+        Ok(loads)
+    }
+
+    /// Returns the innermost target whose rule call contains the position.
+    pub fn get_enclosing_rule(
+        &self,
+        source: &str,
+        position: &Position,
+    ) -> Result<Option<BazelTarget>> {
+        let targets = self.extract_targets(source)?;
+
+        Ok(targets
+            .into_iter()
+            .filter(|target| target.range.start <= *position && *position <= target.range.end)
+            .max_by_key(|target| target.range.start))
+    }
+
+    /// Returns the string literal at the position, if any.
+    pub fn get_string_at(
+        &self,
+        source: &str,
+        position: &Position,
+    ) -> Result<Option<BazelStringValue>> {
+        let tree = self.parse_to_tree(source)?;
+
+        let byte = self.position_to_byte_index(source, position);
+        let mut current = tree.root_node().descendant_for_byte_range(byte, byte);
+        while let Some(node) = current {
+            if node.kind() == "string" {
+                break;
+            }
+            current = node.parent();
+        }
+        let Some(string) = current else {
+            return Ok(None);
+        };
+        let Some(value) = unquote(&source[string.start_byte()..string.end_byte()]) else {
+            return Ok(None);
+        };
+
+        let attribute = std::iter::successors(string.parent(), |node| node.parent())
+            .take_while(|node| node.kind() != "call")
+            .find(|node| node.kind() == "keyword_argument")
+            .and_then(|node| node.child_by_field_name("name"))
+            .map(|name| source[name.start_byte()..name.end_byte()].to_string());
+
+        Ok(Some(BazelStringValue {
+            value: value.to_string(),
+            attribute,
+            range: node_range(string),
+        }))
+    }
+
+    /// Returns true if the position is where a new statement (e.g. a rule call) can be typed,
+    /// i.e. not inside the arguments of a call, a list, a dict or a string.
+    pub fn is_at_statement_level(&self, source: &str, position: &Position) -> Result<bool> {
+        let tree = self.parse_to_tree(source)?;
+
+        let byte = self.position_to_byte_index(source, position);
+        let Some(node) = tree
+            .root_node()
+            .descendant_for_byte_range(byte.saturating_sub(1), byte)
+        else {
+            return Ok(true);
+        };
+
+        let mut current = Some(node);
+        while let Some(node) = current {
+            if matches!(
+                node.kind(),
+                "argument_list" | "list" | "dictionary" | "string" | "comment" | "parameters"
+            ) {
+                return Ok(false);
+            }
+            current = node.parent();
+        }
+
+        Ok(true)
+    }
+
+    /// Returns true if the position is inside a list passed to the keyword argument
+    /// `attribute`, including lists nested in a `select()` or concatenation.
+    pub fn is_in_attribute(
+        &self,
+        source: &str,
+        position: &Position,
+        attribute: &str,
+    ) -> Result<bool> {
+        let tree = self.parse_to_tree(source)?;
+
+        let byte = self.position_to_byte_index(source, position);
+        let mut in_list = false;
+        let mut current = tree.root_node().descendant_for_byte_range(byte, byte);
+        while let Some(node) = current {
+            match node.kind() {
+                "list" if node.start_byte() < byte && byte < node.end_byte() => in_list = true,
+                "keyword_argument" => {
+                    let name = node
+                        .child_by_field_name("name")
+                        .map(|name| &source[name.byte_range()]);
+                    return Ok(in_list && name == Some(attribute));
+                }
+                _ => {}
+            }
+            current = node.parent();
+        }
+
+        Ok(false)
+    }
+
+    /// Returns the name of the keyword argument whose value is being typed at the
+    /// position, e.g. `testonly` for `testonly = Tr|`. Values inside lists and other
+    /// expressions don't count.
+    pub fn attribute_at_value(&self, source: &str, position: &Position) -> Result<Option<String>> {
+        let tree = self.parse_to_tree(source)?;
+        let root = tree.root_node();
+        let bytes = source.as_bytes();
+
+        // Skip back over the part of the value typed so far, e.g. `Tr` or `-1`, and the
+        // blanks before it, to where the `=` should be
+        let mut start = self
+            .position_to_byte_index(source, position)
+            .min(source.len());
+        while start > 0 && (bytes[start - 1].is_ascii_alphanumeric() || bytes[start - 1] == b'-') {
+            start -= 1;
+        }
+        let equals = source[..start].trim_end().len();
+        if equals == 0 || bytes[equals - 1] != b'=' {
+            return Ok(None);
+        }
+
+        // The `=` has to be a token of its own, not part of `==`, a string or a comment
+        let is_equals = root
+            .descendant_for_byte_range(equals - 1, equals)
+            .is_some_and(|node| node.kind() == "=");
+        let name_end = source[..equals - 1].trim_end().len();
+        if !is_equals || name_end == 0 {
+            return Ok(None);
+        }
+
+        // A half-typed argument is recovered as an error next to its name rather than
+        // as a keyword argument, or inside an error starting at the call when the call
+        // isn't closed yet
+        let name = root
+            .descendant_for_byte_range(name_end - 1, name_end)
+            .filter(|node| node.kind() == "identifier")
+            .filter(|node| match node.parent() {
+                Some(parent) if parent.kind() == "ERROR" => {
+                    let mut cursor = parent.walk();
+                    let open_call = parent
+                        .children(&mut cursor)
+                        .take_while(|child| child.id() != node.id())
+                        .any(|child| child.kind() == "(");
+                    open_call
+                }
+                Some(parent) => matches!(parent.kind(), "keyword_argument" | "argument_list"),
+                None => false,
+            });
+
+        Ok(name.map(|name| source[name.byte_range()].to_string()))
+    }
+
+    /// When an attribute name is expected at `position`, e.g. on a blank line between
+    /// the arguments of a rule call or while typing `sr` in `cc_library(sr)`, returns
+    /// the name of the rule and the attributes the call already sets.
+    pub fn attribute_name_context(
+        &self,
+        source: &str,
+        position: &Position,
+    ) -> Result<Option<(String, Vec<String>)>> {
+        let tree = self.parse_to_tree(source)?;
+        let bytes = source.as_bytes();
+
+        // Skip back over the part of the name typed so far
+        let end = self
+            .position_to_byte_index(source, position)
+            .min(source.len());
+        let mut start = end;
+        while start > 0 && (bytes[start - 1].is_ascii_alphanumeric() || bytes[start - 1] == b'_') {
+            start -= 1;
+        }
+
+        // Names start an argument, so they follow the `(` or a `,`
+        let before = source[..start].trim_end();
+        if !before.ends_with('(') && !before.ends_with(',') {
+            return Ok(None);
+        }
+
+        let Some(node) = tree.root_node().descendant_for_byte_range(start, end) else {
+            return Ok(None);
+        };
+        let args = match node.kind() {
+            "argument_list" => Some(node),
+            // A name being typed is parsed as a positional argument, and an empty spot
+            // right before a `,` or the `)` finds that token
+            "identifier" | "," | ")" => node
+                .parent()
+                .filter(|parent| parent.kind() == "argument_list"),
+            _ => None,
+        };
+        let Some(args) = args.filter(|args| args.start_byte() < start) else {
+            return Ok(None);
+        };
+
+        let Some(rule) = args
+            .parent()
+            .filter(|call| call.kind() == "call")
+            .and_then(|call| call.child_by_field_name("function"))
+            .filter(|function| function.kind() == "identifier")
+        else {
+            return Ok(None);
+        };
+
+        let mut cursor = args.walk();
+        let set_attributes = args
+            .named_children(&mut cursor)
+            .filter(|arg| arg.kind() == "keyword_argument")
+            .filter_map(|arg| arg.child_by_field_name("name"))
+            .map(|name| source[name.byte_range()].to_string())
+            .collect();
+
+        Ok(Some((
+            source[rule.byte_range()].to_string(),
+            set_attributes,
+        )))
     }
 
     pub fn is_in_deps_attribute(&self, source: &str, position: &Position) -> Result<bool> {
-        let tree = self
-            .parser
-            .lock()
-            .unwrap()
-            .parse(source, None)
-            .ok_or_else(|| anyhow::anyhow!("Failed to parse BUILD file"))?;
+        let tree = self.parse_to_tree(source)?;
+
+        if self.is_in_conditional_deps(&tree, source, position) {
+            return Ok(true);
+        }
 
         let mut cursor = QueryCursor::new();
-        let mut matches = cursor.matches(&self.deps_query, tree.root_node(), source.as_bytes());
+        let mut matches = cursor.matches(self.deps_query, tree.root_node(), source.as_bytes());
 
         Ok(matches.any(|m| {
             // Find the deps_arg capture (index 2)
@@ -460,6 +2101,66 @@ impl BazelParser {
             }
         }))
     }
+
+    /// Checks whether the position is inside one of the lists making up a `select()`
+    /// or concatenated deps value.
+    fn is_in_conditional_deps(
+        &self,
+        tree: &tree_sitter::Tree,
+        source: &str,
+        position: &Position,
+    ) -> bool {
+        let point = tree_sitter::Point {
+            row: position.line as usize,
+            column: position.character as usize,
+        };
+
+        let mut cursor = QueryCursor::new();
+        let mut matches = cursor.matches(
+            self.conditional_deps_query,
+            tree.root_node(),
+            source.as_bytes(),
+        );
+
+        while let Some(m) = matches.next() {
+            let Some(value) = m.captures.iter().find(|c| c.index == 1).map(|c| c.node) else {
+                continue;
+            };
+
+            let mut stack = vec![value];
+            while let Some(node) = stack.pop() {
+                match node.kind() {
+                    "list" if node.start_position() < point && point < node.end_position() => {
+                        return true;
+                    }
+                    "binary_operator" => {
+                        let mut walker = node.walk();
+                        stack.extend(node.named_children(&mut walker));
+                    }
+                    "call" => {
+                        let is_select = node
+                            .child_by_field_name("function")
+                            .is_some_and(|function| &source[function.byte_range()] == "select");
+                        let dictionary = node
+                            .child_by_field_name("arguments")
+                            .and_then(|args| args.named_child(0))
+                            .filter(|arg| is_select && arg.kind() == "dictionary");
+                        if let Some(dictionary) = dictionary {
+                            let mut walker = dictionary.walk();
+                            stack.extend(
+                                dictionary
+                                    .named_children(&mut walker)
+                                    .filter_map(|pair| pair.child_by_field_name("value")),
+                            );
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        false
+    }
 }
 
 impl Default for BazelParser {
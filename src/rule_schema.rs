@@ -0,0 +1,332 @@
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// An attribute a rule accepts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleAttribute {
+    pub name: String,
+    /// Shown with the attribute when completing it
+    pub doc: Option<String>,
+}
+
+/// Attributes every rule accepts.
+const COMMON_ATTRIBUTES: &[&str] = &[
+    "name",
+    "visibility",
+    "tags",
+    "testonly",
+    "deprecation",
+    "features",
+    "licenses",
+    "compatible_with",
+    "target_compatible_with",
+    "exec_compatible_with",
+];
+
+/// Attributes of the rules built into Bazel and its common rule sets, besides
+/// [`COMMON_ATTRIBUTES`].
+const BUILTIN_RULES: &[(&str, &[&str])] = &[
+    (
+        "cc_library",
+        &[
+            "srcs",
+            "hdrs",
+            "deps",
+            "data",
+            "copts",
+            "defines",
+            "includes",
+            "linkopts",
+            "linkstatic",
+            "alwayslink",
+            "strip_include_prefix",
+            "include_prefix",
+        ],
+    ),
+    (
+        "cc_binary",
+        &[
+            "srcs",
+            "deps",
+            "data",
+            "copts",
+            "defines",
+            "linkopts",
+            "linkstatic",
+            "linkshared",
+            "stamp",
+        ],
+    ),
+    (
+        "cc_test",
+        &[
+            "srcs",
+            "deps",
+            "data",
+            "copts",
+            "defines",
+            "linkopts",
+            "linkstatic",
+            "size",
+            "timeout",
+            "flaky",
+            "shard_count",
+            "local",
+            "args",
+            "env",
+        ],
+    ),
+    ("py_library", &["srcs", "deps", "data", "imports"]),
+    (
+        "py_binary",
+        &[
+            "srcs", "deps", "data", "main", "imports", "args", "env", "stamp",
+        ],
+    ),
+    (
+        "py_test",
+        &[
+            "srcs",
+            "deps",
+            "data",
+            "main",
+            "imports",
+            "size",
+            "timeout",
+            "flaky",
+            "shard_count",
+            "local",
+            "args",
+            "env",
+        ],
+    ),
+    (
+        "java_library",
+        &[
+            "srcs",
+            "deps",
+            "data",
+            "exports",
+            "runtime_deps",
+            "resources",
+            "plugins",
+            "javacopts",
+        ],
+    ),
+    (
+        "java_binary",
+        &[
+            "srcs",
+            "deps",
+            "data",
+            "main_class",
+            "runtime_deps",
+            "resources",
+            "jvm_flags",
+            "args",
+            "stamp",
+        ],
+    ),
+    (
+        "java_test",
+        &[
+            "srcs",
+            "deps",
+            "data",
+            "test_class",
+            "runtime_deps",
+            "resources",
+            "jvm_flags",
+            "size",
+            "timeout",
+            "flaky",
+            "shard_count",
+            "local",
+            "args",
+            "env",
+        ],
+    ),
+    (
+        "go_library",
+        &["srcs", "deps", "data", "importpath", "embed", "cgo"],
+    ),
+    (
+        "go_binary",
+        &[
+            "srcs", "deps", "data", "embed", "cgo", "goos", "goarch", "pure", "static",
+        ],
+    ),
+    (
+        "go_test",
+        &[
+            "srcs",
+            "deps",
+            "data",
+            "embed",
+            "size",
+            "timeout",
+            "flaky",
+            "shard_count",
+            "local",
+            "args",
+            "env",
+        ],
+    ),
+    ("sh_binary", &["srcs", "deps", "data", "args", "env"]),
+    (
+        "sh_test",
+        &[
+            "srcs",
+            "deps",
+            "data",
+            "size",
+            "timeout",
+            "flaky",
+            "shard_count",
+            "local",
+            "args",
+            "env",
+        ],
+    ),
+    ("filegroup", &["srcs", "data", "output_group"]),
+    (
+        "genrule",
+        &[
+            "srcs",
+            "outs",
+            "cmd",
+            "cmd_bash",
+            "cmd_bat",
+            "cmd_ps",
+            "tools",
+            "executable",
+            "local",
+            "stamp",
+        ],
+    ),
+    ("test_suite", &["tests"]),
+    ("alias", &["actual"]),
+    (
+        "config_setting",
+        &[
+            "values",
+            "define_values",
+            "flag_values",
+            "constraint_values",
+        ],
+    ),
+];
+
+/// The attributes of each rule, by rule name, used to complete attribute names.
+///
+/// Starts out with the built-in rules. Teams describe their own rules in a JSON file
+/// mapping rule names to their attributes, each with an optional `doc`:
+///
+/// ```json
+/// {
+///   "my_rule": {
+///     "attributes": {
+///       "config": { "doc": "The config file to apply" }
+///     }
+///   }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleSchema {
+    rules: HashMap<String, Vec<RuleAttribute>>,
+}
+
+impl Default for RuleSchema {
+    fn default() -> Self {
+        let rules = BUILTIN_RULES
+            .iter()
+            .map(|(rule, attributes)| {
+                let attributes = attributes
+                    .iter()
+                    .map(|name| RuleAttribute {
+                        name: name.to_string(),
+                        doc: None,
+                    })
+                    .collect();
+                (rule.to_string(), attributes)
+            })
+            .collect();
+
+        Self { rules }
+    }
+}
+
+impl RuleSchema {
+    /// Parses a schema in the format described on [`RuleSchema`]. Only the rules it
+    /// describes are included.
+    pub fn from_json(value: &Value) -> Result<Self> {
+        let Some(rules) = value.as_object() else {
+            bail!("Expected an object of rules");
+        };
+
+        let mut schema = Self {
+            rules: HashMap::new(),
+        };
+        for (rule, description) in rules {
+            let attributes = match description.get("attributes") {
+                Some(Value::Object(attributes)) => attributes,
+                Some(_) => bail!("Expected an object of attributes for rule `{}`", rule),
+                None => continue,
+            };
+
+            let attributes = attributes
+                .iter()
+                .map(|(name, attribute)| RuleAttribute {
+                    name: name.clone(),
+                    doc: attribute
+                        .get("doc")
+                        .and_then(Value::as_str)
+                        .map(str::to_string),
+                })
+                .collect();
+            schema.rules.insert(rule.clone(), attributes);
+        }
+
+        Ok(schema)
+    }
+
+    /// Reads the schema file at `path`.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let value: Value = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        Self::from_json(&value).with_context(|| format!("Invalid rule schema {}", path.display()))
+    }
+
+    /// Adds the rules and attributes of `other`, replacing attributes of the same rule
+    /// and name.
+    pub fn merge(&mut self, other: RuleSchema) {
+        for (rule, attributes) in other.rules {
+            let existing = self.rules.entry(rule).or_default();
+            for attribute in attributes {
+                match existing.iter_mut().find(|a| a.name == attribute.name) {
+                    Some(existing) => *existing = attribute,
+                    None => existing.push(attribute),
+                }
+            }
+        }
+    }
+
+    /// The attributes `rule` accepts: its own, then the ones every rule accepts.
+    /// Rules the schema doesn't describe get only the latter.
+    pub fn attributes(&self, rule: &str) -> Vec<RuleAttribute> {
+        let mut attributes = self.rules.get(rule).cloned().unwrap_or_default();
+        for name in COMMON_ATTRIBUTES {
+            if !attributes.iter().any(|attribute| attribute.name == *name) {
+                attributes.push(RuleAttribute {
+                    name: name.to_string(),
+                    doc: None,
+                });
+            }
+        }
+        attributes
+    }
+}
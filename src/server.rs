@@ -1,92 +1,200 @@
-use crate::bazel::{find_build_files, find_workspace_root, is_workspace_dir};
-use crate::parser::BazelParser;
+use crate::bazel::{
+    build_stub, deps_tree, find_bazelrc_configs, find_build_files, find_bzl_files, find_executable,
+    find_package_build_file, find_package_files, find_workspace_root, is_build_file, is_bzl_file,
+    is_workspace_dir, parse_label_kinds, parse_query_graph, parse_query_location,
+};
+use crate::config::ServerConfig;
+use crate::coverage::CoverageCache;
+use crate::documents::DocumentStore;
+use crate::label::{parse_label, parse_label_in, split_label_start, Label, LabelStart};
+use crate::parser::{
+    AttributeValue, BazelMacroDef, BazelParser, BazelParserMode, LiteralKind, ParsedAttribute,
+    RuleCategory, SyntaxError,
+};
+use crate::rule_schema::RuleSchema;
 use crate::target_trie::{RuleInfo, TargetTrie};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
 use tokio::io::AsyncReadExt;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
 use tower_lsp::jsonrpc::Result;
 use tower_lsp::lsp_types::SemanticTokensOptions;
 use tower_lsp::lsp_types::*;
-use tower_lsp::{Client, LanguageServer};
+use tower_lsp::{Client, ClientSocket, LanguageServer, LspService};
 use url;
 
+#[derive(Clone)]
 pub struct Backend {
     pub client: Client,
-    pub parser: BazelParser,
-    pub documents: Arc<RwLock<HashMap<String, String>>>,
+    pub parser: Arc<BazelParser>,
+    /// Parser for `.bzl` files
+    pub starlark_parser: Arc<BazelParser>,
+    /// Open documents by URI.
+    ///
+    /// Lock ordering: no other lock is acquired while this one is held. Handlers clone
+    /// the text they need and release the guard first, so a task holding e.g.
+    /// `target_trie` can always get to the documents, and the reverse can't deadlock.
+    pub documents: Arc<RwLock<DocumentStore>>,
     pub target_trie: Arc<RwLock<TargetTrie>>,
-    pub workspace_folders: Arc<RwLock<Vec<WorkspaceFolder>>>,
+    /// Roots of the client's workspace folders that are Bazel workspaces
+    pub workspace_roots: Arc<RwLock<Vec<PathBuf>>>,
+    pub config: Arc<RwLock<ServerConfig>>,
+    /// Line coverage of test targets, shown in their code lenses
+    pub coverage: Arc<RwLock<CoverageCache>>,
+    /// Names of the external repositories declared in WORKSPACE and MODULE.bazel,
+    /// offered when completing `@` labels
+    pub external_repos: Arc<RwLock<Vec<String>>>,
+    /// Macros defined by each `.bzl` file of the workspace
+    pub macro_index: Arc<RwLock<HashMap<PathBuf, Vec<BazelMacroDef>>>>,
+    /// Locations reported by `bazel query --output=location`, by label
+    pub query_locations: Arc<RwLock<HashMap<String, Location>>>,
+    /// One sender per bazel command started by `execute_bazel_command`, which stops
+    /// the command when sent to
+    pub running_commands: Arc<RwLock<Vec<oneshot::Sender<()>>>>,
+    /// The pending analysis of each changed document, replaced by every new change
+    pub analysis_tasks: Arc<RwLock<HashMap<String, JoinHandle<()>>>>,
+    /// Attributes of the built-in rules and of those described by `rule_schema_path`
+    pub rule_schema: Arc<RwLock<RuleSchema>>,
+    /// How many times diagnostics were published, reported by `bazel.stats`
+    pub diagnostics_published: Arc<AtomicU64>,
+    /// Cancelled by `shutdown`, which stops running bazel commands and keeps new ones
+    /// from starting
+    pub shutdown_token: CancellationToken,
+    /// Documents without a path on disk that a warning was logged for, until closed
+    pub non_file_uris: Arc<RwLock<HashSet<String>>>,
 }
 
 #[tower_lsp::async_trait]
 impl LanguageServer for Backend {
     async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
-        if let Some(workspace_folders) = &params.workspace_folders {
-            let mut folders = self.workspace_folders.write().await;
-            *folders = workspace_folders.clone();
-
-            for folder in workspace_folders {
-                let uri = &folder.uri;
-                let path = uri.to_file_path().unwrap_or_default();
-
-                if let Ok(true) = is_workspace_dir(&path) {
-                    let mut trie: tokio::sync::RwLockWriteGuard<'_, TargetTrie> =
-                        self.target_trie.write().await;
+        let mut config =
+            ServerConfig::from_initialization_options(params.initialization_options.as_ref());
+        // An explicit `trace` initialization option wins over the client's LSP trace setting
+        let has_trace_option = params
+            .initialization_options
+            .as_ref()
+            .and_then(|options| options.get("trace"))
+            .is_some();
+        if let (Some(trace), false) = (params.trace, has_trace_option) {
+            config.trace = trace;
+        }
+        let text_document = params.capabilities.text_document.as_ref();
+        let completion_item = text_document
+            .and_then(|text_document| text_document.completion.as_ref())
+            .and_then(|completion| completion.completion_item.as_ref());
+        config.snippet_support = completion_item
+            .and_then(|completion_item| completion_item.snippet_support)
+            .unwrap_or(false);
+        config.completion_resolve_support = completion_item
+            .and_then(|completion_item| completion_item.resolve_support.as_ref())
+            .is_some_and(|resolve| resolve.properties.iter().any(|p| p == "documentation"));
+        // Features are only advertised to clients that declare support for them
+        let supports = |feature: fn(&TextDocumentClientCapabilities) -> bool| {
+            text_document.is_some_and(feature)
+        };
+        self.documents
+            .write()
+            .await
+            .set_capacity(config.max_open_documents);
+        *self.config.write().await = config;
 
-                    let build_files: Vec<PathBuf> = find_build_files(&path).into_iter().collect();
+        if let Some(workspace_folders) = &params.workspace_folders {
+            let roots: Vec<PathBuf> = workspace_folders
+                .iter()
+                .filter_map(|folder| folder.uri.to_file_path().ok())
+                .filter(|path| is_workspace_dir(path).unwrap_or(false))
+                .collect();
+            *self.workspace_roots.write().await = roots.clone();
 
-                    for build_file in build_files.iter() {
-                        let _ = self.populate_trie_from_build_file(build_file, &mut trie);
-                    }
-                }
+            for root in &roots {
+                self.index_workspace_folder(root).await;
             }
         }
+        self.load_rule_schema().await;
+        self.refresh_external_repos().await;
 
         Ok(InitializeResult {
             capabilities: ServerCapabilities {
                 text_document_sync: Some(TextDocumentSyncCapability::Kind(
                     TextDocumentSyncKind::INCREMENTAL,
                 )),
-                code_lens_provider: Some(CodeLensOptions {
-                    resolve_provider: Some(false),
-                }),
-                completion_provider: Some(CompletionOptions {
-                    trigger_characters: Some(vec![':'.into()]),
-                    all_commit_characters: None,
-                    resolve_provider: Some(false),
-                    work_done_progress_options: WorkDoneProgressOptions {
-                        work_done_progress: Some(true),
+                code_lens_provider: supports(|c| c.code_lens.is_some()).then_some(
+                    CodeLensOptions {
+                        resolve_provider: Some(false),
                     },
-                    completion_item: None,
+                ),
+                moniker_provider: supports(|c| c.moniker.is_some()).then_some(OneOf::Left(true)),
+                document_symbol_provider: supports(|c| c.document_symbol.is_some())
+                    .then_some(OneOf::Left(true)),
+                definition_provider: supports(|c| c.definition.is_some())
+                    .then_some(OneOf::Left(true)),
+                type_definition_provider: supports(|c| c.type_definition.is_some())
+                    .then_some(TypeDefinitionProviderCapability::Simple(true)),
+                implementation_provider: supports(|c| c.implementation.is_some())
+                    .then_some(ImplementationProviderCapability::Simple(true)),
+                code_action_provider: supports(|c| c.code_action.is_some())
+                    .then_some(CodeActionProviderCapability::Simple(true)),
+                completion_provider: supports(|c| c.completion.is_some()).then(|| {
+                    CompletionOptions {
+                        trigger_characters: Some(vec![':'.into()]),
+                        all_commit_characters: None,
+                        resolve_provider: Some(true),
+                        work_done_progress_options: WorkDoneProgressOptions {
+                            work_done_progress: Some(true),
+                        },
+                        completion_item: None,
+                    }
                 }),
-                semantic_tokens_provider: Some(
-                    SemanticTokensServerCapabilities::SemanticTokensOptions(
-                        SemanticTokensOptions {
-                            work_done_progress_options: WorkDoneProgressOptions {
-                                work_done_progress: Some(true),
-                            },
-                            legend: SemanticTokensLegend {
-                                token_types: vec![
-                                    SemanticTokenType::new("function"),
-                                    SemanticTokenType::new("property"),
-                                    SemanticTokenType::new("string"),
-                                ],
-                                token_modifiers: vec![],
-                            },
-                            range: Some(true),
-                            full: None,
+                semantic_tokens_provider: supports(|c| c.semantic_tokens.is_some()).then(|| {
+                    SemanticTokensServerCapabilities::SemanticTokensOptions(SemanticTokensOptions {
+                        work_done_progress_options: WorkDoneProgressOptions {
+                            work_done_progress: Some(true),
                         },
-                    ),
-                ),
-                document_formatting_provider: Some(OneOf::Left(true)),
+                        legend: SemanticTokensLegend {
+                            token_types: vec![
+                                SemanticTokenType::new("function"),
+                                SemanticTokenType::new("property"),
+                                SemanticTokenType::new("string"),
+                                SemanticTokenType::new("keyword"),
+                                SemanticTokenType::new("number"),
+                            ],
+                            token_modifiers: vec![],
+                        },
+                        range: Some(true),
+                        full: None,
+                    })
+                }),
+                document_formatting_provider: supports(|c| c.formatting.is_some())
+                    .then_some(OneOf::Left(true)),
+                workspace: Some(WorkspaceServerCapabilities {
+                    workspace_folders: Some(WorkspaceFoldersServerCapabilities {
+                        supported: Some(true),
+                        change_notifications: Some(OneOf::Left(true)),
+                    }),
+                    file_operations: None,
+                }),
                 execute_command_provider: Some(ExecuteCommandOptions {
                     commands: vec![
                         "bazel.build".into(),
                         "bazel.test".into(),
+                        "bazel.testFilter".into(),
                         "bazel.run".into(),
+                        "bazel.debug".into(),
+                        "bazel.format".into(),
+                        "bazel.formatWorkspace".into(),
+                        "bazel.formatPackage".into(),
+                        "bazel.deps".into(),
+                        "bazel.fetch".into(),
+                        "bazel.cancelAll".into(),
+                        "bazel.generateBuildStub".into(),
+                        "bazel.stats".into(),
                     ],
                     work_done_progress_options: WorkDoneProgressOptions {
                         work_done_progress: Some(true),
@@ -107,7 +215,58 @@ impl LanguageServer for Backend {
             .await;
     }
 
+    async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
+        for removed in &params.event.removed {
+            if let Ok(path) = removed.uri.to_file_path() {
+                self.workspace_roots
+                    .write()
+                    .await
+                    .retain(|root| *root != path);
+
+                self.target_trie.write().await.remove_files_under(&path);
+                self.macro_index
+                    .write()
+                    .await
+                    .retain(|bzl_file, _| !bzl_file.starts_with(&path));
+            }
+        }
+
+        for added in &params.event.added {
+            let Ok(path) = added.uri.to_file_path() else {
+                continue;
+            };
+            if !is_workspace_dir(&path).unwrap_or(false) {
+                continue;
+            }
+
+            let mut roots = self.workspace_roots.write().await;
+            if !roots.contains(&path) {
+                roots.push(path.clone());
+            }
+            drop(roots);
+            self.index_workspace_folder(&path).await;
+        }
+
+        self.republish_open_diagnostics(None).await;
+    }
+
     async fn shutdown(&self) -> Result<()> {
+        self.client
+            .log_message(MessageType::INFO, "Bazel LSP server shutting down")
+            .await;
+        self.shutdown_token.cancel();
+
+        // Running commands kill their process and return, ending the progress they
+        // report, before the client is told it may exit
+        let running = std::mem::take(&mut *self.running_commands.write().await);
+        for mut cancel in running {
+            cancel.closed().await;
+        }
+
+        for (_, task) in self.analysis_tasks.write().await.drain() {
+            task.abort();
+        }
+        self.documents.write().await.clear();
         Ok(())
     }
 
@@ -115,8 +274,10 @@ impl LanguageServer for Backend {
         let uri = params.text_document.uri.clone();
         let text = params.text_document.text.clone();
 
-        let mut documents = self.documents.write().await;
-        documents.insert(uri.to_string(), text.clone());
+        self.documents
+            .write()
+            .await
+            .open(uri.to_string(), text.clone());
 
         let message = format!("Opened: {}", uri);
         self.client.log_message(MessageType::INFO, message).await;
@@ -124,73 +285,88 @@ impl LanguageServer for Backend {
         self.publish_diagnostics(&uri, &text).await;
     }
 
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        let uri = params.text_document.uri.as_str();
+        if let Some(task) = self.analysis_tasks.write().await.remove(uri) {
+            task.abort();
+        }
+        self.documents.write().await.close(uri);
+        self.non_file_uris.write().await.remove(uri);
+    }
+
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
         let uri = params.text_document.uri.clone();
 
         self.update_document_content(&uri, &params.content_changes)
             .await;
 
-        let documents = self.documents.read().await;
-        let text = documents.get(uri.as_str()).cloned().unwrap_or_default();
-
-        self.publish_diagnostics(&uri, &text).await;
+        // Typing sends a change per keystroke, so the text is only analyzed once it
+        // stops changing, and each change cancels the analysis of the one before.
+        // The task is registered before it can finish, as it then removes itself.
+        let mut analysis_tasks = self.analysis_tasks.write().await;
+        let backend = self.clone();
+        let task_uri = uri.clone();
+        let task = tokio::spawn(async move {
+            tokio::time::sleep(ANALYSIS_DELAY).await;
+            backend.analyze_changed_document(&task_uri).await;
 
-        self.client
-            .send_request::<request::SemanticTokensRefresh>(())
-            .await
-            .ok();
-        self.client
-            .send_request::<request::CodeLensRefresh>(())
-            .await
-            .ok();
+            let mut analysis_tasks = backend.analysis_tasks.write().await;
+            let is_current = analysis_tasks
+                .get(task_uri.as_str())
+                .is_some_and(|task| task.id() == tokio::task::id());
+            if is_current {
+                analysis_tasks.remove(task_uri.as_str());
+            }
+        });
+        if let Some(superseded) = analysis_tasks.insert(uri.to_string(), task) {
+            superseded.abort();
+        }
     }
 
     async fn code_lens(&self, params: CodeLensParams) -> Result<Option<Vec<CodeLens>>> {
         let uri = params.text_document.uri.clone();
 
-        let documents = self.documents.read().await;
-        let text = documents.get(&uri.to_string()).cloned().unwrap_or_default();
+        let text = self
+            .documents
+            .read()
+            .await
+            .get(uri.as_str())
+            .cloned()
+            .unwrap_or_default();
 
         let mut lenses = Vec::new();
 
-        let file_path = uri.to_file_path().unwrap_or_default();
-        let workspace_folders = self.workspace_folders.read().await;
-        let workspace_root = workspace_folders
-            .iter()
-            .find_map(|folder| {
-                let path = folder.uri.to_file_path().ok()?;
-                if is_workspace_dir(&path).unwrap_or(false) {
-                    Some(path)
-                } else {
-                    None
-                }
-            });
-
-        let package_path = if let Some(workspace_root) = workspace_root {
-            if let Ok(relative_path) = file_path.parent().unwrap().strip_prefix(&workspace_root) {
-                relative_path.to_string_lossy().to_string()
-            } else {
-                String::new()
-            }
-        } else {
-            String::new()
+        // Lenses run bazel on a label, which needs a file on disk to derive the package from
+        let Some(file_path) = self.file_path_from_uri(&uri).await else {
+            return Ok(Some(lenses));
         };
+        let package_path = self.package_path(&file_path).await;
+        let workspace_root = self.workspace_root().await;
+        let enable_debug_lens = self.config.read().await.enable_debug_lens;
 
-        match self.parser.extract_targets(&text) {
+        match self.parser_for(&uri).extract_targets(&text) {
             Ok(targets) => {
                 for target in targets {
-                    let full_target_path = if package_path.is_empty() {
-                        format!("//:{}", target.name)
-                    } else {
-                        format!("//{}:{}", package_path, target.name)
-                    };
+                    let full_target_path = Label::new(&package_path, &target.name).to_string();
 
-                    match target.rule_type.as_str() {
-                        rule if rule.ends_with("_test") => {
+                    match target.rule_category() {
+                        RuleCategory::Test => {
+                            let coverage = match &workspace_root {
+                                Some(root) => {
+                                    self.coverage.write().await.get(root, &full_target_path)
+                                }
+                                None => None,
+                            };
+                            let title = match coverage {
+                                Some(coverage) => {
+                                    format!("Test {} ({:.0}% coverage)", target.name, coverage)
+                                }
+                                None => format!("Test {}", target.name),
+                            };
                             lenses.push(CodeLens {
                                 range: target.rule_type_range.clone(),
                                 command: Some(Command {
-                                    title: format!("Test {}", target.name),
+                                    title,
                                     command: "bazel.test".into(),
                                     arguments: Some(vec![serde_json::json!({
                                         "target": full_target_path
@@ -198,8 +374,20 @@ impl LanguageServer for Backend {
                                 }),
                                 data: None,
                             });
+                            // The client prompts for the filter before running the command
+                            lenses.push(CodeLens {
+                                range: target.rule_type_range,
+                                command: Some(Command {
+                                    title: "Test with filter…".into(),
+                                    command: "bazel.testFilter".into(),
+                                    arguments: Some(vec![serde_json::json!({
+                                        "target": full_target_path
+                                    })]),
+                                }),
+                                data: None,
+                            });
                         }
-                        rule if rule.ends_with("_binary") => {
+                        RuleCategory::Binary => {
                             lenses.push(CodeLens {
                                 range: target.rule_type_range.clone(),
                                 command: Some(Command {
@@ -211,8 +399,21 @@ impl LanguageServer for Backend {
                                 }),
                                 data: None,
                             });
+                            if enable_debug_lens {
+                                lenses.push(CodeLens {
+                                    range: target.rule_type_range,
+                                    command: Some(Command {
+                                        title: format!("🐛 Debug {}", target.name),
+                                        command: "bazel.debug".into(),
+                                        arguments: Some(vec![serde_json::json!({
+                                            "target": full_target_path
+                                        })]),
+                                    }),
+                                    data: None,
+                                });
+                            }
                         }
-                        _ => {}
+                        RuleCategory::Library | RuleCategory::Other => {}
                     }
                     lenses.push(CodeLens {
                         range: target.rule_type_range,
@@ -240,17 +441,340 @@ impl LanguageServer for Backend {
         Ok(Some(lenses))
     }
 
+    async fn goto_definition(
+        &self,
+        params: GotoDefinitionParams,
+    ) -> Result<Option<GotoDefinitionResponse>> {
+        let uri = params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+
+        let text = match self.documents.read().await.get(uri.as_str()) {
+            Some(text) => text.clone(),
+            None => return Ok(None),
+        };
+        let Some(file_path) = self.file_path_from_uri(&uri).await else {
+            return Ok(None);
+        };
+        let parser = self.parser_for(&uri);
+        let package_path = self.package_path(&file_path).await;
+
+        let label = match parser.get_string_at(&text, &position) {
+            Ok(Some(string)) if string.attribute.as_deref() != Some("name") => {
+                canonical_label(&string.value, &package_path)
+            }
+            _ => None,
+        };
+        let Some(label) = label else {
+            return Ok(None);
+        };
+
+        if let Some((build_file, range)) = self.label_declaration(&label).await {
+            if let Ok(uri) = Url::from_file_path(&build_file) {
+                return Ok(Some(GotoDefinitionResponse::Scalar(Location {
+                    uri,
+                    range,
+                })));
+            }
+        }
+
+        if !self.config.read().await.use_bazel_query_fallback {
+            return Ok(None);
+        }
+        Ok(self
+            .query_location(&label)
+            .await
+            .map(GotoDefinitionResponse::Scalar))
+    }
+
+    async fn goto_type_definition(
+        &self,
+        params: request::GotoTypeDefinitionParams,
+    ) -> Result<Option<request::GotoTypeDefinitionResponse>> {
+        let uri = params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+
+        let text = match self.documents.read().await.get(uri.as_str()) {
+            Some(text) => text.clone(),
+            None => return Ok(None),
+        };
+        let parser = self.parser_for(&uri);
+
+        let Ok(Some(function)) = parser.get_called_function(&text, &position) else {
+            return Ok(None);
+        };
+        let Some((load, symbol)) = parser
+            .extract_loads(&text)
+            .unwrap_or_default()
+            .into_iter()
+            .find_map(|load| {
+                let symbol = load.symbols.iter().find(|s| s.name == function)?.clone();
+                Some((load, symbol))
+            })
+        else {
+            return Ok(None);
+        };
+
+        let Some(file_path) = self.file_path_from_uri(&uri).await else {
+            return Ok(None);
+        };
+        let workspace_root = match file_path.parent().map(find_workspace_root) {
+            Some(Ok(Some(root))) => Some(root.to_path_buf()),
+            _ => self.workspace_root().await,
+        };
+        let Some(bzl_path) = load_module_path(&load.module, &file_path, workspace_root.as_deref())
+        else {
+            return Ok(None);
+        };
+        let Ok(bzl_uri) = Url::from_file_path(&bzl_path) else {
+            return Ok(None);
+        };
+
+        // Prefer the editor's copy of the .bzl file, which may have unsaved changes, then
+        // the index, and read files outside the workspace from disk
+        let open_text = self.documents.read().await.get(bzl_uri.as_str()).cloned();
+        let macros = match open_text {
+            Some(text) => self.starlark_parser.extract_defs(&text),
+            None => match self.macro_index.read().await.get(&bzl_path) {
+                Some(macros) => Ok(macros.clone()),
+                None => match fs::read_to_string(&bzl_path) {
+                    Ok(text) => self.starlark_parser.extract_defs(&text),
+                    Err(_) => return Ok(None),
+                },
+            },
+        };
+
+        let def = macros
+            .unwrap_or_default()
+            .into_iter()
+            .find(|def| def.name == symbol.original);
+
+        Ok(def.map(|def| {
+            request::GotoTypeDefinitionResponse::Scalar(Location {
+                uri: bzl_uri,
+                range: def.name_range,
+            })
+        }))
+    }
+
+    async fn goto_implementation(
+        &self,
+        params: request::GotoImplementationParams,
+    ) -> Result<Option<request::GotoImplementationResponse>> {
+        let uri = params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+
+        let text = match self.documents.read().await.get(uri.as_str()) {
+            Some(text) => text.clone(),
+            None => return Ok(None),
+        };
+        let Some(file_path) = self.file_path_from_uri(&uri).await else {
+            return Ok(None);
+        };
+        let parser = self.parser_for(&uri);
+        let package_path = self.package_path(&file_path).await;
+
+        // A label jumps to the sources of the target it names, anything else inside a
+        // rule to the sources of that rule
+        let label = match parser.get_string_at(&text, &position) {
+            Ok(Some(string)) if string.attribute.as_deref() != Some("name") => {
+                canonical_label(&string.value, &package_path)
+            }
+            _ => None,
+        };
+        let (build_file, target_name) = match label {
+            Some(label) => {
+                let trie = self.target_trie.read().await;
+                let Some(rule) = trie.resolve(&label) else {
+                    return Ok(None);
+                };
+                let Some(build_file) = rule.source_file.clone() else {
+                    return Ok(None);
+                };
+                (build_file, rule.name.clone())
+            }
+            None => match parser.get_enclosing_rule(&text, &position) {
+                Ok(Some(target)) => (file_path, target.name),
+                _ => return Ok(None),
+            },
+        };
+
+        // Prefer the editor's copy of the BUILD file, which may have unsaved changes
+        let open_text = match Url::from_file_path(&build_file) {
+            Ok(build_uri) => self.documents.read().await.get(build_uri.as_str()).cloned(),
+            Err(_) => None,
+        };
+        let build_text = match open_text {
+            Some(text) => text,
+            None => match fs::read_to_string(&build_file) {
+                Ok(text) => text,
+                Err(_) => return Ok(None),
+            },
+        };
+        let Some(target) = self
+            .parser
+            .parse_build_file(&build_text)
+            .unwrap_or_default()
+            .into_iter()
+            .find(|target| target.name == target_name)
+        else {
+            return Ok(None);
+        };
+
+        let package_dir = build_file.parent().unwrap_or(Path::new(""));
+        let locations = source_files(target.attributes.get("srcs"))
+            .into_iter()
+            .map(|src| package_dir.join(src))
+            .filter(|path| path.is_file())
+            .filter_map(|path| Url::from_file_path(path).ok())
+            .map(|uri| Location {
+                uri,
+                range: Range::default(),
+            })
+            .collect();
+
+        Ok(Some(request::GotoImplementationResponse::Array(locations)))
+    }
+
+    async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
+        let uri = params.text_document.uri;
+        let position = params.range.start;
+
+        let text = match self.documents.read().await.get(uri.as_str()) {
+            Some(text) => text.clone(),
+            None => return Ok(None),
+        };
+
+        let mut actions = Vec::new();
+        let targets = self
+            .parser_for(&uri)
+            .parse_build_file(&text)
+            .unwrap_or_default();
+        for target in targets {
+            let Some(deps) = target.attributes.get("deps") else {
+                continue;
+            };
+            if !matches!(deps.value, AttributeValue::List(_))
+                || position < deps.range.start
+                || deps.range.end < position
+            {
+                continue;
+            }
+
+            let start = self.position_to_byte_index(&text, &deps.range.start);
+            let end = self.position_to_byte_index(&text, &deps.range.end);
+            let line = text
+                .lines()
+                .nth(deps.range.start.line as usize)
+                .unwrap_or("");
+            let indent = &line[..line.len() - line.trim_start().len()];
+
+            let edit = TextEdit {
+                range: deps.range,
+                new_text: wrap_with_select(&text[start..end], indent),
+            };
+            actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                title: "Wrap with select()".into(),
+                kind: Some(CodeActionKind::REFACTOR_REWRITE),
+                edit: Some(WorkspaceEdit {
+                    changes: Some(HashMap::from([(uri.clone(), vec![edit])])),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }));
+        }
+
+        Ok(Some(actions))
+    }
+
+    async fn moniker(&self, params: MonikerParams) -> Result<Option<Vec<Moniker>>> {
+        let uri = params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+
+        let text = match self.documents.read().await.get(uri.as_str()) {
+            Some(text) => text.clone(),
+            None => return Ok(None),
+        };
+
+        let parser = self.parser_for(&uri);
+        let Ok(Some(target)) = parser.get_enclosing_rule(&text, &position) else {
+            return Ok(None);
+        };
+        let Ok(Some(string)) = parser.get_string_at(&text, &position) else {
+            return Ok(None);
+        };
+
+        let Some(file_path) = self.file_path_from_uri(&uri).await else {
+            return Ok(None);
+        };
+        let package_path = self.package_path(&file_path).await;
+
+        let (identifier, kind) =
+            if string.attribute.as_deref() == Some("name") && string.value == target.name {
+                (
+                    Label::new(&package_path, &target.name).to_string(),
+                    MonikerKind::Export,
+                )
+            } else if let Some(label) = canonical_label(&string.value, &package_path) {
+                (label, MonikerKind::Import)
+            } else {
+                return Ok(None);
+            };
+
+        Ok(Some(vec![Moniker {
+            scheme: "bazel".into(),
+            identifier,
+            unique: UniquenessLevel::Project,
+            kind: Some(kind),
+        }]))
+    }
+
+    /// The functions defined at the top level of a `.bzl` file, i.e. its macros and
+    /// rule implementations.
+    async fn document_symbol(
+        &self,
+        params: DocumentSymbolParams,
+    ) -> Result<Option<DocumentSymbolResponse>> {
+        let uri = params.text_document.uri;
+        let text = match self.documents.read().await.get(uri.as_str()) {
+            Some(text) => text.clone(),
+            None => return Ok(None),
+        };
 
+        let defs = self
+            .parser_for(&uri)
+            .extract_defs(&text)
+            .unwrap_or_default();
+        #[allow(deprecated)] // `deprecated` is superseded by `tags` but must still be set
+        let symbols = defs
+            .into_iter()
+            .map(|def| DocumentSymbol {
+                name: def.name,
+                detail: None,
+                kind: SymbolKind::FUNCTION,
+                tags: None,
+                deprecated: None,
+                range: def.range,
+                selection_range: def.name_range,
+                children: None,
+            })
+            .collect();
+        Ok(Some(DocumentSymbolResponse::Nested(symbols)))
+    }
 
     async fn semantic_tokens_full(
         &self,
         params: SemanticTokensParams,
     ) -> Result<Option<SemanticTokensResult>> {
         let uri = params.text_document.uri.clone();
-        let documents = self.documents.read().await;
-        let text = documents.get(&uri.to_string()).cloned().unwrap_or_default();
+        let text = self
+            .documents
+            .read()
+            .await
+            .get(uri.as_str())
+            .cloned()
+            .unwrap_or_default();
 
-        let tokens = self.get_semantic_tokens(&text);
+        let tokens = self.get_semantic_tokens(self.parser_for(&uri), &text, None);
         Ok(Some(SemanticTokensResult::Tokens(tokens)))
     }
 
@@ -259,78 +783,75 @@ impl LanguageServer for Backend {
         params: SemanticTokensRangeParams,
     ) -> Result<Option<SemanticTokensRangeResult>> {
         let uri = params.text_document.uri.clone();
-        let documents = self.documents.read().await;
-        let text = documents.get(&uri.to_string()).cloned().unwrap_or_default();
+        let text = self
+            .documents
+            .read()
+            .await
+            .get(uri.as_str())
+            .cloned()
+            .unwrap_or_default();
 
-        let tokens = self.get_semantic_tokens(&text);
+        let tokens = self.get_semantic_tokens(self.parser_for(&uri), &text, Some(params.range));
         Ok(Some(SemanticTokensRangeResult::Tokens(tokens)))
     }
 
     async fn formatting(&self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>> {
         let uri = params.text_document.uri;
-        let documents = self.documents.read().await;
-        let text = documents.get(&uri.to_string()).cloned().unwrap_or_default();
+        let text = self
+            .documents
+            .read()
+            .await
+            .get(uri.as_str())
+            .cloned()
+            .unwrap_or_default();
 
-        let formatted_text = self.parser.sort_deps_in_text(&text).map_err(|e| {
-            let mut error =
-                tower_lsp::jsonrpc::Error::new(tower_lsp::jsonrpc::ErrorCode::InternalError);
-            error.data = Some(serde_json::json!({ "message": e.to_string() }));
-            error
-        })?;
+        let path = self.file_path_from_uri(&uri).await;
+        let parser = self.parser_for(&uri);
+        let formatted_text = self
+            .format_text(parser, &text, path.as_deref())
+            .await
+            .map_err(formatting_error)?;
 
-        Ok(Some(vec![TextEdit {
-            range: Range {
-                start: Position {
-                    line: 0,
-                    character: 0,
-                },
-                end: Position {
-                    line: text.lines().count() as u32,
-                    character: 0,
-                },
-            },
-            new_text: formatted_text,
-        }]))
+        Ok(Some(self.formatting_edits(parser, &text, &formatted_text)))
     }
 
     async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
-        let uri = params.text_document_position.text_document.uri;
-        let position = params.text_document_position.position;
-
-        let documents = self.documents.read().await;
-        let text = documents.get(&uri.to_string()).cloned().unwrap_or_default();
-
-        if !self
-            .parser
-            .is_in_deps_attribute(&text, &position)
-            .unwrap_or(false)
-        {
-            return Ok(None);
-        }
+        let mut response = self.complete(params).await?;
+        let items = match &mut response {
+            Some(CompletionResponse::Array(items)) => items,
+            Some(CompletionResponse::List(list)) => &mut list.items,
+            None => return Ok(None),
+        };
 
-        let folders = self.workspace_folders.read().await;
-        let file_path = uri.to_file_path().unwrap_or_default();
-        let is_in_workspace = folders.iter().any(|folder| {
-            if let Ok(folder_path) = folder.uri.to_file_path() {
-                file_path.starts_with(&folder_path)
-            } else {
-                false
+        // Clients that resolve documentation get it once an item is selected, which
+        // keeps long lists of targets small. Others get it right away
+        if self.config.read().await.completion_resolve_support {
+            for item in items {
+                if let Some(documentation) = item.documentation.take() {
+                    item.data = Some(serde_json::json!({ "documentation": documentation }));
+                }
+            }
+        } else {
+            for item in items {
+                self.resolve_target_item(item).await;
             }
-        });
-
-        let line = text.lines().nth(position.line as usize).unwrap_or("");
-        let line_up_to_cursor = &line[..position.character as usize];
-
-        let trigger_result = find_trigger_position(line_up_to_cursor);
-        if trigger_result.is_none() {
-            return Ok(None);
         }
+        Ok(response)
+    }
 
-        if is_in_workspace {
-            self.completion_in_workspace(position, trigger_result).await
-        } else {
-            self.completion_in_file(trigger_result, &text).await
+    async fn completion_resolve(&self, mut item: CompletionItem) -> Result<CompletionItem> {
+        self.resolve_target_item(&mut item).await;
+
+        let documentation = item
+            .data
+            .as_ref()
+            .and_then(|data| data.get("documentation"))
+            .and_then(|documentation| serde_json::from_value(documentation.clone()).ok());
+        if documentation.is_some() {
+            item.documentation = documentation;
+            item.data = None;
         }
+        Ok(item)
     }
 
     async fn execute_command(&self, params: ExecuteCommandParams) -> Result<Option<serde_json::Value>> {
@@ -340,7 +861,12 @@ impl LanguageServer for Backend {
                     if let Some(target_obj) = target.as_object() {
                         if let Some(target_name) = target_obj.get("target") {
                             if let Some(target_str) = target_name.as_str() {
-                                self.execute_bazel_command("build", target_str).await;
+                                self.execute_bazel_command(
+                                    "build",
+                                    target_str,
+                                    &config_args(Some(target)),
+                                )
+                                .await;
                             }
                         }
                     }
@@ -352,388 +878,2875 @@ impl LanguageServer for Backend {
                     if let Some(target_obj) = target.as_object() {
                         if let Some(target_name) = target_obj.get("target") {
                             if let Some(target_str) = target_name.as_str() {
-                                self.execute_bazel_command("test", target_str).await;
+                                self.execute_bazel_command(
+                                    "test",
+                                    target_str,
+                                    &config_args(Some(target)),
+                                )
+                                .await;
+                                self.refresh_coverage(target_str).await;
                             }
                         }
                     }
                 }
                 Ok(None)
             }
+            "bazel.testFilter" => {
+                let argument = params.arguments.first();
+                let target = argument
+                    .and_then(|arg| arg.get("target"))
+                    .and_then(|target| target.as_str())
+                    .ok_or_else(|| {
+                        tower_lsp::jsonrpc::Error::invalid_params(
+                            "bazel.testFilter expects an argument of the form {\"target\": \"//pkg:name\", \"filter\": \"...\"}",
+                        )
+                    })?;
+                let mut extra_args = config_args(argument);
+                extra_args.extend(test_filter_args(
+                    argument
+                        .and_then(|arg| arg.get("filter"))
+                        .and_then(|filter| filter.as_str()),
+                ));
+                self.execute_bazel_command("test", target, &extra_args)
+                    .await;
+                self.refresh_coverage(target).await;
+                Ok(None)
+            }
             "bazel.run" => {
                 if let Some(target) = params.arguments.get(0) {
                     if let Some(target_obj) = target.as_object() {
                         if let Some(target_name) = target_obj.get("target") {
                             if let Some(target_str) = target_name.as_str() {
-                                self.execute_bazel_command("run", target_str).await;
+                                self.execute_bazel_command(
+                                    "run",
+                                    target_str,
+                                    &config_args(Some(target)),
+                                )
+                                .await;
                             }
                         }
                     }
                 }
                 Ok(None)
             }
+            "bazel.debug" => {
+                let target = params
+                    .arguments
+                    .first()
+                    .and_then(|arg| arg.get("target"))
+                    .and_then(|target| target.as_str())
+                    .ok_or_else(|| {
+                        tower_lsp::jsonrpc::Error::invalid_params(
+                            "bazel.debug expects an argument of the form {\"target\": \"//pkg:name\"}",
+                        )
+                    })?;
+                let mut debug_args = config_args(params.arguments.first());
+                debug_args.push(DEBUG_COMPILATION_MODE.to_string());
+                if !self
+                    .execute_bazel_command("build", target, &debug_args)
+                    .await
+                {
+                    let mut error = tower_lsp::jsonrpc::Error::new(
+                        tower_lsp::jsonrpc::ErrorCode::InternalError,
+                    );
+                    error.message = format!("Failed to build {} for debugging", target).into();
+                    return Err(error);
+                }
 
-            _ => {
+                // Ask with the same compilation mode, which selects the dbg output tree
+                let bazel_bin = self
+                    .run_bazel_output("info", "bazel-bin", &[DEBUG_COMPILATION_MODE])
+                    .await?;
+                let program = debug_program_path(bazel_bin.trim(), target).ok_or_else(|| {
+                    tower_lsp::jsonrpc::Error::invalid_params(format!(
+                        "Not a target label: {}",
+                        target
+                    ))
+                })?;
+
+                let params = serde_json::json!({
+                    "target": target,
+                    "program": program,
+                });
                 self.client
-                    .log_message(
-                        MessageType::ERROR,
-                        format!("Unknown command: {}", params.command),
+                    .send_notification::<DebugStart>(params.clone())
+                    .await;
+                self.client
+                    .show_message(
+                        MessageType::INFO,
+                        format!("{} is ready to debug: {}", target, program.display()),
                     )
                     .await;
-                Ok(None)
+                Ok(Some(params))
             }
-        }
-    }
-}
-
-fn create_edit_text_in_workspace<'a>(
-    trigger_result: &Option<TriggerResult<'a>>,
-    rule: &RuleInfo,
-) -> String {
-    if let Some(result) = trigger_result {
-        if result.text_after_trigger.starts_with("//") {
-            rule.full_build_path.clone()
-        } else if result.text_after_trigger.starts_with(':') {
-            format!(":{}", rule.name)
-        } else {
-            rule.full_build_path.clone()
-        }
+            "bazel.format" => {
+                let uri = params
+                    .arguments
+                    .first()
+                    .and_then(|arg| arg.get("uri"))
+                    .and_then(|uri| uri.as_str())
+                    .and_then(|uri| Url::parse(uri).ok())
+                    .ok_or_else(|| {
+                        tower_lsp::jsonrpc::Error::invalid_params(
+                            "bazel.format expects an argument of the form {\"uri\": \"file:///...\"}",
+                        )
+                    })?;
+                self.format_with_buildifier(&uri).await
+            }
+            "bazel.formatWorkspace" => match self.workspace_root().await {
+                Some(workspace_root) => self.format_build_files(&workspace_root).await,
+                None => Err(tower_lsp::jsonrpc::Error::invalid_params(
+                    "No Bazel workspace is open",
+                )),
+            },
+            "bazel.formatPackage" => {
+                let package = params
+                    .arguments
+                    .first()
+                    .and_then(|arg| arg.get("package"))
+                    .and_then(|package| package.as_str())
+                    .ok_or_else(|| {
+                        tower_lsp::jsonrpc::Error::invalid_params(
+                            "bazel.formatPackage expects an argument of the form {\"package\": \"//path/to/package\"}",
+                        )
+                    })?;
+                // Only packages of the workspace may be rewritten, not any directory on disk
+                let package_path = match parse_label(package) {
+                    Ok(label) if label.repo.is_none() => label.package,
+                    // The root package has no name that `//` could be short for
+                    _ if package == "//" => String::new(),
+                    _ => {
+                        return Err(tower_lsp::jsonrpc::Error::invalid_params(format!(
+                            "{:?} is not a package of the workspace",
+                            package
+                        )))
+                    }
+                };
+                match self.workspace_root().await {
+                    Some(workspace_root) => {
+                        let package_dir = workspace_root.join(&package_path);
+                        if !package_dir.starts_with(&workspace_root) {
+                            return Err(tower_lsp::jsonrpc::Error::invalid_params(format!(
+                                "{:?} is not a package of the workspace",
+                                package
+                            )));
+                        }
+                        self.format_build_files(&package_dir).await
+                    }
+                    None => Err(tower_lsp::jsonrpc::Error::invalid_params(
+                        "No Bazel workspace is open",
+                    )),
+                }
+            }
+            "bazel.deps" => {
+                let argument = params.arguments.first();
+                let target = argument
+                    .and_then(|arg| arg.get("target"))
+                    .and_then(|target| target.as_str())
+                    .ok_or_else(|| {
+                        tower_lsp::jsonrpc::Error::invalid_params(
+                            "bazel.deps expects an argument of the form {\"target\": \"//pkg:name\", \"depth\": 3}",
+                        )
+                    })?;
+                let depth = argument
+                    .and_then(|arg| arg.get("depth"))
+                    .and_then(|depth| depth.as_u64())
+                    .unwrap_or(DEFAULT_DEPS_DEPTH);
+
+                let expression = format!("deps({}, {})", target, depth);
+                let graph = self
+                    .run_bazel_query(&expression, &["--output=graph", "--nograph:factored"])
+                    .await?;
+                let kinds = self
+                    .run_bazel_query(&expression, &["--output=label_kind"])
+                    .await?;
+
+                Ok(Some(deps_tree(
+                    target,
+                    &parse_query_graph(&graph),
+                    &parse_label_kinds(&kinds),
+                    depth as usize,
+                )))
+            }
+            "bazel.fetch" => {
+                let token = params.work_done_progress_params.work_done_token;
+                if let Some(token) = &token {
+                    self.send_progress(
+                        token,
+                        WorkDoneProgress::Begin(WorkDoneProgressBegin {
+                            title: "Fetching external dependencies".into(),
+                            ..Default::default()
+                        }),
+                    )
+                    .await;
+                }
+
+                self.execute_bazel_command("fetch", "//...", &[]).await;
+                // Fetching doesn't change the declared repositories, but the files may
+                // have been edited while it ran
+                self.refresh_external_repos().await;
+
+                if let Some(token) = &token {
+                    self.send_progress(
+                        token,
+                        WorkDoneProgress::End(WorkDoneProgressEnd::default()),
+                    )
+                    .await;
+                }
+                Ok(None)
+            }
+            "bazel.cancelAll" => {
+                let running = std::mem::take(&mut *self.running_commands.write().await);
+                // Commands that finished since have dropped their receiver
+                let killed = running
+                    .into_iter()
+                    .map(|cancel| cancel.send(()))
+                    .filter(|sent| sent.is_ok())
+                    .count();
+                Ok(Some(serde_json::json!({ "killed": killed })))
+            }
+            "bazel.generateBuildStub" => {
+                let argument = params.arguments.first();
+                let directory = argument
+                    .and_then(|arg| arg.get("directory"))
+                    .and_then(|directory| directory.as_str())
+                    .ok_or_else(|| {
+                        tower_lsp::jsonrpc::Error::invalid_params(
+                            "bazel.generateBuildStub expects an argument of the form {\"directory\": \"/path/to/dir\", \"write\": false}",
+                        )
+                    })?;
+                let write = argument
+                    .and_then(|arg| arg.get("write"))
+                    .and_then(|write| write.as_bool())
+                    .unwrap_or(false);
+
+                self.generate_build_stub(Path::new(directory), write).await
+            }
+            "bazel.stats" => Ok(Some(serde_json::json!({
+                "diagnostics_published": self.diagnostics_published.load(Ordering::Relaxed),
+            }))),
+
+            _ => {
+                self.client
+                    .log_message(
+                        MessageType::ERROR,
+                        format!("Unknown command: {}", params.command),
+                    )
+                    .await;
+                Ok(None)
+            }
+        }
+    }
+}
+
+fn create_edit_text_in_workspace<'a>(
+    trigger_result: &Option<TriggerResult<'a>>,
+    rule: &RuleInfo,
+) -> String {
+    let relative = trigger_result
+        .as_ref()
+        .and_then(|result| split_label_start(result.text_after_trigger))
+        .is_some_and(|(start, _)| start == LabelStart::Relative);
+
+    match parse_label(&rule.full_build_path) {
+        Ok(label) if relative => format!(":{}", label.name),
+        _ => rule.full_build_path.clone(),
+    }
+}
+
+/// Computes a single edit replacing the lines that differ between `old` and `new`.
+///
+/// Returns None if the texts are identical.
+fn diff_text_edit(old: &str, new: &str) -> Option<TextEdit> {
+    if old == new {
+        return None;
+    }
+
+    let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+
+    let prefix = old_lines
+        .iter()
+        .zip(new_lines.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = old_lines[prefix..]
+        .iter()
+        .rev()
+        .zip(new_lines[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let old_end = old_lines.len() - suffix;
+    let new_end = new_lines.len() - suffix;
+
+    // Past the last line when the document doesn't end with a newline.
+    let end = if old_end == old_lines.len() && !old.is_empty() && !old.ends_with('\n') {
+        Position {
+            line: (old_end - 1) as u32,
+            character: old_lines[old_end - 1].chars().count() as u32,
+        }
+    } else {
+        Position {
+            line: old_end as u32,
+            character: 0,
+        }
+    };
+
+    Some(TextEdit {
+        range: Range {
+            start: Position {
+                line: prefix as u32,
+                character: 0,
+            },
+            end,
+        },
+        new_text: new_lines[prefix..new_end].concat(),
+    })
+}
+
+/// The file a `load` statement refers to: `:defs.bzl` is relative to the loading
+/// file's package and `//pkg:defs.bzl` to the workspace root. Modules in external
+/// repositories cannot be resolved.
+fn load_module_path(
+    module: &str,
+    current_file: &Path,
+    workspace_root: Option<&Path>,
+) -> Option<PathBuf> {
+    let module = module
+        .strip_prefix('@')
+        .filter(|m| m.starts_with("//"))
+        .unwrap_or(module);
+
+    let label = parse_label(module).ok()?;
+    if label.repo.is_some() {
+        return None;
+    }
+
+    if module.starts_with(':') {
+        return Some(current_file.parent()?.join(label.name));
+    }
+    Some(workspace_root?.join(label.package).join(label.name))
+}
+
+/// Resolves a label written in the package `package_path` to its `//pkg:name` form.
+/// Returns None for strings that are not labels.
+fn canonical_label(label: &str, package_path: &str) -> Option<String> {
+    parse_label_in(label, package_path)
+        .ok()
+        .map(|label| label.to_string())
+}
+
+/// The `label` parameter of a custom request, made absolute, with `:name` taken to be
+/// in the root package. None when it isn't a label.
+fn label_param(params: &serde_json::Value, method: &str) -> Result<Option<String>> {
+    let label = params
+        .get("label")
+        .and_then(|label| label.as_str())
+        .ok_or_else(|| {
+            tower_lsp::jsonrpc::Error::invalid_params(format!(
+                "{} expects parameters of the form {{\"label\": \"//pkg:name\"}}",
+                method
+            ))
+        })?;
+    Ok(canonical_label(label, ""))
+}
+
+/// The files listed in a `srcs` attribute, relative to the package. Globs and other
+/// computed values can't be resolved without bazel and give no files.
+fn source_files(attribute: Option<&ParsedAttribute>) -> Vec<String> {
+    let Some(ParsedAttribute {
+        value: AttributeValue::List(srcs),
+        ..
+    }) = attribute
+    else {
+        return Vec::new();
+    };
+    srcs.iter()
+        .filter(|src| !src.starts_with("//") && !src.starts_with('@'))
+        .map(|src| src.trim_start_matches(':').to_string())
+        .collect()
+}
+
+/// Turns a list into a `select` whose default branch is that list. `indent` is the
+/// indentation of the line the list starts on; lines of a multi-line list move one
+/// level deeper along with it.
+fn wrap_with_select(list: &str, indent: &str) -> String {
+    let list = list.replace('\n', "\n    ");
+    format!(
+        "select({{\n{indent}    \"//conditions:default\": {list},\n{indent}}})",
+        indent = indent,
+        list = list
+    )
+}
+
+/// The labels of a `deps` attribute, including those of every `select` branch.
+fn dep_labels(deps: &ParsedAttribute) -> Vec<&String> {
+    match &deps.value {
+        AttributeValue::List(labels) => labels.iter().collect(),
+        AttributeValue::Select(branches) => branches
+            .iter()
+            .filter_map(|(_, value)| match value {
+                AttributeValue::List(labels) => Some(labels),
+                _ => None,
+            })
+            .flatten()
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Whether `rule` may be used from the package `package_path`. None when that depends
+/// on something the index doesn't record: the package's default visibility for rules
+/// without a `visibility` attribute, or the members of a package group.
+fn is_visible(rule: &RuleInfo, package_path: &str) -> Option<bool> {
+    let rule_package = parse_label(&rule.full_build_path).ok()?.package;
+    if rule_package == package_path {
+        return Some(true);
+    }
+    if rule.visibility.is_empty() {
+        return None;
+    }
+
+    let mut visible = Some(false);
+    for entry in &rule.visibility {
+        let entry = parse_label_in(entry, &rule_package).ok()?;
+        match (entry.package.as_str(), entry.name.as_str()) {
+            ("visibility", "public") => return Some(true),
+            ("visibility", "private") => {}
+            (package, "__pkg__") if package == package_path => return Some(true),
+            (package, "__subpackages__")
+                if package.is_empty()
+                    || package == package_path
+                    || package_path.starts_with(&format!("{}/", package)) =>
+            {
+                return Some(true)
+            }
+            (_, "__pkg__" | "__subpackages__") => {}
+            // A package group
+            _ => visible = None,
+        }
+    }
+    visible
+}
+
+/// The value of a `testonly` attribute, which defaults to false. None when it is
+/// not a literal, e.g. a variable.
+fn testonly_value(attribute: Option<&ParsedAttribute>) -> Option<bool> {
+    let Some(attribute) = attribute else {
+        return Some(false);
+    };
+    match &attribute.value {
+        AttributeValue::Other(value) => match value.as_str() {
+            "True" | "1" => Some(true),
+            "False" | "0" => Some(false),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Tags with a special meaning to Bazel, with a short explanation of each.
+const COMMON_TAGS: &[(&str, &str)] = &[
+    (
+        "manual",
+        "Excludes the target from wildcard patterns such as `//...` and `:all`.",
+    ),
+    (
+        "no-cache",
+        "Never caches the results of the target's actions, locally or remotely.",
+    ),
+    (
+        "no-remote",
+        "Never executes or caches the target's actions remotely.",
+    ),
+    (
+        "no-sandbox",
+        "Runs the target's actions without sandboxing.",
+    ),
+    (
+        "local",
+        "Runs the target's actions locally, without sandboxing or remote execution.",
+    ),
+    (
+        "exclusive",
+        "Runs the test on its own, not in parallel with any other test.",
+    ),
+    (
+        "flaky",
+        "Marks the test as flaky so that it is retried on failure.",
+    ),
+    (
+        "external",
+        "Treats the test as depending on an external resource and never caches its result.",
+    ),
+    (
+        "requires-network",
+        "Allows the target's actions to access the network inside the sandbox.",
+    ),
+];
+
+/// Attributes of the call skeletons inserted for well-known rules, after `name`.
+const RULE_SNIPPET_ATTRIBUTES: &[(&str, &[&str])] = &[
+    ("cc_library", &["srcs", "hdrs", "deps"]),
+    ("cc_binary", &["srcs", "deps"]),
+    ("cc_test", &["srcs", "deps"]),
+    ("py_library", &["srcs", "deps"]),
+    ("py_binary", &["srcs", "deps"]),
+    ("py_test", &["srcs", "deps"]),
+    ("java_library", &["srcs", "deps"]),
+    ("java_binary", &["srcs", "main_class", "deps"]),
+    ("java_test", &["srcs", "test_class", "deps"]),
+    ("go_library", &["srcs", "importpath", "deps"]),
+    ("go_binary", &["srcs", "deps"]),
+    ("go_test", &["srcs", "deps"]),
+    ("sh_binary", &["srcs"]),
+    ("sh_test", &["srcs"]),
+    ("filegroup", &["srcs"]),
+    ("genrule", &["srcs", "outs", "cmd"]),
+];
+
+/// Attributes of the skeleton for rules not in [`RULE_SNIPPET_ATTRIBUTES`].
+const DEFAULT_SNIPPET_ATTRIBUTES: &[&str] = &["deps"];
+
+/// Attributes that take a single string rather than a list.
+const STRING_ATTRIBUTES: &[&str] = &["cmd", "importpath", "main_class", "test_class"];
+
+/// A snippet expanding `rule` into a call with a tabstop for the name and each of its
+/// common attributes, e.g. `cc_test(name = "${1:name}", srcs = [$2], deps = [$3])`.
+fn rule_snippet(rule: &str) -> String {
+    let attributes = RULE_SNIPPET_ATTRIBUTES
+        .iter()
+        .find(|(name, _)| *name == rule)
+        .map(|(_, attributes)| *attributes)
+        .unwrap_or(DEFAULT_SNIPPET_ATTRIBUTES);
+
+    let mut snippet = format!("{}(\n    name = \"${{1:name}}\",\n", rule);
+    for (index, attribute) in attributes.iter().enumerate() {
+        let tabstop = index + 2;
+        if STRING_ATTRIBUTES.contains(attribute) {
+            snippet.push_str(&format!("    {} = \"${}\",\n", attribute, tabstop));
+        } else {
+            snippet.push_str(&format!("    {} = [${}],\n", attribute, tabstop));
+        }
+    }
+    snippet.push_str(")$0");
+    snippet
+}
+
+/// The package a recursive `...` pattern is being typed for, given the text after its
+/// `//`: `foo` for `foo/...`, and the root package for `...` alone.
+fn recursive_wildcard_package(text_after_trigger: &str) -> Option<&str> {
+    let package = text_after_trigger.strip_suffix("...")?;
+    if package.is_empty() {
+        Some(package)
+    } else {
+        package.strip_suffix('/')
+    }
+}
+
+/// Sort text listing the targets of `package` first, then the other targets of the
+/// workspace, then those of external repositories.
+fn target_sort_text(label: &str, package: &str) -> String {
+    let rank = match parse_label(label) {
+        Ok(label) if label.repo.is_some() => 2,
+        Ok(label) if label.package == package => 0,
+        _ => 1,
+    };
+    format!("{}{}", rank, label)
+}
+
+/// A completion list of at most `max_items` items, marked incomplete when longer lists
+/// were cut so the client asks again instead of filtering what it already has.
+fn completion_list(mut items: Vec<CompletionItem>, max_items: usize) -> CompletionResponse {
+    let is_incomplete = items.len() > max_items;
+    items.truncate(max_items);
+    CompletionResponse::List(CompletionList {
+        is_incomplete,
+        items,
+    })
+}
+
+/// Files for a `srcs` list: the `package_files` on disk, see [`find_package_files`],
+/// and the outputs of the package's genrules. Outside a string literal the path is
+/// inserted with its quotes.
+///
+/// Files a `glob` in the `srcs` of the target at `position` already picks up are left
+/// out, while those it excludes are still offered.
+fn completion_srcs(
+    parser: &BazelParser,
+    text: &str,
+    position: &Position,
+    package_files: Vec<PathBuf>,
+    in_string: bool,
+) -> CompletionResponse {
+    let mut files: Vec<(String, String)> = package_files
+        .into_iter()
+        .filter(|file| !is_build_file(file))
+        .map(|file| {
+            (
+                file.to_string_lossy().to_string(),
+                "Source file".to_string(),
+            )
+        })
+        .collect();
+    for generated in parser.generated_files(text).unwrap_or_default() {
+        files.retain(|(path, _)| *path != generated.path);
+        files.push((
+            generated.path,
+            format!("Generated by :{}", generated.generator),
+        ));
+    }
+
+    if let Ok(Some(rule)) = parser.get_enclosing_rule(text, position) {
+        let globs: Vec<_> = parser
+            .extract_globs(text)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|glob| glob.attribute.as_deref() == Some("srcs"))
+            .filter(|glob| rule.range.start <= glob.range.start && glob.range.end <= rule.range.end)
+            .collect();
+        files.retain(|(path, _)| !globs.iter().any(|glob| glob.matches(path)));
+    }
+
+    let items = files
+        .into_iter()
+        .map(|(path, detail)| CompletionItem {
+            insert_text: Some(if in_string {
+                path.clone()
+            } else {
+                format!("\"{}\"", path)
+            }),
+            label: path,
+            kind: Some(CompletionItemKind::FILE),
+            detail: Some(detail),
+            ..Default::default()
+        })
+        .collect();
+
+    CompletionResponse::Array(items)
+}
+
+/// Visibility labels every target can use.
+const VISIBILITY_CONSTANTS: &[&str] = &["//visibility:public", "//visibility:private"];
+
+/// Completion items for the values of a `tags` list. Outside a string literal the
+/// tag is inserted with its quotes.
+fn completion_tags(in_string: bool) -> CompletionResponse {
+    let items = COMMON_TAGS
+        .iter()
+        .map(|(tag, documentation)| CompletionItem {
+            label: tag.to_string(),
+            kind: Some(CompletionItemKind::VALUE),
+            documentation: Some(Documentation::String(documentation.to_string())),
+            insert_text: Some(if in_string {
+                tag.to_string()
+            } else {
+                format!("\"{}\"", tag)
+            }),
+            ..Default::default()
+        })
+        .collect();
+
+    CompletionResponse::Array(items)
+}
+
+/// Suffixes of the target names offered besides the directory name itself.
+const TARGET_NAME_SUFFIXES: &[&str] = &["_lib", "_main", "_test"];
+
+/// Completion items for a target's `name`: the name of its package's `directory`,
+/// which conventionally names the main target, and variants of it. Names in `taken`
+/// are already declared in the file and left out.
+fn completion_target_names(directory: &str, taken: &[String]) -> CompletionResponse {
+    let items = std::iter::once(String::new())
+        .chain(TARGET_NAME_SUFFIXES.iter().map(|suffix| suffix.to_string()))
+        .map(|suffix| format!("{}{}", directory, suffix))
+        .filter(|name| !taken.contains(name))
+        .map(|name| CompletionItem {
+            label: name,
+            kind: Some(CompletionItemKind::VALUE),
+            ..Default::default()
+        })
+        .collect();
+
+    CompletionResponse::Array(items)
+}
+
+const BOOLEAN_VALUES: &[&str] = &["True", "False"];
+
+/// The values of attributes that take one of a few, by attribute name.
+const ATTRIBUTE_VALUES: &[(&str, &[&str])] = &[
+    ("testonly", BOOLEAN_VALUES),
+    ("linkstatic", BOOLEAN_VALUES),
+    ("linkshared", BOOLEAN_VALUES),
+    ("alwayslink", BOOLEAN_VALUES),
+    ("flaky", BOOLEAN_VALUES),
+    ("local", BOOLEAN_VALUES),
+    // Always, never, or only with --stamp
+    ("stamp", &["1", "0", "-1"]),
+];
+
+/// Completion items for the value of an attribute from [`ATTRIBUTE_VALUES`], or None
+/// when any value goes.
+fn completion_attribute_values(attribute: &str) -> Option<CompletionResponse> {
+    let (_, values) = ATTRIBUTE_VALUES
+        .iter()
+        .find(|(name, _)| *name == attribute)?;
+    let items = values
+        .iter()
+        .map(|value| CompletionItem {
+            label: value.to_string(),
+            kind: Some(CompletionItemKind::VALUE),
+            ..Default::default()
+        })
+        .collect();
+
+    Some(CompletionResponse::Array(items))
+}
+
+/// LSP's `RequestFailed`: the request was valid, but the document can't be processed.
+const REQUEST_FAILED: i64 = -32803;
+
+/// The error returned for a document that couldn't be formatted. Syntax errors are
+/// reported as `RequestFailed` with `{"kind": "syntax_error", "message", "range"}` data,
+/// so clients can point at the error; anything else is an internal error.
+fn formatting_error(err: anyhow::Error) -> tower_lsp::jsonrpc::Error {
+    match err.downcast_ref::<SyntaxError>() {
+        Some(syntax_error) => {
+            let mut error = tower_lsp::jsonrpc::Error::new(
+                tower_lsp::jsonrpc::ErrorCode::ServerError(REQUEST_FAILED),
+            );
+            error.message = format!("Cannot format: {}", syntax_error.message).into();
+            error.data = Some(serde_json::json!({
+                "kind": "syntax_error",
+                "message": syntax_error.message,
+                "range": syntax_error.range,
+            }));
+            error
+        }
+        None => {
+            let mut error =
+                tower_lsp::jsonrpc::Error::new(tower_lsp::jsonrpc::ErrorCode::InternalError);
+            error.data = Some(serde_json::json!({
+                "kind": "internal",
+                "message": err.to_string(),
+            }));
+            error
+        }
+    }
+}
+
+/// The error returned when the files of a workspace couldn't be read.
+fn filesystem_error(err: anyhow::Error) -> tower_lsp::jsonrpc::Error {
+    let mut error = tower_lsp::jsonrpc::Error::new(tower_lsp::jsonrpc::ErrorCode::InternalError);
+    error.message = format!("{:#}", err).into();
+    error
+}
+
+async fn run_buildifier_on_text(
+    buildifier: &Path,
+    text: &str,
+    path: Option<&Path>,
+) -> anyhow::Result<String> {
+    use tokio::io::AsyncWriteExt;
+
+    let mut cmd = tokio::process::Command::new(buildifier);
+    if let Some(path) = path {
+        // Lets buildifier pick BUILD vs .bzl formatting rules
+        cmd.arg(format!("--path={}", path.display()));
+    }
+    cmd.stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped());
+
+    let mut child = cmd.spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes()).await?;
+    }
+
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "buildifier failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Arguments for running only the test cases matching `filter`. An empty or missing
+/// filter runs the whole test.
+fn test_filter_args(filter: Option<&str>) -> Vec<String> {
+    match filter.map(str::trim) {
+        Some(filter) if !filter.is_empty() => vec![format!("--test_filter={}", filter)],
+        _ => Vec::new(),
+    }
+}
+
+/// Arguments for the `.bazelrc` config named by the `config` of a command argument, e.g.
+/// `{"target": "//pkg:name", "config": "opt"}`.
+fn config_args(argument: Option<&serde_json::Value>) -> Vec<String> {
+    match argument
+        .and_then(|arg| arg.get("config"))
+        .and_then(|config| config.as_str())
+        .map(str::trim)
+    {
+        Some(config) if !config.is_empty() => vec![format!("--config={}", config)],
+        _ => Vec::new(),
+    }
+}
+
+/// How long a document has to stay unchanged before it is analyzed again.
+const ANALYSIS_DELAY: Duration = Duration::from_millis(150);
+
+/// How long the output of a bazel command is still read after the command exited.
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Builds binaries with debug symbols for `bazel.debug`.
+const DEBUG_COMPILATION_MODE: &str = "--compilation_mode=dbg";
+
+/// Sent after `bazel.debug` has built a binary, so the client can launch a debugger on it.
+enum DebugStart {}
+
+impl notification::Notification for DebugStart {
+    type Params = serde_json::Value;
+    const METHOD: &'static str = "debug/start";
+}
+
+/// The executable bazel builds for `label`: `<bazel-bin>/<package>/<name>`.
+fn debug_program_path(bazel_bin: &str, label: &str) -> Option<PathBuf> {
+    let label = parse_label(label).ok()?;
+    Some(Path::new(bazel_bin).join(label.package).join(label.name))
+}
+
+/// The target patterns in `target` as separate arguments, e.g. `//foo/...` and
+/// `-//foo/bar/...` for `//foo/... -//foo/bar/...`. Bazel would read a negative pattern
+/// as an option, so they are preceded by `--`.
+fn target_patterns(target: &str) -> Vec<&str> {
+    let patterns: Vec<&str> = target.split_whitespace().collect();
+    if patterns.iter().any(|pattern| pattern.starts_with('-')) {
+        std::iter::once("--").chain(patterns).collect()
     } else {
-        rule.full_build_path.clone()
+        patterns
+    }
+}
+
+/// Depth of the `bazel.deps` graph when the command doesn't specify one.
+const DEFAULT_DEPS_DEPTH: u64 = 3;
+
+/// Builds the `bazel <command> <extra args> <targets>` process.
+///
+/// Output is plain text (`TERM=dumb` and `bazel_output_flags`), `HOME` and `PATH` are
+/// passed through from the server process, and `bazel_env` from the config is applied on top.
+fn bazel_command(
+    config: &ServerConfig,
+    command: &str,
+    targets: &[&str],
+    extra_args: &[String],
+    workspace_root: Option<&Path>,
+) -> tokio::process::Command {
+    let mut cmd = tokio::process::Command::new("bazel");
+    cmd.arg(command)
+        .args(&config.bazel_output_flags)
+        .args(extra_args)
+        .args(targets);
+
+    cmd.env("TERM", "dumb");
+    for var in ["HOME", "PATH"] {
+        if let Some(value) = std::env::var_os(var) {
+            cmd.env(var, value);
+        }
+    }
+    cmd.envs(&config.bazel_env);
+
+    if let Some(workspace_path) = workspace_root {
+        cmd.current_dir(workspace_path);
     }
+
+    cmd
+}
+
+/// Sorts `(range, token type)` pairs by position and drops every token that starts
+/// inside the one before it, since clients reject overlapping tokens. Of tokens
+/// starting at the same position the most specific one is kept: the lower type index
+/// wins, so function beats property beats string.
+fn remove_overlapping_tokens(tokens: &mut Vec<(Range, u32)>) {
+    tokens.sort_by_key(|(range, token_type)| (range.start, *token_type));
+
+    let mut covered_until: Option<Position> = None;
+    tokens.retain(|(range, _)| {
+        if covered_until.is_some_and(|end| range.start < end) {
+            return false;
+        }
+        covered_until = Some(range.end);
+        true
+    });
+}
+
+/// Builds the LSP service for the backend created by `init`, including the custom
+/// `bazel/*` requests and the `$/setTrace` notification.
+pub fn build_service(init: impl FnOnce(Client) -> Backend) -> (LspService<Backend>, ClientSocket) {
+    LspService::build(init)
+        .custom_method("bazel/resolveLabel", Backend::resolve_label)
+        .custom_method("bazel/dependencies", Backend::dependencies)
+        .custom_method("bazel/dependents", Backend::dependents)
+        .custom_method("bazel/buildFileForSource", Backend::build_file_for_source)
+        .custom_method("bazel/configs", Backend::configs)
+        .custom_method("bazel/parseLabel", Backend::parse_label)
+        .custom_method("$/setTrace", Backend::set_trace)
+        .finish()
 }
 
 impl Backend {
     pub fn new(client: Client) -> Self {
         Self {
             client,
-            parser: BazelParser::default(),
-            documents: Arc::new(RwLock::new(HashMap::new())),
+            parser: Arc::new(BazelParser::default()),
+            starlark_parser: Arc::new(
+                BazelParser::new_with_mode(BazelParserMode::Starlark)
+                    .expect("Failed to initialize Starlark parser"),
+            ),
+            documents: Arc::new(RwLock::new(DocumentStore::default())),
             target_trie: Arc::new(RwLock::new(TargetTrie::new())),
-            workspace_folders: Arc::new(RwLock::new(Vec::new())),
+            workspace_roots: Arc::new(RwLock::new(Vec::new())),
+            config: Arc::new(RwLock::new(ServerConfig::default())),
+            coverage: Arc::new(RwLock::new(CoverageCache::new())),
+            external_repos: Arc::new(RwLock::new(Vec::new())),
+            macro_index: Arc::new(RwLock::new(HashMap::new())),
+            query_locations: Arc::new(RwLock::new(HashMap::new())),
+            running_commands: Arc::new(RwLock::new(Vec::new())),
+            analysis_tasks: Arc::new(RwLock::new(HashMap::new())),
+            rule_schema: Arc::new(RwLock::new(RuleSchema::default())),
+            diagnostics_published: Arc::new(AtomicU64::new(0)),
+            shutdown_token: CancellationToken::new(),
+            non_file_uris: Arc::new(RwLock::new(HashSet::new())),
         }
     }
 
-    pub async fn publish_diagnostics(&self, uri: &url::Url, text: &str) {
-        let mut diagnostics = Vec::new();
+    /// Handles `bazel/resolveLabel`: finds the BUILD file declaring a label, given as
+    /// `{"label": "//pkg:name"}`, and the range of the declaration in it. Returns null
+    /// for labels that aren't declared in the workspace.
+    pub async fn resolve_label(
+        &self,
+        params: serde_json::Value,
+    ) -> Result<Option<serde_json::Value>> {
+        let Some(label) = label_param(&params, "bazel/resolveLabel")? else {
+            return Ok(None);
+        };
 
-        match self.parser.parse(text) {
-            Ok(_) => {
-                self.client
-                    .publish_diagnostics(uri.clone(), diagnostics, None)
-                    .await;
+        Ok(self
+            .label_declaration(&label)
+            .await
+            .map(|(build_file, range)| {
+                serde_json::json!({
+                    "path": build_file,
+                    "range": range,
+                })
+            }))
+    }
+
+    /// Handles `bazel/dependencies`: the labels in the `deps` of the indexed target
+    /// given as `{"label": "//pkg:name"}`, or null for targets that aren't indexed.
+    /// Read from BUILD files, so dependencies added by macros or toolchains are missing.
+    pub async fn dependencies(
+        &self,
+        params: serde_json::Value,
+    ) -> Result<Option<serde_json::Value>> {
+        let Some(label) = label_param(&params, "bazel/dependencies")? else {
+            return Ok(None);
+        };
+
+        let trie = self.target_trie.read().await;
+        Ok(trie
+            .resolve(&label)
+            .map(|rule| serde_json::json!(rule.deps)))
+    }
+
+    /// Handles `bazel/dependents`: the indexed targets with the label given as
+    /// `{"label": "//pkg:name"}` in their `deps`, sorted.
+    pub async fn dependents(&self, params: serde_json::Value) -> Result<Option<serde_json::Value>> {
+        let Some(label) = label_param(&params, "bazel/dependents")? else {
+            return Ok(None);
+        };
+
+        let trie = self.target_trie.read().await;
+        let mut dependents: Vec<&String> = trie
+            .starts_with("")
+            .into_iter()
+            .flatten()
+            .filter(|rule| rule.deps.contains(&label))
+            .map(|rule| &rule.full_build_path)
+            .collect();
+        dependents.sort();
+        dependents.dedup();
+        Ok(Some(serde_json::json!(dependents)))
+    }
+
+    /// Handles `bazel/buildFileForSource`: the URI of the BUILD file of the package the
+    /// file given as `{"uri": "file:///..."}` belongs to, or null for files outside of
+    /// any package of a workspace.
+    pub async fn build_file_for_source(
+        &self,
+        params: serde_json::Value,
+    ) -> Result<Option<serde_json::Value>> {
+        let path = params
+            .get("uri")
+            .and_then(|uri| uri.as_str())
+            .and_then(|uri| Url::parse(uri).ok())
+            .and_then(|uri| uri.to_file_path().ok())
+            .ok_or_else(|| {
+                tower_lsp::jsonrpc::Error::invalid_params(
+                    "bazel/buildFileForSource expects parameters of the form {\"uri\": \"file:///...\"}",
+                )
+            })?;
+
+        let build_file = find_package_build_file(&path).map_err(filesystem_error)?;
+        Ok(build_file
+            .and_then(|build_file| Url::from_file_path(build_file).ok())
+            .map(|uri| serde_json::json!(uri)))
+    }
+
+    /// Handles `$/setTrace`: the client changed the trace level, e.g. from its output
+    /// panel. Overrides the `trace` initialization option.
+    pub async fn set_trace(&self, params: SetTraceParams) {
+        self.config.write().await.trace = params.value;
+    }
+
+    /// Handles `bazel/configs`: the names of the configs the workspace's `.bazelrc`
+    /// defines, which the build, test and run commands accept as `config`.
+    pub async fn configs(&self) -> Result<Vec<String>> {
+        let Some(root) = self.workspace_root().await else {
+            return Ok(Vec::new());
+        };
+        find_bazelrc_configs(&root).map_err(filesystem_error)
+    }
+
+    /// Handles `bazel/parseLabel`: checks the syntax of the label given as
+    /// `{"label": "//pkg:name"}` without looking it up. Returns its `repo`, `package`
+    /// and `name` when it is valid, and the reason it isn't otherwise.
+    pub async fn parse_label(&self, params: serde_json::Value) -> Result<serde_json::Value> {
+        let label = params
+            .get("label")
+            .and_then(|label| label.as_str())
+            .ok_or_else(|| {
+                tower_lsp::jsonrpc::Error::invalid_params(
+                    "bazel/parseLabel expects parameters of the form {\"label\": \"//pkg:name\"}",
+                )
+            })?;
+
+        Ok(match parse_label(label) {
+            Ok(label) => serde_json::json!({
+                "valid": true,
+                "repo": label.repo,
+                "package": label.package,
+                "name": label.name,
+            }),
+            Err(err) => serde_json::json!({
+                "valid": false,
+                "message": err.to_string(),
+            }),
+        })
+    }
+
+    /// The BUILD file declaring a canonical label and the range of the declaration in
+    /// it, i.e. the rule call or the `exports_files` entry.
+    async fn label_declaration(&self, label: &str) -> Option<(PathBuf, Range)> {
+        let Label { package, name, .. } = parse_label(label).ok()?;
+
+        // The index knows where each target is declared; packages that aren't
+        // indexed are looked up in the workspace
+        let indexed = self
+            .target_trie
+            .read()
+            .await
+            .resolve(label)
+            .and_then(|rule| rule.source_file.clone());
+        let build_files = match indexed {
+            Some(build_file) => vec![build_file],
+            None => match self.workspace_root().await {
+                Some(root) => ["BUILD.bazel", "BUILD"]
+                    .iter()
+                    .map(|file| root.join(&package).join(file))
+                    .filter(|path| path.is_file())
+                    .collect(),
+                None => Vec::new(),
+            },
+        };
+
+        for build_file in build_files {
+            let open_text = match Url::from_file_path(&build_file) {
+                Ok(build_uri) => self.documents.read().await.get(build_uri.as_str()).cloned(),
+                Err(_) => None,
+            };
+            let Some(text) = open_text.or_else(|| fs::read_to_string(&build_file).ok()) else {
+                continue;
+            };
+
+            let declaration = self
+                .parser
+                .extract_targets(&text)
+                .unwrap_or_default()
+                .into_iter()
+                .find(|target| target.name == name)
+                .map(|target| target.rule_call_range)
+                .or_else(|| {
+                    self.parser
+                        .extract_exported_files(&text)
+                        .unwrap_or_default()
+                        .into_iter()
+                        .find(|file| file.name == name)
+                        .map(|file| file.range)
+                });
+            if let Some(range) = declaration {
+                return Some((build_file, range));
             }
-            Err(err) => {
-                let diagnostic = Diagnostic {
-                    range: Range {
-                        start: Position {
-                            line: 0,
-                            character: 0,
-                        },
-                        end: Position {
-                            line: 0,
-                            character: 0,
-                        },
-                    },
-                    severity: Some(DiagnosticSeverity::ERROR),
-                    code: Some(NumberOrString::String("parse_error".to_string())),
-                    code_description: None,
-                    source: Some("bazel-lsp".to_string()),
-                    message: err.to_string(),
-                    related_information: None,
-                    tags: None,
-                    data: None,
-                };
+        }
+
+        None
+    }
+
+    /// Asks bazel where a label is defined, for targets that no BUILD file declares
+    /// directly, e.g. ones generated by macros. Found locations are cached.
+    async fn query_location(&self, label: &str) -> Option<Location> {
+        if let Some(location) = self.query_locations.read().await.get(label) {
+            return Some(location.clone());
+        }
+
+        let output = self
+            .run_bazel_query(label, &["--output=location"])
+            .await
+            .ok()?;
+        let (path, line, character) = parse_query_location(&output)?;
+        let position = Position::new(line, character);
+        let location = Location {
+            uri: Url::from_file_path(path).ok()?,
+            range: Range::new(position, position),
+        };
+
+        self.query_locations
+            .write()
+            .await
+            .insert(label.to_string(), location.clone());
+        Some(location)
+    }
+
+    /// Logs an internal event when the trace level is `verbose`.
+    pub async fn log_trace(&self, message: impl std::fmt::Display) {
+        if self.config.read().await.trace == TraceValue::Verbose {
+            self.client.log_message(MessageType::LOG, message).await;
+        }
+    }
+
+    /// Re-indexes a changed document, publishes its diagnostics and asks the client to
+    /// refresh what depends on its content.
+    async fn analyze_changed_document(&self, uri: &Url) {
+        let Some(text) = self.documents.read().await.get(uri.as_str()).cloned() else {
+            return;
+        };
+
+        // Keep completion in other files in sync with unsaved edits
+        let mut reindexed = false;
+        if let Some(file_path) = self.file_path_from_uri(uri).await {
+            if is_build_file(&file_path) {
+                let mut trie = self.target_trie.write().await;
+                trie.remove_file(&file_path);
+                let _ = self.index_build_file_content(&file_path, &text, &mut trie);
+                drop(trie);
+                // Edits move declarations, so bazel has to be asked again
+                self.query_locations.write().await.clear();
+                reindexed = true;
+            } else if is_bzl_file(&file_path) {
+                let macros = self.starlark_parser.extract_defs(&text).unwrap_or_default();
+                self.macro_index.write().await.insert(file_path, macros);
+            }
+        }
+
+        self.publish_diagnostics(uri, &text).await;
+        if reindexed {
+            self.republish_open_diagnostics(Some(uri)).await;
+        }
+
+        self.client
+            .send_request::<request::SemanticTokensRefresh>(())
+            .await
+            .ok();
+        self.client
+            .send_request::<request::CodeLensRefresh>(())
+            .await
+            .ok();
+    }
+
+    pub async fn publish_diagnostics(&self, uri: &url::Url, text: &str) {
+        let mut diagnostics = Vec::new();
+
+        let started = std::time::Instant::now();
+        let checked = self.parser_for(uri).check_syntax(text);
+        self.log_trace(format!("Parsed {} in {:?}", uri, started.elapsed()))
+            .await;
+
+        let syntax_errors = match checked {
+            Ok(errors) => errors,
+            // The document couldn't be parsed at all
+            Err(err) => vec![SyntaxError {
+                range: Range::default(),
+                message: err.to_string(),
+            }],
+        };
+        diagnostics.extend(syntax_errors.into_iter().map(|error| Diagnostic {
+            range: error.range,
+            severity: Some(DiagnosticSeverity::ERROR),
+            code: Some(NumberOrString::String("parse_error".to_string())),
+            source: Some("bazel-lsp".to_string()),
+            message: error.message,
+            ..Default::default()
+        }));
+
+        // Checking deps needs the package the file belongs to
+        if let Some(file_path) = self.file_path_from_uri(uri).await {
+            let package_path = self.package_path(&file_path).await;
+            diagnostics.extend(self.testonly_diagnostics(uri, text, &package_path).await);
+            diagnostics.extend(self.visibility_diagnostics(uri, text, &package_path).await);
+            diagnostics.extend(
+                self.undefined_target_diagnostics(uri, text, &file_path, &package_path)
+                    .await,
+            );
+            diagnostics.extend(self.empty_glob_diagnostics(text, &file_path, &package_path));
+        }
+        diagnostics.extend(self.unsortable_deps_diagnostics(uri, text));
+
+        {
+            let config = self.config.read().await;
+            for diagnostic in diagnostics.iter_mut() {
+                if let Some(NumberOrString::String(code)) = &diagnostic.code {
+                    let default = diagnostic.severity.unwrap_or(DiagnosticSeverity::ERROR);
+                    diagnostic.severity = Some(config.diagnostic_severity(code, default));
+                }
+            }
+        }
+
+        let count = |severity| {
+            diagnostics
+                .iter()
+                .filter(|diagnostic| diagnostic.severity == Some(severity))
+                .count()
+        };
+        let summary = format!(
+            "diagnostics: uri={} errors={} warnings={}",
+            uri,
+            count(DiagnosticSeverity::ERROR),
+            count(DiagnosticSeverity::WARNING)
+        );
+
+        self.diagnostics_published.fetch_add(1, Ordering::Relaxed);
+        self.client
+            .publish_diagnostics(uri.clone(), diagnostics, None)
+            .await;
+        self.client.log_message(MessageType::LOG, summary).await;
+    }
+
+    /// Publishes the diagnostics of the open documents other than `except` again, after
+    /// the index changed and with it which of their deps are declared.
+    async fn republish_open_diagnostics(&self, except: Option<&Url>) {
+        let open_documents = self.documents.read().await.open_documents();
+        for (uri, text) in open_documents {
+            let Ok(uri) = Url::parse(&uri) else {
+                continue;
+            };
+            if Some(&uri) != except {
+                self.publish_diagnostics(&uri, &text).await;
+            }
+        }
+    }
+
+    /// Flags the `glob` calls of a BUILD file that match none of its package's files once
+    /// their `exclude` patterns are applied, which bazel rejects by default.
+    fn empty_glob_diagnostics(
+        &self,
+        text: &str,
+        file_path: &Path,
+        package_path: &str,
+    ) -> Vec<Diagnostic> {
+        let Some(package_dir) = file_path.parent().filter(|dir| dir.is_dir()) else {
+            return Vec::new();
+        };
+        if !is_build_file(file_path) {
+            return Vec::new();
+        }
+
+        let Ok(files) = find_package_files(package_dir) else {
+            return Vec::new();
+        };
+        let files: Vec<String> = files
+            .iter()
+            .map(|file| file.to_string_lossy().to_string())
+            .collect();
+        let files: Vec<&str> = files.iter().map(String::as_str).collect();
+
+        self.parser
+            .extract_globs(text)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|glob| !glob.include.is_empty() && glob.sources(&files).is_empty())
+            .map(|glob| Diagnostic {
+                range: glob.range,
+                severity: Some(DiagnosticSeverity::WARNING),
+                code: Some(NumberOrString::String("empty_glob".to_string())),
+                source: Some("bazel-lsp".to_string()),
+                message: format!("glob matches no files in package '//{}'", package_path),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    /// Notes the `deps` values that formatting leaves unsorted because they aren't
+    /// built from lists and `select()`s.
+    fn unsortable_deps_diagnostics(&self, uri: &Url, text: &str) -> Vec<Diagnostic> {
+        let ranges = self
+            .parser_for(uri)
+            .unsortable_deps(text)
+            .unwrap_or_default();
+
+        ranges
+            .into_iter()
+            .map(|range| Diagnostic {
+                range,
+                severity: Some(DiagnosticSeverity::INFORMATION),
+                code: Some(NumberOrString::String("unsortable_deps".to_string())),
+                source: Some("bazel-lsp".to_string()),
+                message: "deps is not built from lists and select(), so formatting can't sort it"
+                    .to_string(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    /// Flags deps of non-testonly targets on indexed targets marked `testonly = True`.
+    /// Deps whose testonly status is unknown are skipped.
+    async fn testonly_diagnostics(
+        &self,
+        uri: &Url,
+        text: &str,
+        package_path: &str,
+    ) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+
+        let parser = self.parser_for(uri);
+        let Ok(targets) = parser.parse_build_file(text) else {
+            return diagnostics;
+        };
+        let dep_strings = parser.extract_dep_strings(text).unwrap_or_default();
+        let trie = self.target_trie.read().await;
+
+        for target in targets {
+            // Tests are implicitly testonly
+            if RuleCategory::from_rule_type(&target.rule_type) == RuleCategory::Test
+                || testonly_value(target.attributes.get("testonly")) != Some(false)
+            {
+                continue;
+            }
+            let Some(deps) = target.attributes.get("deps") else {
+                continue;
+            };
+
+            let labels = dep_strings.iter().filter(|label| {
+                deps.range.start <= label.range.start && label.range.end <= deps.range.end
+            });
+            for label in labels {
+                let Some(dep) = canonical_label(&label.value, package_path)
+                    .and_then(|label| trie.resolve(&label))
+                else {
+                    continue;
+                };
+                if dep.testonly != Some(true) {
+                    continue;
+                }
+
+                diagnostics.push(Diagnostic {
+                    range: label.range,
+                    severity: Some(DiagnosticSeverity::ERROR),
+                    code: Some(NumberOrString::String("testonly_violation".to_string())),
+                    source: Some("bazel-lsp".to_string()),
+                    message: format!(
+                        "Non-testonly target '{}' depends on testonly target '{}'",
+                        target.name, dep.full_build_path
+                    ),
+                    ..Default::default()
+                });
+            }
+        }
+
+        diagnostics
+    }
+
+    /// Flags deps on indexed targets whose `visibility` doesn't include the current
+    /// package. Targets without an explicit visibility, or with package groups the
+    /// server can't expand, are skipped.
+    async fn visibility_diagnostics(
+        &self,
+        uri: &Url,
+        text: &str,
+        package_path: &str,
+    ) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+
+        let parser = self.parser_for(uri);
+        let Ok(targets) = parser.parse_build_file(text) else {
+            return diagnostics;
+        };
+        let dep_strings = parser.extract_dep_strings(text).unwrap_or_default();
+        let trie = self.target_trie.read().await;
+
+        for target in targets {
+            let Some(deps) = target.attributes.get("deps") else {
+                continue;
+            };
+
+            let labels = dep_strings.iter().filter(|label| {
+                deps.range.start <= label.range.start && label.range.end <= deps.range.end
+            });
+            for label in labels {
+                let Some(dep) = canonical_label(&label.value, package_path)
+                    .and_then(|label| trie.resolve(&label))
+                else {
+                    continue;
+                };
+                if is_visible(dep, package_path) != Some(false) {
+                    continue;
+                }
+
+                diagnostics.push(Diagnostic {
+                    range: label.range,
+                    severity: Some(DiagnosticSeverity::WARNING),
+                    code: Some(NumberOrString::String("visibility".to_string())),
+                    source: Some("bazel-lsp".to_string()),
+                    message: format!(
+                        "Target '{}' is not visible from package '//{}'",
+                        dep.full_build_path, package_path
+                    ),
+                    ..Default::default()
+                });
+            }
+        }
+
+        diagnostics
+    }
+
+    /// Flags labels in `deps` that name no target. Targets of the file's own package are
+    /// looked up in the document, which may be ahead of the index, and those of other
+    /// packages in the index. Only labels of the main repository are checked, and files
+    /// on disk count as targets.
+    async fn undefined_target_diagnostics(
+        &self,
+        uri: &Url,
+        text: &str,
+        file_path: &Path,
+        package_path: &str,
+    ) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+
+        // Without a workspace there is no index to look labels up in
+        let Some(workspace_root) = self
+            .workspace_root()
+            .await
+            .filter(|root| file_path.starts_with(root))
+        else {
+            return diagnostics;
+        };
+        let parser = self.parser_for(uri);
+        let Ok(deps) = parser.extract_dep_strings(text) else {
+            return diagnostics;
+        };
+        let mut declared: HashSet<String> = parser
+            .extract_targets(text)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|target| target.name)
+            .collect();
+        declared.extend(
+            parser
+                .extract_exported_files(text)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|file| file.name),
+        );
+        declared.extend(
+            parser
+                .generated_files(text)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|file| file.path),
+        );
+        let trie = self.target_trie.read().await;
+
+        for dep in deps {
+            let Ok(label) = parse_label_in(&dep.value, package_path) else {
+                continue;
+            };
+            if label.repo.is_some() {
+                continue;
+            }
+            let package_dir = workspace_root.join(&label.package);
+            if package_dir.join(&label.name).exists() {
+                continue;
+            }
+            let build_file = ["BUILD.bazel", "BUILD"]
+                .iter()
+                .map(|name| package_dir.join(name))
+                .find(|build_file| build_file.is_file());
+
+            let message = match build_file {
+                _ if label.package == package_path => {
+                    if declared.contains(&label.name) {
+                        continue;
+                    }
+                    format!(
+                        "Target '{}' is not declared in package '//{}'",
+                        label, label.package
+                    )
+                }
+                // Packages that aren't indexed, e.g. in excluded directories or with a
+                // BUILD file that couldn't be parsed, may declare anything
+                Some(build_file) => {
+                    if !trie.contains_file(&build_file)
+                        || trie.resolve(&label.to_string()).is_some()
+                    {
+                        continue;
+                    }
+                    format!(
+                        "Target '{}' is not declared in package '//{}'",
+                        label, label.package
+                    )
+                }
+                None => format!(
+                    "Target '{}' is not defined: package '//{}' has no BUILD file",
+                    label, label.package
+                ),
+            };
+            diagnostics.push(Diagnostic {
+                range: dep.range,
+                severity: Some(DiagnosticSeverity::WARNING),
+                code: Some(NumberOrString::String("undefined_target".to_string())),
+                source: Some("bazel-lsp".to_string()),
+                message,
+                ..Default::default()
+            });
+        }
+
+        diagnostics
+    }
+
+    pub async fn update_document_content(
+        &self,
+        uri: &url::Url,
+        content_changes: &[TextDocumentContentChangeEvent],
+    ) {
+        let mut documents = self.documents.write().await;
+        let current_text = documents.get(uri.as_str()).cloned().unwrap_or_default();
+
+        let mut new_text = current_text;
+        for change in content_changes {
+            if let Some(range) = &change.range {
+                let start_byte = self.position_to_byte_index(&new_text, &range.start);
+                let end_byte = self.position_to_byte_index(&new_text, &range.end);
+
+                new_text.replace_range(start_byte..end_byte, &change.text);
+            } else {
+                new_text = change.text.clone();
+            }
+        }
+
+        documents.insert(uri.to_string(), new_text);
+    }
+
+    fn position_to_byte_index(&self, text: &str, position: &Position) -> usize {
+        let lines: Vec<&str> = text.lines().collect();
+        let mut byte_index = 0;
+
+        for i in 0..position.line as usize {
+            if i < lines.len() {
+                byte_index += lines[i].len() + 1; // +1 for the newline character
+            }
+        }
+
+        if (position.line as usize) < lines.len() {
+            let line = lines[position.line as usize];
+            let char_index = position.character as usize;
+            let mut chars = 0;
+            let mut bytes = 0;
+
+            for c in line.chars() {
+                if chars >= char_index {
+                    break;
+                }
+                bytes += c.len_utf8();
+                chars += 1;
+            }
+
+            byte_index += bytes;
+        }
+
+        byte_index
+    }
+
+    /// Semantic tokens of the document, or only of those overlapping `range`.
+    ///
+    /// Tokens of a range are encoded like those of the whole document, so the first
+    /// one is still relative to the start of the document.
+    fn get_semantic_tokens(
+        &self,
+        parser: &BazelParser,
+        text: &str,
+        range: Option<Range>,
+    ) -> SemanticTokens {
+        let mut tokens = Vec::new();
+
+        let targets = parser.extract_targets(text).unwrap_or_default();
+
+        let attributes = parser.extract_attributes(text).unwrap_or_default();
+
+        let strings = parser.extract_strings(text).unwrap_or_default();
+
+        let literals = parser.extract_literals(text).unwrap_or_default();
+
+        let defs = parser.extract_defs(text).unwrap_or_default();
+
+        let mut all_tokens: Vec<(Range, u32)> = Vec::new();
+
+        for target in targets {
+            all_tokens.push((target.rule_type_range, 0));
+        }
+
+        for def in defs {
+            all_tokens.push((def.name_range, 0));
+        }
+
+        for attr in attributes {
+            all_tokens.push((attr.range, 1));
+        }
+
+        for string in strings {
+            all_tokens.push((string.range, 2));
+        }
+
+        for literal in literals {
+            let token_type = match literal.kind {
+                LiteralKind::Boolean => 3,
+                LiteralKind::Number => 4,
+            };
+            all_tokens.push((literal.range, token_type));
+        }
+
+        if let Some(range) = range {
+            all_tokens.retain(|(token, _)| token.start < range.end && range.start < token.end);
+        }
+
+        remove_overlapping_tokens(&mut all_tokens);
+
+        let mut prev_line = 0;
+        let mut prev_start = 0;
+
+        for (range, token_type) in all_tokens {
+            let delta_line = range.start.line;
+            let delta_start = if delta_line == prev_line {
+                if range.start.character >= prev_start {
+                    range.start.character - prev_start
+                } else {
+                    0
+                }
+            } else {
+                range.start.character
+            };
+
+            let delta_line_value = if tokens.is_empty() {
+                delta_line
+            } else {
+                if delta_line >= prev_line {
+                    delta_line - prev_line
+                } else {
+                    0
+                }
+            };
+
+            tokens.push(SemanticToken {
+                delta_line: delta_line_value,
+                delta_start: delta_start as u32,
+                length: (range.end.character - range.start.character) as u32,
+                token_type,
+                token_modifiers_bitset: 0,
+            });
+
+            prev_line = delta_line;
+            prev_start = range.start.character;
+        }
+
+        SemanticTokens {
+            result_id: None,
+            data: tokens,
+        }
+    }
+
+    /// Adds the targets of every BUILD file under a workspace folder to the trie.
+    async fn index_workspace_folder(&self, path: &Path) {
+        let excluded_dirs = self.config.read().await.excluded_dirs.clone();
+        let started = std::time::Instant::now();
+        let files = find_build_files(path, &excluded_dirs)
+            .and_then(|build_files| Ok((build_files, find_bzl_files(path, &excluded_dirs)?)));
+        let (build_files, bzl_files) = match files {
+            Ok(files) => files,
+            Err(err) => {
+                self.client
+                    .log_message(
+                        MessageType::ERROR,
+                        format!("Failed to index {}: {:#}", path.display(), err),
+                    )
+                    .await;
+                return;
+            }
+        };
+
+        let mut trie: tokio::sync::RwLockWriteGuard<'_, TargetTrie> =
+            self.target_trie.write().await;
+
+        for build_file in build_files.iter() {
+            // Re-indexing a file replaces its previous targets
+            trie.remove_file(build_file);
+            let _ = self.populate_trie_from_build_file(build_file, &mut trie);
+        }
+        drop(trie);
+
+        let mut macro_index = self.macro_index.write().await;
+        for bzl_file in bzl_files.iter() {
+            if let Ok(content) = fs::read_to_string(bzl_file) {
+                let macros = self
+                    .starlark_parser
+                    .extract_defs(&content)
+                    .unwrap_or_default();
+                macro_index.insert(bzl_file.clone(), macros);
+            }
+        }
+        drop(macro_index);
+
+        self.log_trace(format!(
+            "Indexed {} BUILD files and {} .bzl files under {} in {:?}",
+            build_files.len(),
+            bzl_files.len(),
+            path.display(),
+            started.elapsed()
+        ))
+        .await;
+    }
+
+    fn populate_trie_from_build_file(
+        &self,
+        build_file: &Path,
+        trie: &mut TargetTrie,
+    ) -> anyhow::Result<()> {
+        if let Ok(content) = fs::read_to_string(build_file) {
+            self.index_build_file_content(build_file, &content, trie)?;
+        }
+        Ok(())
+    }
+
+    /// Adds the targets declared by `content` to the trie under the package of `build_file`.
+    ///
+    /// The content doesn't have to match the file on disk, so unsaved edits can be indexed.
+    fn index_build_file_content(
+        &self,
+        build_file: &Path,
+        content: &str,
+        trie: &mut TargetTrie,
+    ) -> anyhow::Result<()> {
+        let package_path = if let Some(workspace_root) = find_workspace_root(build_file)? {
+            build_file
+                .parent()
+                .and_then(|dir| dir.strip_prefix(workspace_root).ok())
+                .map(|relative_path| relative_path.to_string_lossy().to_string())
+                .unwrap_or_default()
+        } else {
+            String::new()
+        };
+
+        if let Ok(targets) = self.parser.parse_build_file(content) {
+            for target in targets {
+                // The label doubles as the trie key, so lookups by label find the rule
+                let label = Label::new(&package_path, &target.name).to_string();
+
+                let visibility = match target.attributes.get("visibility") {
+                    Some(ParsedAttribute {
+                        value: AttributeValue::List(labels),
+                        ..
+                    }) => labels.clone(),
+                    _ => Vec::new(),
+                };
+
+                let rule = RuleInfo {
+                    name: target.name.clone(),
+                    full_build_path: label.clone(),
+                    testonly: testonly_value(target.attributes.get("testonly")),
+                    deps: target
+                        .attributes
+                        .get("deps")
+                        .map(|deps| {
+                            dep_labels(deps)
+                                .into_iter()
+                                .filter_map(|dep| canonical_label(dep, &package_path))
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                    rule_type: target.rule_type,
+                    visibility,
+                    source_file: Some(build_file.to_path_buf()),
+                };
+
+                trie.insert_file_target(build_file, &label, rule);
+            }
+        }
+
+        // `exports_files` declares file targets, which have no `name` attribute
+        for file in self
+            .parser
+            .extract_exported_files(content)
+            .unwrap_or_default()
+        {
+            let label = Label::new(&package_path, &file.name).to_string();
+
+            let rule = RuleInfo {
+                name: file.name.clone(),
+                full_build_path: label.clone(),
+                rule_type: "exports_files".to_string(),
+                testonly: Some(false),
+                source_file: Some(build_file.to_path_buf()),
+                ..Default::default()
+            };
+
+            trie.insert_file_target(build_file, &label, rule);
+        }
+        Ok(())
+    }
+
+    /// The completion items at a position, before they are tailored to what the client
+    /// supports.
+    async fn complete(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
+        let uri = params.text_document_position.text_document.uri;
+        let position = params.text_document_position.position;
+
+        let text = self
+            .documents
+            .read()
+            .await
+            .get(uri.as_str())
+            .cloned()
+            .unwrap_or_default();
+        let file_path = self.file_path_from_uri(&uri).await;
+
+        let parser = self.parser_for(&uri);
+        if parser
+            .is_at_statement_level(&text, &position)
+            .unwrap_or(false)
+        {
+            self.log_trace(format!(
+                "Completion at {}:{}: statement level, completing rule names",
+                position.line, position.character
+            ))
+            .await;
+            let snippets = self.config.read().await.snippet_support;
+            return Ok(Some(self.completion_rule_names(parser, &text, snippets)));
+        }
+
+        if parser
+            .is_in_attribute(&text, &position, "tags")
+            .unwrap_or(false)
+        {
+            self.log_trace(format!(
+                "Completion at {}:{}: inside tags, completing common tags",
+                position.line, position.character
+            ))
+            .await;
+            let in_string = matches!(parser.get_string_at(&text, &position), Ok(Some(_)));
+            return Ok(Some(completion_tags(in_string)));
+        }
+
+        if parser
+            .is_in_attribute(&text, &position, "visibility")
+            .unwrap_or(false)
+        {
+            self.log_trace(format!(
+                "Completion at {}:{}: inside visibility, completing package groups",
+                position.line, position.character
+            ))
+            .await;
+            let in_string = matches!(parser.get_string_at(&text, &position), Ok(Some(_)));
+            return Ok(Some(
+                self.completion_visibility(parser, &text, in_string).await,
+            ));
+        }
+
+        if parser
+            .is_in_attribute(&text, &position, "srcs")
+            .unwrap_or(false)
+        {
+            self.log_trace(format!(
+                "Completion at {}:{}: inside srcs, completing the package's files",
+                position.line, position.character
+            ))
+            .await;
+            let in_string = matches!(parser.get_string_at(&text, &position), Ok(Some(_)));
+            // Listing a large package takes a while, so it is kept off the async workers
+            let package_files = match file_path.as_deref().and_then(Path::parent) {
+                Some(dir) => {
+                    let dir = dir.to_path_buf();
+                    tokio::task::spawn_blocking(move || find_package_files(&dir))
+                        .await
+                        .ok()
+                        .and_then(|files| files.ok())
+                        .unwrap_or_default()
+                }
+                None => Vec::new(),
+            };
+            return Ok(Some(completion_srcs(
+                parser,
+                &text,
+                &position,
+                package_files,
+                in_string,
+            )));
+        }
+
+        let name_string = parser
+            .get_string_at(&text, &position)
+            .unwrap_or_default()
+            .filter(|string| string.attribute.as_deref() == Some("name"))
+            .filter(|string| string.range.start < position);
+        let directory = file_path
+            .as_deref()
+            .and_then(|path| Some(path.parent()?.file_name()?.to_string_lossy().to_string()));
+        if let (Some(_), Some(directory)) = (name_string, directory) {
+            self.log_trace(format!(
+                "Completion at {}:{}: inside name, completing names after the directory",
+                position.line, position.character
+            ))
+            .await;
+            let taken: Vec<String> = parser
+                .extract_targets(&text)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|target| target.name)
+                .collect();
+            return Ok(Some(completion_target_names(&directory, &taken)));
+        }
+
+        if let Some(values) = parser
+            .attribute_at_value(&text, &position)
+            .unwrap_or_default()
+            .and_then(|attribute| completion_attribute_values(&attribute))
+        {
+            self.log_trace(format!(
+                "Completion at {}:{}: attribute value, completing its known values",
+                position.line, position.character
+            ))
+            .await;
+            return Ok(Some(values));
+        }
+
+        if let Some((rule, set_attributes)) = parser
+            .attribute_name_context(&text, &position)
+            .unwrap_or_default()
+        {
+            self.log_trace(format!(
+                "Completion at {}:{}: attribute name of {}, completing its attributes",
+                position.line, position.character, rule
+            ))
+            .await;
+            return Ok(Some(
+                self.completion_attribute_names(&rule, &set_attributes)
+                    .await,
+            ));
+        }
+
+        if !parser
+            .is_in_deps_attribute(&text, &position)
+            .unwrap_or(false)
+        {
+            self.log_trace(format!(
+                "Completion at {}:{}: not inside a deps attribute",
+                position.line, position.character
+            ))
+            .await;
+            return Ok(None);
+        }
+
+        let is_in_workspace = match &file_path {
+            Some(file_path) => self
+                .workspace_roots
+                .read()
+                .await
+                .iter()
+                .any(|root| file_path.starts_with(root)),
+            None => false,
+        };
+        let use_index = is_in_workspace
+            || (self.config.read().await.index_completion_outside_workspace
+                && !self.target_trie.read().await.is_empty());
+
+        let line = text.lines().nth(position.line as usize).unwrap_or("");
+        // `character` counts characters, not bytes, and clients may send one past the end
+        let cursor = self.position_to_byte_index(
+            line,
+            &Position {
+                line: 0,
+                character: position.character,
+            },
+        );
+        let line_up_to_cursor = &line[..cursor.min(line.len())];
+
+        let trigger_result = find_trigger_position(line_up_to_cursor);
+        if trigger_result.is_none() {
+            self.log_trace(format!(
+                "Completion at {}:{}: no `//` or `:` trigger before the cursor",
+                position.line, position.character
+            ))
+            .await;
+            return Ok(None);
+        }
+
+        self.log_trace(format!(
+            "Completion at {}:{}: {:?}, completing from {}",
+            position.line,
+            position.character,
+            trigger_result,
+            if use_index {
+                "workspace index"
+            } else {
+                "current file"
+            }
+        ))
+        .await;
+
+        if let Some(TriggerResult {
+            trigger_type: TriggerType::At,
+            trigger_pos,
+            ..
+        }) = trigger_result
+        {
+            return Ok(Some(
+                self.completion_external_repos(position, trigger_pos).await,
+            ));
+        }
 
-                diagnostics.push(diagnostic);
-                self.client
-                    .publish_diagnostics(uri.clone(), diagnostics, None)
-                    .await;
-            }
+        if use_index {
+            let package_path = match &file_path {
+                Some(file_path) => self.package_path(file_path).await,
+                None => String::new(),
+            };
+            self.completion_in_workspace(position, trigger_result, &package_path)
+                .await
+        } else {
+            self.completion_in_file(parser, trigger_result, &text).await
         }
     }
 
-    pub async fn update_document_content(
+    /// Rule names that can start a statement: rules already used in the document and
+    /// symbols imported through `load`. With `snippets` each one expands into a call
+    /// skeleton, see [`rule_snippet`].
+    fn completion_rule_names(
         &self,
-        uri: &url::Url,
-        content_changes: &[TextDocumentContentChangeEvent],
-    ) {
-        let mut documents = self.documents.write().await;
-        let current_text = documents.get(&uri.to_string()).cloned().unwrap_or_default();
+        parser: &BazelParser,
+        text: &str,
+        snippets: bool,
+    ) -> CompletionResponse {
+        let mut items: Vec<CompletionItem> = Vec::new();
 
-        let mut new_text = current_text;
-        for change in content_changes {
-            if let Some(range) = &change.range {
-                let start_byte = self.position_to_byte_index(&new_text, &range.start);
-                let end_byte = self.position_to_byte_index(&new_text, &range.end);
+        for load in parser.extract_loads(text).unwrap_or_default() {
+            for symbol in load.symbols {
+                if items.iter().any(|item| item.label == symbol.name) {
+                    continue;
+                }
+                items.push(CompletionItem {
+                    label: symbol.name.clone(),
+                    kind: Some(CompletionItemKind::FUNCTION),
+                    detail: Some(format!("Loaded from {}", load.module)),
+                    documentation: Some(Documentation::String(format!(
+                        "`{}` from {}",
+                        symbol.original, load.module
+                    ))),
+                    ..Default::default()
+                });
+            }
+        }
 
-                new_text.replace_range(start_byte..end_byte, &change.text);
-            } else {
-                new_text = change.text.clone();
+        for target in parser.extract_targets(text).unwrap_or_default() {
+            if items.iter().any(|item| item.label == target.rule_type) {
+                continue;
             }
+            items.push(CompletionItem {
+                label: target.rule_type.clone(),
+                kind: Some(CompletionItemKind::FUNCTION),
+                detail: Some("Rule".to_string()),
+                ..Default::default()
+            });
         }
 
-        documents.insert(uri.to_string(), new_text);
+        if snippets {
+            for item in &mut items {
+                item.insert_text = Some(rule_snippet(&item.label));
+                item.insert_text_format = Some(InsertTextFormat::SNIPPET);
+            }
+        }
+
+        CompletionResponse::Array(items)
     }
 
-    fn position_to_byte_index(&self, text: &str, position: &Position) -> usize {
-        let lines: Vec<&str> = text.lines().collect();
-        let mut byte_index = 0;
+    /// The attributes `rule` accepts according to the rule schema, except those in
+    /// `set_attributes`.
+    async fn completion_attribute_names(
+        &self,
+        rule: &str,
+        set_attributes: &[String],
+    ) -> CompletionResponse {
+        let items = self
+            .rule_schema
+            .read()
+            .await
+            .attributes(rule)
+            .into_iter()
+            .filter(|attribute| !set_attributes.contains(&attribute.name))
+            .map(|attribute| CompletionItem {
+                insert_text: Some(format!("{} = ", attribute.name)),
+                label: attribute.name,
+                kind: Some(CompletionItemKind::PROPERTY),
+                documentation: attribute.doc.map(Documentation::String),
+                ..Default::default()
+            })
+            .collect();
 
-        for i in 0..position.line as usize {
-            if i < lines.len() {
-                byte_index += lines[i].len() + 1; // +1 for the newline character
-            }
-        }
+        CompletionResponse::Array(items)
+    }
 
-        if (position.line as usize) < lines.len() {
-            let line = lines[position.line as usize];
-            let char_index = position.character as usize;
-            let mut chars = 0;
-            let mut bytes = 0;
+    /// Values for a `visibility` list: the `//visibility` constants and the package
+    /// groups of the workspace and the current file.
+    async fn completion_visibility(
+        &self,
+        parser: &BazelParser,
+        text: &str,
+        in_string: bool,
+    ) -> CompletionResponse {
+        let mut labels: Vec<(String, &str)> = VISIBILITY_CONSTANTS
+            .iter()
+            .map(|label| (label.to_string(), "Visibility"))
+            .collect();
 
-            for c in line.chars() {
-                if chars >= char_index {
-                    break;
-                }
-                bytes += c.len_utf8();
-                chars += 1;
+        let trie = self.target_trie.read().await;
+        for rule in trie.starts_with("").into_iter().flatten() {
+            if rule.rule_type == "package_group" {
+                labels.push((rule.full_build_path.clone(), "Package group"));
+            }
+        }
+        // Groups of the current file, which isn't indexed outside a workspace
+        for target in parser.extract_targets(text).unwrap_or_default() {
+            if target.rule_type == "package_group" {
+                labels.push((format!(":{}", target.name), "Package group"));
             }
+        }
 
-            byte_index += bytes;
+        let mut items: Vec<CompletionItem> = Vec::new();
+        for (label, detail) in labels {
+            if items.iter().any(|item| item.label == label) {
+                continue;
+            }
+            items.push(CompletionItem {
+                insert_text: Some(if in_string {
+                    label.clone()
+                } else {
+                    format!("\"{}\"", label)
+                }),
+                label,
+                kind: Some(CompletionItemKind::VALUE),
+                detail: Some(detail.to_string()),
+                ..Default::default()
+            });
         }
 
-        byte_index
+        CompletionResponse::Array(items)
     }
 
-    fn get_semantic_tokens(&self, text: &str) -> SemanticTokens {
-        let mut tokens = Vec::new();
+    async fn completion_in_file<'a>(
+        &self,
+        parser: &BazelParser,
+        trigger_result: Option<TriggerResult<'a>>,
+        text: &str,
+    ) -> Result<Option<CompletionResponse>> {
+        if trigger_result
+            .as_ref()
+            .map(|t| t.trigger_type == TriggerType::DoubleSlash)
+            .unwrap_or(false)
+        {
+            return Ok(None);
+        }
 
-        let targets = match self.parser.extract_targets(text) {
+        let targets = match parser.extract_targets(text) {
             Ok(targets) => targets,
-            Err(_) => Vec::new(),
+            Err(err) => {
+                self.client
+                    .log_message(
+                        MessageType::ERROR,
+                        format!("Failed to extract targets: {}", err),
+                    )
+                    .await;
+                return Ok(None);
+            }
         };
 
-        let attributes = match self.parser.extract_attributes(text) {
-            Ok(attributes) => attributes,
-            Err(_) => Vec::new(),
+        let exported_files = parser.extract_exported_files(text).unwrap_or_default();
+        let max_items = self.config.read().await.max_completion_items;
+
+        return match trigger_result {
+            Some(result) => Ok(Some(completion_list(
+                targets
+                    .iter()
+                    .filter(|t| t.name.starts_with(result.text_after_trigger))
+                    .map(|t| CompletionItem {
+                        label: t.name.clone(),
+                        kind: Some(CompletionItemKind::TEXT),
+                        detail: Some(format!("Target: {}", t.name)),
+                        documentation: Some(Documentation::String(format!(
+                            "Bazel target: {}",
+                            t.name
+                        ))),
+                        ..Default::default()
+                    })
+                    .chain(
+                        exported_files
+                            .iter()
+                            .filter(|f| f.name.starts_with(result.text_after_trigger))
+                            .map(|f| CompletionItem {
+                                label: f.name.clone(),
+                                kind: Some(CompletionItemKind::FILE),
+                                detail: Some(format!("Exported file: {}", f.name)),
+                                ..Default::default()
+                            }),
+                    )
+                    .collect(),
+                max_items,
+            ))),
+            None => Ok(Some(completion_list(Vec::new(), max_items))),
         };
+    }
+
+    /// Completes the indexed targets matching the label typed so far, listing those of
+    /// `package_path`, the package being edited, first.
+    async fn completion_in_workspace<'a>(
+        &self,
+        position: Position,
+        trigger_result: Option<TriggerResult<'a>>,
+        package_path: &str,
+    ) -> Result<Option<CompletionResponse>> {
+        if let Some(result) = &trigger_result {
+            if let Some(package) = recursive_wildcard_package(result.text_after_trigger) {
+                return Ok(Some(
+                    self.completion_subpackages(position, result.trigger_pos, package)
+                        .await,
+                ));
+            }
+        }
 
-        let strings = match self.parser.extract_strings(text) {
-            Ok(strings) => strings,
-            Err(_) => Vec::new(),
+        let trie = self.target_trie.read().await;
+        let matching_rules = match &trigger_result {
+            Some(result) => trie.starts_with(result.text_after_trigger),
+            None => Vec::new(),
         };
 
-        let mut all_tokens: Vec<(Range, u32)> = Vec::new();
+        // A label declared twice, e.g. while a target is being copied, has a rule per
+        // declaration, and is offered once
+        let mut seen = HashSet::new();
+        let rules = matching_rules
+            .into_iter()
+            .flatten()
+            .filter(|rule| seen.insert(rule.full_build_path.as_str()));
 
-        for target in targets {
-            all_tokens.push((target.rule_type_range, 0));
-        }
+        let mut completion_items = Vec::new();
+        for rule in rules {
+            let edit_text = create_edit_text_in_workspace(&trigger_result, rule);
 
-        for attr in attributes {
-            all_tokens.push((attr.range, 1));
+            // The rule kind and BUILD file are looked up once the item is resolved, see
+            // resolve_target_item
+            let item = CompletionItem {
+                label: rule.full_build_path.clone(),
+                kind: Some(CompletionItemKind::TEXT),
+                data: Some(serde_json::json!({ "target": rule.full_build_path })),
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+                    range: Range {
+                        start: Position {
+                            line: position.line,
+                            character: trigger_result
+                                .as_ref()
+                                .map(|r| r.trigger_pos as u32)
+                                .unwrap_or(0),
+                        },
+                        end: position,
+                    },
+                    new_text: edit_text.clone(),
+                })),
+                sort_text: Some(target_sort_text(&rule.full_build_path, package_path)),
+                ..Default::default()
+            };
+            completion_items.push(item);
         }
+        // The trie yields rules in no particular order; sort so a cut list is stable
+        // and keeps the targets listed first
+        completion_items.sort_by(|a, b| a.sort_text.cmp(&b.sort_text));
 
-        for string in strings {
-            all_tokens.push((string.range, 2));
-        }
+        let max_items = self.config.read().await.max_completion_items;
+        Ok(Some(completion_list(completion_items, max_items)))
+    }
 
-        all_tokens.sort_by(|a, b| {
-            let line_cmp = a.0.start.line.cmp(&b.0.start.line);
-            if line_cmp == std::cmp::Ordering::Equal {
-                a.0.start.character.cmp(&b.0.start.character)
-            } else {
-                line_cmp
-            }
+    /// Fills in the rule kind and BUILD file of a target offered by
+    /// [`completion_in_workspace`](Self::completion_in_workspace), whose items only carry
+    /// the label as their data. Other items are left alone.
+    async fn resolve_target_item(&self, item: &mut CompletionItem) {
+        let Some(label) = item
+            .data
+            .as_ref()
+            .and_then(|data| data.get("target"))
+            .and_then(serde_json::Value::as_str)
+            .map(str::to_string)
+        else {
+            return;
+        };
+
+        let workspace_root = self.workspace_root().await;
+        let trie = self.target_trie.read().await;
+        let rule = trie.resolve(&label);
+
+        item.detail = Some(match rule.filter(|rule| !rule.rule_type.is_empty()) {
+            Some(rule) => rule.rule_type.clone(),
+            None => format!("Target: {}", label),
         });
+        let mut documentation = format!("Bazel target: {}", label);
+        if let Some(build_file) = rule.and_then(|rule| rule.source_file.as_deref()) {
+            let build_file = workspace_root
+                .as_deref()
+                .and_then(|root| build_file.strip_prefix(root).ok())
+                .unwrap_or(build_file);
+            documentation.push_str(&format!("\n\nDeclared in {}", build_file.display()));
+        }
+        item.documentation = Some(Documentation::String(documentation));
+        item.data = None;
+    }
 
-        let mut prev_line = 0;
-        let mut prev_start = 0;
+    /// Completion items for the `//pkg/...` patterns of `package` and of each indexed
+    /// package below it, replacing the pattern typed since `trigger_pos`.
+    async fn completion_subpackages(
+        &self,
+        position: Position,
+        trigger_pos: usize,
+        package: &str,
+    ) -> CompletionResponse {
+        let trie = self.target_trie.read().await;
+        let mut packages: Vec<String> = trie
+            .starts_with(package)
+            .into_iter()
+            .flatten()
+            .filter_map(|rule| Some(parse_label(&rule.full_build_path).ok()?.package))
+            .filter(|rule_package| {
+                package.is_empty()
+                    || *rule_package == package
+                    || rule_package
+                        .strip_prefix(package)
+                        .is_some_and(|rest| rest.starts_with('/'))
+            })
+            .collect();
+        packages.sort();
+        packages.dedup();
 
-        for (range, token_type) in all_tokens {
-            let delta_line = range.start.line;
-            let delta_start = if delta_line == prev_line {
-                if range.start.character >= prev_start {
-                    range.start.character - prev_start
+        let items = packages
+            .into_iter()
+            .map(|rule_package| {
+                let pattern = if rule_package.is_empty() {
+                    "//...".to_string()
                 } else {
-                    0
+                    format!("//{}/...", rule_package)
+                };
+                CompletionItem {
+                    label: pattern.clone(),
+                    kind: Some(CompletionItemKind::FOLDER),
+                    detail: Some(format!("All targets in //{} and below", rule_package)),
+                    text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+                        range: Range {
+                            start: Position {
+                                line: position.line,
+                                character: trigger_pos as u32,
+                            },
+                            end: position,
+                        },
+                        new_text: pattern,
+                    })),
+                    ..Default::default()
                 }
-            } else {
-                range.start.character
-            };
+            })
+            .collect();
 
-            let delta_line_value = if tokens.is_empty() {
-                delta_line
-            } else {
-                if delta_line >= prev_line {
-                    delta_line - prev_line
-                } else {
-                    0
+        let max_items = self.config.read().await.max_completion_items;
+        completion_list(items, max_items)
+    }
+
+    /// The package path of the BUILD file at `file_path`, relative to the
+    /// workspace root. Empty for the root package.
+    async fn package_path(&self, file_path: &Path) -> String {
+        let Some(workspace_root) = self.workspace_root().await else {
+            return String::new();
+        };
+
+        file_path
+            .parent()
+            .and_then(|dir| dir.strip_prefix(&workspace_root).ok())
+            .map(|relative_path| relative_path.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    /// The path of a `file:` URI. Documents with other schemes, e.g. `untitled:` or
+    /// `git:`, have no path on disk; a warning is logged the first time one is seen
+    /// and None returned.
+    async fn file_path_from_uri(&self, uri: &Url) -> Option<PathBuf> {
+        match uri.to_file_path() {
+            Ok(path) => Some(path),
+            Err(()) => {
+                if self.non_file_uris.write().await.insert(uri.to_string()) {
+                    self.client
+                        .log_message(
+                            MessageType::WARNING,
+                            format!(
+                                "{} is not a file on disk, skipping what needs its path",
+                                uri
+                            ),
+                        )
+                        .await;
                 }
+                None
+            }
+        }
+    }
+
+    /// The parser for the file type of `uri`.
+    fn parser_for(&self, uri: &Url) -> &BazelParser {
+        match BazelParserMode::from_file_name(uri.path()) {
+            BazelParserMode::Build => &self.parser,
+            BazelParserMode::Starlark => &self.starlark_parser,
+        }
+    }
+
+    /// Re-reads the external repositories declared by the workspace.
+    async fn refresh_external_repos(&self) {
+        let Some(workspace_root) = self.workspace_root().await else {
+            return;
+        };
+
+        let mut repos = Vec::new();
+        for file in ["MODULE.bazel", "WORKSPACE", "WORKSPACE.bazel"] {
+            let Ok(content) = fs::read_to_string(workspace_root.join(file)) else {
+                continue;
             };
+            for target in self.parser.parse_build_file(&content).unwrap_or_default() {
+                // `module` and `workspace` name the main repository
+                if target.rule_type == "module" || target.rule_type == "workspace" {
+                    continue;
+                }
+                // A `bazel_dep` is visible under its `repo_name` when it has one
+                let name = match target.attributes.get("repo_name") {
+                    Some(ParsedAttribute {
+                        value: AttributeValue::String(repo_name),
+                        ..
+                    }) => repo_name.clone(),
+                    _ => target.name,
+                };
+                if !repos.contains(&name) {
+                    repos.push(name);
+                }
+            }
+        }
 
-            tokens.push(SemanticToken {
-                delta_line: delta_line_value,
-                delta_start: delta_start as u32,
-                length: (range.end.character - range.start.character) as u32,
-                token_type,
-                token_modifiers_bitset: 0,
-            });
+        self.log_trace(format!("Found {} external repositories", repos.len()))
+            .await;
+        *self.external_repos.write().await = repos;
+    }
+
+    /// `@repo` completions for the external repositories of the workspace.
+    async fn completion_external_repos(
+        &self,
+        position: Position,
+        trigger_pos: usize,
+    ) -> CompletionResponse {
+        let repos = self.external_repos.read().await;
+        let items = repos
+            .iter()
+            .map(|repo| CompletionItem {
+                label: format!("@{}", repo),
+                kind: Some(CompletionItemKind::MODULE),
+                detail: Some("External repository".into()),
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+                    range: Range {
+                        start: Position {
+                            line: position.line,
+                            character: trigger_pos as u32,
+                        },
+                        end: position,
+                    },
+                    new_text: format!("@{}//", repo),
+                })),
+                ..Default::default()
+            })
+            .collect();
+        CompletionResponse::Array(items)
+    }
+
+    async fn send_progress(&self, token: &ProgressToken, progress: WorkDoneProgress) {
+        self.client
+            .send_notification::<notification::Progress>(ProgressParams {
+                token: token.clone(),
+                value: ProgressParamsValue::WorkDone(progress),
+            })
+            .await;
+    }
+
+    async fn workspace_root(&self) -> Option<PathBuf> {
+        self.workspace_roots.read().await.first().cloned()
+    }
+
+    /// Merges the rules described by the `rule_schema_path` file over the built-in ones.
+    async fn load_rule_schema(&self) {
+        let Some(path) = self.config.read().await.rule_schema_path.clone() else {
+            return;
+        };
+        let path = match self.workspace_root().await {
+            Some(root) if path.is_relative() => root.join(path),
+            _ => path,
+        };
+
+        match RuleSchema::load(&path) {
+            Ok(schema) => self.rule_schema.write().await.merge(schema),
+            Err(err) => {
+                self.client
+                    .log_message(
+                        MessageType::ERROR,
+                        format!("Failed to load rule schema: {:#}", err),
+                    )
+                    .await;
+            }
+        }
+    }
+
+    /// The edits turning `text` into `formatted_text`, leaving unchanged regions alone so
+    /// the editor keeps the cursor in place: one per `deps` list when sorting them is
+    /// all formatting did, and one spanning the changed lines otherwise, e.g. after
+    /// buildifier. No edits for documents that are already formatted, e.g. empty ones.
+    fn formatting_edits(
+        &self,
+        parser: &BazelParser,
+        text: &str,
+        formatted_text: &str,
+    ) -> Vec<TextEdit> {
+        if let Ok(edits) = parser.compute_deps_sort_edits(text) {
+            if parser.apply_edits(text, &edits) == formatted_text {
+                return edits
+                    .into_iter()
+                    .map(|(range, new_text)| TextEdit { range, new_text })
+                    .collect();
+            }
+        }
+        diff_text_edit(text, formatted_text).into_iter().collect()
+    }
+
+    /// Formats a document with buildifier when preferred and available, and with
+    /// [`BazelParser::format_document`] otherwise. Then sorts its targets if `sort_targets` is set.
+    /// `parser` has to match the file type, as `.bzl` files may define functions.
+    async fn format_text(
+        &self,
+        parser: &BazelParser,
+        text: &str,
+        path: Option<&Path>,
+    ) -> anyhow::Result<String> {
+        let (prefer_buildifier, sort_targets) = {
+            let config = self.config.read().await;
+            (config.prefer_buildifier, config.sort_targets)
+        };
+
+        let buildifier = if prefer_buildifier {
+            find_executable("buildifier")
+        } else {
+            None
+        };
+        let formatted = match buildifier {
+            Some(buildifier) => run_buildifier_on_text(&buildifier, text, path).await?,
+            None => parser.format_document(text)?,
+        };
 
-            prev_line = delta_line;
-            prev_start = range.start.character;
+        match sort_targets {
+            Some(key) => parser.sort_targets_in_text(&formatted, key),
+            None => Ok(formatted),
         }
+    }
 
-        SemanticTokens {
-            result_id: None,
-            data: tokens,
+    /// Proposes a BUILD file for `dir` with a library of its source files, and writes
+    /// and indexes it when `write` is set. Returns the `path` and `content` of the file.
+    async fn generate_build_stub(
+        &self,
+        dir: &Path,
+        write: bool,
+    ) -> Result<Option<serde_json::Value>> {
+        if dir.join("BUILD").exists() || dir.join("BUILD.bazel").exists() {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params(format!(
+                "{} already has a BUILD file",
+                dir.display()
+            )));
+        }
+
+        let name = dir
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let files = find_package_files(dir).map_err(filesystem_error)?;
+        let Some(content) = build_stub(&name, &files) else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params(format!(
+                "{} has no C, C++ or Python source files",
+                dir.display()
+            )));
+        };
+
+        let build_file = dir.join("BUILD");
+        if write {
+            fs::write(&build_file, &content).map_err(|err| {
+                let mut error =
+                    tower_lsp::jsonrpc::Error::new(tower_lsp::jsonrpc::ErrorCode::InternalError);
+                error.message = format!("Failed to write {}: {}", build_file.display(), err).into();
+                error
+            })?;
+            let mut trie = self.target_trie.write().await;
+            let _ = self.index_build_file_content(&build_file, &content, &mut trie);
+            drop(trie);
+            self.republish_open_diagnostics(None).await;
         }
+
+        Ok(Some(serde_json::json!({
+            "path": build_file,
+            "content": content,
+        })))
     }
 
-    fn populate_trie_from_build_file(
-        &self,
-        build_file: &Path,
-        trie: &mut TargetTrie,
-    ) -> anyhow::Result<()> {
-        if let Ok(content) = fs::read_to_string(build_file) {
-            if let Ok(targets) = self.parser.extract_targets(&content) {
-                let package_path = if let Some(workspace_root) = find_workspace_root(build_file)? {
-                    if let Ok(relative_path) =
-                        build_file.parent().unwrap().strip_prefix(workspace_root)
-                    {
-                        relative_path.to_string_lossy().to_string()
-                    } else {
-                        String::new()
-                    }
-                } else {
-                    String::new()
-                };
+    /// Formats every BUILD file under `dir`.
+    ///
+    /// Open documents are updated through `workspace/applyEdit` so unsaved changes are kept,
+    /// closed ones are rewritten on disk.
+    async fn format_build_files(&self, dir: &Path) -> Result<Option<serde_json::Value>> {
+        let excluded_dirs = self.config.read().await.excluded_dirs.clone();
+        let build_files = find_build_files(dir, &excluded_dirs).map_err(filesystem_error)?;
+        let mut changes = HashMap::new();
+        let mut changed_files = 0;
+        let mut failed_files = 0;
 
-                for target in targets {
-                    let full_target_path = if package_path.is_empty() {
-                        target.name.clone()
-                    } else {
-                        format!("{}:{}", package_path, target.name)
-                    };
+        for build_file in build_files.iter() {
+            let Ok(uri) = Url::from_file_path(build_file) else {
+                continue;
+            };
 
-                    let rule = RuleInfo::new(
-                        target.name.clone(),
-                        format!("//{}:{}", package_path, target.name),
-                    );
+            let open_text = self.documents.read().await.get(uri.as_str()).cloned();
+            let text = match &open_text {
+                Some(text) => text.clone(),
+                None => match fs::read_to_string(build_file) {
+                    Ok(text) => text,
+                    Err(_) => {
+                        failed_files += 1;
+                        continue;
+                    }
+                },
+            };
 
-                    trie.insert_target(&full_target_path, rule);
+            let formatted_text = match self
+                .format_text(self.parser_for(&uri), &text, Some(build_file))
+                .await
+            {
+                Ok(formatted_text) => formatted_text,
+                Err(err) => {
+                    self.client
+                        .log_message(
+                            MessageType::ERROR,
+                            format!("Failed to format {}: {}", build_file.display(), err),
+                        )
+                        .await;
+                    failed_files += 1;
+                    continue;
                 }
-            }
-        }
-        Ok(())
-    }
+            };
 
-    async fn completion_in_file<'a>(
-        &self,
-        trigger_result: Option<TriggerResult<'a>>,
-        text: &str,
-    ) -> Result<Option<CompletionResponse>> {
-        if trigger_result
-            .as_ref()
-            .map(|t| t.trigger_type == TriggerType::DoubleSlash)
-            .unwrap_or(false)
-        {
-            return Ok(None);
+            let Some(edit) = diff_text_edit(&text, &formatted_text) else {
+                continue;
+            };
+
+            if open_text.is_some() {
+                changes.insert(uri, vec![edit]);
+            } else if fs::write(build_file, formatted_text).is_err() {
+                failed_files += 1;
+                continue;
+            }
+            changed_files += 1;
         }
 
-        let targets = match self.parser.extract_targets(text) {
-            Ok(targets) => targets,
-            Err(err) => {
+        if !changes.is_empty() {
+            let edit = WorkspaceEdit {
+                changes: Some(changes),
+                ..Default::default()
+            };
+            if let Err(err) = self.client.apply_edit(edit).await {
                 self.client
                     .log_message(
                         MessageType::ERROR,
-                        format!("Failed to extract targets: {}", err),
+                        format!("Failed to apply formatting edits: {}", err),
                     )
                     .await;
-                return Ok(None);
             }
+        }
+
+        Ok(Some(serde_json::json!({
+            "totalFiles": build_files.len(),
+            "changedFiles": changed_files,
+            "failedFiles": failed_files,
+        })))
+    }
+
+    async fn format_with_buildifier(&self, uri: &Url) -> Result<Option<serde_json::Value>> {
+        let path = self.file_path_from_uri(uri).await.ok_or_else(|| {
+            tower_lsp::jsonrpc::Error::invalid_params(format!("Not a file URI: {}", uri))
+        })?;
+
+        let buildifier = find_executable("buildifier").ok_or_else(|| {
+            let mut error =
+                tower_lsp::jsonrpc::Error::new(tower_lsp::jsonrpc::ErrorCode::InternalError);
+            error.message = "buildifier was not found on PATH. Install it with \
+                `go install github.com/bazelbuild/buildtools/buildifier@latest` \
+                or from https://github.com/bazelbuild/buildtools/releases"
+                .into();
+            error
+        })?;
+
+        let current_text = match self.documents.read().await.get(uri.as_str()) {
+            Some(text) => text.clone(),
+            None => fs::read_to_string(&path).unwrap_or_default(),
         };
 
-        return match trigger_result {
-            Some(result) => Ok(Some(CompletionResponse::Array(
-                targets
-                    .iter()
-                    .filter(|t| t.name.starts_with(result.text_after_trigger))
-                    .map(|t| CompletionItem {
-                        label: t.name.clone(),
-                        kind: Some(CompletionItemKind::TEXT),
-                        detail: Some(format!("Target: {}", t.name)),
-                        documentation: Some(Documentation::String(format!(
-                            "Bazel target: {}",
-                            t.name
-                        ))),
-                        ..Default::default()
-                    })
-                    .collect(),
-            ))),
-            None => Ok(Some(CompletionResponse::Array(vec![]))),
+        // The text is piped through buildifier so the file on disk, which may be behind
+        // unsaved edits, is left alone
+        let formatted_text = run_buildifier_on_text(&buildifier, &current_text, Some(&path))
+            .await
+            .map_err(|e| {
+                let mut error =
+                    tower_lsp::jsonrpc::Error::new(tower_lsp::jsonrpc::ErrorCode::InternalError);
+                error.message = "buildifier failed".into();
+                error.data = Some(serde_json::json!({ "message": e.to_string() }));
+                error
+            })?;
+
+        let edits: Vec<TextEdit> = diff_text_edit(&current_text, &formatted_text)
+            .into_iter()
+            .collect();
+        let edit = WorkspaceEdit {
+            changes: Some(HashMap::from([(uri.clone(), edits)])),
+            ..Default::default()
         };
+
+        if let Err(err) = self.client.apply_edit(edit.clone()).await {
+            self.client
+                .log_message(
+                    MessageType::ERROR,
+                    format!("Failed to apply buildifier edits: {}", err),
+                )
+                .await;
+        }
+
+        Ok(serde_json::to_value(edit).ok())
     }
 
-    async fn completion_in_workspace<'a>(
+    /// Drops the cached coverage of a test target and asks the client to redraw
+    /// code lenses so that a new report is picked up.
+    async fn refresh_coverage(&self, target: &str) {
+        self.coverage.write().await.invalidate(target);
+        self.client
+            .send_request::<request::CodeLensRefresh>(())
+            .await
+            .ok();
+    }
+
+    /// Runs `bazel query <expression> <flags>` and returns its stdout. A failing query
+    /// is reported as an error carrying bazel's stderr.
+    async fn run_bazel_query(&self, expression: &str, flags: &[&str]) -> Result<String> {
+        self.run_bazel_output("query", expression, flags).await
+    }
+
+    /// Runs `bazel <command> <flags> <argument>` and returns its stdout, or an error
+    /// carrying its stderr when it fails.
+    async fn run_bazel_output(
         &self,
-        position: Position,
-        trigger_result: Option<TriggerResult<'a>>,
-    ) -> Result<Option<CompletionResponse>> {
-        let trie = self.target_trie.read().await;
-        let matching_rules = match &trigger_result {
-            Some(result) => trie.starts_with(result.text_after_trigger),
-            None => Vec::new(),
+        command: &str,
+        argument: &str,
+        flags: &[&str],
+    ) -> Result<String> {
+        let workspace_root = self.workspace_root().await;
+        let mut cmd = {
+            let config = self.config.read().await;
+            let flags: Vec<String> = flags.iter().map(|flag| flag.to_string()).collect();
+            bazel_command(
+                &config,
+                command,
+                &[argument],
+                &flags,
+                workspace_root.as_deref(),
+            )
         };
 
-        let mut completion_items = Vec::new();
-        for rules in matching_rules {
-            for rule in rules {
-                let edit_text = create_edit_text_in_workspace(&trigger_result, rule);
-
-                let item = CompletionItem {
-                    label: rule.full_build_path.clone(),
-                    kind: Some(CompletionItemKind::TEXT),
-                    detail: Some(format!("Target: {}", rule.full_build_path)),
-                    documentation: Some(Documentation::String(format!(
-                        "Bazel target: {}",
-                        rule.full_build_path
-                    ))),
-                    text_edit: Some(CompletionTextEdit::Edit(TextEdit {
-                        range: Range {
-                            start: Position {
-                                line: position.line,
-                                character: trigger_result
-                                    .as_ref()
-                                    .map(|r| r.trigger_pos as u32)
-                                    .unwrap_or(0),
-                            },
-                            end: position,
-                        },
-                        new_text: edit_text.clone(),
-                    })),
-                    ..Default::default()
-                };
-                completion_items.push(item);
-            }
+        self.log_trace(format!(
+            "Running bazel {} {} {}",
+            command,
+            argument,
+            flags.join(" ")
+        ))
+        .await;
+
+        let output = cmd.output().await.map_err(|e| {
+            let mut error =
+                tower_lsp::jsonrpc::Error::new(tower_lsp::jsonrpc::ErrorCode::InternalError);
+            error.message = format!("Failed to run bazel {}: {}", command, e).into();
+            error
+        })?;
+
+        if !output.status.success() {
+            let mut error =
+                tower_lsp::jsonrpc::Error::new(tower_lsp::jsonrpc::ErrorCode::InternalError);
+            error.message = String::from_utf8_lossy(&output.stderr)
+                .trim()
+                .to_string()
+                .into();
+            return Err(error);
         }
 
-        Ok(Some(CompletionResponse::Array(completion_items)))
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
     }
 
-    async fn execute_bazel_command(&self, command: &str, target: &str) {
-        let workspace_folders = self.workspace_folders.read().await;
-        let workspace_root = workspace_folders
-            .iter()
-            .find_map(|folder| {
-                let path = folder.uri.to_file_path().ok()?;
-                if is_workspace_dir(&path).unwrap_or(false) {
-                    Some(path)
-                } else {
-                    None
-                }
-            });
+    /// Runs a bazel command on `target`, streaming its output to the client log.
+    /// Returns whether it succeeded.
+    async fn execute_bazel_command(
+        &self,
+        command: &str,
+        target: &str,
+        extra_args: &[String],
+    ) -> bool {
+        if self.shutdown_token.is_cancelled() {
+            return false;
+        }
+        let workspace_root = self.workspace_root().await;
 
-        let command_str = format!("bazel {} {}", command, target);
+        let command_str = if extra_args.is_empty() {
+            format!("bazel {} {}", command, target)
+        } else {
+            format!("bazel {} {} {}", command, extra_args.join(" "), target)
+        };
         self.client
             .log_message(
                 MessageType::INFO,
@@ -741,14 +3754,22 @@ impl Backend {
             )
             .await;
 
-        let mut cmd = tokio::process::Command::new("bazel");
-        cmd.arg(command).arg(target);
-        
-        if let Some(workspace_path) = workspace_root {
-            cmd.current_dir(workspace_path);
-        }
+        let mut cmd = {
+            let config = self.config.read().await;
+            bazel_command(
+                &config,
+                command,
+                &target_patterns(target),
+                extra_args,
+                workspace_root.as_deref(),
+            )
+        };
 
-        // Use spawn to get real-time output
+        // Use spawn to get real-time output. Without pipes bazel would write to the
+        // server's stdout, which may be the connection to the client
+        cmd.stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped());
         let mut child = match cmd.spawn() {
             Ok(child) => child,
             Err(e) => {
@@ -758,7 +3779,7 @@ impl Backend {
                         format!("Failed to spawn bazel {} for {}: {}", command, target, e),
                     )
                     .await;
-                return;
+                return false;
             }
         };
 
@@ -768,12 +3789,18 @@ impl Backend {
         // Spawn tasks to read stdout and stderr in real-time
         let client_stdout = self.client.clone();
         let client_stderr = self.client.clone();
+        let shutdown_stdout = self.shutdown_token.clone();
+        let shutdown_stderr = self.shutdown_token.clone();
 
         let stdout_task = async move {
             if let Some(mut stdout) = stdout {
                 let mut buffer = [0; 1024];
                 loop {
-                    match stdout.read(&mut buffer).await {
+                    let read = tokio::select! {
+                        read = stdout.read(&mut buffer) => read,
+                        _ = shutdown_stdout.cancelled() => break,
+                    };
+                    match read {
                         Ok(0) => break, // EOF
                         Ok(n) => {
                             let output = String::from_utf8_lossy(&buffer[..n]);
@@ -791,7 +3818,11 @@ impl Backend {
             if let Some(mut stderr) = stderr {
                 let mut buffer = [0; 1024];
                 loop {
-                    match stderr.read(&mut buffer).await {
+                    let read = tokio::select! {
+                        read = stderr.read(&mut buffer) => read,
+                        _ = shutdown_stderr.cancelled() => break,
+                    };
+                    match read {
                         Ok(0) => break, // EOF
                         Ok(n) => {
                             let output = String::from_utf8_lossy(&buffer[..n]);
@@ -805,11 +3836,51 @@ impl Backend {
             }
         };
 
-        // Run both tasks concurrently
-        let (_, _) = tokio::join!(stdout_task, stderr_task);
+        let (cancel, cancelled) = oneshot::channel();
+        {
+            let mut running_commands = self.running_commands.write().await;
+            running_commands.retain(|cancel| !cancel.is_closed());
+            running_commands.push(cancel);
+        }
+
+        // Read the output while waiting for the process to finish, unless bazel.cancelAll
+        // or shutdown stops it first
+        let exited = CancellationToken::new();
+        let output = async {
+            let readers = async {
+                tokio::join!(stdout_task, stderr_task);
+            };
+            tokio::pin!(readers);
+            tokio::select! {
+                _ = &mut readers => return,
+                _ = exited.cancelled() => {}
+            }
+            // Processes bazel leaves behind may keep its output open after it exited, so
+            // only what it wrote before is waited for
+            let _ = tokio::time::timeout(OUTPUT_DRAIN_TIMEOUT, readers).await;
+        };
+        let wait = async {
+            let status = child.wait().await;
+            exited.cancel();
+            status
+        };
+        let finished = tokio::select! {
+            (status, ()) = async { tokio::join!(wait, output) } => Some(status),
+            Ok(()) = cancelled => None,
+            _ = self.shutdown_token.cancelled() => None,
+        };
+        let Some(finished) = finished else {
+            let _ = child.kill().await;
+            self.client
+                .log_message(
+                    MessageType::WARNING,
+                    format!("Cancelled bazel {} for {}", command, target),
+                )
+                .await;
+            return false;
+        };
 
-        // Wait for the process to finish
-        match child.wait().await {
+        match finished {
             Ok(status) => {
                 if status.success() {
                     let success_msg = match command {
@@ -832,6 +3903,27 @@ impl Backend {
                         .log_message(MessageType::ERROR, error_msg)
                         .await;
                 }
+                if matches!(command, "build" | "test")
+                    && self.config.read().await.show_command_result_popup
+                {
+                    let (typ, message) = if status.success() {
+                        (
+                            MessageType::INFO,
+                            format!("✓ bazel {} {} succeeded", command, target),
+                        )
+                    } else {
+                        let reason = match status.code() {
+                            Some(code) => format!("exit code {}", code),
+                            None => status.to_string(),
+                        };
+                        (
+                            MessageType::ERROR,
+                            format!("✗ bazel {} {} failed ({})", command, target, reason),
+                        )
+                    };
+                    self.client.show_message(typ, message).await;
+                }
+                status.success()
             }
             Err(e) => {
                 self.client
@@ -840,6 +3932,7 @@ impl Backend {
                         format!("Failed to wait for bazel {} for {}: {}", command, target, e),
                     )
                     .await;
+                false
             }
         }
     }
@@ -849,6 +3942,8 @@ impl Backend {
 enum TriggerType {
     DoubleSlash,
     Colon,
+    /// A repository name after `@`, before its `//`
+    At,
 }
 
 #[derive(Debug, PartialEq)]
@@ -860,16 +3955,19 @@ struct TriggerResult<'a> {
 
 fn find_trigger_position<'a>(line_up_to_cursor: &'a str) -> Option<TriggerResult<'a>> {
     let trigger_pos = if let Some(quote_pos) = line_up_to_cursor.rfind('"') {
-        let after_quote = &line_up_to_cursor[quote_pos + 1..];
-        if after_quote.len() >= 2
-            && after_quote.as_bytes()[0] == b'/'
-            && after_quote.as_bytes()[1] == b'/'
-        {
-            Some((quote_pos + 1, TriggerType::DoubleSlash, &after_quote[2..]))
-        } else if after_quote.starts_with(':') {
-            Some((quote_pos + 1, TriggerType::Colon, &after_quote[1..]))
-        } else {
-            None
+        let mut start = quote_pos + 1;
+        // A negative target pattern, e.g. `-//foo/bar/...`, completes like the label after the `-`
+        if line_up_to_cursor[start..].starts_with('-') {
+            start += 1;
+        }
+        match split_label_start(&line_up_to_cursor[start..]) {
+            Some((LabelStart::Absolute, rest)) => Some((start, TriggerType::DoubleSlash, rest)),
+            Some((LabelStart::Relative, rest)) => Some((start, TriggerType::Colon, rest)),
+            // Only the repository name itself is completed
+            Some((LabelStart::Repository, rest)) if !rest.contains('/') && !rest.contains(':') => {
+                Some((start, TriggerType::At, rest))
+            }
+            _ => None,
         }
     } else {
         None
@@ -898,6 +3996,62 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_wrap_with_select_multiline() {
+        assert_eq!(
+            wrap_with_select("[\n        \"//a\",\n    ]", "    "),
+            "select({\n        \"//conditions:default\": [\n            \"//a\",\n        ],\n    })"
+        );
+    }
+
+    #[test]
+    fn test_is_visible() {
+        let visible = |visibility: &[&str], package_path| {
+            let rule = RuleInfo {
+                full_build_path: "//lib:lib".into(),
+                visibility: visibility.iter().map(|entry| entry.to_string()).collect(),
+                ..Default::default()
+            };
+            is_visible(&rule, package_path)
+        };
+
+        assert_eq!(visible(&["//visibility:private"], "lib"), Some(true));
+        assert_eq!(visible(&["//visibility:private"], "app"), Some(false));
+        assert_eq!(visible(&["//visibility:public"], "app"), Some(true));
+        assert_eq!(visible(&["//app:__pkg__"], "app"), Some(true));
+        assert_eq!(visible(&["//app:__pkg__"], "app/sub"), Some(false));
+        assert_eq!(visible(&["//app:__subpackages__"], "app/sub"), Some(true));
+        assert_eq!(visible(&[":__subpackages__"], "lib/sub"), Some(true));
+        assert_eq!(visible(&["//groups:friends"], "app"), None);
+        assert_eq!(visible(&[], "app"), None);
+    }
+
+    #[test]
+    fn test_rule_snippet() {
+        assert_eq!(
+            rule_snippet("java_binary"),
+            "java_binary(\n    name = \"${1:name}\",\n    srcs = [$2],\n    main_class = \"$3\",\n    deps = [$4],\n)$0"
+        );
+        assert_eq!(
+            rule_snippet("my_macro"),
+            "my_macro(\n    name = \"${1:name}\",\n    deps = [$2],\n)$0"
+        );
+    }
+
+    #[test]
+    fn test_at_after_quote() {
+        assert_eq!(
+            find_trigger_position("\"@rules_"),
+            Some(TriggerResult {
+                trigger_type: TriggerType::At,
+                trigger_pos: 1,
+                text_after_trigger: "rules_"
+            })
+        );
+        // Past the repository name the label is not completed
+        assert_eq!(find_trigger_position("\"@rules_cc//cc"), None);
+    }
+
     #[test]
     fn test_colon_after_quote() {
         assert_eq!(
@@ -959,6 +4113,43 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_negative_pattern_after_quote() {
+        assert_eq!(
+            find_trigger_position("\"-//foo/..."),
+            Some(TriggerResult {
+                trigger_type: TriggerType::DoubleSlash,
+                trigger_pos: 2,
+                text_after_trigger: "foo/..."
+            })
+        );
+    }
+
+    #[test]
+    fn test_target_sort_text() {
+        assert_eq!(target_sort_text("//app:main", "app"), "0//app:main");
+        assert_eq!(target_sort_text("//:root", ""), "0//:root");
+        assert_eq!(target_sort_text("//app/sub:lib", "app"), "1//app/sub:lib");
+        assert_eq!(target_sort_text("@zlib//:zlib", ""), "2@zlib//:zlib");
+    }
+
+    #[test]
+    fn test_recursive_wildcard_package() {
+        assert_eq!(recursive_wildcard_package("foo/bar/..."), Some("foo/bar"));
+        assert_eq!(recursive_wildcard_package("..."), Some(""));
+        assert_eq!(recursive_wildcard_package("foo..."), None);
+        assert_eq!(recursive_wildcard_package("foo/bar"), None);
+    }
+
+    #[test]
+    fn test_target_patterns() {
+        assert_eq!(target_patterns("//foo:bar"), vec!["//foo:bar"]);
+        assert_eq!(
+            target_patterns("//foo/... -//foo/bar/..."),
+            vec!["--", "//foo/...", "-//foo/bar/..."]
+        );
+    }
+
     #[test]
     fn test_create_edit_text_in_workspace_double_slash() {
         let trigger_result = Some(TriggerResult {
@@ -969,6 +4160,7 @@ mod tests {
         let rule = RuleInfo {
             name: "target".to_string(),
             full_build_path: "//path/to/target".to_string(),
+            ..Default::default()
         };
         assert_eq!(
             create_edit_text_in_workspace(&trigger_result, &rule),
@@ -986,6 +4178,7 @@ mod tests {
         let rule = RuleInfo {
             name: "target".to_string(),
             full_build_path: "//path/to/target".to_string(),
+            ..Default::default()
         };
         assert_eq!(
             create_edit_text_in_workspace(&trigger_result, &rule),
@@ -999,6 +4192,7 @@ mod tests {
         let rule = RuleInfo {
             name: "target".to_string(),
             full_build_path: "//path/to/target".to_string(),
+            ..Default::default()
         };
         assert_eq!(
             create_edit_text_in_workspace(&trigger_result, &rule),
@@ -1016,6 +4210,7 @@ mod tests {
         let rule = RuleInfo {
             name: "target".to_string(),
             full_build_path: "//path/to/target".to_string(),
+            ..Default::default()
         };
         assert_eq!(
             create_edit_text_in_workspace(&trigger_result, &rule),
@@ -1033,6 +4228,7 @@ mod tests {
         let rule = RuleInfo {
             name: "target".to_string(),
             full_build_path: "//path/to/target".to_string(),
+            ..Default::default()
         };
         assert_eq!(
             create_edit_text_in_workspace(&trigger_result, &rule),
@@ -1050,10 +4246,149 @@ mod tests {
         let rule = RuleInfo {
             name: "target".to_string(),
             full_build_path: "//path/to/target".to_string(),
+            ..Default::default()
         };
         assert_eq!(
             create_edit_text_in_workspace(&trigger_result, &rule),
             "//path/to/target"
         );
     }
+
+    #[test]
+    fn test_diff_text_edit_identical() {
+        assert_eq!(diff_text_edit("a\nb\n", "a\nb\n"), None);
+    }
+
+    #[test]
+    fn test_diff_text_edit_changed_middle_line() {
+        let edit = diff_text_edit("a\nb\nc\n", "a\nB\nc\n").unwrap();
+        assert_eq!((edit.range.start.line, edit.range.start.character), (1, 0));
+        assert_eq!((edit.range.end.line, edit.range.end.character), (2, 0));
+        assert_eq!(edit.new_text, "B\n");
+    }
+
+    #[test]
+    fn test_diff_text_edit_missing_trailing_newline() {
+        let edit = diff_text_edit("a\nb", "a\nb\n").unwrap();
+        assert_eq!((edit.range.start.line, edit.range.start.character), (1, 0));
+        assert_eq!((edit.range.end.line, edit.range.end.character), (1, 1));
+        assert_eq!(edit.new_text, "b\n");
+    }
+
+    #[test]
+    fn test_bazel_command_env() {
+        let mut config = ServerConfig::default();
+        config.bazel_env.insert(
+            "BAZEL_DO_NOT_DETECT_CPP_TOOLCHAIN".to_string(),
+            "1".to_string(),
+        );
+
+        let cmd = bazel_command(&config, "build", &["//a:b"], &[], None);
+        let envs: HashMap<_, _> = cmd
+            .as_std()
+            .get_envs()
+            .filter_map(|(key, value)| Some((key.to_str()?, value?.to_str()?)))
+            .collect();
+
+        assert_eq!(envs.get("TERM"), Some(&"dumb"));
+        assert_eq!(envs.get("BAZEL_DO_NOT_DETECT_CPP_TOOLCHAIN"), Some(&"1"));
+        assert_eq!(
+            envs.get("PATH").copied(),
+            std::env::var("PATH").ok().as_deref()
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_bazel_command_output_flags() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let bin_dir = tempfile::TempDir::new().unwrap();
+        let args_file = bin_dir.path().join("args");
+        let mock_bazel = bin_dir.path().join("bazel");
+        fs::write(
+            &mock_bazel,
+            format!("#!/bin/sh\necho \"$@\" > {}\n", args_file.display()),
+        )
+        .unwrap();
+        fs::set_permissions(&mock_bazel, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut config = ServerConfig::default();
+        config.bazel_env.insert(
+            "PATH".to_string(),
+            bin_dir.path().to_string_lossy().to_string(),
+        );
+
+        let status = bazel_command(&config, "build", &["//a:b"], &[], None)
+            .status()
+            .await
+            .unwrap();
+        assert!(status.success());
+
+        assert_eq!(
+            fs::read_to_string(&args_file).unwrap().trim(),
+            "build --noshow_progress --noshow_loading_progress --color=no //a:b"
+        );
+
+        let status = bazel_command(
+            &config,
+            "test",
+            &["//a:b_test"],
+            &test_filter_args(Some("FooTest.Bar")),
+            None,
+        )
+        .status()
+        .await
+        .unwrap();
+        assert!(status.success());
+
+        assert_eq!(
+            fs::read_to_string(&args_file).unwrap().trim(),
+            "test --noshow_progress --noshow_loading_progress --color=no --test_filter=FooTest.Bar //a:b_test"
+        );
+    }
+
+    #[test]
+    fn test_test_filter_args() {
+        assert_eq!(test_filter_args(Some("Foo.*")), ["--test_filter=Foo.*"]);
+        assert!(test_filter_args(Some("  ")).is_empty());
+        assert!(test_filter_args(None).is_empty());
+    }
+
+    #[test]
+    fn test_debug_program_path() {
+        assert_eq!(
+            debug_program_path("/out/bin", "//app/cli:tool"),
+            Some(PathBuf::from("/out/bin/app/cli/tool"))
+        );
+        assert_eq!(
+            debug_program_path("/out/bin", "//:tool"),
+            Some(PathBuf::from("/out/bin/tool"))
+        );
+        assert_eq!(debug_program_path("/out/bin", "tool"), None);
+    }
+
+    #[test]
+    fn test_remove_overlapping_tokens() {
+        let range = |line, start, end| Range {
+            start: Position::new(line, start),
+            end: Position::new(line, end),
+        };
+        let mut tokens = vec![
+            (range(1, 4, 8), 1),
+            (range(0, 0, 10), 2),
+            (range(0, 0, 9), 0),
+            (range(0, 4, 6), 2),
+            (range(0, 10, 12), 2),
+        ];
+        remove_overlapping_tokens(&mut tokens);
+        assert_eq!(
+            tokens,
+            [
+                (range(0, 0, 9), 0),
+                (range(0, 10, 12), 2),
+                (range(1, 4, 8), 1)
+            ]
+        );
+    }
 }
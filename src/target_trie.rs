@@ -1,9 +1,25 @@
+use crate::label::{expand_label, parse_label, Label};
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Clone)]
+/// A target stored in the trie.
+///
+/// Fields beyond `name` and `full_build_path` are optional, so construct it with
+/// struct update syntax: `RuleInfo { name, full_build_path, ..Default::default() }`.
+#[derive(Debug, Clone, Default)]
 pub struct RuleInfo {
     pub name: String,
     pub full_build_path: String,
+    /// The rule that declares the target, e.g. `cc_library`
+    pub rule_type: String,
+    /// Labels from the target's `visibility` attribute
+    pub visibility: Vec<String>,
+    /// The BUILD file the target is declared in
+    pub source_file: Option<PathBuf>,
+    /// The target's `testonly` attribute, or None when it could not be determined
+    pub testonly: Option<bool>,
+    /// Absolute labels from the target's `deps`, including every branch of a `select()`
+    pub deps: Vec<String>,
 }
 
 impl RuleInfo {
@@ -11,6 +27,7 @@ impl RuleInfo {
         Self {
             name,
             full_build_path,
+            ..Default::default()
         }
     }
 }
@@ -39,6 +56,8 @@ impl TrieNode {
 #[derive(Debug)]
 pub struct TargetTrie {
     root: TrieNode,
+    /// Trie keys inserted from each BUILD file, so a file's targets can be removed again
+    files: HashMap<PathBuf, Vec<String>>,
 }
 
 impl TargetTrie {
@@ -51,36 +70,108 @@ impl TargetTrie {
                 rules: Vec::new(),
                 children: HashMap::new(),
             },
+            files: HashMap::new(),
         }
     }
 
-    pub fn insert_target(&mut self, path: &str, rule: RuleInfo) {
-        let mut current = &mut self.root;
+    /// Inserts a target and remembers the BUILD file it was declared in.
+    pub fn insert_file_target(&mut self, build_file: &Path, path: &str, rule: RuleInfo) {
+        self.files
+            .entry(build_file.to_path_buf())
+            .or_default()
+            .push(path.to_string());
+        self.insert_target(path, rule);
+    }
 
-        let (package_path, rule_name) = if path.contains(':') {
-            let parts: Vec<&str> = path.split(':').collect();
-            (parts[0], parts[1])
-        } else {
-            ("", path)
+    /// Removes every target that was inserted from `build_file`.
+    pub fn remove_file(&mut self, build_file: &Path) {
+        let Some(paths) = self.files.remove(build_file) else {
+            return;
         };
 
-        if !package_path.is_empty() {
-            let parts: Vec<&str> = package_path.split('/').collect();
-            for (i, part) in parts.iter().enumerate() {
-                for c in part.chars() {
-                    current = current
-                        .children
-                        .entry(c)
-                        .or_insert_with(|| TrieNode::new(c));
+        for path in paths {
+            let key = Self::key(&path);
+            let mut current = &mut self.root;
+            let mut found = true;
+            for c in key.chars() {
+                match current.children.get_mut(&c) {
+                    Some(node) => current = node,
+                    None => {
+                        found = false;
+                        break;
+                    }
                 }
+            }
 
-                if i < parts.len() - 1 {
-                    current.is_package_end = true;
-                }
+            if found {
+                current.rules.clear();
+                current.is_end = false;
+            }
+        }
+    }
+
+    /// Removes the targets of every BUILD file under `dir`, including files that have
+    /// since been deleted from disk.
+    pub fn remove_files_under(&mut self, dir: &Path) {
+        let build_files: Vec<PathBuf> = self
+            .files
+            .keys()
+            .filter(|build_file| build_file.starts_with(dir))
+            .cloned()
+            .collect();
+        for build_file in build_files {
+            self.remove_file(&build_file);
+        }
+    }
+
+    /// Whether targets of `build_file` are indexed.
+    pub fn contains_file(&self, build_file: &Path) -> bool {
+        self.files.contains_key(build_file)
+    }
+
+    /// Returns the number of BUILD files whose targets are indexed.
+    pub fn file_count(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Whether no target is indexed, which the trie's nodes alone don't tell as
+    /// [`remove_file`](Self::remove_file) leaves them in place.
+    pub fn is_empty(&self) -> bool {
+        let mut stack = vec![&self.root];
+        while let Some(node) = stack.pop() {
+            if node.is_end && !node.rules.is_empty() {
+                return false;
+            }
+            stack.extend(node.children.values());
+        }
+        true
+    }
+
+    pub fn insert_target(&mut self, path: &str, rule: RuleInfo) {
+        let mut current = &mut self.root;
+
+        let (package_path, rule_name) = Self::key_parts(path);
+
+        // Package separators are kept in the key so that a package name is never
+        // mistaken for a prefix of a rule name (`a/b:c` vs `a:bc`).
+        let parts: Vec<&str> = package_path.split('/').collect();
+        for (i, part) in parts.iter().enumerate() {
+            if i > 0 {
+                current.is_package_end = true;
+                current = current
+                    .children
+                    .entry('/')
+                    .or_insert_with(|| TrieNode::new('/'));
+            }
+            for c in part.chars() {
+                current = current
+                    .children
+                    .entry(c)
+                    .or_insert_with(|| TrieNode::new(c));
             }
         }
 
-        for c in rule_name.chars() {
+        for c in std::iter::once(':').chain(rule_name.chars()) {
             current = current
                 .children
                 .entry(c)
@@ -91,50 +182,91 @@ impl TargetTrie {
         current.rules.push(rule);
     }
 
+    /// The trie key of a target path: `pkg/path:name`, without the leading `//`.
+    fn key(path: &str) -> String {
+        let (package_path, rule_name) = Self::key_parts(path);
+        format!("{}:{}", package_path, rule_name)
+    }
+
+    /// The package and target name a target path is keyed by. The `@repo//` of an
+    /// external label is kept in the package, and the leading `//` may be left out,
+    /// e.g. `pkg/path:name`.
+    fn key_parts(path: &str) -> (String, String) {
+        let label = if path.starts_with("//") || path.starts_with('@') {
+            parse_label(path)
+        } else {
+            parse_label(&format!("//{}", path))
+        };
+
+        match label {
+            Ok(Label {
+                repo: Some(repo),
+                package,
+                name,
+            }) => (format!("@{}//{}", repo, package), name),
+            Ok(Label { package, name, .. }) => (package, name),
+            // Not a valid label, keyed as a name in the root package
+            Err(_) => (String::new(), path.to_string()),
+        }
+    }
+
+    /// Looks up the rule a label refers to, expanding implicit target names.
+    pub fn resolve(&self, label: &str) -> Option<&RuleInfo> {
+        let label = expand_label(label);
+        let full_build_path = if label.starts_with("//") {
+            label.clone()
+        } else {
+            format!("//{}", label)
+        };
+
+        self.starts_with(&label)
+            .into_iter()
+            .flatten()
+            .find(|rule| rule.full_build_path == full_build_path)
+    }
+
+    /// Returns the rules of every target whose label starts with `prefix`.
+    ///
+    /// A prefix without a colon is ambiguous while the user is still typing: in
+    /// `//a/b` the trailing `b` may be the start of a sub-package of `a` or the
+    /// start of a rule name in package `a`. Both interpretations are returned.
     pub fn starts_with(&self, prefix: &str) -> Vec<&Vec<RuleInfo>> {
         let mut result = Vec::new();
-        let mut current = &self.root;
+        let prefix = prefix.trim_start_matches('/');
 
-        if prefix.is_empty() {
-            let mut stack = vec![current];
-            while let Some(node) = stack.pop() {
-                if node.is_end && !node.rules.is_empty() {
-                    result.push(&node.rules);
-                }
-                for child in node.children.values() {
-                    stack.push(child);
-                }
+        if prefix.contains(':') {
+            if let Some(node) = self.find_node(prefix) {
+                Self::collect_rules(node, &mut result);
             }
             return result;
         }
 
-        let (package_path, rule_prefix) = if prefix.contains(':') {
-            let parts: Vec<&str> = prefix.split(':').collect();
-            (parts[0], parts[1])
-        } else {
-            (prefix, "")
-        };
+        // `prefix` as a (partial) package path, including all sub-packages
+        if let Some(node) = self.find_node(prefix) {
+            Self::collect_rules(node, &mut result);
+        }
 
-        let parts: Vec<&str> = package_path.split('/').collect();
-        for part in parts.iter() {
-            for c in part.chars() {
-                match current.children.get(&c) {
-                    Some(node) => current = node,
-                    None => return result,
-                }
+        // The last segment of `prefix` as a partial rule name in its parent package
+        if !prefix.is_empty() {
+            let (package_path, rule_prefix) = prefix.rsplit_once('/').unwrap_or(("", prefix));
+            if let Some(node) = self.find_node(&format!("{}:{}", package_path, rule_prefix)) {
+                Self::collect_rules(node, &mut result);
             }
         }
 
-        if !rule_prefix.is_empty() {
-            for c in rule_prefix.chars() {
-                match current.children.get(&c) {
-                    Some(node) => current = node,
-                    None => return result,
-                }
-            }
+        result
+    }
+
+    fn find_node(&self, key: &str) -> Option<&TrieNode> {
+        let mut current = &self.root;
+        for c in key.chars() {
+            current = current.children.get(&c)?;
         }
+        Some(current)
+    }
 
-        let mut stack = vec![current];
+    fn collect_rules<'a>(node: &'a TrieNode, result: &mut Vec<&'a Vec<RuleInfo>>) {
+        let mut stack = vec![node];
         while let Some(node) = stack.pop() {
             if node.is_end && !node.rules.is_empty() {
                 result.push(&node.rules);
@@ -143,8 +275,6 @@ impl TargetTrie {
                 stack.push(child);
             }
         }
-
-        result
     }
 }
 
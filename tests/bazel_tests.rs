@@ -1,6 +1,13 @@
-use bazel_lsp::bazel::find_build_files;
-use bazel_lsp::bazel::{find_workspace_root, get_package_path, is_workspace_dir};
+use bazel_lsp::bazel::{build_stub, find_bazelrc_configs, find_build_files, find_bzl_files};
+use bazel_lsp::bazel::{
+    deps_tree, parse_bazelrc_configs, parse_label_kinds, parse_query_graph, parse_query_location,
+};
+use bazel_lsp::bazel::{
+    find_package_build_file, find_workspace_root, find_workspace_root_with_depth_limit,
+    get_package_path, is_workspace_dir,
+};
 use std::fs;
+use std::path::PathBuf;
 use tempfile::TempDir;
 
 #[test]
@@ -18,6 +25,31 @@ fn test_is_workspace_dir() {
     assert!(is_workspace_dir(temp_path).unwrap());
 }
 
+#[cfg(unix)]
+#[test]
+fn test_is_workspace_dir_without_permission() {
+    use std::os::unix::fs::PermissionsExt;
+
+    // Permissions don't keep root out
+    if unsafe { libc::geteuid() } == 0 {
+        return;
+    }
+
+    let temp_dir = TempDir::new().unwrap();
+    let locked = temp_dir.path().join("locked");
+    fs::create_dir(&locked).unwrap();
+    fs::create_dir(locked.join("inner")).unwrap();
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+
+    let is_workspace = is_workspace_dir(&locked);
+    let inner_is_workspace = is_workspace_dir(&locked.join("inner"));
+    // Let the temporary directory be removed
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+
+    assert!(!is_workspace.unwrap());
+    assert!(!inner_is_workspace.unwrap());
+}
+
 #[test]
 fn test_find_workspace_root() {
     let temp_dir = TempDir::new().unwrap();
@@ -34,6 +66,33 @@ fn test_find_workspace_root() {
     assert_eq!(found_root, temp_path);
 }
 
+#[test]
+fn test_find_workspace_root_with_depth_limit() {
+    let temp_dir = TempDir::new().unwrap();
+    let temp_path = temp_dir.path();
+    fs::write(temp_path.join("WORKSPACE"), "").unwrap();
+
+    let nested_dir = temp_path.join("a").join("b").join("c");
+    fs::create_dir_all(&nested_dir).unwrap();
+
+    // The workspace root is three parents above `c`
+    assert!(find_workspace_root_with_depth_limit(&nested_dir, 2)
+        .unwrap()
+        .is_none());
+    assert_eq!(
+        find_workspace_root_with_depth_limit(&nested_dir, 3)
+            .unwrap()
+            .unwrap(),
+        temp_path
+    );
+    assert_eq!(
+        find_workspace_root_with_depth_limit(temp_path, 0)
+            .unwrap()
+            .unwrap(),
+        temp_path
+    );
+}
+
 #[test]
 fn test_get_package_path() {
     let temp_dir = TempDir::new().unwrap();
@@ -48,10 +107,33 @@ fn test_get_package_path() {
     assert_eq!(package_path, "src/main");
 }
 
+#[test]
+fn test_find_package_build_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let temp_path = temp_dir.path();
+
+    fs::write(temp_path.join("WORKSPACE"), "").unwrap();
+    fs::create_dir_all(temp_path.join("lib/src/detail")).unwrap();
+    fs::write(temp_path.join("lib/BUILD"), "").unwrap();
+    fs::write(temp_path.join("lib/BUILD.bazel"), "").unwrap();
+    fs::write(temp_path.join("lib/src/detail/impl.cc"), "").unwrap();
+    fs::write(temp_path.join("top.cc"), "").unwrap();
+
+    assert_eq!(
+        find_package_build_file(&temp_path.join("lib/src/detail/impl.cc")).unwrap(),
+        Some(temp_path.join("lib/BUILD.bazel"))
+    );
+    // The workspace root has no BUILD file, and the search stops there
+    assert_eq!(
+        find_package_build_file(&temp_path.join("top.cc")).unwrap(),
+        None
+    );
+}
+
 #[test]
 fn test_find_build_files_empty_dir() {
     let temp_dir = TempDir::new().unwrap();
-    let build_files = find_build_files(temp_dir.path());
+    let build_files = find_build_files(temp_dir.path(), &[]).unwrap();
     assert!(build_files.is_empty());
 }
 
@@ -60,7 +142,7 @@ fn test_find_build_files_single_build() {
     let temp_dir = TempDir::new().unwrap();
     fs::write(temp_dir.path().join("BUILD"), "").unwrap();
 
-    let build_files = find_build_files(temp_dir.path());
+    let build_files = find_build_files(temp_dir.path(), &[]).unwrap();
     assert_eq!(build_files.len(), 1);
     assert_eq!(build_files[0].file_name().unwrap(), "BUILD");
 }
@@ -70,7 +152,7 @@ fn test_find_build_files_build_bazel() {
     let temp_dir = TempDir::new().unwrap();
     fs::write(temp_dir.path().join("BUILD.bazel"), "").unwrap();
 
-    let build_files = find_build_files(temp_dir.path());
+    let build_files = find_build_files(temp_dir.path(), &[]).unwrap();
     assert_eq!(build_files.len(), 1);
     assert_eq!(build_files[0].file_name().unwrap(), "BUILD.bazel");
 }
@@ -84,7 +166,7 @@ fn test_find_build_files_nested() {
     fs::write(temp_dir.path().join("BUILD"), "").unwrap();
     fs::write(subdir.join("BUILD"), "").unwrap();
 
-    let build_files = find_build_files(temp_dir.path());
+    let build_files = find_build_files(temp_dir.path(), &[]).unwrap();
     assert_eq!(build_files.len(), 2);
 }
 
@@ -97,7 +179,7 @@ fn test_find_build_files_ignore_hidden() {
     fs::write(temp_dir.path().join("BUILD"), "").unwrap();
     fs::write(hidden_dir.join("BUILD"), "").unwrap();
 
-    let build_files = find_build_files(temp_dir.path());
+    let build_files = find_build_files(temp_dir.path(), &[]).unwrap();
     assert_eq!(build_files.len(), 1);
     assert_eq!(build_files[0].file_name().unwrap(), "BUILD");
 }
@@ -111,7 +193,7 @@ fn test_find_build_files_ignore_bazel_out() {
     fs::write(temp_dir.path().join("BUILD"), "").unwrap();
     fs::write(bazel_out.join("BUILD"), "").unwrap();
 
-    let build_files = find_build_files(temp_dir.path());
+    let build_files = find_build_files(temp_dir.path(), &[]).unwrap();
     assert_eq!(build_files.len(), 1);
     assert_eq!(build_files[0].file_name().unwrap(), "BUILD");
 }
@@ -147,6 +229,190 @@ fn test_find_build_files_complex_structure() {
         fs::write(temp_dir.path().join(location), "").unwrap();
     }
 
-    let build_files = find_build_files(temp_dir.path());
+    let build_files = find_build_files(temp_dir.path(), &[]).unwrap();
     assert_eq!(build_files.len(), 4); // Should only find the BUILD files in non-hidden, non-bazel-out directories
 }
+
+#[test]
+fn test_find_build_files_excluded_dirs() {
+    let temp_dir = TempDir::new().unwrap();
+    for dir in ["lib", "node_modules/pkg", "third_party/vendor"] {
+        fs::create_dir_all(temp_dir.path().join(dir)).unwrap();
+        fs::write(temp_dir.path().join(dir).join("BUILD"), "").unwrap();
+    }
+
+    let excluded_dirs = vec!["node_modules".to_string(), "vendor".to_string()];
+    let build_files = find_build_files(temp_dir.path(), &excluded_dirs).unwrap();
+    assert_eq!(build_files, vec![temp_dir.path().join("lib/BUILD")]);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_find_build_files_skips_dirs_without_permission() {
+    use std::os::unix::fs::PermissionsExt;
+
+    // Permissions don't keep root out
+    if unsafe { libc::geteuid() } == 0 {
+        return;
+    }
+
+    let temp_dir = TempDir::new().unwrap();
+    for dir in ["lib", "locked"] {
+        fs::create_dir_all(temp_dir.path().join(dir)).unwrap();
+        fs::write(temp_dir.path().join(dir).join("BUILD"), "").unwrap();
+    }
+    let locked = temp_dir.path().join("locked");
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+
+    let build_files = find_build_files(temp_dir.path(), &[]);
+    // Let the temporary directory be removed
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+
+    assert_eq!(
+        build_files.unwrap(),
+        vec![temp_dir.path().join("lib/BUILD")]
+    );
+}
+
+const QUERY_GRAPH: &str = r#"digraph mygraph {
+  node [shape=box];
+  "//app:bin"
+  "//app:bin" -> "//lib:a"
+  "//app:bin" -> "//app:main.cc"
+  "//lib:a"
+  "//lib:a" -> "//lib:b"
+  "//app:main.cc"
+  "//lib:b"
+}
+"#;
+
+const LABEL_KINDS: &str = "cc_binary rule //app:bin
+cc_library rule //lib:a
+cc_library rule //lib:b
+source file //app:main.cc
+";
+
+#[test]
+fn test_parse_query_graph() {
+    let graph = parse_query_graph(QUERY_GRAPH);
+    assert_eq!(graph.len(), 4);
+    assert_eq!(graph["//app:bin"], ["//lib:a", "//app:main.cc"]);
+    assert_eq!(graph["//lib:a"], ["//lib:b"]);
+    assert!(graph["//lib:b"].is_empty());
+}
+
+#[test]
+fn test_parse_label_kinds() {
+    let kinds = parse_label_kinds(LABEL_KINDS);
+    assert_eq!(kinds["//app:bin"], "cc_binary");
+    assert_eq!(kinds["//app:main.cc"], "source file");
+}
+
+#[test]
+fn test_parse_query_location() {
+    assert_eq!(
+        parse_query_location("/ws/gen/BUILD:7:1: genrule rule //gen:out\n"),
+        Some((PathBuf::from("/ws/gen/BUILD"), 6, 0))
+    );
+    assert_eq!(parse_query_location(""), None);
+    assert_eq!(parse_query_location("no location here"), None);
+}
+
+#[test]
+fn test_deps_tree_depth_limit() {
+    let graph = parse_query_graph(QUERY_GRAPH);
+    let kinds = parse_label_kinds(LABEL_KINDS);
+
+    let tree = deps_tree("//app:bin", &graph, &kinds, 3);
+    assert_eq!(tree["kind"], "cc_binary");
+    assert_eq!(tree["deps"][0]["label"], "//lib:a");
+    assert_eq!(tree["deps"][0]["deps"][0]["label"], "//lib:b");
+
+    let tree = deps_tree("//app:bin", &graph, &kinds, 1);
+    assert_eq!(tree["deps"][0]["label"], "//lib:a");
+    assert_eq!(tree["deps"][0]["deps"], serde_json::json!([]));
+}
+
+#[test]
+fn test_find_bzl_files() {
+    let temp_dir = TempDir::new().unwrap();
+    let tools_dir = temp_dir.path().join("tools");
+    let hidden_dir = temp_dir.path().join(".cache");
+    fs::create_dir(&tools_dir).unwrap();
+    fs::create_dir(&hidden_dir).unwrap();
+
+    fs::write(temp_dir.path().join("BUILD"), "").unwrap();
+    fs::write(tools_dir.join("defs.bzl"), "").unwrap();
+    fs::write(tools_dir.join("notes.bzl.txt"), "").unwrap();
+    fs::write(hidden_dir.join("cached.bzl"), "").unwrap();
+
+    let bzl_files = find_bzl_files(temp_dir.path(), &[]).unwrap();
+    assert_eq!(bzl_files, [tools_dir.join("defs.bzl")]);
+}
+
+#[test]
+fn test_build_stub() {
+    let files: Vec<PathBuf> = ["README.md", "lib.cc", "lib.h", "util.cc", "gen.py"]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+
+    assert_eq!(
+        build_stub("lib", &files).unwrap(),
+        r#"cc_library(
+    name = "lib",
+    srcs = [
+        "lib.cc",
+        "util.cc",
+    ],
+    hdrs = ["lib.h"],
+    deps = [],
+)
+
+py_library(
+    name = "lib_py",
+    srcs = ["gen.py"],
+    deps = [],
+)
+"#
+    );
+
+    assert_eq!(build_stub("docs", &[PathBuf::from("README.md")]), None);
+}
+
+const BAZELRC: &str = r#"# Shared settings
+build --announce_rc
+build:opt --compilation_mode=opt
+build:opt --copt=-O3
+build:asan --copt=-fsanitize=address
+test:ci --test_output=errors
+common:remote --remote_cache=grpc://cache
+try-import %workspace%/user.bazelrc
+"#;
+
+#[test]
+fn test_parse_bazelrc_configs() {
+    assert_eq!(
+        parse_bazelrc_configs(BAZELRC),
+        vec!["asan", "ci", "opt", "remote"]
+    );
+}
+
+#[test]
+fn test_find_bazelrc_configs_follows_imports() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join(".bazelrc"), BAZELRC).unwrap();
+    fs::write(
+        temp_dir.path().join("user.bazelrc"),
+        "build:mine --jobs=4\n# build:commented --jobs=1\n",
+    )
+    .unwrap();
+
+    assert_eq!(
+        find_bazelrc_configs(temp_dir.path()).unwrap(),
+        vec!["asan", "ci", "mine", "opt", "remote"]
+    );
+    assert!(find_bazelrc_configs(&temp_dir.path().join("missing"))
+        .unwrap()
+        .is_empty());
+}
@@ -0,0 +1,40 @@
+mod common;
+
+use common::{initialize, read_response, send_message, setup_server};
+use std::fs;
+use tempfile::TempDir;
+
+#[tokio::test]
+async fn test_configs_request_lists_bazelrc_configs() -> Result<(), anyhow::Error> {
+    let workspace = TempDir::new()?;
+    fs::write(workspace.path().join("WORKSPACE"), "")?;
+    fs::write(
+        workspace.path().join(".bazelrc"),
+        "build:opt --compilation_mode=opt\ntest:ci --test_output=errors\n",
+    )?;
+
+    let workspace_uri = url::Url::from_file_path(workspace.path()).unwrap();
+    let (mut stdin, mut stdout) = setup_server();
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "workspaceFolders": [{ "uri": workspace_uri, "name": "workspace" }],
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let configs_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "bazel/configs"
+    });
+    send_message(&mut stdin, configs_params).await?;
+
+    let (response, _) = read_response(&mut stdout, 2).await?;
+    assert_eq!(response["result"], serde_json::json!(["ci", "opt"]));
+
+    Ok(())
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use common::{initialize, read_response, send_message, setup_server};
+use std::fs;
+use tempfile::TempDir;
+
+#[tokio::test]
+async fn test_build_file_for_nested_source() -> Result<(), anyhow::Error> {
+    let workspace = TempDir::new()?;
+    fs::write(workspace.path().join("WORKSPACE"), "")?;
+    fs::create_dir_all(workspace.path().join("app/lib/src"))?;
+    fs::write(workspace.path().join("app/BUILD"), "")?;
+    fs::write(workspace.path().join("app/lib/BUILD.bazel"), "")?;
+    let source = workspace.path().join("app/lib/src/util.cc");
+    fs::write(&source, "")?;
+
+    let (mut stdin, mut stdout) = setup_server();
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({ "capabilities": {}, "processId": 1 }),
+    )
+    .await?;
+
+    let request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "bazel/buildFileForSource",
+        "params": { "uri": url::Url::from_file_path(&source).unwrap() }
+    });
+    send_message(&mut stdin, request).await?;
+
+    let (response, _) = read_response(&mut stdout, 2).await?;
+    let expected = url::Url::from_file_path(workspace.path().join("app/lib/BUILD.bazel")).unwrap();
+    assert_eq!(response["result"], expected.as_str());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_build_file_for_source_outside_workspace() -> Result<(), anyhow::Error> {
+    let dir = TempDir::new()?;
+    fs::write(dir.path().join("BUILD"), "")?;
+    let source = dir.path().join("util.cc");
+    fs::write(&source, "")?;
+
+    let (mut stdin, mut stdout) = setup_server();
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({ "capabilities": {}, "processId": 1 }),
+    )
+    .await?;
+
+    let request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "bazel/buildFileForSource",
+        "params": { "uri": url::Url::from_file_path(&source).unwrap() }
+    });
+    send_message(&mut stdin, request).await?;
+
+    let (response, _) = read_response(&mut stdout, 2).await?;
+    assert!(response["result"].is_null());
+
+    Ok(())
+}
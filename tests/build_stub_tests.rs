@@ -0,0 +1,109 @@
+mod common;
+
+use common::{initialize, read_response, send_message, setup_server};
+use std::fs;
+use tempfile::TempDir;
+
+const PY_STUB: &str = r#"py_library(
+    name = "tools",
+    srcs = [
+        "main.py",
+        "util/strings.py",
+    ],
+    deps = [],
+)
+"#;
+
+#[tokio::test]
+async fn test_generate_build_stub_for_python_sources() -> Result<(), anyhow::Error> {
+    let workspace = TempDir::new()?;
+    fs::write(workspace.path().join("WORKSPACE"), "")?;
+    let tools = workspace.path().join("tools");
+    fs::create_dir_all(tools.join("util"))?;
+    fs::write(tools.join("main.py"), "")?;
+    fs::write(tools.join("util/strings.py"), "")?;
+    fs::write(tools.join("README.md"), "")?;
+    // A package of its own, so its sources aren't part of the stub
+    fs::create_dir_all(tools.join("plugin"))?;
+    fs::write(tools.join("plugin/BUILD"), "")?;
+    fs::write(tools.join("plugin/plugin.py"), "")?;
+
+    let workspace_uri = url::Url::from_file_path(workspace.path()).unwrap();
+    let (mut stdin, mut stdout) = setup_server();
+
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "workspaceFolders": [{ "uri": workspace_uri, "name": "workspace" }],
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let execute_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "workspace/executeCommand",
+        "params": {
+            "command": "bazel.generateBuildStub",
+            "arguments": [{ "directory": tools }]
+        }
+    });
+    send_message(&mut stdin, execute_params).await?;
+
+    let (response, _) = read_response(&mut stdout, 2).await?;
+    assert_eq!(response["result"]["content"], PY_STUB);
+    assert_eq!(
+        response["result"]["path"],
+        serde_json::json!(tools.join("BUILD"))
+    );
+    assert!(!tools.join("BUILD").exists());
+
+    // Writing the stub indexes its target
+    let execute_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 3,
+        "method": "workspace/executeCommand",
+        "params": {
+            "command": "bazel.generateBuildStub",
+            "arguments": [{ "directory": tools, "write": true }]
+        }
+    });
+    send_message(&mut stdin, execute_params).await?;
+    read_response(&mut stdout, 3).await?;
+    assert_eq!(fs::read_to_string(tools.join("BUILD"))?, PY_STUB);
+
+    let resolve_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 4,
+        "method": "bazel/resolveLabel",
+        "params": { "label": "//tools:tools" }
+    });
+    send_message(&mut stdin, resolve_params).await?;
+    let (response, _) = read_response(&mut stdout, 4).await?;
+    assert_eq!(
+        response["result"]["path"],
+        serde_json::json!(tools.join("BUILD"))
+    );
+
+    // The package has a BUILD file now
+    let execute_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 5,
+        "method": "workspace/executeCommand",
+        "params": {
+            "command": "bazel.generateBuildStub",
+            "arguments": [{ "directory": tools }]
+        }
+    });
+    send_message(&mut stdin, execute_params).await?;
+    let (response, _) = read_response(&mut stdout, 5).await?;
+    assert!(response["error"]["message"]
+        .as_str()
+        .unwrap()
+        .ends_with("already has a BUILD file"));
+
+    Ok(())
+}
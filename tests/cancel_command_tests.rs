@@ -0,0 +1,180 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::{initialize, read_message, send_message, setup_server};
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::time::Duration;
+use tempfile::TempDir;
+
+// Records that it started, then runs far longer than the test may take
+const MOCK_BAZEL: &str = r#"#!/bin/sh
+echo "$*" >> "${0%/*}/started"
+exec /bin/sleep 60
+"#;
+
+#[tokio::test]
+async fn test_cancel_all_stops_running_commands() -> Result<(), anyhow::Error> {
+    let bin_dir = TempDir::new()?;
+    let mock_bazel = bin_dir.path().join("bazel");
+    fs::write(&mock_bazel, MOCK_BAZEL)?;
+    fs::set_permissions(&mock_bazel, fs::Permissions::from_mode(0o755))?;
+
+    let (mut stdin, mut stdout) = setup_server();
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "initializationOptions": {
+                "bazel_env": { "PATH": bin_dir.path() }
+            },
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    for (id, command) in [(2, "bazel.build"), (3, "bazel.run")] {
+        let execute_params = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "workspace/executeCommand",
+            "params": {
+                "command": command,
+                "arguments": [{ "target": "//app:slow" }]
+            }
+        });
+        send_message(&mut stdin, execute_params).await?;
+    }
+
+    let started = bin_dir.path().join("started");
+    for _ in 0..100 {
+        let count = fs::read_to_string(&started).map_or(0, |started| started.lines().count());
+        if count == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    let cancel_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 4,
+        "method": "workspace/executeCommand",
+        "params": { "command": "bazel.cancelAll", "arguments": [] }
+    });
+    send_message(&mut stdin, cancel_params).await?;
+
+    // The cancelled commands answer too, in no particular order
+    let mut responses = HashMap::new();
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while responses.len() < 3 {
+            let message = read_message(&mut stdout).await?;
+            if let Some(id) = message.get("id").and_then(|id| id.as_u64()) {
+                if message.get("method").is_none() {
+                    responses.insert(id, message);
+                }
+            }
+        }
+        Ok::<_, anyhow::Error>(())
+    })
+    .await??;
+
+    assert_eq!(responses[&4]["result"], serde_json::json!({ "killed": 2 }));
+    assert!(responses[&2]["error"].is_null());
+    assert!(responses[&3]["error"].is_null());
+
+    // Nothing is left to cancel
+    let cancel_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 5,
+        "method": "workspace/executeCommand",
+        "params": { "command": "bazel.cancelAll", "arguments": [] }
+    });
+    send_message(&mut stdin, cancel_params).await?;
+    let (response, _) = common::read_response(&mut stdout, 5).await?;
+    assert_eq!(response["result"], serde_json::json!({ "killed": 0 }));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_shutdown_kills_running_commands() -> Result<(), anyhow::Error> {
+    let bin_dir = TempDir::new()?;
+    let mock_bazel = bin_dir.path().join("bazel");
+    // `exec` keeps the shell's pid, so the recorded pid is the sleeping process
+    fs::write(
+        &mock_bazel,
+        "#!/bin/sh\necho $$ > \"${0%/*}/pid\"\nexec /bin/sleep 60\n",
+    )?;
+    fs::set_permissions(&mock_bazel, fs::Permissions::from_mode(0o755))?;
+
+    let (mut stdin, mut stdout) = setup_server();
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "initializationOptions": {
+                "bazel_env": { "PATH": bin_dir.path() }
+            },
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let execute_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "workspace/executeCommand",
+        "params": {
+            "command": "bazel.build",
+            "arguments": [{ "target": "//app:slow" }]
+        }
+    });
+    send_message(&mut stdin, execute_params).await?;
+
+    let pid_file = bin_dir.path().join("pid");
+    let mut pid = String::new();
+    for _ in 0..100 {
+        pid = fs::read_to_string(&pid_file).unwrap_or_default();
+        if pid.ends_with('\n') {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    let pid = pid.trim().to_string();
+    assert!(!pid.is_empty(), "bazel was not started");
+
+    let shutdown_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 3,
+        "method": "shutdown"
+    });
+    send_message(&mut stdin, shutdown_params).await?;
+
+    let mut responses = HashMap::new();
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while responses.len() < 2 {
+            let message = read_message(&mut stdout).await?;
+            if let Some(id) = message.get("id").and_then(|id| id.as_u64()) {
+                if message.get("method").is_none() {
+                    responses.insert(id, message);
+                }
+            }
+        }
+        Ok::<_, anyhow::Error>(())
+    })
+    .await??;
+
+    assert!(responses[&3]["error"].is_null());
+    let alive = std::process::Command::new("/bin/kill")
+        .args(["-0", &pid])
+        .stderr(std::process::Stdio::null())
+        .status()?
+        .success();
+    assert!(!alive, "bazel process {} is still running", pid);
+
+    Ok(())
+}
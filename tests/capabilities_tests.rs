@@ -0,0 +1,144 @@
+mod common;
+
+use common::{initialize, read_response, send_message, setup_server};
+
+#[tokio::test]
+async fn test_minimal_client_gets_only_supported_providers() -> Result<(), anyhow::Error> {
+    let (mut stdin, mut stdout) = setup_server();
+
+    let response = initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({ "capabilities": {}, "processId": 1 }),
+    )
+    .await?;
+
+    let capabilities = &response["result"]["capabilities"];
+    assert!(capabilities["textDocumentSync"].is_number());
+    assert!(capabilities["executeCommandProvider"].is_object());
+    for provider in [
+        "semanticTokensProvider",
+        "codeLensProvider",
+        "completionProvider",
+        "definitionProvider",
+        "documentFormattingProvider",
+        "documentSymbolProvider",
+    ] {
+        assert!(
+            capabilities.get(provider).is_none(),
+            "{} advertised to a client without support for it",
+            provider
+        );
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_client_capabilities_enable_providers() -> Result<(), anyhow::Error> {
+    let (mut stdin, mut stdout) = setup_server();
+
+    let response = initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {
+                "textDocument": {
+                    "semanticTokens": {
+                        "requests": { "range": true },
+                        "tokenTypes": [],
+                        "tokenModifiers": [],
+                        "formats": ["relative"]
+                    },
+                    "completion": {
+                        "completionItem": {
+                            "resolveSupport": { "properties": ["documentation"] }
+                        }
+                    }
+                }
+            },
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let capabilities = &response["result"]["capabilities"];
+    assert!(capabilities["semanticTokensProvider"]["legend"].is_object());
+    assert_eq!(capabilities["completionProvider"]["resolveProvider"], true);
+    assert!(capabilities.get("codeLensProvider").is_none());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_completion_documentation_is_resolved() -> Result<(), anyhow::Error> {
+    let (mut stdin, mut stdout) = setup_server();
+
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {
+                "textDocument": {
+                    "completion": {
+                        "completionItem": {
+                            "resolveSupport": { "properties": ["documentation"] }
+                        }
+                    }
+                }
+            },
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let text = "cc_library(\n    name = \"lib\",\n    tags = [],\n)\n";
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": "file:///test/BUILD",
+                "languageId": "starlark",
+                "version": 1,
+                "text": text
+            }
+        }
+    });
+    send_message(&mut stdin, did_open_params).await?;
+
+    let completion_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "textDocument/completion",
+        "params": {
+            "textDocument": { "uri": "file:///test/BUILD" },
+            "position": { "line": 2, "character": 12 }
+        }
+    });
+    send_message(&mut stdin, completion_params).await?;
+
+    let (response, _) = read_response(&mut stdout, 2).await?;
+    let item = response["result"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|item| item["label"] == "manual")
+        .unwrap()
+        .clone();
+    assert!(item.get("documentation").is_none());
+
+    let resolve_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 3,
+        "method": "completionItem/resolve",
+        "params": item
+    });
+    send_message(&mut stdin, resolve_params).await?;
+
+    let (response, _) = read_response(&mut stdout, 3).await?;
+    assert!(response["result"]["documentation"].is_string());
+    assert!(response["result"].get("data").is_none());
+
+    Ok(())
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use common::{initialize, read_response, send_message, setup_server};
+
+const BUILD: &str = r#"cc_library(
+    name = "lib",
+    srcs = ["lib.cc"],
+    deps = ["//a", "//b"],
+)
+"#;
+
+async fn code_actions_at(line: u32, character: u32) -> Result<serde_json::Value, anyhow::Error> {
+    let (mut stdin, mut stdout) = setup_server();
+
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({ "capabilities": {}, "processId": 1 }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": "file:///test/BUILD",
+                "languageId": "starlark",
+                "version": 1,
+                "text": BUILD
+            }
+        }
+    });
+    send_message(&mut stdin, did_open_params).await?;
+
+    let position = serde_json::json!({ "line": line, "character": character });
+    let code_action_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "textDocument/codeAction",
+        "params": {
+            "textDocument": { "uri": "file:///test/BUILD" },
+            "range": { "start": position, "end": position },
+            "context": { "diagnostics": [] }
+        }
+    });
+    send_message(&mut stdin, code_action_params).await?;
+
+    let (response, _) = read_response(&mut stdout, 2).await?;
+    Ok(response["result"].clone())
+}
+
+#[tokio::test]
+async fn test_wrap_deps_with_select() -> Result<(), anyhow::Error> {
+    let actions = code_actions_at(3, 14).await?;
+
+    assert_eq!(actions.as_array().unwrap().len(), 1);
+    assert_eq!(actions[0]["title"], "Wrap with select()");
+    assert_eq!(
+        actions[0]["edit"]["changes"]["file:///test/BUILD"],
+        serde_json::json!([{
+            "range": {
+                "start": { "line": 3, "character": 11 },
+                "end": { "line": 3, "character": 25 }
+            },
+            "newText": "select({\n        \"//conditions:default\": [\"//a\", \"//b\"],\n    })"
+        }])
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_no_select_action_outside_deps() -> Result<(), anyhow::Error> {
+    let actions = code_actions_at(2, 14).await?;
+
+    assert_eq!(actions, serde_json::json!([]));
+
+    Ok(())
+}
@@ -1,5 +1,10 @@
+mod common;
+
 use anyhow::Result;
 use bazel_lsp::parser::BazelParser;
+use common::{initialize, read_response, send_message, setup_server};
+use std::fs;
+use tempfile::TempDir;
 use tower_lsp::lsp_types::{CodeLens, Command};
 
 #[test]
@@ -362,3 +367,204 @@ py_library(
 
     Ok(())
 }
+
+async fn code_lenses(
+    workspace: &std::path::Path,
+    uri: &str,
+    text: &str,
+) -> Result<serde_json::Value> {
+    code_lenses_with_options(workspace, uri, text, serde_json::json!({})).await
+}
+
+async fn code_lenses_with_options(
+    workspace: &std::path::Path,
+    uri: &str,
+    text: &str,
+    initialization_options: serde_json::Value,
+) -> Result<serde_json::Value> {
+    let workspace_uri = url::Url::from_file_path(workspace).unwrap();
+    let (mut stdin, mut stdout) = setup_server();
+
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "workspaceFolders": [{ "uri": workspace_uri, "name": "workspace" }],
+            "initializationOptions": initialization_options,
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": uri,
+                "languageId": "starlark",
+                "version": 1,
+                "text": text
+            }
+        }
+    });
+    send_message(&mut stdin, did_open_params).await?;
+
+    let code_lens_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "textDocument/codeLens",
+        "params": { "textDocument": { "uri": uri } }
+    });
+    send_message(&mut stdin, code_lens_params).await?;
+
+    let (response, _) = read_response(&mut stdout, 2).await?;
+    Ok(response["result"].clone())
+}
+
+#[tokio::test]
+async fn test_code_lens_root_build_file() -> Result<()> {
+    let workspace = TempDir::new()?;
+    fs::write(workspace.path().join("WORKSPACE"), "")?;
+    let build_text = "cc_binary(\n    name = \"app\",\n)\n";
+    fs::write(workspace.path().join("BUILD"), build_text)?;
+
+    let uri = url::Url::from_file_path(workspace.path().join("BUILD")).unwrap();
+    let lenses = code_lenses(workspace.path(), uri.as_str(), build_text).await?;
+
+    let targets: Vec<&str> = lenses
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|lens| lens["command"]["arguments"][0]["target"].as_str())
+        .collect();
+    assert_eq!(targets, ["//:app", "//:app"]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_code_lens_pathless_uri() -> Result<()> {
+    let workspace = TempDir::new()?;
+    fs::write(workspace.path().join("WORKSPACE"), "")?;
+
+    let lenses = code_lenses(
+        workspace.path(),
+        "untitled:BUILD",
+        "cc_binary(\n    name = \"app\",\n)\n",
+    )
+    .await?;
+    assert_eq!(lenses, serde_json::json!([]));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_code_lens_shows_test_coverage() -> Result<()> {
+    let workspace = TempDir::new()?;
+    fs::write(workspace.path().join("WORKSPACE"), "")?;
+    fs::create_dir_all(workspace.path().join("app"))?;
+    let build_text = "cc_test(\n    name = \"my_test\",\n)\n";
+    fs::write(workspace.path().join("app/BUILD"), build_text)?;
+
+    let report_dir = workspace.path().join("bazel-testlogs/app/my_test");
+    fs::create_dir_all(&report_dir)?;
+    fs::write(
+        report_dir.join("coverage.dat"),
+        "SF:app/a.cc\nLF:100\nLH:87\nend_of_record\n",
+    )?;
+
+    let uri = url::Url::from_file_path(workspace.path().join("app/BUILD")).unwrap();
+    let lenses = code_lenses(workspace.path(), uri.as_str(), build_text).await?;
+
+    let titles: Vec<&str> = lenses
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|lens| lens["command"]["title"].as_str())
+        .collect();
+    assert!(
+        titles.contains(&"Test my_test (87% coverage)"),
+        "{:?}",
+        titles
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_code_lens_debug_is_opt_in() -> Result<()> {
+    let workspace = TempDir::new()?;
+    fs::write(workspace.path().join("WORKSPACE"), "")?;
+    let build_text = "cc_binary(\n    name = \"app\",\n)\n";
+    fs::write(workspace.path().join("BUILD"), build_text)?;
+    let uri = url::Url::from_file_path(workspace.path().join("BUILD")).unwrap();
+
+    let debug_lenses = |lenses: &serde_json::Value| {
+        lenses
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|lens| lens["command"]["command"] == "bazel.debug")
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+
+    let lenses = code_lenses(workspace.path(), uri.as_str(), build_text).await?;
+    assert!(debug_lenses(&lenses).is_empty());
+
+    let lenses = code_lenses_with_options(
+        workspace.path(),
+        uri.as_str(),
+        build_text,
+        serde_json::json!({ "enable_debug_lens": true }),
+    )
+    .await?;
+    let debug = debug_lenses(&lenses);
+    assert_eq!(debug.len(), 1);
+    assert_eq!(debug[0]["command"]["title"], "🐛 Debug app");
+    assert_eq!(debug[0]["command"]["arguments"][0]["target"], "//:app");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_code_lens_binary_target() -> Result<()> {
+    let workspace = TempDir::new()?;
+    fs::write(workspace.path().join("WORKSPACE"), "")?;
+    fs::create_dir_all(workspace.path().join("tools/app"))?;
+    let build_text = "cc_binary(\n    name = \"app\",\n    srcs = [\"main.cc\"],\n)\n";
+    fs::write(workspace.path().join("tools/app/BUILD"), build_text)?;
+
+    let uri = url::Url::from_file_path(workspace.path().join("tools/app/BUILD")).unwrap();
+    let lenses = code_lenses(workspace.path(), uri.as_str(), build_text).await?;
+
+    let range = serde_json::json!({
+        "start": { "line": 0, "character": 0 },
+        "end": { "line": 0, "character": 9 }
+    });
+    assert_eq!(
+        lenses,
+        serde_json::json!([
+            {
+                "range": range,
+                "command": {
+                    "title": "▶ Run app",
+                    "command": "bazel.run",
+                    "arguments": [{ "target": "//tools/app:app" }]
+                }
+            },
+            {
+                "range": range,
+                "command": {
+                    "title": "Build app",
+                    "command": "bazel.build",
+                    "arguments": [{ "target": "//tools/app:app" }]
+                }
+            }
+        ])
+    );
+
+    Ok(())
+}
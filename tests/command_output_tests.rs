@@ -0,0 +1,155 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::{initialize, read_response, send_message, setup_server};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::time::Duration;
+use tempfile::TempDir;
+
+// Writes to both outputs and leaves a process behind that keeps them open, like the
+// server bazel starts
+const MOCK_BAZEL: &str = r#"#!/bin/sh
+echo "Build completed"
+echo "Loading packages" >&2
+/bin/sleep 5 &
+exit 0
+"#;
+
+#[tokio::test]
+async fn test_command_output_is_logged_and_background_processes_are_not_waited_for(
+) -> Result<(), anyhow::Error> {
+    let bin_dir = TempDir::new()?;
+    let mock_bazel = bin_dir.path().join("bazel");
+    fs::write(&mock_bazel, MOCK_BAZEL)?;
+    fs::set_permissions(&mock_bazel, fs::Permissions::from_mode(0o755))?;
+
+    let (mut stdin, mut stdout) = setup_server();
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "initializationOptions": {
+                "bazel_env": { "PATH": bin_dir.path() }
+            },
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let execute_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "workspace/executeCommand",
+        "params": {
+            "command": "bazel.build",
+            "arguments": [{ "target": "//app:main" }]
+        }
+    });
+    send_message(&mut stdin, execute_params).await?;
+
+    // Well before the leftover process closes the output
+    let (response, notifications) =
+        tokio::time::timeout(Duration::from_secs(3), read_response(&mut stdout, 2)).await??;
+    assert!(response.get("error").is_none(), "{}", response);
+
+    let logged = |message_type: u64, message: &str| {
+        notifications.iter().any(|n| {
+            n["method"] == "window/logMessage"
+                && n["params"]["type"] == message_type
+                && n["params"]["message"] == message
+        })
+    };
+    assert!(logged(3, "Build completed\n"), "{:?}", notifications);
+    assert!(logged(1, "Loading packages\n"), "{:?}", notifications);
+    assert!(logged(3, "Successfully built target: //app:main"));
+    assert!(notifications
+        .iter()
+        .any(|n| n["method"] == "window/showMessage"
+            && n["params"]["type"] == 3
+            && n["params"]["message"] == "✓ bazel build //app:main succeeded"));
+
+    Ok(())
+}
+
+/// Runs `bazel.build` for `//app:main` with a mock bazel that exits with `exit_code`,
+/// returning the notifications sent until the command finished.
+async fn build_notifications(
+    exit_code: i32,
+    initialization_options: serde_json::Value,
+) -> Result<Vec<serde_json::Value>, anyhow::Error> {
+    let bin_dir = TempDir::new()?;
+    let mock_bazel = bin_dir.path().join("bazel");
+    fs::write(&mock_bazel, format!("#!/bin/sh\nexit {}\n", exit_code))?;
+    fs::set_permissions(&mock_bazel, fs::Permissions::from_mode(0o755))?;
+
+    let mut options = initialization_options;
+    options["bazel_env"] = serde_json::json!({ "PATH": bin_dir.path() });
+
+    let (mut stdin, mut stdout) = setup_server();
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "initializationOptions": options,
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let execute_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "workspace/executeCommand",
+        "params": {
+            "command": "bazel.build",
+            "arguments": [{ "target": "//app:main" }]
+        }
+    });
+    send_message(&mut stdin, execute_params).await?;
+
+    let (_, notifications) =
+        tokio::time::timeout(Duration::from_secs(5), read_response(&mut stdout, 2)).await??;
+    Ok(notifications)
+}
+
+fn shown_messages(notifications: &[serde_json::Value]) -> Vec<&serde_json::Value> {
+    notifications
+        .iter()
+        .filter(|n| n["method"] == "window/showMessage")
+        .map(|n| &n["params"])
+        .collect()
+}
+
+#[tokio::test]
+async fn test_failed_build_shows_error_message() -> Result<(), anyhow::Error> {
+    let notifications = build_notifications(3, serde_json::json!({})).await?;
+
+    let shown = shown_messages(&notifications);
+    assert_eq!(shown.len(), 1, "{:?}", notifications);
+    assert_eq!(shown[0]["type"], 1);
+    assert_eq!(
+        shown[0]["message"],
+        "✗ bazel build //app:main failed (exit code 3)"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_command_result_popup_can_be_disabled() -> Result<(), anyhow::Error> {
+    let options = serde_json::json!({ "show_command_result_popup": false });
+    for exit_code in [0, 1] {
+        let notifications = build_notifications(exit_code, options.clone()).await?;
+        assert!(
+            shown_messages(&notifications).is_empty(),
+            "{:?}",
+            notifications
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,177 @@
+#![allow(dead_code)]
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf};
+use tower_lsp::Server;
+
+use bazel_lsp::server::{build_service, Backend};
+
+pub type ClientWriter = WriteHalf<DuplexStream>;
+pub type ClientReader = ReadHalf<DuplexStream>;
+
+/// Starts a server and returns the client ends of its input and output streams.
+pub fn setup_server_with(
+    init: impl FnOnce(Backend) -> Backend + Send + 'static,
+) -> (ClientWriter, ClientReader) {
+    let (service, socket) = build_service(|client| init(Backend::new(client)));
+
+    let (client_stream, server_stream) = tokio::io::duplex(1024);
+    let (server_read, server_write) = tokio::io::split(server_stream);
+    let (client_read, client_write) = tokio::io::split(client_stream);
+    let server_fut = Server::new(server_read, server_write, socket).serve(service);
+    tokio::spawn(server_fut);
+
+    (client_write, client_read)
+}
+
+pub fn setup_server() -> (ClientWriter, ClientReader) {
+    setup_server_with(|backend| backend)
+}
+
+pub async fn send_message(
+    writer: &mut ClientWriter,
+    message: serde_json::Value,
+) -> Result<(), anyhow::Error> {
+    let message_str = message.to_string();
+    let header = format!("Content-Length: {}\r\n\r\n", message_str.len());
+    writer.write_all(header.as_bytes()).await?;
+    writer.write_all(message_str.as_bytes()).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+pub async fn read_message(reader: &mut ClientReader) -> Result<serde_json::Value, anyhow::Error> {
+    let mut header = String::new();
+    loop {
+        let mut buf = [0; 1];
+        reader.read_exact(&mut buf).await?;
+        header.push(buf[0] as char);
+        if header.ends_with("\r\n\r\n") {
+            break;
+        }
+    }
+
+    let content_length = header
+        .lines()
+        .find(|line| line.starts_with("Content-Length: "))
+        .and_then(|line| line.split(": ").nth(1))
+        .and_then(|len| len.parse::<usize>().ok())
+        .ok_or_else(|| anyhow::anyhow!("Invalid Content-Length header"))?;
+
+    let mut content = vec![0; content_length];
+    reader.read_exact(&mut content).await?;
+    let response = serde_json::from_slice(&content)?;
+    println!("Received response: {}", response);
+    Ok(response)
+}
+
+/// Reads messages until the response to request `id` arrives.
+///
+/// Returns the response together with the server notifications received before it.
+pub async fn read_response(
+    reader: &mut ClientReader,
+    id: u64,
+) -> Result<(serde_json::Value, Vec<serde_json::Value>), anyhow::Error> {
+    let mut notifications = Vec::new();
+    loop {
+        let message = read_message(reader).await?;
+        if message.get("method").is_some() {
+            notifications.push(message);
+        } else if message["id"] == id {
+            return Ok((message, notifications));
+        }
+    }
+}
+
+/// Reads messages until a notification with the given method arrives.
+pub async fn read_notification(
+    reader: &mut ClientReader,
+    method: &str,
+) -> Result<serde_json::Value, anyhow::Error> {
+    loop {
+        let message = read_message(reader).await?;
+        if message["method"] == method {
+            return Ok(message);
+        }
+    }
+}
+
+/// Sends `initialize` and `initialized`, returning the initialize result.
+pub async fn initialize(
+    writer: &mut ClientWriter,
+    reader: &mut ClientReader,
+    params: serde_json::Value,
+) -> Result<serde_json::Value, anyhow::Error> {
+    send_message(
+        writer,
+        serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "initialize",
+            "params": params
+        }),
+    )
+    .await?;
+    let (response, _) = read_response(reader, 1).await?;
+
+    send_message(
+        writer,
+        serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "initialized",
+            "params": {}
+        }),
+    )
+    .await?;
+
+    Ok(response)
+}
+
+/// Starts a server with `init`, initializes it with `params`, opens `uri` with `text` and
+/// requests completion at `line`:`character` as request 2.
+///
+/// Returns the client's streams, for follow-up requests, and the completion response.
+pub async fn open_and_complete(
+    init: impl FnOnce(Backend) -> Backend + Send + 'static,
+    params: serde_json::Value,
+    uri: &str,
+    text: &str,
+    line: u32,
+    character: u32,
+) -> Result<(ClientWriter, ClientReader, serde_json::Value), anyhow::Error> {
+    let (mut writer, mut reader) = setup_server_with(init);
+    initialize(&mut writer, &mut reader, params).await?;
+
+    send_message(
+        &mut writer,
+        serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": {
+                "textDocument": {
+                    "uri": uri,
+                    "languageId": "starlark",
+                    "version": 1,
+                    "text": text
+                }
+            }
+        }),
+    )
+    .await?;
+
+    send_message(
+        &mut writer,
+        serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 2,
+            "method": "textDocument/completion",
+            "params": {
+                "textDocument": { "uri": uri },
+                "position": { "line": line, "character": character }
+            }
+        }),
+    )
+    .await?;
+    let (response, _) = read_response(&mut reader, 2).await?;
+
+    Ok((writer, reader, response))
+}
@@ -1,3 +1,5 @@
+mod common;
+
 use std::sync::Arc;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::sync::RwLock;
@@ -149,9 +151,9 @@ async fn test_completion_with_colon() -> Result<(), anyhow::Error> {
     println!("Completion response: {}", response);
 
     assert_eq!(response["id"], 2);
-    assert!(response["result"].is_array() || response["result"].is_null());
-    if response["result"].is_array() {
-        let items = response["result"].as_array().unwrap();
+    assert!(response["result"]["items"].is_array() || response["result"].is_null());
+    if response["result"]["items"].is_array() {
+        let items = response["result"]["items"].as_array().unwrap();
         assert_eq!(items.len(), 2);
         assert!(items.iter().any(|item| item["label"] == "//a:inside_a"));
         assert!(items.iter().any(|item| item["label"] == "//a:inside_b"));
@@ -239,9 +241,9 @@ async fn test_completion_with_double_slash() -> Result<(), anyhow::Error> {
     println!("Completion response: {}", response);
 
     assert_eq!(response["id"], 2);
-    assert!(response["result"].is_array() || response["result"].is_null());
-    if response["result"].is_array() {
-        let items = response["result"].as_array().unwrap();
+    assert!(response["result"]["items"].is_array() || response["result"].is_null());
+    if response["result"]["items"].is_array() {
+        let items = response["result"]["items"].as_array().unwrap();
         assert_eq!(items.len(), 2);
         assert!(items.iter().any(|item| item["label"] == "//a/b:target1"));
         assert!(items.iter().any(|item| item["label"] == "//a/c:target2"));
@@ -313,9 +315,9 @@ async fn test_completion_with_existing_path() -> Result<(), anyhow::Error> {
     println!("Completion response: {}", response);
 
     assert_eq!(response["id"], 2);
-    assert!(response["result"].is_array() || response["result"].is_null());
-    if response["result"].is_array() {
-        let items = response["result"].as_array().unwrap();
+    assert!(response["result"]["items"].is_array() || response["result"].is_null());
+    if response["result"]["items"].is_array() {
+        let items = response["result"]["items"].as_array().unwrap();
         assert_eq!(items.len(), 2);
         assert!(items.iter().any(|item| item["label"] == "//a/b:target1"));
         assert!(items.iter().any(|item| item["label"] == "//a/b:target2"));
@@ -323,3 +325,1072 @@ async fn test_completion_with_existing_path() -> Result<(), anyhow::Error> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_completion_loaded_macro_at_statement_level() -> Result<(), anyhow::Error> {
+    let (mut stdin, mut stdout) = common::setup_server();
+
+    common::initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({ "capabilities": {}, "processId": 1 }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": "file:///test/BUILD",
+                "languageId": "starlark",
+                "version": 1,
+                "text": "load(\":defs.bzl\", \"my_rule\")\n\nmy_"
+            }
+        }
+    });
+    common::send_message(&mut stdin, did_open_params).await?;
+
+    let completion_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "textDocument/completion",
+        "params": {
+            "textDocument": { "uri": "file:///test/BUILD" },
+            "position": { "line": 2, "character": 3 }
+        }
+    });
+    common::send_message(&mut stdin, completion_params).await?;
+
+    let (response, _) = common::read_response(&mut stdout, 2).await?;
+    let items = response["result"].as_array().unwrap();
+    let item = items
+        .iter()
+        .find(|item| item["label"] == "my_rule")
+        .expect("my_rule should be offered");
+    assert_eq!(item["kind"], 3); // CompletionItemKind::FUNCTION
+    assert!(item["detail"].as_str().unwrap().contains(":defs.bzl"));
+    // The client didn't declare snippet support
+    assert!(item["insertTextFormat"].is_null());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_completion_rule_snippet() -> Result<(), anyhow::Error> {
+    let (mut stdin, mut stdout) = common::setup_server();
+
+    common::initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {
+                "textDocument": {
+                    "completion": { "completionItem": { "snippetSupport": true } }
+                }
+            },
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": "file:///test/BUILD",
+                "languageId": "starlark",
+                "version": 1,
+                "text": "cc_test(\n    name = \"a_test\",\n)\n\ncc_"
+            }
+        }
+    });
+    common::send_message(&mut stdin, did_open_params).await?;
+
+    let completion_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "textDocument/completion",
+        "params": {
+            "textDocument": { "uri": "file:///test/BUILD" },
+            "position": { "line": 4, "character": 3 }
+        }
+    });
+    common::send_message(&mut stdin, completion_params).await?;
+
+    let (response, _) = common::read_response(&mut stdout, 2).await?;
+    let items = response["result"].as_array().unwrap();
+    let item = items
+        .iter()
+        .find(|item| item["label"] == "cc_test")
+        .expect("cc_test should be offered");
+    assert_eq!(item["insertTextFormat"], 2); // InsertTextFormat::SNIPPET
+    assert_eq!(
+        item["insertText"],
+        "cc_test(\n    name = \"${1:name}\",\n    srcs = [$2],\n    deps = [$3],\n)$0"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_completion_common_tags() -> Result<(), anyhow::Error> {
+    let (mut stdin, mut stdout) = common::setup_server();
+
+    common::initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({ "capabilities": {}, "processId": 1 }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": "file:///test/BUILD",
+                "languageId": "starlark",
+                "version": 1,
+                "text": "cc_test(\n    name = \"test\",\n    tags = [],\n)\n"
+            }
+        }
+    });
+    common::send_message(&mut stdin, did_open_params).await?;
+
+    let completion_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "textDocument/completion",
+        "params": {
+            "textDocument": { "uri": "file:///test/BUILD" },
+            "position": { "line": 2, "character": 12 }
+        }
+    });
+    common::send_message(&mut stdin, completion_params).await?;
+
+    let (response, _) = common::read_response(&mut stdout, 2).await?;
+    let items = response["result"].as_array().unwrap();
+    let item = items
+        .iter()
+        .find(|item| item["label"] == "manual")
+        .expect("manual should be offered");
+    assert_eq!(item["insertText"], "\"manual\"");
+    assert!(item["documentation"].as_str().is_some());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_completion_exported_files_in_workspace() -> Result<(), anyhow::Error> {
+    let workspace = tempfile::TempDir::new()?;
+    std::fs::write(workspace.path().join("WORKSPACE"), "")?;
+    std::fs::create_dir_all(workspace.path().join("lib"))?;
+    std::fs::write(
+        workspace.path().join("lib/BUILD"),
+        "exports_files([\"data.txt\"])\n",
+    )?;
+    let app_build = "cc_library(\n    name = \"app\",\n    deps = [\"//lib:\"],\n)\n";
+    std::fs::create_dir_all(workspace.path().join("app"))?;
+    std::fs::write(workspace.path().join("app/BUILD"), app_build)?;
+
+    let workspace_uri = url::Url::from_file_path(workspace.path()).unwrap();
+    let app_uri = url::Url::from_file_path(workspace.path().join("app/BUILD")).unwrap();
+    let (mut stdin, mut stdout) = common::setup_server();
+
+    common::initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "workspaceFolders": [{ "uri": workspace_uri, "name": "workspace" }],
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": app_uri,
+                "languageId": "starlark",
+                "version": 1,
+                "text": app_build
+            }
+        }
+    });
+    common::send_message(&mut stdin, did_open_params).await?;
+
+    let completion_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "textDocument/completion",
+        "params": {
+            "textDocument": { "uri": app_uri },
+            "position": { "line": 2, "character": 19 }
+        }
+    });
+    common::send_message(&mut stdin, completion_params).await?;
+
+    let (response, _) = common::read_response(&mut stdout, 2).await?;
+    assert_eq!(response["result"]["isIncomplete"], false);
+    let items = response["result"]["items"].as_array().unwrap();
+    assert!(
+        items.iter().any(|item| item["label"] == "//lib:data.txt"),
+        "//lib:data.txt should be offered in {:?}",
+        items
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_completion_sees_targets_added_by_did_change() -> Result<(), anyhow::Error> {
+    let workspace = tempfile::TempDir::new()?;
+    std::fs::write(workspace.path().join("WORKSPACE"), "")?;
+    let lib_build = "cc_library(\n    name = \"old\",\n)\n";
+    std::fs::create_dir_all(workspace.path().join("lib"))?;
+    std::fs::write(workspace.path().join("lib/BUILD"), lib_build)?;
+    let app_build = "cc_library(\n    name = \"app\",\n    deps = [\"//lib:\"],\n)\n";
+    std::fs::create_dir_all(workspace.path().join("app"))?;
+    std::fs::write(workspace.path().join("app/BUILD"), app_build)?;
+
+    let workspace_uri = url::Url::from_file_path(workspace.path()).unwrap();
+    let lib_uri = url::Url::from_file_path(workspace.path().join("lib/BUILD")).unwrap();
+    let app_uri = url::Url::from_file_path(workspace.path().join("app/BUILD")).unwrap();
+    let (mut stdin, mut stdout) = common::setup_server();
+
+    common::initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "workspaceFolders": [{ "uri": workspace_uri, "name": "workspace" }],
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    for (uri, text) in [(&lib_uri, lib_build), (&app_uri, app_build)] {
+        let did_open_params = serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": {
+                "textDocument": {
+                    "uri": uri,
+                    "languageId": "starlark",
+                    "version": 1,
+                    "text": text
+                }
+            }
+        });
+        common::send_message(&mut stdin, did_open_params).await?;
+    }
+
+    // The edit is never saved, so only the open document knows about the new target
+    let did_change_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didChange",
+        "params": {
+            "textDocument": { "uri": lib_uri, "version": 2 },
+            "contentChanges": [{
+                "text": "cc_library(\n    name = \"new\",\n)\n"
+            }]
+        }
+    });
+    common::send_message(&mut stdin, did_change_params).await?;
+    // The server asks for a refresh once it has processed the change
+    common::read_notification(&mut stdout, "workspace/semanticTokens/refresh").await?;
+
+    let completion_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "textDocument/completion",
+        "params": {
+            "textDocument": { "uri": app_uri },
+            "position": { "line": 2, "character": 19 }
+        }
+    });
+    common::send_message(&mut stdin, completion_params).await?;
+
+    let (response, _) = common::read_response(&mut stdout, 2).await?;
+    assert_eq!(response["result"]["isIncomplete"], false);
+    let items = response["result"]["items"].as_array().unwrap();
+    assert!(
+        items.iter().any(|item| item["label"] == "//lib:new"),
+        "//lib:new should be offered in {:?}",
+        items
+    );
+    assert!(
+        !items.iter().any(|item| item["label"] == "//lib:old"),
+        "//lib:old was removed by the edit but is offered in {:?}",
+        items
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_completion_visibility_offers_package_groups() -> Result<(), anyhow::Error> {
+    let workspace = tempfile::TempDir::new()?;
+    std::fs::write(workspace.path().join("WORKSPACE"), "")?;
+    std::fs::create_dir_all(workspace.path().join("groups"))?;
+    std::fs::write(
+        workspace.path().join("groups/BUILD"),
+        "package_group(\n    name = \"friends\",\n    packages = [\"//app/...\"],\n)\n",
+    )?;
+    let app_build = "cc_library(\n    name = \"app\",\n    visibility = [],\n)\n";
+    std::fs::create_dir_all(workspace.path().join("app"))?;
+    std::fs::write(workspace.path().join("app/BUILD"), app_build)?;
+
+    let workspace_uri = url::Url::from_file_path(workspace.path()).unwrap();
+    let app_uri = url::Url::from_file_path(workspace.path().join("app/BUILD")).unwrap();
+    let (mut stdin, mut stdout) = common::setup_server();
+
+    common::initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "workspaceFolders": [{ "uri": workspace_uri, "name": "workspace" }],
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": app_uri,
+                "languageId": "starlark",
+                "version": 1,
+                "text": app_build
+            }
+        }
+    });
+    common::send_message(&mut stdin, did_open_params).await?;
+
+    let completion_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "textDocument/completion",
+        "params": {
+            "textDocument": { "uri": app_uri },
+            "position": { "line": 2, "character": 18 }
+        }
+    });
+    common::send_message(&mut stdin, completion_params).await?;
+
+    let (response, _) = common::read_response(&mut stdout, 2).await?;
+    let items = response["result"].as_array().unwrap();
+    let item = items
+        .iter()
+        .find(|item| item["label"] == "//groups:friends")
+        .expect("//groups:friends should be offered");
+    assert_eq!(item["detail"], "Package group");
+    assert_eq!(item["insertText"], "\"//groups:friends\"");
+    assert!(items
+        .iter()
+        .any(|item| item["label"] == "//visibility:public"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_completion_list_is_incomplete_when_cut() -> Result<(), anyhow::Error> {
+    let workspace = tempfile::TempDir::new()?;
+    std::fs::write(workspace.path().join("WORKSPACE"), "")?;
+    std::fs::create_dir_all(workspace.path().join("lib"))?;
+    std::fs::write(
+        workspace.path().join("lib/BUILD"),
+        "cc_library(name = \"c\")\ncc_library(name = \"a\")\ncc_library(name = \"b\")\n",
+    )?;
+    let app_build = "cc_library(\n    name = \"app\",\n    deps = [\"//lib:\"],\n)\n";
+    std::fs::create_dir_all(workspace.path().join("app"))?;
+    std::fs::write(workspace.path().join("app/BUILD"), app_build)?;
+
+    let workspace_uri = url::Url::from_file_path(workspace.path()).unwrap();
+    let app_uri = url::Url::from_file_path(workspace.path().join("app/BUILD")).unwrap();
+    let (mut stdin, mut stdout) = common::setup_server();
+
+    common::initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "workspaceFolders": [{ "uri": workspace_uri, "name": "workspace" }],
+            "initializationOptions": { "max_completion_items": 2 },
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": app_uri,
+                "languageId": "starlark",
+                "version": 1,
+                "text": app_build
+            }
+        }
+    });
+    common::send_message(&mut stdin, did_open_params).await?;
+
+    let completion_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "textDocument/completion",
+        "params": {
+            "textDocument": { "uri": app_uri },
+            "position": { "line": 2, "character": 19 }
+        }
+    });
+    common::send_message(&mut stdin, completion_params).await?;
+
+    let (response, _) = common::read_response(&mut stdout, 2).await?;
+    assert_eq!(response["result"]["isIncomplete"], true);
+    let labels: Vec<&str> = response["result"]["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["label"].as_str().unwrap())
+        .collect();
+    assert_eq!(labels, ["//lib:a", "//lib:b"]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_completion_boolean_attribute_values() -> Result<(), anyhow::Error> {
+    let (mut stdin, mut stdout) = common::setup_server();
+
+    common::initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({ "capabilities": {}, "processId": 1 }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": "file:///test/BUILD",
+                "languageId": "starlark",
+                "version": 1,
+                "text": "cc_library(\n    name = \"lib\",\n    testonly = \n)\n"
+            }
+        }
+    });
+    common::send_message(&mut stdin, did_open_params).await?;
+
+    let completion_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "textDocument/completion",
+        "params": {
+            "textDocument": { "uri": "file:///test/BUILD" },
+            "position": { "line": 2, "character": 15 }
+        }
+    });
+    common::send_message(&mut stdin, completion_params).await?;
+
+    let (response, _) = common::read_response(&mut stdout, 2).await?;
+    let labels: Vec<&str> = response["result"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["label"].as_str().unwrap())
+        .collect();
+    assert_eq!(labels, ["True", "False"]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_completion_recursive_wildcard_offers_subpackages() -> Result<(), anyhow::Error> {
+    let workspace = tempfile::TempDir::new()?;
+    std::fs::write(workspace.path().join("WORKSPACE"), "")?;
+    for package in ["lib", "lib/sub", "library"] {
+        std::fs::create_dir_all(workspace.path().join(package))?;
+        std::fs::write(
+            workspace.path().join(package).join("BUILD"),
+            "cc_library(name = \"x\")\n",
+        )?;
+    }
+    let app_build = "cc_library(\n    name = \"app\",\n    deps = [\"//lib/...\"],\n)\n";
+    std::fs::create_dir_all(workspace.path().join("app"))?;
+    std::fs::write(workspace.path().join("app/BUILD"), app_build)?;
+
+    let workspace_uri = url::Url::from_file_path(workspace.path()).unwrap();
+    let app_uri = url::Url::from_file_path(workspace.path().join("app/BUILD")).unwrap();
+    let (_, _, response) = common::open_and_complete(
+        |backend| backend,
+        serde_json::json!({
+            "capabilities": {},
+            "workspaceFolders": [{ "uri": workspace_uri, "name": "workspace" }],
+            "processId": 1
+        }),
+        app_uri.as_str(),
+        app_build,
+        2,
+        22,
+    )
+    .await?;
+    let items = response["result"]["items"].as_array().unwrap();
+    let labels: Vec<&str> = items
+        .iter()
+        .map(|item| item["label"].as_str().unwrap())
+        .collect();
+    assert_eq!(labels, vec!["//lib/...", "//lib/sub/..."]);
+    assert_eq!(
+        items[1]["textEdit"]["range"],
+        serde_json::json!({
+            "start": { "line": 2, "character": 13 },
+            "end": { "line": 2, "character": 22 }
+        })
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_completion_attributes_from_rule_schema() -> Result<(), anyhow::Error> {
+    let workspace = tempfile::TempDir::new()?;
+    std::fs::write(workspace.path().join("WORKSPACE"), "")?;
+    std::fs::write(
+        workspace.path().join("rules.json"),
+        r#"{ "my_rule": { "attributes": { "config": { "doc": "The config file to apply" } } } }"#,
+    )?;
+    let app_build = "my_rule(\n    name = \"app\",\n    \n)\n";
+    std::fs::create_dir_all(workspace.path().join("app"))?;
+    std::fs::write(workspace.path().join("app/BUILD"), app_build)?;
+
+    let workspace_uri = url::Url::from_file_path(workspace.path()).unwrap();
+    let app_uri = url::Url::from_file_path(workspace.path().join("app/BUILD")).unwrap();
+    let (_, _, response) = common::open_and_complete(
+        |backend| backend,
+        serde_json::json!({
+            "capabilities": {},
+            "workspaceFolders": [{ "uri": workspace_uri, "name": "workspace" }],
+            "initializationOptions": { "ruleSchemaPath": "rules.json" },
+            "processId": 1
+        }),
+        app_uri.as_str(),
+        app_build,
+        2,
+        4,
+    )
+    .await?;
+    let items = response["result"].as_array().unwrap();
+    let config = items
+        .iter()
+        .find(|item| item["label"] == "config")
+        .expect("config should be offered");
+    assert_eq!(config["insertText"], "config = ");
+    assert_eq!(config["documentation"], "The config file to apply");
+    assert!(
+        items.iter().any(|item| item["label"] == "visibility"),
+        "visibility should be offered in {:?}",
+        items
+    );
+    assert!(
+        !items.iter().any(|item| item["label"] == "name"),
+        "name is already set in {:?}",
+        items
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_completion_lists_local_targets_first() -> Result<(), anyhow::Error> {
+    let workspace = tempfile::TempDir::new()?;
+    std::fs::write(workspace.path().join("WORKSPACE"), "")?;
+    std::fs::create_dir_all(workspace.path().join("lib"))?;
+    std::fs::write(
+        workspace.path().join("lib/BUILD"),
+        "cc_library(name = \"alpha\")\n",
+    )?;
+    let app_build = "cc_library(\n    name = \"zeta\",\n)\n\ncc_binary(\n    name = \"app\",\n    deps = [\"//\"],\n)\n";
+    std::fs::create_dir_all(workspace.path().join("app"))?;
+    std::fs::write(workspace.path().join("app/BUILD"), app_build)?;
+
+    let workspace_uri = url::Url::from_file_path(workspace.path()).unwrap();
+    let app_uri = url::Url::from_file_path(workspace.path().join("app/BUILD")).unwrap();
+    let (_, _, response) = common::open_and_complete(
+        |backend| backend,
+        serde_json::json!({
+            "capabilities": {},
+            "workspaceFolders": [{ "uri": workspace_uri, "name": "workspace" }],
+            "processId": 1
+        }),
+        app_uri.as_str(),
+        app_build,
+        6,
+        15,
+    )
+    .await?;
+    let items = response["result"]["items"].as_array().unwrap();
+    let sort_texts: Vec<(&str, &str)> = items
+        .iter()
+        .map(|item| {
+            (
+                item["label"].as_str().unwrap(),
+                item["sortText"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        sort_texts,
+        vec![
+            ("//app:app", "0//app:app"),
+            ("//app:zeta", "0//app:zeta"),
+            ("//lib:alpha", "1//lib:alpha"),
+        ]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_completion_name_offers_directory_name() -> Result<(), anyhow::Error> {
+    let workspace = tempfile::TempDir::new()?;
+    std::fs::write(workspace.path().join("WORKSPACE"), "")?;
+    let build = "cc_library(\n    name = \"bar_lib\",\n)\n\ncc_binary(\n    name = \"\",\n)\n";
+    std::fs::create_dir_all(workspace.path().join("foo/bar"))?;
+    std::fs::write(workspace.path().join("foo/bar/BUILD"), build)?;
+
+    let workspace_uri = url::Url::from_file_path(workspace.path()).unwrap();
+    let build_uri = url::Url::from_file_path(workspace.path().join("foo/bar/BUILD")).unwrap();
+    let (_, _, response) = common::open_and_complete(
+        |backend| backend,
+        serde_json::json!({
+            "capabilities": {},
+            "workspaceFolders": [{ "uri": workspace_uri, "name": "workspace" }],
+            "processId": 1
+        }),
+        build_uri.as_str(),
+        build,
+        5,
+        12,
+    )
+    .await?;
+    let labels: Vec<&str> = response["result"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["label"].as_str().unwrap())
+        .collect();
+    // bar_lib is declared already
+    assert_eq!(labels, vec!["bar", "bar_main", "bar_test"]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_completion_offers_each_target_once() -> Result<(), anyhow::Error> {
+    let workspace = tempfile::TempDir::new()?;
+    std::fs::write(workspace.path().join("WORKSPACE"), "")?;
+    std::fs::create_dir_all(workspace.path().join("lib/sub"))?;
+    // `dup` is declared twice, and `//lib` prefixes the targets of both packages
+    std::fs::write(
+        workspace.path().join("lib/BUILD"),
+        "cc_library(name = \"dup\")\n\ncc_library(name = \"dup\")\n\ncc_library(name = \"other\")\n",
+    )?;
+    std::fs::write(
+        workspace.path().join("lib/sub/BUILD"),
+        "cc_library(name = \"dup\")\n",
+    )?;
+    let app_build = "cc_binary(\n    name = \"app\",\n    deps = [\"//lib\"],\n)\n";
+    std::fs::create_dir_all(workspace.path().join("app"))?;
+    std::fs::write(workspace.path().join("app/BUILD"), app_build)?;
+
+    let workspace_uri = url::Url::from_file_path(workspace.path()).unwrap();
+    let app_uri = url::Url::from_file_path(workspace.path().join("app/BUILD")).unwrap();
+    let (_, _, response) = common::open_and_complete(
+        |backend| backend,
+        serde_json::json!({
+            "capabilities": {},
+            "workspaceFolders": [{ "uri": workspace_uri, "name": "workspace" }],
+            "processId": 1
+        }),
+        app_uri.as_str(),
+        app_build,
+        2,
+        18,
+    )
+    .await?;
+    let mut labels: Vec<&str> = response["result"]["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["label"].as_str().unwrap())
+        .collect();
+    labels.sort();
+    assert_eq!(labels, ["//lib/sub:dup", "//lib:dup", "//lib:other"]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_completion_srcs_offers_generated_outputs() -> Result<(), anyhow::Error> {
+    let dir = tempfile::TempDir::new()?;
+    std::fs::write(dir.path().join("main.cc"), "")?;
+    let text = "genrule(\n    name = \"gen\",\n    outs = [\"gen.cc\"],\n    cmd = \"touch $@\",\n)\n\ncc_library(\n    name = \"lib\",\n    srcs = [\"\"],\n)\n";
+    std::fs::write(dir.path().join("BUILD"), text)?;
+    let uri = url::Url::from_file_path(dir.path().join("BUILD")).unwrap();
+
+    let (_, _, response) = common::open_and_complete(
+        |backend| backend,
+        serde_json::json!({ "capabilities": {}, "processId": 1 }),
+        uri.as_str(),
+        text,
+        8,
+        13,
+    )
+    .await?;
+    let items = response["result"].as_array().unwrap();
+    let generated = items
+        .iter()
+        .find(|item| item["label"] == "gen.cc")
+        .expect("gen.cc is offered");
+    assert_eq!(generated["detail"], "Generated by :gen");
+    assert_eq!(generated["insertText"], "gen.cc");
+    assert!(items.iter().any(|item| item["label"] == "main.cc"));
+    assert!(!items.iter().any(|item| item["label"] == "BUILD"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_completion_srcs_leaves_out_globbed_files() -> Result<(), anyhow::Error> {
+    let dir = tempfile::TempDir::new()?;
+    for file in ["lib.cc", "lib_test.cc", "util.h"] {
+        std::fs::write(dir.path().join(file), "")?;
+    }
+    let text = "cc_library(\n    name = \"lib\",\n    srcs = glob([\"*.cc\"], exclude = [\"*_test.cc\"]) + [],\n)\n\ncc_library(\n    name = \"other\",\n    srcs = [],\n)\n";
+    std::fs::write(dir.path().join("BUILD"), text)?;
+    let uri = url::Url::from_file_path(dir.path().join("BUILD")).unwrap();
+
+    let mut labels = Vec::new();
+    for (line, character) in [(2, 54), (7, 12)] {
+        let (_, _, response) = common::open_and_complete(
+            |backend| backend,
+            serde_json::json!({ "capabilities": {}, "processId": 1 }),
+            uri.as_str(),
+            text,
+            line,
+            character,
+        )
+        .await?;
+        labels.push(completion_labels(&response));
+    }
+
+    // The excluded test source is still offered for the globbing target
+    assert_eq!(labels[0], ["lib_test.cc", "util.h"]);
+    assert_eq!(labels[1], ["lib.cc", "lib_test.cc", "util.h"]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_completion_character_past_line_end() -> Result<(), anyhow::Error> {
+    let (mut stdin, mut stdout) = common::setup_server();
+    common::initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({ "capabilities": {}, "processId": 1 }),
+    )
+    .await?;
+
+    // A multi-byte character before the cursor, and an unterminated last line with the
+    // cursor far past its end
+    let documents = [
+        (
+            2,
+            "file:///test/a/BUILD",
+            "cc_library(\n    name = \"a\",\n    deps = [\"//ä\"],\n)\n",
+            16,
+        ),
+        (
+            3,
+            "file:///test/b/BUILD",
+            "cc_library(\n    name = \"b\",\n    deps = [\"//",
+            100,
+        ),
+    ];
+    for (id, uri, text, character) in documents {
+        let did_open_params = serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": {
+                "textDocument": {
+                    "uri": uri,
+                    "languageId": "starlark",
+                    "version": 1,
+                    "text": text
+                }
+            }
+        });
+        common::send_message(&mut stdin, did_open_params).await?;
+
+        let completion_params = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "textDocument/completion",
+            "params": {
+                "textDocument": { "uri": uri },
+                "position": { "line": 2, "character": character }
+            }
+        });
+        common::send_message(&mut stdin, completion_params).await?;
+
+        let (response, _) = common::read_response(&mut stdout, id).await?;
+        assert!(response.get("error").is_none(), "{}", response);
+    }
+
+    Ok(())
+}
+
+/// Completes `//lib:` in a workspace with a `cc_library` in `lib`, returning the item
+/// offered for it along with the client's streams.
+async fn complete_workspace_target(
+    capabilities: serde_json::Value,
+) -> Result<
+    (
+        common::ClientWriter,
+        common::ClientReader,
+        serde_json::Value,
+        tempfile::TempDir,
+    ),
+    anyhow::Error,
+> {
+    let workspace = tempfile::TempDir::new()?;
+    std::fs::write(workspace.path().join("WORKSPACE"), "")?;
+    std::fs::create_dir_all(workspace.path().join("lib"))?;
+    std::fs::write(
+        workspace.path().join("lib/BUILD"),
+        "cc_library(name = \"core\")\n",
+    )?;
+    let app_build = "cc_library(\n    name = \"app\",\n    deps = [\"//lib:\"],\n)\n";
+    std::fs::create_dir_all(workspace.path().join("app"))?;
+    std::fs::write(workspace.path().join("app/BUILD"), app_build)?;
+
+    let workspace_uri = url::Url::from_file_path(workspace.path()).unwrap();
+    let app_uri = url::Url::from_file_path(workspace.path().join("app/BUILD")).unwrap();
+    let (stdin, stdout, response) = common::open_and_complete(
+        |backend| backend,
+        serde_json::json!({
+            "capabilities": capabilities,
+            "workspaceFolders": [{ "uri": workspace_uri, "name": "workspace" }],
+            "processId": 1
+        }),
+        app_uri.as_str(),
+        app_build,
+        2,
+        19,
+    )
+    .await?;
+    let item = response["result"]["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|item| item["label"] == "//lib:core")
+        .expect("//lib:core should be offered")
+        .clone();
+    Ok((stdin, stdout, item, workspace))
+}
+
+#[tokio::test]
+async fn test_completion_resolve_fills_target_documentation() -> Result<(), anyhow::Error> {
+    let (mut stdin, mut stdout, item, _workspace) = complete_workspace_target(serde_json::json!({
+        "textDocument": {
+            "completion": {
+                "completionItem": {
+                    "resolveSupport": { "properties": ["documentation", "detail"] }
+                }
+            }
+        }
+    }))
+    .await?;
+    assert!(item.get("detail").is_none(), "{}", item);
+    assert!(item.get("documentation").is_none(), "{}", item);
+
+    let resolve_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 3,
+        "method": "completionItem/resolve",
+        "params": item
+    });
+    common::send_message(&mut stdin, resolve_params).await?;
+
+    let (response, _) = common::read_response(&mut stdout, 3).await?;
+    let resolved = &response["result"];
+    assert_eq!(resolved["detail"], "cc_library");
+    assert_eq!(
+        resolved["documentation"],
+        "Bazel target: //lib:core\n\nDeclared in lib/BUILD"
+    );
+    assert!(resolved.get("data").is_none());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_completion_target_documentation_without_resolve_support() -> Result<(), anyhow::Error>
+{
+    let (_, _, item, _workspace) = complete_workspace_target(serde_json::json!({})).await?;
+
+    assert_eq!(item["detail"], "cc_library");
+    assert_eq!(
+        item["documentation"],
+        "Bazel target: //lib:core\n\nDeclared in lib/BUILD"
+    );
+    assert!(item.get("data").is_none());
+
+    Ok(())
+}
+
+/// Completes `//a/b:` in a BUILD file outside every workspace folder, with a seeded
+/// index, returning the labels offered.
+async fn complete_outside_workspace(
+    initialization_options: serde_json::Value,
+) -> Result<Vec<String>, anyhow::Error> {
+    let text = "cc_library(\n    name = \"app\",\n    deps = [\"//a/b:\"],\n)\n";
+    let (_, _, response) = common::open_and_complete(
+        |backend| {
+            backend.target_trie.try_write().unwrap().insert_target(
+                "//a/b:target1",
+                RuleInfo::new("target1".into(), "//a/b:target1".into()),
+            );
+            backend
+        },
+        serde_json::json!({
+            "capabilities": {},
+            "initializationOptions": initialization_options,
+            "processId": 1
+        }),
+        "file:///elsewhere/BUILD",
+        text,
+        2,
+        19,
+    )
+    .await?;
+
+    Ok(completion_labels(&response))
+}
+
+#[tokio::test]
+async fn test_completion_from_index_outside_workspace() -> Result<(), anyhow::Error> {
+    let labels = complete_outside_workspace(serde_json::json!({
+        "index_completion_outside_workspace": true
+    }))
+    .await?;
+    assert_eq!(labels, ["//a/b:target1"]);
+
+    // Off by default, completing from the file alone
+    let labels = complete_outside_workspace(serde_json::json!({})).await?;
+    assert!(labels.is_empty(), "{:?}", labels);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_removed_workspace_folder_targets_no_longer_complete() -> Result<(), anyhow::Error> {
+    let lib_workspace = tempfile::TempDir::new()?;
+    std::fs::write(lib_workspace.path().join("WORKSPACE"), "")?;
+    std::fs::create_dir_all(lib_workspace.path().join("lib"))?;
+    std::fs::write(
+        lib_workspace.path().join("lib/BUILD"),
+        "cc_library(name = \"core\")\n",
+    )?;
+    let app_workspace = tempfile::TempDir::new()?;
+    std::fs::write(app_workspace.path().join("WORKSPACE"), "")?;
+    let app_build = "cc_library(\n    name = \"app\",\n    deps = [\"//lib:\"],\n)\n";
+    std::fs::write(app_workspace.path().join("BUILD"), app_build)?;
+
+    let lib_workspace_uri = url::Url::from_file_path(lib_workspace.path()).unwrap();
+    let app_workspace_uri = url::Url::from_file_path(app_workspace.path()).unwrap();
+    let app_uri = url::Url::from_file_path(app_workspace.path().join("BUILD")).unwrap();
+    let (mut stdin, mut stdout, response) = common::open_and_complete(
+        |backend| backend,
+        serde_json::json!({
+            "capabilities": {},
+            "workspaceFolders": [
+                { "uri": lib_workspace_uri, "name": "lib" },
+                { "uri": app_workspace_uri, "name": "app" }
+            ],
+            "processId": 1
+        }),
+        app_uri.as_str(),
+        app_build,
+        2,
+        19,
+    )
+    .await?;
+    let labels = completion_labels(&response);
+    assert!(labels.contains(&"//lib:core".to_string()), "{:?}", labels);
+
+    // The folder goes away together with its files on disk
+    std::fs::remove_dir_all(lib_workspace.path().join("lib"))?;
+    let did_change_folders_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "workspace/didChangeWorkspaceFolders",
+        "params": {
+            "event": {
+                "added": [],
+                "removed": [{ "uri": lib_workspace_uri, "name": "lib" }]
+            }
+        }
+    });
+    common::send_message(&mut stdin, did_change_folders_params).await?;
+
+    let labels = complete_labels(&mut stdin, &mut stdout, &app_uri, 3).await?;
+    assert!(!labels.contains(&"//lib:core".to_string()), "{:?}", labels);
+
+    Ok(())
+}
+
+/// Completes `//lib:` in the deps of `uri`, returning the labels offered.
+async fn complete_labels(
+    stdin: &mut common::ClientWriter,
+    stdout: &mut common::ClientReader,
+    uri: &url::Url,
+    id: u64,
+) -> Result<Vec<String>, anyhow::Error> {
+    let completion_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": "textDocument/completion",
+        "params": {
+            "textDocument": { "uri": uri },
+            "position": { "line": 2, "character": 19 }
+        }
+    });
+    common::send_message(stdin, completion_params).await?;
+
+    let (response, _) = common::read_response(stdout, id).await?;
+    Ok(completion_labels(&response))
+}
+
+/// The labels of the items of a completion response, either a list or a `CompletionList`.
+fn completion_labels(response: &serde_json::Value) -> Vec<String> {
+    let result = &response["result"];
+    result["items"]
+        .as_array()
+        .or(result.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|item| Some(item["label"].as_str()?.to_string()))
+        .collect()
+}
@@ -0,0 +1,47 @@
+use bazel_lsp::coverage::{coverage_report_path, parse_line_coverage, CoverageCache};
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+#[test]
+fn test_parse_line_coverage_totals() {
+    let report =
+        "SF:a.cc\nDA:1,1\nLF:10\nLH:9\nend_of_record\nSF:b.cc\nLF:10\nLH:5\nend_of_record\n";
+    assert_eq!(parse_line_coverage(report), Some(70.0));
+}
+
+#[test]
+fn test_parse_line_coverage_from_da_lines() {
+    let report = "SF:a.cc\nDA:1,3\nDA:2,0\nDA:3,1\nDA:4,0\nend_of_record\n";
+    assert_eq!(parse_line_coverage(report), Some(50.0));
+}
+
+#[test]
+fn test_parse_line_coverage_empty_report() {
+    assert_eq!(parse_line_coverage(""), None);
+}
+
+#[test]
+fn test_coverage_report_path() {
+    assert_eq!(
+        coverage_report_path(Path::new("/ws"), "//app/tests:my_test").unwrap(),
+        Path::new("/ws/bazel-testlogs/app/tests/my_test/coverage.dat")
+    );
+}
+
+#[test]
+fn test_coverage_cache_invalidate() {
+    let workspace = TempDir::new().unwrap();
+    let report = coverage_report_path(workspace.path(), "//app:my_test").unwrap();
+    fs::create_dir_all(report.parent().unwrap()).unwrap();
+    fs::write(&report, "LF:4\nLH:1\nend_of_record\n").unwrap();
+
+    let mut cache = CoverageCache::new();
+    assert_eq!(cache.get(workspace.path(), "//app:my_test"), Some(25.0));
+
+    fs::write(&report, "LF:4\nLH:3\nend_of_record\n").unwrap();
+    assert_eq!(cache.get(workspace.path(), "//app:my_test"), Some(25.0));
+
+    cache.invalidate("//app:my_test");
+    assert_eq!(cache.get(workspace.path(), "//app:my_test"), Some(75.0));
+}
@@ -0,0 +1,105 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::{initialize, read_response, send_message, setup_server};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use tempfile::TempDir;
+
+// Records every invocation so the test can check the flags bazel was called with
+const MOCK_BAZEL: &str = r#"#!/bin/sh
+echo "$*" >> "${0%/*}/invocations"
+case "$*" in
+  build*broken*)
+    echo "ERROR: no such package 'broken'" >&2
+    exit 1
+    ;;
+  info*)
+    echo "/execroot/bazel-out/k8-dbg/bin"
+    ;;
+esac
+"#;
+
+async fn execute_debug(
+    bin_dir: &TempDir,
+    target: &str,
+) -> Result<(serde_json::Value, Vec<serde_json::Value>), anyhow::Error> {
+    let mock_bazel = bin_dir.path().join("bazel");
+    fs::write(&mock_bazel, MOCK_BAZEL)?;
+    fs::set_permissions(&mock_bazel, fs::Permissions::from_mode(0o755))?;
+
+    let (mut stdin, mut stdout) = setup_server();
+
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "initializationOptions": {
+                "bazel_env": { "PATH": bin_dir.path() }
+            },
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let execute_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "workspace/executeCommand",
+        "params": {
+            "command": "bazel.debug",
+            "arguments": [{ "target": target }]
+        }
+    });
+    send_message(&mut stdin, execute_params).await?;
+
+    read_response(&mut stdout, 2).await
+}
+
+#[tokio::test]
+async fn test_debug_builds_with_dbg_and_notifies_client() -> Result<(), anyhow::Error> {
+    let bin_dir = TempDir::new()?;
+    let (response, notifications) = execute_debug(&bin_dir, "//app:server").await?;
+
+    let program = "/execroot/bazel-out/k8-dbg/bin/app/server";
+    assert_eq!(response["result"]["target"], "//app:server");
+    assert_eq!(response["result"]["program"], program);
+
+    let debug_start = notifications
+        .iter()
+        .find(|notification| notification["method"] == "debug/start")
+        .expect("expected a debug/start notification");
+    assert_eq!(debug_start["params"]["program"], program);
+
+    let invocations = fs::read_to_string(bin_dir.path().join("invocations"))?;
+    let invocations: Vec<&str> = invocations.lines().collect();
+    assert_eq!(invocations.len(), 2);
+    assert!(invocations[0].starts_with("build "));
+    assert!(invocations[0].contains("--compilation_mode=dbg"));
+    assert!(invocations[0].ends_with("//app:server"));
+    assert!(invocations[1].starts_with("info "));
+    assert!(invocations[1].contains("--compilation_mode=dbg"));
+    assert!(invocations[1].ends_with("bazel-bin"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_debug_reports_build_failure() -> Result<(), anyhow::Error> {
+    let bin_dir = TempDir::new()?;
+    let (response, _) = execute_debug(&bin_dir, "//broken:server").await?;
+
+    assert!(response["result"].is_null());
+    assert_eq!(
+        response["error"]["message"],
+        "Failed to build //broken:server for debugging"
+    );
+
+    // No debugger is started for a binary that failed to build
+    let invocations = fs::read_to_string(bin_dir.path().join("invocations"))?;
+    assert_eq!(invocations.lines().count(), 1);
+
+    Ok(())
+}
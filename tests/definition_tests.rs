@@ -0,0 +1,153 @@
+mod common;
+
+use common::{initialize, read_response, send_message, setup_server};
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+const BUILD: &str = r#"cc_binary(
+    name = "app",
+    deps = [
+        "//lib:util",
+        "//gen:out",
+    ],
+)
+"#;
+
+const LIB_BUILD: &str = r#"load("//tools:defs.bzl", "codegen")
+
+cc_library(
+    name = "util",
+)
+"#;
+
+/// Opens the root BUILD file of `workspace` and asks for the definition of the labels
+/// on `lines` of it, one request per line.
+async fn definition(
+    workspace: &Path,
+    options: serde_json::Value,
+    lines: &[u32],
+) -> Result<Vec<serde_json::Value>, anyhow::Error> {
+    let workspace_uri = url::Url::from_file_path(workspace).unwrap();
+    let build_uri = url::Url::from_file_path(workspace.join("BUILD")).unwrap();
+    let (mut stdin, mut stdout) = setup_server();
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "workspaceFolders": [{ "uri": workspace_uri, "name": "workspace" }],
+            "initializationOptions": options,
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": build_uri,
+                "languageId": "starlark",
+                "version": 1,
+                "text": BUILD
+            }
+        }
+    });
+    send_message(&mut stdin, did_open_params).await?;
+
+    let mut results = Vec::new();
+    for (id, line) in (2..).zip(lines) {
+        let definition_params = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "textDocument/definition",
+            "params": {
+                "textDocument": { "uri": build_uri },
+                "position": { "line": line, "character": 12 }
+            }
+        });
+        send_message(&mut stdin, definition_params).await?;
+        let (response, _) = read_response(&mut stdout, id).await?;
+        results.push(response["result"].clone());
+    }
+    Ok(results)
+}
+
+fn workspace() -> Result<TempDir, anyhow::Error> {
+    let workspace = TempDir::new()?;
+    fs::write(workspace.path().join("WORKSPACE"), "")?;
+    fs::write(workspace.path().join("BUILD"), BUILD)?;
+    fs::create_dir_all(workspace.path().join("lib"))?;
+    fs::write(workspace.path().join("lib/BUILD"), LIB_BUILD)?;
+    Ok(workspace)
+}
+
+#[tokio::test]
+async fn test_definition_of_declared_target() -> Result<(), anyhow::Error> {
+    let workspace = workspace()?;
+
+    let results = definition(workspace.path(), serde_json::json!({}), &[3, 4]).await?;
+    let lib_uri = url::Url::from_file_path(workspace.path().join("lib/BUILD")).unwrap();
+    assert_eq!(
+        results[0],
+        serde_json::json!({
+            "uri": lib_uri,
+            "range": {
+                "start": { "line": 2, "character": 0 },
+                "end": { "line": 4, "character": 1 }
+            }
+        })
+    );
+    // Generated targets need the bazel query fallback, which is off by default
+    assert!(results[1].is_null());
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_definition_falls_back_to_bazel_query() -> Result<(), anyhow::Error> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let workspace = workspace()?;
+    let gen_build = workspace.path().join("gen/BUILD");
+
+    // Reports where //gen:out was generated and counts the queries
+    let bin_dir = TempDir::new()?;
+    let mock_bazel = bin_dir.path().join("bazel");
+    fs::write(
+        &mock_bazel,
+        format!(
+            "#!/bin/sh\necho \"$*\" >> \"${{0%/*}}/invocations\"\necho '{}:7:1: genrule rule //gen:out'\n",
+            gen_build.display()
+        ),
+    )?;
+    fs::set_permissions(&mock_bazel, fs::Permissions::from_mode(0o755))?;
+
+    let options = serde_json::json!({
+        "bazel_env": { "PATH": bin_dir.path() },
+        "useBazelQueryFallback": true
+    });
+    let results = definition(workspace.path(), options, &[4, 4]).await?;
+
+    let gen_uri = url::Url::from_file_path(&gen_build).unwrap();
+    let expected = serde_json::json!({
+        "uri": gen_uri,
+        "range": {
+            "start": { "line": 6, "character": 0 },
+            "end": { "line": 6, "character": 0 }
+        }
+    });
+    assert_eq!(results, [expected.clone(), expected]);
+
+    let invocations = fs::read_to_string(bin_dir.path().join("invocations"))?;
+    assert_eq!(invocations.lines().count(), 1);
+    assert!(invocations.starts_with("query "));
+    assert!(invocations
+        .trim_end()
+        .ends_with("--output=location //gen:out"));
+
+    Ok(())
+}
@@ -0,0 +1,150 @@
+mod common;
+
+use common::{initialize, read_response, send_message, setup_server};
+use std::fs;
+use tempfile::TempDir;
+
+const LIB_BUILD: &str = r#"cc_library(
+    name = "util",
+    deps = [":base"],
+)
+
+cc_library(
+    name = "base",
+)
+"#;
+
+const APP_BUILD: &str = r#"cc_binary(
+    name = "app",
+    deps = [
+        ":helper",
+        "//lib:util",
+    ],
+)
+
+cc_library(
+    name = "helper",
+    deps = select({
+        "//conditions:windows": ["//lib:base"],
+        "//conditions:default": ["//lib:util"],
+    }),
+)
+"#;
+
+async fn request_all(
+    method: &str,
+    labels: &[&str],
+) -> Result<Vec<serde_json::Value>, anyhow::Error> {
+    let workspace = TempDir::new()?;
+    fs::write(workspace.path().join("WORKSPACE"), "")?;
+    for (package, build) in [("lib", LIB_BUILD), ("app", APP_BUILD)] {
+        fs::create_dir_all(workspace.path().join(package))?;
+        fs::write(workspace.path().join(package).join("BUILD"), build)?;
+    }
+
+    let workspace_uri = url::Url::from_file_path(workspace.path()).unwrap();
+    let (mut stdin, mut stdout) = setup_server();
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "workspaceFolders": [{ "uri": workspace_uri, "name": "workspace" }],
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let mut results = Vec::new();
+    for (id, label) in (2..).zip(labels) {
+        let params = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": { "label": label }
+        });
+        send_message(&mut stdin, params).await?;
+        let (response, _) = read_response(&mut stdout, id).await?;
+        results.push(response["result"].clone());
+    }
+    Ok(results)
+}
+
+#[tokio::test]
+async fn test_direct_dependencies() -> Result<(), anyhow::Error> {
+    let results = request_all(
+        "bazel/dependencies",
+        &["//app:app", "//app:helper", "//lib:base", "//lib:missing"],
+    )
+    .await?;
+
+    assert_eq!(
+        results[0],
+        serde_json::json!(["//app:helper", "//lib:util"])
+    );
+    assert_eq!(results[1], serde_json::json!(["//lib:base", "//lib:util"]));
+    assert_eq!(results[2], serde_json::json!([]));
+    assert!(results[3].is_null());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_direct_dependents() -> Result<(), anyhow::Error> {
+    let results = request_all("bazel/dependents", &["//lib:util", "//app:app"]).await?;
+
+    assert_eq!(results[0], serde_json::json!(["//app:app", "//app:helper"]));
+    assert_eq!(results[1], serde_json::json!([]));
+
+    Ok(())
+}
+
+async fn dependents_with_vendored_package(
+    initialization_options: serde_json::Value,
+) -> Result<serde_json::Value, anyhow::Error> {
+    let workspace = TempDir::new()?;
+    fs::write(workspace.path().join("WORKSPACE"), "")?;
+    fs::create_dir_all(workspace.path().join("lib"))?;
+    fs::write(workspace.path().join("lib/BUILD"), LIB_BUILD)?;
+    fs::create_dir_all(workspace.path().join("vendor/example"))?;
+    fs::write(
+        workspace.path().join("vendor/example/BUILD"),
+        "cc_library(\n    name = \"example\",\n    deps = [\"//lib:util\"],\n)\n",
+    )?;
+
+    let workspace_uri = url::Url::from_file_path(workspace.path()).unwrap();
+    let (mut stdin, mut stdout) = setup_server();
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "initializationOptions": initialization_options,
+            "workspaceFolders": [{ "uri": workspace_uri, "name": "workspace" }],
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "bazel/dependents",
+        "params": { "label": "//lib:util" }
+    });
+    send_message(&mut stdin, params).await?;
+    let (response, _) = read_response(&mut stdout, 2).await?;
+    Ok(response["result"].clone())
+}
+
+#[tokio::test]
+async fn test_vendor_is_not_indexed_by_default() -> Result<(), anyhow::Error> {
+    let dependents = dependents_with_vendored_package(serde_json::json!({})).await?;
+    assert_eq!(dependents, serde_json::json!([]));
+
+    let dependents =
+        dependents_with_vendored_package(serde_json::json!({ "excluded_dirs": [] })).await?;
+    assert_eq!(dependents, serde_json::json!(["//vendor/example:example"]));
+
+    Ok(())
+}
@@ -0,0 +1,85 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::{initialize, read_response, send_message, setup_server};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use tempfile::TempDir;
+
+const MOCK_BAZEL: &str = r#"#!/bin/sh
+case "$*" in
+  *broken*)
+    echo "ERROR: no such package 'broken'" >&2
+    exit 7
+    ;;
+  *--output=graph*)
+    printf '%s\n' 'digraph mygraph {' '  "//app:bin"' '  "//app:bin" -> "//lib:a"' '  "//lib:a"' '}'
+    ;;
+  *--output=label_kind*)
+    printf '%s\n' 'cc_binary rule //app:bin' 'cc_library rule //lib:a'
+    ;;
+esac
+"#;
+
+async fn execute_deps(bin_dir: &TempDir, target: &str) -> Result<serde_json::Value, anyhow::Error> {
+    let mock_bazel = bin_dir.path().join("bazel");
+    fs::write(&mock_bazel, MOCK_BAZEL)?;
+    fs::set_permissions(&mock_bazel, fs::Permissions::from_mode(0o755))?;
+
+    let (mut stdin, mut stdout) = setup_server();
+
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "initializationOptions": {
+                "bazel_env": { "PATH": bin_dir.path() }
+            },
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let execute_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "workspace/executeCommand",
+        "params": {
+            "command": "bazel.deps",
+            "arguments": [{ "target": target, "depth": 2 }]
+        }
+    });
+    send_message(&mut stdin, execute_params).await?;
+
+    let (response, _) = read_response(&mut stdout, 2).await?;
+    Ok(response)
+}
+
+#[tokio::test]
+async fn test_deps_command_returns_tree() -> Result<(), anyhow::Error> {
+    let bin_dir = TempDir::new()?;
+    let response = execute_deps(&bin_dir, "//app:bin").await?;
+
+    let tree = &response["result"];
+    assert_eq!(tree["label"], "//app:bin");
+    assert_eq!(tree["kind"], "cc_binary");
+    assert_eq!(tree["deps"][0]["label"], "//lib:a");
+    assert_eq!(tree["deps"][0]["kind"], "cc_library");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_deps_command_reports_query_errors() -> Result<(), anyhow::Error> {
+    let bin_dir = TempDir::new()?;
+    let response = execute_deps(&bin_dir, "//broken:target").await?;
+
+    assert_eq!(
+        response["error"]["message"],
+        "ERROR: no such package 'broken'"
+    );
+
+    Ok(())
+}
@@ -0,0 +1,748 @@
+mod common;
+
+use common::{
+    initialize, read_notification, read_response, send_message, setup_server, setup_server_with,
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+async fn publish_for(
+    initialization_options: serde_json::Value,
+    text: &str,
+) -> Result<serde_json::Value, anyhow::Error> {
+    publish_for_uri(initialization_options, "file:///test/BUILD", text).await
+}
+
+async fn publish_for_uri(
+    initialization_options: serde_json::Value,
+    uri: &str,
+    text: &str,
+) -> Result<serde_json::Value, anyhow::Error> {
+    let (mut stdin, mut stdout) = setup_server();
+
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "initializationOptions": initialization_options,
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": uri,
+                "languageId": "starlark",
+                "version": 1,
+                "text": text
+            }
+        }
+    });
+    send_message(&mut stdin, did_open_params).await?;
+
+    let notification = read_notification(&mut stdout, "textDocument/publishDiagnostics").await?;
+    Ok(notification["params"]["diagnostics"].clone())
+}
+
+#[tokio::test]
+async fn test_parse_error_default_severity() -> Result<(), anyhow::Error> {
+    let diagnostics = publish_for(serde_json::json!({}), "cc_library(name = \"lib\"\n").await?;
+
+    assert_eq!(diagnostics[0]["code"], "parse_error");
+    assert_eq!(diagnostics[0]["severity"], 1); // DiagnosticSeverity::ERROR
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_parse_error_configured_as_warning() -> Result<(), anyhow::Error> {
+    let diagnostics = publish_for(
+        serde_json::json!({ "diagnostic_levels": { "parse_error": "warning" } }),
+        "cc_library(name = \"lib\"\n",
+    )
+    .await?;
+
+    assert_eq!(diagnostics[0]["code"], "parse_error");
+    assert_eq!(diagnostics[0]["severity"], 2); // DiagnosticSeverity::WARNING
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_valid_file_has_no_diagnostics() -> Result<(), anyhow::Error> {
+    let diagnostics = publish_for(serde_json::json!({}), "cc_library(name = \"lib\")\n").await?;
+
+    assert_eq!(diagnostics.as_array().unwrap().len(), 0);
+
+    Ok(())
+}
+
+const MACRO_DEFINITION: &str = "def my_macro(name):\n    native.cc_library(name = name)\n";
+
+#[tokio::test]
+async fn test_concatenated_deps_are_noted_as_unsortable() -> Result<(), anyhow::Error> {
+    let text = "cc_library(\n    name = \"lib\",\n    deps = COMMON_DEPS + [\"//extra\"],\n)\n\ncc_library(\n    name = \"other\",\n    deps = [\"//b\", \"//a\"],\n)\n";
+    let diagnostics = publish_for(serde_json::json!({}), text).await?;
+
+    let diagnostics = diagnostics.as_array().unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0]["code"], "unsortable_deps");
+    assert_eq!(diagnostics[0]["severity"], 3); // DiagnosticSeverity::INFORMATION
+    assert_eq!(
+        diagnostics[0]["range"],
+        serde_json::json!({
+            "start": { "line": 2, "character": 11 },
+            "end": { "line": 2, "character": 36 }
+        })
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_function_definition_allowed_in_bzl_file() -> Result<(), anyhow::Error> {
+    let diagnostics = publish_for_uri(
+        serde_json::json!({}),
+        "file:///test/macros.bzl",
+        MACRO_DEFINITION,
+    )
+    .await?;
+
+    assert_eq!(diagnostics.as_array().unwrap().len(), 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_function_definition_rejected_in_build_file() -> Result<(), anyhow::Error> {
+    let diagnostics = publish_for(serde_json::json!({}), MACRO_DEFINITION).await?;
+
+    assert_eq!(diagnostics[0]["code"], "parse_error");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_testonly_violation() -> Result<(), anyhow::Error> {
+    let workspace = tempfile::TempDir::new()?;
+    std::fs::write(workspace.path().join("WORKSPACE"), "")?;
+    std::fs::create_dir_all(workspace.path().join("lib"))?;
+    std::fs::create_dir_all(workspace.path().join("app"))?;
+    std::fs::write(
+        workspace.path().join("lib/BUILD"),
+        "cc_library(\n    name = \"fake\",\n    testonly = True,\n)\n\ncc_library(\n    name = \"real\",\n)\n",
+    )?;
+    let app_build = r#"cc_library(
+    name = "app",
+    deps = [
+        "//lib:fake",
+        "//lib:real",
+        "//unknown:dep",
+    ],
+)
+
+cc_test(
+    name = "app_test",
+    deps = ["//lib:fake"],
+)
+"#;
+    std::fs::write(workspace.path().join("app/BUILD"), app_build)?;
+
+    let workspace_uri = url::Url::from_file_path(workspace.path()).unwrap();
+    let app_uri = url::Url::from_file_path(workspace.path().join("app/BUILD")).unwrap();
+    let (mut stdin, mut stdout) = setup_server();
+
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "workspaceFolders": [{ "uri": workspace_uri, "name": "workspace" }],
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": app_uri,
+                "languageId": "starlark",
+                "version": 1,
+                "text": app_build
+            }
+        }
+    });
+    send_message(&mut stdin, did_open_params).await?;
+
+    let notification = read_notification(&mut stdout, "textDocument/publishDiagnostics").await?;
+    let diagnostics = notification["params"]["diagnostics"].as_array().unwrap();
+
+    // The unknown dep is only reported as undefined
+    let codes: Vec<&str> = diagnostics
+        .iter()
+        .filter_map(|diagnostic| diagnostic["code"].as_str())
+        .collect();
+    assert_eq!(codes, ["testonly_violation", "undefined_target"]);
+    assert!(diagnostics[0]["message"]
+        .as_str()
+        .unwrap()
+        .contains("//lib:fake"));
+    assert_eq!(
+        diagnostics[0]["range"],
+        serde_json::json!({
+            "start": { "line": 3, "character": 8 },
+            "end": { "line": 3, "character": 20 }
+        })
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_visibility_violation() -> Result<(), anyhow::Error> {
+    let workspace = tempfile::TempDir::new()?;
+    std::fs::write(workspace.path().join("WORKSPACE"), "")?;
+    std::fs::create_dir_all(workspace.path().join("lib"))?;
+    std::fs::create_dir_all(workspace.path().join("app"))?;
+    std::fs::write(
+        workspace.path().join("lib/BUILD"),
+        r#"cc_library(
+    name = "private",
+    visibility = ["//visibility:private"],
+)
+
+cc_library(
+    name = "public",
+    visibility = ["//visibility:public"],
+)
+
+cc_library(
+    name = "friends_only",
+    visibility = ["//app:__pkg__"],
+)
+
+cc_library(
+    name = "grouped",
+    visibility = ["//groups:friends"],
+)
+
+cc_library(
+    name = "default",
+)
+"#,
+    )?;
+    let app_build = r#"cc_library(
+    name = "app",
+    deps = [
+        "//lib:private",
+        "//lib:public",
+        "//lib:friends_only",
+        "//lib:grouped",
+        "//lib:default",
+    ],
+)
+"#;
+    std::fs::write(workspace.path().join("app/BUILD"), app_build)?;
+
+    let workspace_uri = url::Url::from_file_path(workspace.path()).unwrap();
+    let app_uri = url::Url::from_file_path(workspace.path().join("app/BUILD")).unwrap();
+    let (mut stdin, mut stdout) = setup_server();
+
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "workspaceFolders": [{ "uri": workspace_uri, "name": "workspace" }],
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": app_uri,
+                "languageId": "starlark",
+                "version": 1,
+                "text": app_build
+            }
+        }
+    });
+    send_message(&mut stdin, did_open_params).await?;
+
+    let notification = read_notification(&mut stdout, "textDocument/publishDiagnostics").await?;
+    let diagnostics = notification["params"]["diagnostics"].as_array().unwrap();
+
+    // Only the private target is flagged: the group and default visibility are unknown
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0]["code"], "visibility");
+    assert_eq!(diagnostics[0]["severity"], 2); // DiagnosticSeverity::WARNING
+    assert!(diagnostics[0]["message"]
+        .as_str()
+        .unwrap()
+        .contains("//lib:private"));
+    assert_eq!(
+        diagnostics[0]["range"],
+        serde_json::json!({
+            "start": { "line": 3, "character": 8 },
+            "end": { "line": 3, "character": 23 }
+        })
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_finished_analysis_is_forgotten() -> Result<(), anyhow::Error> {
+    let analysis_tasks = Arc::new(RwLock::new(HashMap::new()));
+    let server_tasks = analysis_tasks.clone();
+    let (mut stdin, mut stdout) = setup_server_with(move |mut backend| {
+        backend.analysis_tasks = server_tasks;
+        backend
+    });
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({ "capabilities": {}, "processId": 1 }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": "file:///test/BUILD",
+                "languageId": "starlark",
+                "version": 1,
+                "text": "cc_library(name = \"lib\")\n"
+            }
+        }
+    });
+    send_message(&mut stdin, did_open_params).await?;
+    read_notification(&mut stdout, "textDocument/publishDiagnostics").await?;
+
+    let did_change_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didChange",
+        "params": {
+            "textDocument": { "uri": "file:///test/BUILD", "version": 2 },
+            "contentChanges": [{ "text": "cc_library(name = \"other\")\n" }]
+        }
+    });
+    send_message(&mut stdin, did_change_params).await?;
+    read_notification(&mut stdout, "textDocument/publishDiagnostics").await?;
+
+    // The analysis ends by asking for refreshes and waiting for the answers
+    for method in [
+        "workspace/semanticTokens/refresh",
+        "workspace/codeLens/refresh",
+    ] {
+        let request = read_notification(&mut stdout, method).await?;
+        let response = serde_json::json!({ "jsonrpc": "2.0", "id": request["id"], "result": null });
+        send_message(&mut stdin, response).await?;
+    }
+
+    // The task removes itself once it is done
+    let started = std::time::Instant::now();
+    while !analysis_tasks.read().await.is_empty() {
+        assert!(
+            started.elapsed() < std::time::Duration::from_secs(5),
+            "The finished analysis is still tracked"
+        );
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_rapid_changes_are_diagnosed_once() -> Result<(), anyhow::Error> {
+    let (mut stdin, mut stdout) = setup_server();
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({ "capabilities": {}, "processId": 1 }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": "file:///test/BUILD",
+                "languageId": "starlark",
+                "version": 1,
+                "text": "cc_library(name = \"lib\")\n"
+            }
+        }
+    });
+    send_message(&mut stdin, did_open_params).await?;
+    read_notification(&mut stdout, "textDocument/publishDiagnostics").await?;
+
+    // Each version breaks a later line, so the diagnostic tells which one was parsed
+    for version in 2..=6 {
+        let text = format!("{}cc_library(\n", "\n".repeat(version));
+        let did_change_params = serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didChange",
+            "params": {
+                "textDocument": { "uri": "file:///test/BUILD", "version": version },
+                "contentChanges": [{ "text": text }]
+            }
+        });
+        send_message(&mut stdin, did_change_params).await?;
+    }
+
+    let notification = read_notification(&mut stdout, "textDocument/publishDiagnostics").await?;
+    let diagnostics = notification["params"]["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(
+        diagnostics[0]["message"],
+        "Syntax error at line 7, column 1"
+    );
+
+    // Give superseded changes time to show up if they were diagnosed anyway
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    let semantic_tokens_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "textDocument/semanticTokens/full",
+        "params": { "textDocument": { "uri": "file:///test/BUILD" } }
+    });
+    send_message(&mut stdin, semantic_tokens_params).await?;
+    let (_, notifications) = common::read_response(&mut stdout, 2).await?;
+    assert!(!notifications
+        .iter()
+        .any(|message| message["method"] == "textDocument/publishDiagnostics"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_published_diagnostics_are_summarized_and_counted() -> Result<(), anyhow::Error> {
+    let (mut stdin, mut stdout) = setup_server();
+
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({ "capabilities": {}, "processId": 1 }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": "file:///test/BUILD",
+                "languageId": "starlark",
+                "version": 1,
+                "text": "cc_library(name = \"lib\"\n"
+            }
+        }
+    });
+    send_message(&mut stdin, did_open_params).await?;
+    read_notification(&mut stdout, "textDocument/publishDiagnostics").await?;
+
+    let stats_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "workspace/executeCommand",
+        "params": { "command": "bazel.stats", "arguments": [] }
+    });
+    send_message(&mut stdin, stats_params).await?;
+
+    let (response, notifications) = read_response(&mut stdout, 2).await?;
+    assert_eq!(response["result"]["diagnostics_published"], 1);
+
+    let logs: Vec<&str> = notifications
+        .iter()
+        .filter(|n| n["method"] == "window/logMessage" && n["params"]["type"] == 4)
+        .filter_map(|n| n["params"]["message"].as_str())
+        .collect();
+    assert_eq!(
+        logs,
+        ["diagnostics: uri=file:///test/BUILD errors=1 warnings=0"]
+    );
+
+    Ok(())
+}
+
+/// Opens `app/BUILD` with `app_build` in a workspace with a `cc_library` in `lib`, and
+/// returns the diagnostics published for it.
+async fn app_diagnostics(app_build: &str) -> Result<Vec<serde_json::Value>, anyhow::Error> {
+    let workspace = tempfile::TempDir::new()?;
+    std::fs::write(workspace.path().join("WORKSPACE"), "")?;
+    std::fs::create_dir_all(workspace.path().join("lib"))?;
+    std::fs::create_dir_all(workspace.path().join("app"))?;
+    std::fs::write(
+        workspace.path().join("lib/BUILD"),
+        "cc_library(\n    name = \"lib\",\n)\n",
+    )?;
+    // Packages the index doesn't know: an excluded directory and a BUILD file that
+    // doesn't parse
+    std::fs::create_dir_all(workspace.path().join("vendor/dep"))?;
+    std::fs::write(
+        workspace.path().join("vendor/dep/BUILD"),
+        "cc_library(\n    name = \"dep\",\n)\n",
+    )?;
+    std::fs::create_dir_all(workspace.path().join("broken"))?;
+    std::fs::write(workspace.path().join("broken/BUILD"), "cc_library(\n")?;
+    std::fs::write(workspace.path().join("app/BUILD"), app_build)?;
+    std::fs::write(workspace.path().join("app/data.txt"), "")?;
+
+    let workspace_uri = url::Url::from_file_path(workspace.path()).unwrap();
+    let app_uri = url::Url::from_file_path(workspace.path().join("app/BUILD")).unwrap();
+    let (mut stdin, mut stdout) = setup_server();
+
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "workspaceFolders": [{ "uri": workspace_uri, "name": "workspace" }],
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": app_uri,
+                "languageId": "starlark",
+                "version": 1,
+                "text": app_build
+            }
+        }
+    });
+    send_message(&mut stdin, did_open_params).await?;
+
+    let notification = read_notification(&mut stdout, "textDocument/publishDiagnostics").await?;
+    Ok(notification["params"]["diagnostics"]
+        .as_array()
+        .unwrap()
+        .clone())
+}
+
+#[tokio::test]
+async fn test_undefined_target() -> Result<(), anyhow::Error> {
+    let app_build = "cc_library(\n    name = \"app\",\n    deps = [\"//nonexistent:target\"],\n)\n";
+
+    let diagnostics = app_diagnostics(app_build).await?;
+
+    assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+    assert_eq!(diagnostics[0]["code"], "undefined_target");
+    assert_eq!(diagnostics[0]["severity"], 2); // Warning
+    assert_eq!(
+        diagnostics[0]["range"],
+        serde_json::json!({
+            "start": { "line": 2, "character": 12 },
+            "end": { "line": 2, "character": 34 }
+        })
+    );
+    assert_eq!(
+        diagnostics[0]["message"],
+        "Target '//nonexistent:target' is not defined: package '//nonexistent' has no BUILD file"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_undefined_target_only_flags_missing_targets() -> Result<(), anyhow::Error> {
+    let app_build = r#"cc_library(
+    name = "app",
+    deps = [
+        ":helper",
+        ":data.txt",
+        "//lib",
+        "//lib:missing",
+        "@other//pkg:dep",
+    ] + select({
+        "//conditions:default": [":gone"],
+    }),
+)
+
+cc_library(
+    name = "helper",
+)
+"#;
+
+    let diagnostics = app_diagnostics(app_build).await?;
+
+    let flagged: Vec<(&str, u64)> = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic["code"] == "undefined_target")
+        .map(|diagnostic| {
+            (
+                diagnostic["message"].as_str().unwrap(),
+                diagnostic["range"]["start"]["line"].as_u64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        flagged,
+        [
+            (
+                "Target '//lib:missing' is not declared in package '//lib'",
+                6
+            ),
+            ("Target '//app:gone' is not declared in package '//app'", 9),
+        ]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_undefined_target_skips_packages_that_are_not_indexed() -> Result<(), anyhow::Error> {
+    let app_build = "cc_library(\n    name = \"app\",\n    deps = [\n        \"//vendor/dep\",\n        \"//broken:target\",\n    ],\n)\n";
+
+    let diagnostics = app_diagnostics(app_build).await?;
+
+    assert!(
+        diagnostics
+            .iter()
+            .all(|diagnostic| diagnostic["code"] != "undefined_target"),
+        "{:?}",
+        diagnostics
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_undefined_target_cleared_when_dependency_is_declared() -> Result<(), anyhow::Error> {
+    let workspace = tempfile::TempDir::new()?;
+    std::fs::write(workspace.path().join("WORKSPACE"), "")?;
+    std::fs::create_dir_all(workspace.path().join("lib"))?;
+    std::fs::create_dir_all(workspace.path().join("app"))?;
+    let lib_build = "cc_library(\n    name = \"lib\",\n)\n";
+    let app_build = "cc_library(\n    name = \"app\",\n    deps = [\"//lib:new\"],\n)\n";
+    std::fs::write(workspace.path().join("lib/BUILD"), lib_build)?;
+    std::fs::write(workspace.path().join("app/BUILD"), app_build)?;
+
+    let workspace_uri = url::Url::from_file_path(workspace.path()).unwrap();
+    let lib_uri = url::Url::from_file_path(workspace.path().join("lib/BUILD")).unwrap();
+    let app_uri = url::Url::from_file_path(workspace.path().join("app/BUILD")).unwrap();
+    let (mut stdin, mut stdout) = setup_server();
+
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "workspaceFolders": [{ "uri": workspace_uri, "name": "workspace" }],
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    for (uri, text) in [(&app_uri, app_build), (&lib_uri, lib_build)] {
+        send_message(
+            &mut stdin,
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didOpen",
+                "params": {
+                    "textDocument": {
+                        "uri": uri,
+                        "languageId": "starlark",
+                        "version": 1,
+                        "text": text
+                    }
+                }
+            }),
+        )
+        .await?;
+    }
+
+    let notification = read_notification(&mut stdout, "textDocument/publishDiagnostics").await?;
+    assert_eq!(notification["params"]["uri"], app_uri.as_str());
+    assert_eq!(
+        notification["params"]["diagnostics"][0]["code"],
+        "undefined_target"
+    );
+
+    send_message(
+        &mut stdin,
+        serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didChange",
+            "params": {
+                "textDocument": { "uri": lib_uri, "version": 2 },
+                "contentChanges": [{
+                    "text": "cc_library(\n    name = \"lib\",\n)\n\ncc_library(\n    name = \"new\",\n)\n"
+                }]
+            }
+        }),
+    )
+    .await?;
+
+    let diagnostics = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+        loop {
+            let notification =
+                read_notification(&mut stdout, "textDocument/publishDiagnostics").await?;
+            if notification["params"]["uri"] == app_uri.as_str() {
+                return Ok::<_, anyhow::Error>(notification["params"]["diagnostics"].clone());
+            }
+        }
+    })
+    .await??;
+    assert_eq!(diagnostics, serde_json::json!([]));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_empty_glob_after_excludes() -> Result<(), anyhow::Error> {
+    let app_build = r#"filegroup(
+    name = "data",
+    srcs = glob(["*.txt"]),
+)
+
+filegroup(
+    name = "none",
+    srcs = glob(["*.txt"], exclude = ["data.txt"]),
+)
+"#;
+
+    let diagnostics = app_diagnostics(app_build).await?;
+
+    let empty_globs: Vec<&serde_json::Value> = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic["code"] == "empty_glob")
+        .collect();
+    assert_eq!(empty_globs.len(), 1, "{:?}", diagnostics);
+    assert_eq!(empty_globs[0]["severity"], 2); // Warning
+    assert_eq!(
+        empty_globs[0]["message"],
+        "glob matches no files in package '//app'"
+    );
+    assert_eq!(
+        empty_globs[0]["range"],
+        serde_json::json!({
+            "start": { "line": 7, "character": 11 },
+            "end": { "line": 7, "character": 50 }
+        })
+    );
+
+    Ok(())
+}
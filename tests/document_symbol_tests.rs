@@ -0,0 +1,102 @@
+mod common;
+
+use common::{initialize, read_response, send_message, setup_server};
+
+const DEFS_BZL: &str = r#"load("//tools:defs.bzl", "helper")
+
+def my_macro(name, **kwargs):
+    helper(name = name)
+
+_PRIVATE = 1
+
+def _impl(ctx):
+    pass
+"#;
+
+async fn document_symbols(uri: &str, text: &str) -> Result<serde_json::Value, anyhow::Error> {
+    let (mut stdin, mut stdout) = setup_server();
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": { "textDocument": { "documentSymbol": {} } },
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": uri,
+                "languageId": "starlark",
+                "version": 1,
+                "text": text
+            }
+        }
+    });
+    send_message(&mut stdin, did_open_params).await?;
+
+    let symbol_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "textDocument/documentSymbol",
+        "params": { "textDocument": { "uri": uri } }
+    });
+    send_message(&mut stdin, symbol_params).await?;
+
+    let (response, _) = read_response(&mut stdout, 2).await?;
+    Ok(response["result"].clone())
+}
+
+#[tokio::test]
+async fn test_document_symbols_list_defs() -> Result<(), anyhow::Error> {
+    let symbols = document_symbols("file:///test/defs.bzl", DEFS_BZL).await?;
+
+    // SymbolKind::FUNCTION is 12
+    assert_eq!(
+        symbols,
+        serde_json::json!([
+            {
+                "name": "my_macro",
+                "kind": 12,
+                "range": {
+                    "start": { "line": 2, "character": 0 },
+                    "end": { "line": 3, "character": 23 }
+                },
+                "selectionRange": {
+                    "start": { "line": 2, "character": 4 },
+                    "end": { "line": 2, "character": 12 }
+                }
+            },
+            {
+                "name": "_impl",
+                "kind": 12,
+                "range": {
+                    "start": { "line": 7, "character": 0 },
+                    "end": { "line": 8, "character": 8 }
+                },
+                "selectionRange": {
+                    "start": { "line": 7, "character": 4 },
+                    "end": { "line": 7, "character": 9 }
+                }
+            }
+        ])
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_document_symbols_of_build_file_without_defs() -> Result<(), anyhow::Error> {
+    let symbols = document_symbols(
+        "file:///test/BUILD",
+        "cc_library(\n    name = \"lib\",\n)\n",
+    )
+    .await?;
+    assert_eq!(symbols, serde_json::json!([]));
+
+    Ok(())
+}
@@ -0,0 +1,58 @@
+use bazel_lsp::config::ServerConfig;
+use bazel_lsp::documents::DocumentStore;
+
+#[test]
+fn test_evicts_least_recently_used_closed_document() {
+    let mut documents = DocumentStore::new(2);
+
+    documents.insert("file:///a/BUILD".into(), "a".into());
+    documents.insert("file:///b/BUILD".into(), "b".into());
+    documents.touch("file:///a/BUILD");
+    documents.insert("file:///c/BUILD".into(), "c".into());
+
+    assert_eq!(documents.len(), 2);
+    assert!(!documents.contains_key("file:///b/BUILD"));
+    assert_eq!(documents.get("file:///a/BUILD").unwrap(), "a");
+    assert_eq!(documents.get("file:///c/BUILD").unwrap(), "c");
+}
+
+#[test]
+fn test_open_documents_are_never_evicted() {
+    let mut documents = DocumentStore::new(1);
+
+    documents.open("file:///a/BUILD".into(), "a".into());
+    documents.open("file:///b/BUILD".into(), "b".into());
+    documents.insert("file:///c/BUILD".into(), "c".into());
+
+    assert!(documents.is_open("file:///a/BUILD"));
+    assert!(documents.is_open("file:///b/BUILD"));
+    assert!(!documents.contains_key("file:///c/BUILD"));
+
+    documents.close("file:///a/BUILD");
+    assert!(!documents.contains_key("file:///a/BUILD"));
+    assert_eq!(documents.get("file:///b/BUILD").unwrap(), "b");
+}
+
+#[test]
+fn test_max_open_documents_option() {
+    for key in ["max_open_documents", "maxOpenDocuments"] {
+        let options = serde_json::json!({ key: 3 });
+        let config = ServerConfig::from_initialization_options(Some(&options));
+        assert_eq!(config.max_open_documents, 3, "{}", key);
+    }
+}
+
+#[test]
+fn test_camel_case_options() {
+    let options = serde_json::json!({
+        "preferBuildifier": true,
+        "maxCompletionItems": 7,
+        "excludedDirs": ["node_modules"],
+        "indexCompletionOutsideWorkspace": true,
+    });
+    let config = ServerConfig::from_initialization_options(Some(&options));
+    assert!(config.prefer_buildifier);
+    assert_eq!(config.max_completion_items, 7);
+    assert_eq!(config.excluded_dirs, vec!["node_modules".to_string()]);
+    assert!(config.index_completion_outside_workspace);
+}
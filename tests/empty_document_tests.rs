@@ -0,0 +1,130 @@
+mod common;
+
+use common::{initialize, read_response, send_message, setup_server};
+use tempfile::TempDir;
+
+/// Opens `text` as a BUILD file on disk and returns the result of `method` on it.
+async fn request_on(
+    text: &str,
+    method: &str,
+    params: serde_json::Value,
+) -> Result<serde_json::Value, anyhow::Error> {
+    let dir = TempDir::new()?;
+    let build_file = dir.path().join("BUILD");
+    std::fs::write(&build_file, text)?;
+    let uri = url::Url::from_file_path(&build_file).unwrap();
+
+    let (mut stdin, mut stdout) = setup_server();
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({ "capabilities": {}, "processId": 1 }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": uri,
+                "languageId": "starlark",
+                "version": 1,
+                "text": text
+            }
+        }
+    });
+    send_message(&mut stdin, did_open_params).await?;
+
+    let mut params = params;
+    params["textDocument"] = serde_json::json!({ "uri": uri });
+    let request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": method,
+        "params": params
+    });
+    send_message(&mut stdin, request).await?;
+
+    let (response, _) = read_response(&mut stdout, 2).await?;
+    assert!(response.get("error").is_none(), "{}", response);
+    Ok(response["result"].clone())
+}
+
+#[tokio::test]
+async fn test_formatting_empty_document() -> Result<(), anyhow::Error> {
+    let options = serde_json::json!({ "options": { "tabSize": 4, "insertSpaces": true } });
+
+    let result = request_on("", "textDocument/formatting", options.clone()).await?;
+    assert_eq!(result, serde_json::json!([]));
+
+    // Whitespace-only documents format to an empty file
+    let result = request_on("  \n\n\t\n  ", "textDocument/formatting", options).await?;
+    assert_eq!(
+        result,
+        serde_json::json!([{
+            "range": {
+                "start": { "line": 0, "character": 0 },
+                "end": { "line": 3, "character": 2 }
+            },
+            "newText": ""
+        }])
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_completion_empty_document() -> Result<(), anyhow::Error> {
+    // Positions past the end of the document are clamped to it
+    for (text, line, character) in [
+        ("", 0, 0),
+        ("", 3, 4),
+        ("   ", 0, 3),
+        ("   ", 2, 0),
+        ("\n\n  \n", 2, 2),
+    ] {
+        let position = serde_json::json!({
+            "position": { "line": line, "character": character }
+        });
+        let result = request_on(text, "textDocument/completion", position).await?;
+        // Rule names come from the document's loads and targets, of which there are none
+        assert_eq!(result, serde_json::json!([]));
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_code_lens_empty_document() -> Result<(), anyhow::Error> {
+    for text in ["", " \n\n "] {
+        let result = request_on(text, "textDocument/codeLens", serde_json::json!({})).await?;
+        assert_eq!(result, serde_json::json!([]));
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_semantic_tokens_empty_document() -> Result<(), anyhow::Error> {
+    for text in ["", " \n\n "] {
+        let result = request_on(
+            text,
+            "textDocument/semanticTokens/full",
+            serde_json::json!({}),
+        )
+        .await?;
+        assert_eq!(result["data"], serde_json::json!([]));
+
+        let range = serde_json::json!({
+            "range": {
+                "start": { "line": 0, "character": 0 },
+                "end": { "line": 5, "character": 0 }
+            }
+        });
+        let result = request_on(text, "textDocument/semanticTokens/range", range).await?;
+        assert_eq!(result["data"], serde_json::json!([]));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,136 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::{initialize, read_response, send_message, setup_server};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use tempfile::TempDir;
+
+// Fetching declares a new dependency, so the test can tell whether the repos were re-read
+const MOCK_BAZEL: &str = r#"#!/bin/sh
+case "$*" in
+  fetch*//...)
+    echo 'bazel_dep(name = "abseil-cpp", repo_name = "absl")' >> MODULE.bazel
+    ;;
+esac
+"#;
+
+const WORKSPACE: &str = r#"workspace(name = "main")
+
+http_archive(
+    name = "zlib",
+    urls = ["https://example.com/zlib.tar.gz"],
+)
+"#;
+
+const MODULE: &str = r#"module(name = "main")
+
+bazel_dep(name = "rules_cc", version = "0.0.9")
+"#;
+
+const BUILD: &str = r#"cc_library(
+    name = "app",
+    deps = ["@"],
+)
+"#;
+
+#[tokio::test]
+async fn test_fetch_reports_progress_and_refreshes_external_repos() -> Result<(), anyhow::Error> {
+    let bin_dir = TempDir::new()?;
+    let mock_bazel = bin_dir.path().join("bazel");
+    fs::write(&mock_bazel, MOCK_BAZEL)?;
+    fs::set_permissions(&mock_bazel, fs::Permissions::from_mode(0o755))?;
+
+    let workspace = TempDir::new()?;
+    fs::write(workspace.path().join("WORKSPACE"), WORKSPACE)?;
+    fs::write(workspace.path().join("MODULE.bazel"), MODULE)?;
+    fs::write(workspace.path().join("BUILD"), BUILD)?;
+
+    let workspace_uri = url::Url::from_file_path(workspace.path()).unwrap();
+    let build_uri = url::Url::from_file_path(workspace.path().join("BUILD")).unwrap();
+    let (mut stdin, mut stdout) = setup_server();
+
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "workspaceFolders": [{ "uri": workspace_uri, "name": "workspace" }],
+            "initializationOptions": {
+                "bazel_env": { "PATH": bin_dir.path() }
+            },
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": build_uri,
+                "languageId": "starlark",
+                "version": 1,
+                "text": BUILD
+            }
+        }
+    });
+    send_message(&mut stdin, did_open_params).await?;
+
+    let completion_params = |id: u64| {
+        serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "textDocument/completion",
+            "params": {
+                "textDocument": { "uri": build_uri },
+                "position": { "line": 2, "character": 14 }
+            }
+        })
+    };
+    let labels = |response: &serde_json::Value| {
+        response["result"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["label"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+
+    send_message(&mut stdin, completion_params(2)).await?;
+    let (response, _) = read_response(&mut stdout, 2).await?;
+    assert_eq!(labels(&response), ["@rules_cc", "@zlib"]);
+    assert_eq!(response["result"][0]["textEdit"]["newText"], "@rules_cc//");
+
+    let execute_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 3,
+        "method": "workspace/executeCommand",
+        "params": {
+            "command": "bazel.fetch",
+            "arguments": [],
+            "workDoneToken": "fetch-1"
+        }
+    });
+    send_message(&mut stdin, execute_params).await?;
+    let (response, notifications) = read_response(&mut stdout, 3).await?;
+    assert!(response["result"].is_null());
+
+    let progress: Vec<_> = notifications
+        .iter()
+        .filter(|notification| notification["method"] == "$/progress")
+        .map(|notification| notification["params"].clone())
+        .collect();
+    assert_eq!(progress.len(), 2);
+    assert_eq!(progress[0]["token"], "fetch-1");
+    assert_eq!(progress[0]["value"]["kind"], "begin");
+    assert_eq!(progress[1]["value"]["kind"], "end");
+
+    send_message(&mut stdin, completion_params(4)).await?;
+    let (response, _) = read_response(&mut stdout, 4).await?;
+    assert_eq!(labels(&response), ["@rules_cc", "@absl", "@zlib"]);
+
+    Ok(())
+}
@@ -0,0 +1,397 @@
+mod common;
+
+use common::{initialize, read_response, send_message, setup_server};
+
+/// Opens `text` as the document `uri`, formats it over the wire and applies the
+/// returned edits to it, as a client would.
+async fn format_over_the_wire(uri: &str, text: &str) -> Result<String, anyhow::Error> {
+    let (mut stdin, mut stdout) = setup_server();
+
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": uri,
+                "languageId": "starlark",
+                "version": 1,
+                "text": text
+            }
+        }
+    });
+    send_message(&mut stdin, did_open_params).await?;
+
+    let formatting_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "textDocument/formatting",
+        "params": {
+            "textDocument": { "uri": uri },
+            "options": { "tabSize": 4, "insertSpaces": true }
+        }
+    });
+    send_message(&mut stdin, formatting_params).await?;
+
+    let (response, _) = read_response(&mut stdout, 2).await?;
+    let edits: Vec<tower_lsp::lsp_types::TextEdit> =
+        serde_json::from_value(response["result"].clone())?;
+    Ok(apply_text_edits(text, &edits))
+}
+
+/// Applies non-overlapping edits the way the client does, last one first so the
+/// positions of the others stay valid.
+fn apply_text_edits(text: &str, edits: &[tower_lsp::lsp_types::TextEdit]) -> String {
+    let offset = |position: tower_lsp::lsp_types::Position| {
+        let line_start: usize = text
+            .split_inclusive('\n')
+            .take(position.line as usize)
+            .map(str::len)
+            .sum();
+        let line = text[line_start..].lines().next().unwrap_or("");
+        line_start
+            + line
+                .chars()
+                .take(position.character as usize)
+                .map(char::len_utf8)
+                .sum::<usize>()
+    };
+
+    let mut edits = edits.to_vec();
+    edits.sort_by_key(|edit| std::cmp::Reverse(edit.range.start));
+    let mut result = text.to_string();
+    for edit in edits {
+        result.replace_range(
+            offset(edit.range.start)..offset(edit.range.end),
+            &edit.new_text,
+        );
+    }
+    result
+}
+
+#[tokio::test]
+async fn test_formatting_broken_file_reports_syntax_error() -> Result<(), anyhow::Error> {
+    let text = "cc_library(\n    name = \"lib\",\n    deps = [\"//b\", \"//a\"],\n)\n\ncc_library(\n    name = \"broken\"\n";
+    let (mut stdin, mut stdout) = setup_server();
+
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": "file:///test/BUILD",
+                "languageId": "starlark",
+                "version": 1,
+                "text": text
+            }
+        }
+    });
+    send_message(&mut stdin, did_open_params).await?;
+
+    let formatting_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "textDocument/formatting",
+        "params": {
+            "textDocument": { "uri": "file:///test/BUILD" },
+            "options": { "tabSize": 4, "insertSpaces": true }
+        }
+    });
+    send_message(&mut stdin, formatting_params).await?;
+
+    let (response, _) = read_response(&mut stdout, 2).await?;
+    let error = &response["error"];
+    assert_eq!(error["code"], -32803); // RequestFailed
+    assert_eq!(error["data"]["kind"], "syntax_error");
+    assert_eq!(error["data"]["range"]["start"]["line"], 5);
+    assert!(error["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("Cannot format: Syntax error at line 6"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_formatting_sorts_targets_when_enabled() -> Result<(), anyhow::Error> {
+    let text = "# Second\ncc_library(name = \"b\")\n\n# First\ncc_library(name = \"a\")\n";
+    let (mut stdin, mut stdout) = setup_server();
+
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "initializationOptions": { "sortTargets": "name" },
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": "file:///test/BUILD",
+                "languageId": "starlark",
+                "version": 1,
+                "text": text
+            }
+        }
+    });
+    send_message(&mut stdin, did_open_params).await?;
+
+    let formatting_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "textDocument/formatting",
+        "params": {
+            "textDocument": { "uri": "file:///test/BUILD" },
+            "options": { "tabSize": 4, "insertSpaces": true }
+        }
+    });
+    send_message(&mut stdin, formatting_params).await?;
+
+    let (response, _) = read_response(&mut stdout, 2).await?;
+    assert_eq!(
+        response["result"][0]["newText"],
+        "# First\ncc_library(name = \"a\")\n\n# Second\ncc_library(name = \"b\")\n"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_formatting_edits_only_the_unsorted_list() -> Result<(), anyhow::Error> {
+    let text = "cc_library(\n    name = \"a\",\n    deps = [\n        \"//b\",\n        \"//a\",\n    ],\n)\n\ncc_library(\n    name = \"b\",\n)\n";
+    let (mut stdin, mut stdout) = setup_server();
+
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": "file:///test/BUILD",
+                "languageId": "starlark",
+                "version": 1,
+                "text": text
+            }
+        }
+    });
+    send_message(&mut stdin, did_open_params).await?;
+
+    let formatting_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "textDocument/formatting",
+        "params": {
+            "textDocument": { "uri": "file:///test/BUILD" },
+            "options": { "tabSize": 4, "insertSpaces": true }
+        }
+    });
+    send_message(&mut stdin, formatting_params).await?;
+
+    // The deps list is replaced, not the whole document
+    let (response, _) = read_response(&mut stdout, 2).await?;
+    assert_eq!(
+        response["result"],
+        serde_json::json!([{
+            "range": {
+                "start": { "line": 2, "character": 4 },
+                "end": { "line": 5, "character": 5 }
+            },
+            "newText": "deps = [\n        \"//a\",\n        \"//b\",\n    ]"
+        }])
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_formatting_document_with_byte_order_mark() -> Result<(), anyhow::Error> {
+    // The byte order mark is one character of the first line, like any other
+    let text = "\u{feff}cc_library(name = \"lib\", deps = [\n    \"//b\",\n    \"//a\",\n])\n";
+    let (mut stdin, mut stdout) = setup_server();
+
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": "file:///test/BUILD",
+                "languageId": "starlark",
+                "version": 1,
+                "text": text
+            }
+        }
+    });
+    send_message(&mut stdin, did_open_params).await?;
+
+    // Renames the target from "lib" to "core"
+    let did_change_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didChange",
+        "params": {
+            "textDocument": { "uri": "file:///test/BUILD", "version": 2 },
+            "contentChanges": [{
+                "range": {
+                    "start": { "line": 0, "character": 20 },
+                    "end": { "line": 0, "character": 23 }
+                },
+                "text": "core"
+            }]
+        }
+    });
+    send_message(&mut stdin, did_change_params).await?;
+
+    let formatting_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "textDocument/formatting",
+        "params": {
+            "textDocument": { "uri": "file:///test/BUILD" },
+            "options": { "tabSize": 4, "insertSpaces": true }
+        }
+    });
+    send_message(&mut stdin, formatting_params).await?;
+
+    let (response, _) = read_response(&mut stdout, 2).await?;
+    assert_eq!(
+        response["result"],
+        serde_json::json!([{
+            "range": {
+                "start": { "line": 0, "character": 27 },
+                "end": { "line": 3, "character": 1 }
+            },
+            "newText": "deps = [\n        \"//a\",\n        \"//b\",\n    ]"
+        }])
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_formatting_sorts_deps_end_to_end() -> Result<(), anyhow::Error> {
+    let text = r#"cc_library(
+    name = "a",
+    deps = [
+        "//third_party:lib3",
+        "//base:lib1",
+        "//core:lib2",
+    ],
+)
+
+cc_library(
+    name = "sorted",
+    deps = [
+        "//base:lib1",
+        "//core:lib2",
+    ],
+)
+
+cc_binary(
+    name = "b",
+    deps = [
+        ":a",
+        "//base:lib1",
+        ":a",
+    ],
+)
+"#;
+
+    let expected = r#"cc_library(
+    name = "a",
+    deps = [
+        "//base:lib1",
+        "//core:lib2",
+        "//third_party:lib3",
+    ],
+)
+
+cc_library(
+    name = "sorted",
+    deps = [
+        "//base:lib1",
+        "//core:lib2",
+    ],
+)
+
+cc_binary(
+    name = "b",
+    deps = [
+        "//base:lib1",
+        ":a",
+    ],
+)
+"#;
+
+    assert_eq!(
+        format_over_the_wire("file:///test/BUILD", text).await?,
+        expected
+    );
+
+    // With other formatting to do the edit isn't scoped to the deps lists, and the
+    // result must be the same
+    let untidy = text.replace("\n\ncc_binary", "  \n\n\n\ncc_binary");
+    assert_eq!(
+        format_over_the_wire("file:///test/BUILD", &untidy).await?,
+        expected
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_formatting_bzl_file_with_def() -> Result<(), anyhow::Error> {
+    // Function definitions are only an error in BUILD files
+    let text = "def my_macro(name):  \n    native.cc_library(name = name)\n\n\n\nMY_CONSTANT = 1\n";
+
+    let formatted = format_over_the_wire("file:///test/defs.bzl", text).await?;
+
+    assert_eq!(
+        formatted,
+        "def my_macro(name):\n    native.cc_library(name = name)\n\nMY_CONSTANT = 1\n"
+    );
+
+    Ok(())
+}
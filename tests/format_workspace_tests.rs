@@ -0,0 +1,134 @@
+mod common;
+
+use common::{initialize, read_response, send_message, setup_server};
+use std::fs;
+use tempfile::TempDir;
+
+const UNSORTED_BUILD: &str = r#"cc_library(
+    name = "lib",
+    deps = [
+        "//z:z",
+        "//a:a",
+    ],
+)
+"#;
+
+const SORTED_BUILD: &str = r#"cc_library(
+    name = "lib",
+    deps = [
+        "//a:a",
+        "//z:z",
+    ],
+)
+"#;
+
+#[tokio::test]
+async fn test_format_workspace_sorts_all_build_files() -> Result<(), anyhow::Error> {
+    let workspace = TempDir::new()?;
+    fs::write(workspace.path().join("WORKSPACE"), "")?;
+    fs::create_dir_all(workspace.path().join("foo"))?;
+    fs::create_dir_all(workspace.path().join("bar"))?;
+    fs::write(workspace.path().join("foo/BUILD"), UNSORTED_BUILD)?;
+    fs::write(workspace.path().join("bar/BUILD.bazel"), UNSORTED_BUILD)?;
+
+    let workspace_uri = url::Url::from_file_path(workspace.path()).unwrap();
+    let (mut stdin, mut stdout) = setup_server();
+
+    let init_response = initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "rootUri": workspace_uri,
+            "workspaceFolders": [{ "uri": workspace_uri, "name": "workspace" }],
+            "processId": 1
+        }),
+    )
+    .await?;
+    assert_eq!(init_response["id"], 1);
+
+    let execute_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "workspace/executeCommand",
+        "params": {
+            "command": "bazel.formatWorkspace",
+            "arguments": []
+        }
+    });
+    send_message(&mut stdin, execute_params).await?;
+
+    let (response, _) = read_response(&mut stdout, 2).await?;
+    assert_eq!(response["result"]["totalFiles"], 2);
+    assert_eq!(response["result"]["changedFiles"], 2);
+
+    assert_eq!(
+        fs::read_to_string(workspace.path().join("foo/BUILD"))?,
+        SORTED_BUILD
+    );
+    assert_eq!(
+        fs::read_to_string(workspace.path().join("bar/BUILD.bazel"))?,
+        SORTED_BUILD
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_format_package_refuses_packages_outside_the_workspace() -> Result<(), anyhow::Error> {
+    let dir = TempDir::new()?;
+    let workspace = dir.path().join("workspace");
+    fs::create_dir_all(workspace.join("foo"))?;
+    fs::write(workspace.join("WORKSPACE"), "")?;
+    fs::write(workspace.join("foo/BUILD"), UNSORTED_BUILD)?;
+    let outside = dir.path().join("outside");
+    fs::create_dir_all(&outside)?;
+    fs::write(outside.join("BUILD"), UNSORTED_BUILD)?;
+
+    let workspace_uri = url::Url::from_file_path(&workspace).unwrap();
+    let (mut stdin, mut stdout) = setup_server();
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "workspaceFolders": [{ "uri": workspace_uri, "name": "workspace" }],
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let packages = [
+        "//../outside".to_string(),
+        outside.to_string_lossy().to_string(),
+        "@other//foo".to_string(),
+        "//foo".to_string(),
+    ];
+    for (id, package) in (2..).zip(packages) {
+        let execute_params = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "workspace/executeCommand",
+            "params": {
+                "command": "bazel.formatPackage",
+                "arguments": [{ "package": package }]
+            }
+        });
+        send_message(&mut stdin, execute_params).await?;
+
+        let (response, _) = read_response(&mut stdout, id).await?;
+        if package == "//foo" {
+            assert_eq!(response["result"]["changedFiles"], 1, "{}", response);
+        } else {
+            assert_eq!(response["error"]["code"], -32602, "{}", response); // InvalidParams
+        }
+    }
+
+    assert_eq!(fs::read_to_string(outside.join("BUILD"))?, UNSORTED_BUILD);
+    assert_eq!(
+        fs::read_to_string(workspace.join("foo/BUILD"))?,
+        SORTED_BUILD
+    );
+
+    Ok(())
+}
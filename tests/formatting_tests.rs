@@ -1,4 +1,5 @@
-use bazel_lsp::parser::BazelParser;
+use bazel_lsp::parser::{BazelParser, SyntaxError, TargetSortKey};
+use tower_lsp::lsp_types::{Position, Range};
 
 #[test]
 fn test_sort_deps_basic() {
@@ -263,3 +264,282 @@ cc_binary(
     let result = parser.sort_deps_in_text(input).unwrap();
     assert_eq!(result, expected);
 }
+
+#[test]
+fn test_sort_deps_comment_only_dep() {
+    let parser = BazelParser::default();
+    let input = r##"
+cc_library(
+    name = "lib",
+    deps = ["#"],
+)
+"##;
+
+    assert!(parser.sort_deps_in_text(input).is_ok());
+}
+
+#[test]
+fn test_sort_deps_leaves_multi_line_strings() {
+    let parser = BazelParser::default();
+    let input = r#"
+cc_binary(
+    name = "my_binary",
+    deps = [
+        "//base:lib2",
+        """//base:
+lib1""",
+    ],
+)
+
+cc_library(
+    name = "my_library",
+    deps = [
+        "//b",
+        "//a",
+    ],
+)
+"#;
+
+    let expected = r#"
+cc_binary(
+    name = "my_binary",
+    deps = [
+        "//base:lib2",
+        """//base:
+lib1""",
+    ],
+)
+
+cc_library(
+    name = "my_library",
+    deps = [
+        "//a",
+        "//b",
+    ],
+)
+"#;
+
+    let result = parser.sort_deps_in_text(input).unwrap();
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn test_sort_deps_fails_on_syntax_error() {
+    let parser = BazelParser::default();
+    let input = r#"
+cc_binary(
+    name = "my_binary",
+    deps = [
+        "//b",
+        "//a",
+    ],
+)
+
+cc_library(
+    name = "broken"
+"#;
+
+    let err = parser.sort_deps_in_text(input).unwrap_err();
+    let syntax_error = err
+        .downcast_ref::<SyntaxError>()
+        .expect("a syntax error should be reported as such");
+    assert_eq!(syntax_error.range.start.line, 9);
+    assert!(syntax_error.message.starts_with("Syntax error at line 10"));
+}
+
+#[test]
+fn test_sort_targets_keeps_leading_comments() {
+    let parser = BazelParser::default();
+    let input = r#"load("//rules:defs.bzl", "my_rule")
+
+# The main library
+cc_library(
+    name = "zeta",
+)
+
+# Tests for alpha
+# and more
+cc_test(
+    name = "alpha",
+)  # flaky
+"#;
+
+    let expected = r#"load("//rules:defs.bzl", "my_rule")
+
+# Tests for alpha
+# and more
+cc_test(
+    name = "alpha",
+)  # flaky
+
+# The main library
+cc_library(
+    name = "zeta",
+)
+"#;
+
+    let result = parser
+        .sort_targets_in_text(input, TargetSortKey::Name)
+        .unwrap();
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn test_sort_targets_by_kind() {
+    let parser = BazelParser::default();
+    let input =
+        "cc_test(name = \"a_test\")\n\ncc_library(name = \"b\")\n\ncc_library(name = \"a\")\n";
+
+    let result = parser
+        .sort_targets_in_text(input, TargetSortKey::Kind)
+        .unwrap();
+    assert_eq!(
+        result,
+        "cc_library(name = \"a\")\n\ncc_library(name = \"b\")\n\ncc_test(name = \"a_test\")\n"
+    );
+}
+
+#[test]
+fn test_sort_targets_stops_at_sections_and_statements() {
+    let parser = BazelParser::default();
+    // Neither the section comment nor the `package` call is crossed
+    let input = r#"cc_library(name = "d")
+cc_library(name = "c")
+
+# Tests
+
+cc_test(name = "b_test")
+package(default_visibility = ["//visibility:public"])
+cc_test(name = "a_test")
+"#;
+
+    let result = parser
+        .sort_targets_in_text(input, TargetSortKey::Name)
+        .unwrap();
+    assert_eq!(
+        result,
+        r#"cc_library(name = "c")
+cc_library(name = "d")
+
+# Tests
+
+cc_test(name = "b_test")
+package(default_visibility = ["//visibility:public"])
+cc_test(name = "a_test")
+"#
+    );
+}
+
+#[test]
+fn test_format_document_strips_trailing_whitespace() {
+    let parser = BazelParser::default();
+    let input = "cc_library(   \n    name = \"lib\",\t\n)  \n";
+
+    let result = parser.format_document(input).unwrap();
+    assert_eq!(result, "cc_library(\n    name = \"lib\",\n)\n");
+}
+
+#[test]
+fn test_format_document_collapses_blank_lines() {
+    let parser = BazelParser::default();
+    let input = "\
+load(\"//:defs.bzl\", \"my_rule\")
+
+
+
+cc_library(name = \"a\")
+    \t
+  
+cc_library(name = \"b\")
+";
+
+    let result = parser.format_document(input).unwrap();
+    assert_eq!(
+        result,
+        "load(\"//:defs.bzl\", \"my_rule\")\n\ncc_library(name = \"a\")\n\ncc_library(name = \"b\")\n"
+    );
+}
+
+#[test]
+fn test_format_document_ends_with_one_newline() {
+    let parser = BazelParser::default();
+
+    assert_eq!(
+        parser.format_document("cc_library(name = \"a\")").unwrap(),
+        "cc_library(name = \"a\")\n"
+    );
+    assert_eq!(
+        parser
+            .format_document("cc_library(name = \"a\")\n\n\n  \n")
+            .unwrap(),
+        "cc_library(name = \"a\")\n"
+    );
+    assert_eq!(parser.format_document("\n\n").unwrap(), "");
+}
+
+#[test]
+fn test_format_document_keeps_multi_line_strings() {
+    let parser = BazelParser::default();
+    let input =
+        "genrule(\n    name = \"gen\",\n    cmd = \"\"\"echo a  \n\n\necho b\"\"\",   \n)\n";
+
+    let result = parser.format_document(input).unwrap();
+    assert_eq!(
+        result,
+        "genrule(\n    name = \"gen\",\n    cmd = \"\"\"echo a  \n\n\necho b\"\"\",\n)\n"
+    );
+}
+
+#[test]
+fn test_compute_deps_sort_edits_only_unsorted_lists() {
+    let parser = BazelParser::default();
+    let input = r#"cc_library(
+    name = "sorted",
+    deps = [
+        "//a",
+        "//b",
+    ],
+)
+
+cc_library(
+    name = "unsorted",
+    deps = [
+        "//d",
+        "//c",
+    ],
+)
+"#;
+
+    let edits = parser.compute_deps_sort_edits(input).unwrap();
+    assert_eq!(
+        edits,
+        vec![(
+            Range::new(Position::new(10, 4), Position::new(13, 5)),
+            "deps = [\n        \"//c\",\n        \"//d\",\n    ]".to_string()
+        )]
+    );
+    assert_eq!(
+        parser.apply_edits(input, &edits),
+        parser.sort_deps_in_text(input).unwrap()
+    );
+}
+
+#[test]
+fn test_compute_deps_sort_edits_apply_to_sorted_text() {
+    let parser = BazelParser::default();
+    let inputs = [
+        "cc_library(\n    name = \"a\",\n    deps = [\n        \"//z\",  # last\n        \"//y\",\n        \"//z\",\n    ],\n)\n",
+        "cc_library(\n    name = \"a\",\n    deps = [],\n)\n\ncc_binary(\n    name = \"b\",\n    deps = [\n        \":c\",\n        \":b\",\n    ],\n)\n",
+        "cc_library(\n    name = \"ünïcode\",\n    deps = [\n        \"//é\",\n        \"//a\",\n    ],\n)\n",
+        "cc_library(name = \"no_deps\")\n",
+    ];
+    for input in inputs {
+        let edits = parser.compute_deps_sort_edits(input).unwrap();
+        assert_eq!(
+            parser.apply_edits(input, &edits),
+            parser.sort_deps_in_text(input).unwrap(),
+            "{}",
+            input
+        );
+    }
+}
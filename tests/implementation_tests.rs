@@ -0,0 +1,124 @@
+mod common;
+
+use common::{initialize, read_response, send_message, setup_server};
+use std::fs;
+use tempfile::TempDir;
+
+const LIB_BUILD: &str = r#"cc_library(
+    name = "lib",
+    srcs = ["lib.cc", "missing.cc"],
+    hdrs = ["lib.h"],
+)
+
+cc_library(
+    name = "globbed",
+    srcs = glob(["*.cc"]),
+)
+"#;
+
+const APP_BUILD: &str = r#"cc_binary(
+    name = "app",
+    deps = [
+        "//lib",
+        "//lib:globbed",
+    ],
+)
+"#;
+
+/// Returns the implementation locations and the workspace they point into.
+async fn implementation_at(
+    line: u32,
+    character: u32,
+) -> Result<(serde_json::Value, TempDir), anyhow::Error> {
+    let workspace = TempDir::new()?;
+    fs::write(workspace.path().join("WORKSPACE"), "")?;
+    fs::create_dir_all(workspace.path().join("lib"))?;
+    fs::create_dir_all(workspace.path().join("app"))?;
+    fs::write(workspace.path().join("lib/BUILD"), LIB_BUILD)?;
+    fs::write(
+        workspace.path().join("lib/lib.cc"),
+        "int answer() { return 42; }\n",
+    )?;
+    fs::write(workspace.path().join("lib/lib.h"), "int answer();\n")?;
+    fs::write(workspace.path().join("app/BUILD"), APP_BUILD)?;
+
+    let workspace_uri = url::Url::from_file_path(workspace.path()).unwrap();
+    let app_uri = url::Url::from_file_path(workspace.path().join("app/BUILD")).unwrap();
+    let (mut stdin, mut stdout) = setup_server();
+
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "workspaceFolders": [{ "uri": workspace_uri, "name": "workspace" }],
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": app_uri,
+                "languageId": "starlark",
+                "version": 1,
+                "text": APP_BUILD
+            }
+        }
+    });
+    send_message(&mut stdin, did_open_params).await?;
+
+    let implementation_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "textDocument/implementation",
+        "params": {
+            "textDocument": { "uri": app_uri },
+            "position": { "line": line, "character": character }
+        }
+    });
+    send_message(&mut stdin, implementation_params).await?;
+
+    let (response, _) = read_response(&mut stdout, 2).await?;
+    Ok((response["result"].clone(), workspace))
+}
+
+#[tokio::test]
+async fn test_implementation_of_dep_jumps_to_srcs() -> Result<(), anyhow::Error> {
+    let (locations, workspace) = implementation_at(3, 11).await?;
+
+    // Headers aren't implementations and missing files can't be opened
+    let lib_cc = url::Url::from_file_path(workspace.path().join("lib/lib.cc")).unwrap();
+    assert_eq!(
+        locations,
+        serde_json::json!([{
+            "uri": lib_cc,
+            "range": {
+                "start": { "line": 0, "character": 0 },
+                "end": { "line": 0, "character": 0 }
+            }
+        }])
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_implementation_of_globbed_srcs_is_empty() -> Result<(), anyhow::Error> {
+    let (locations, _workspace) = implementation_at(4, 12).await?;
+    assert_eq!(locations, serde_json::json!([]));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_implementation_of_rule_without_srcs_is_empty() -> Result<(), anyhow::Error> {
+    // Inside the binary, which has no srcs of its own
+    let (locations, _workspace) = implementation_at(1, 5).await?;
+    assert_eq!(locations, serde_json::json!([]));
+
+    Ok(())
+}
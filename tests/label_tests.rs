@@ -0,0 +1,251 @@
+mod common;
+
+use bazel_lsp::label::{
+    expand_label, parse_label, parse_label_in, split_label_start, Label, LabelStart,
+};
+use common::{initialize, read_response, send_message, setup_server};
+
+fn label(repo: Option<&str>, package: &str, name: &str) -> Label {
+    Label {
+        repo: repo.map(str::to_string),
+        package: package.to_string(),
+        name: name.to_string(),
+    }
+}
+
+#[test]
+fn test_parse_absolute_label() {
+    assert_eq!(parse_label("//a/b:c").unwrap(), label(None, "a/b", "c"));
+    assert_eq!(parse_label("//:root").unwrap(), label(None, "", "root"));
+    assert_eq!(
+        parse_label("//pkg:sub/dir/file.txt").unwrap(),
+        label(None, "pkg", "sub/dir/file.txt")
+    );
+}
+
+#[test]
+fn test_parse_label_with_repo() {
+    assert_eq!(
+        parse_label("@repo//pkg:name").unwrap(),
+        label(Some("repo"), "pkg", "name")
+    );
+    assert_eq!(
+        parse_label("@com_google_absl//:absl").unwrap(),
+        label(Some("com_google_absl"), "", "absl")
+    );
+    assert_eq!(
+        parse_label("@rules_cc+//cc:defs.bzl").unwrap(),
+        label(Some("rules_cc+"), "cc", "defs.bzl")
+    );
+}
+
+#[test]
+fn test_parse_label_with_implicit_name() {
+    assert_eq!(parse_label("//a/b").unwrap(), label(None, "a/b", "b"));
+    assert_eq!(parse_label("//pkg").unwrap(), label(None, "pkg", "pkg"));
+    assert_eq!(
+        parse_label("@repo//a/b").unwrap(),
+        label(Some("repo"), "a/b", "b")
+    );
+    assert_eq!(
+        parse_label("@repo").unwrap(),
+        label(Some("repo"), "", "repo")
+    );
+}
+
+#[test]
+fn test_parse_relative_label() {
+    assert_eq!(parse_label(":name").unwrap(), label(None, "", "name"));
+    assert_eq!(
+        parse_label(":gen/out.h").unwrap(),
+        label(None, "", "gen/out.h")
+    );
+}
+
+#[test]
+fn test_parse_label_in_package() {
+    assert_eq!(
+        parse_label_in(":name", "a/b").unwrap(),
+        label(None, "a/b", "name")
+    );
+    assert_eq!(
+        parse_label_in("//c:d", "a/b").unwrap(),
+        label(None, "c", "d")
+    );
+    assert_eq!(
+        parse_label_in("@repo//:d", "a/b").unwrap(),
+        label(Some("repo"), "", "d")
+    );
+    assert!(parse_label_in("name", "a/b").is_err());
+}
+
+#[test]
+fn test_label_new() {
+    assert_eq!(Label::new("", "app"), label(None, "", "app"));
+    assert_eq!(Label::new("a\\b", "c"), label(None, "a/b", "c"));
+}
+
+#[test]
+fn test_format_label() {
+    assert_eq!(Label::new("", "app").to_string(), "//:app");
+    assert_eq!(Label::new("a/b", "c").to_string(), "//a/b:c");
+    assert_eq!(
+        parse_label("@repo//pkg").unwrap().to_string(),
+        "@repo//pkg:pkg"
+    );
+    // Parsing the formatted label gives the label back
+    for text in ["//a/b:c", "//:root", "@repo//pkg:name", "@repo//:repo"] {
+        assert_eq!(parse_label(text).unwrap().to_string(), text);
+    }
+}
+
+#[test]
+fn test_expand_label() {
+    assert_eq!(expand_label("//foo/bar"), "//foo/bar:bar");
+    assert_eq!(expand_label("//foo/bar:baz"), "//foo/bar:baz");
+    assert_eq!(expand_label("//:root"), "//:root");
+    assert_eq!(expand_label("@repo//foo"), "@repo//foo:foo");
+    assert_eq!(expand_label("@repo"), "@repo//:repo");
+    // Strings that aren't labels, e.g. a label being typed, are kept
+    assert_eq!(expand_label("//foo/"), "//foo/");
+    assert_eq!(expand_label("pkg:name"), "pkg:name");
+}
+
+#[test]
+fn test_parse_label_with_punctuation_in_package() {
+    for package in ["a+b", "c~d", "e=f", "g$h", "i,j", "k(l)", "m@n", "o!p"] {
+        assert_eq!(
+            parse_label(&format!("//{}:name", package)).unwrap(),
+            label(None, package, "name")
+        );
+    }
+}
+
+#[test]
+fn test_split_label_start() {
+    assert_eq!(
+        split_label_start("//pkg:na"),
+        Some((LabelStart::Absolute, "pkg:na"))
+    );
+    assert_eq!(split_label_start(":na"), Some((LabelStart::Relative, "na")));
+    assert_eq!(
+        split_label_start("@rules_"),
+        Some((LabelStart::Repository, "rules_"))
+    );
+    assert_eq!(split_label_start("//"), Some((LabelStart::Absolute, "")));
+    assert_eq!(split_label_start("/pkg"), None);
+    assert_eq!(split_label_start("pkg:name"), None);
+}
+
+#[test]
+fn test_parse_invalid_labels() {
+    for invalid in [
+        "",
+        "name",
+        "pkg:name",
+        "/pkg:name",
+        "//",
+        "//pkg/",
+        "//pkg:",
+        ":",
+        "//a//b:c",
+        "//a/../b:c",
+        "//./a:c",
+        "//pkg:a//b",
+        "//pkg:../c",
+        "//pkg:na me",
+        "//pkg:a:b",
+        "//pkg:a\\b",
+        "//p kg:name",
+        "//pk\\g:name",
+        "@",
+        "@//pkg:name",
+        "@1repo//pkg:name",
+        "@re/po//pkg:name",
+        "@repo:name",
+        "@repo//",
+        " //pkg:name",
+    ] {
+        assert!(
+            parse_label(invalid).is_err(),
+            "{:?} should be invalid",
+            invalid
+        );
+    }
+}
+
+#[test]
+fn test_parse_invalid_label_messages() {
+    assert_eq!(
+        parse_label("pkg:name").unwrap_err().to_string(),
+        "\"pkg:name\" doesn't start with `//`, `:` or `@`"
+    );
+    assert_eq!(
+        parse_label("//pkg:").unwrap_err().to_string(),
+        "Target name is empty"
+    );
+    assert_eq!(
+        parse_label("//a/../b:c").unwrap_err().to_string(),
+        "Package \"a/../b\" may not contain \"..\""
+    );
+}
+
+#[tokio::test]
+async fn test_parse_label_request() -> Result<(), anyhow::Error> {
+    let (mut stdin, mut stdout) = setup_server();
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({ "capabilities": {}, "processId": 1 }),
+    )
+    .await?;
+
+    for (id, label) in [(2, "@repo//pkg:name"), (3, "//pkg:"), (4, ":name")] {
+        send_message(
+            &mut stdin,
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "method": "bazel/parseLabel",
+                "params": { "label": label }
+            }),
+        )
+        .await?;
+    }
+
+    let (response, _) = read_response(&mut stdout, 2).await?;
+    assert_eq!(
+        response["result"],
+        serde_json::json!({
+            "valid": true,
+            "repo": "repo",
+            "package": "pkg",
+            "name": "name"
+        })
+    );
+
+    let (response, _) = read_response(&mut stdout, 3).await?;
+    assert_eq!(
+        response["result"],
+        serde_json::json!({ "valid": false, "message": "Target name is empty" })
+    );
+
+    let (response, _) = read_response(&mut stdout, 4).await?;
+    assert_eq!(response["result"]["repo"], serde_json::Value::Null);
+    assert_eq!(response["result"]["package"], "");
+
+    send_message(
+        &mut stdin,
+        serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 5,
+            "method": "bazel/parseLabel",
+            "params": {}
+        }),
+    )
+    .await?;
+    let (response, _) = read_response(&mut stdout, 5).await?;
+    assert_eq!(response["error"]["code"], -32602);
+
+    Ok(())
+}
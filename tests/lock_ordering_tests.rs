@@ -0,0 +1,81 @@
+mod common;
+
+use bazel_lsp::documents::DocumentStore;
+use bazel_lsp::target_trie::TargetTrie;
+use common::{initialize, read_notification, read_response, send_message, setup_server_with};
+use std::sync::Arc;
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::sync::RwLock;
+
+#[tokio::test]
+async fn test_completion_releases_documents_before_trie() -> Result<(), anyhow::Error> {
+    let workspace = TempDir::new()?;
+    std::fs::write(workspace.path().join("WORKSPACE"), "")?;
+    let text = "cc_library(\n    name = \"lib\",\n    deps = [\"//\"],\n)\n";
+    std::fs::write(workspace.path().join("BUILD"), text)?;
+    let workspace_uri = url::Url::from_file_path(workspace.path()).unwrap();
+    let uri = url::Url::from_file_path(workspace.path().join("BUILD")).unwrap();
+
+    let documents = Arc::new(RwLock::new(DocumentStore::default()));
+    let target_trie = Arc::new(RwLock::new(TargetTrie::new()));
+    let (server_documents, server_trie) = (documents.clone(), target_trie.clone());
+    let (mut stdin, mut stdout) = setup_server_with(move |mut backend| {
+        backend.documents = server_documents;
+        backend.target_trie = server_trie;
+        backend
+    });
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "workspaceFolders": [{ "uri": workspace_uri, "name": "workspace" }],
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": uri,
+                "languageId": "starlark",
+                "version": 1,
+                "text": text
+            }
+        }
+    });
+    send_message(&mut stdin, did_open_params).await?;
+    read_notification(&mut stdout, "textDocument/publishDiagnostics").await?;
+
+    // Completing a dep has to wait for the trie, which is being written to
+    let trie_guard = target_trie.write().await;
+    let completion_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "textDocument/completion",
+        "params": {
+            "textDocument": { "uri": uri },
+            "position": { "line": 2, "character": 15 }
+        }
+    });
+    send_message(&mut stdin, completion_params).await?;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // Meanwhile the documents must stay available, e.g. to apply a change
+    let documents_guard = tokio::time::timeout(Duration::from_secs(2), documents.write()).await;
+    assert!(
+        documents_guard.is_ok(),
+        "completion held the documents lock while waiting for the trie"
+    );
+    drop(documents_guard);
+    drop(trie_guard);
+
+    let (response, _) = read_response(&mut stdout, 2).await?;
+    assert!(response.get("error").is_none(), "{}", response);
+
+    Ok(())
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use common::{initialize, read_response, send_message, setup_server};
+
+async fn moniker_at(line: u32, character: u32) -> Result<serde_json::Value, anyhow::Error> {
+    let (mut stdin, mut stdout) = setup_server();
+
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({ "capabilities": {}, "processId": 1 }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": "file:///test/BUILD",
+                "languageId": "starlark",
+                "version": 1,
+                "text": "cc_library(\n    name = \"lib\",\n    deps = [\":base\", \"//foo/bar\"],\n)\n"
+            }
+        }
+    });
+    send_message(&mut stdin, did_open_params).await?;
+
+    let moniker_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "textDocument/moniker",
+        "params": {
+            "textDocument": { "uri": "file:///test/BUILD" },
+            "position": { "line": line, "character": character }
+        }
+    });
+    send_message(&mut stdin, moniker_params).await?;
+
+    let (response, _) = read_response(&mut stdout, 2).await?;
+    Ok(response["result"].clone())
+}
+
+#[tokio::test]
+async fn test_moniker_target_declaration() -> Result<(), anyhow::Error> {
+    let result = moniker_at(1, 13).await?;
+
+    assert_eq!(result[0]["scheme"], "bazel");
+    assert_eq!(result[0]["identifier"], "//:lib");
+    assert_eq!(result[0]["kind"], "export");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_moniker_dep_reference() -> Result<(), anyhow::Error> {
+    let result = moniker_at(2, 15).await?;
+    assert_eq!(result[0]["identifier"], "//:base");
+    assert_eq!(result[0]["kind"], "import");
+
+    let result = moniker_at(2, 27).await?;
+    assert_eq!(result[0]["identifier"], "//foo/bar:bar");
+    assert_eq!(result[0]["kind"], "import");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_moniker_outside_strings() -> Result<(), anyhow::Error> {
+    let result = moniker_at(1, 5).await?;
+    assert!(result.is_null());
+
+    Ok(())
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use common::{initialize, read_notification, read_response, send_message, setup_server};
+
+const URI: &str = "git://example.com/repo/app/BUILD";
+
+#[tokio::test]
+async fn test_non_file_uri_logs_warning() -> Result<(), anyhow::Error> {
+    let text = "cc_library(\n    name = \"app\",\n    deps = [\"//\"],\n)\n";
+    let (mut stdin, mut stdout) = setup_server();
+
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": URI,
+                "languageId": "starlark",
+                "version": 1,
+                "text": text
+            }
+        }
+    });
+    send_message(&mut stdin, did_open_params).await?;
+
+    let completion_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "textDocument/completion",
+        "params": {
+            "textDocument": { "uri": URI },
+            "position": { "line": 2, "character": 14 }
+        }
+    });
+    send_message(&mut stdin, completion_params).await?;
+
+    // The server keeps serving the document, without what needs a path
+    let (response, notifications) = read_response(&mut stdout, 2).await?;
+    assert!(response.get("error").is_none(), "{}", response);
+
+    let warnings: Vec<&serde_json::Value> = notifications
+        .iter()
+        .filter(|n| n["method"] == "window/logMessage" && n["params"]["type"] == 2)
+        .collect();
+    assert!(!warnings.is_empty(), "no warning in {:?}", notifications);
+    assert!(warnings[0]["params"]["message"]
+        .as_str()
+        .unwrap()
+        .starts_with(URI));
+    // didOpen doesn't block requests, so the diagnostics may come after the response
+    if !notifications
+        .iter()
+        .any(|n| n["method"] == "textDocument/publishDiagnostics")
+    {
+        read_notification(&mut stdout, "textDocument/publishDiagnostics").await?;
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_non_file_uri_warns_once() -> Result<(), anyhow::Error> {
+    let text = "cc_library(\n    name = \"app\",\n    deps = [\"//\"],\n)\n";
+    let (mut stdin, mut stdout) = setup_server();
+
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": URI,
+                "languageId": "starlark",
+                "version": 1,
+                "text": text
+            }
+        }
+    });
+    send_message(&mut stdin, did_open_params).await?;
+
+    let mut notifications = Vec::new();
+    for id in [2, 3, 4] {
+        let completion_params = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "textDocument/completion",
+            "params": {
+                "textDocument": { "uri": URI },
+                "position": { "line": 2, "character": 14 }
+            }
+        });
+        send_message(&mut stdin, completion_params).await?;
+
+        let (_, received) = read_response(&mut stdout, id).await?;
+        notifications.extend(received);
+    }
+
+    let warnings = notifications
+        .iter()
+        .filter(|n| n["method"] == "window/logMessage" && n["params"]["type"] == 2)
+        .count();
+    assert_eq!(warnings, 1, "{:?}", notifications);
+
+    Ok(())
+}
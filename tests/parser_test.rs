@@ -1,4 +1,6 @@
-use bazel_lsp::parser::BazelParser;
+use bazel_lsp::parser::{
+    AttributeValue, BazelParser, BazelParserMode, GeneratedFile, RuleCategory,
+};
 use tower_lsp::lsp_types::{Position, Range};
 
 #[test]
@@ -107,6 +109,58 @@ cc_binary(  # another comment
     assert_eq!(target.range.end.character, 1);
 }
 
+#[test]
+fn test_rule_call_range_with_comment_before_paren() {
+    let parser = BazelParser::new().unwrap();
+    // Inside brackets a comment may sit between the rule and its arguments
+    let source = r#"TARGETS = [
+    cc_binary  # the main binary
+    (
+        name = "my_target",
+    ),
+]"#;
+
+    let targets = parser.extract_targets(source).unwrap();
+    assert_eq!(targets.len(), 1);
+    let target = &targets[0];
+
+    assert_eq!(target.rule_type_range.start, Position::new(1, 4));
+    assert_eq!(target.rule_type_range.end, Position::new(1, 13));
+    assert_eq!(target.rule_call_range.start, Position::new(1, 4));
+    assert_eq!(target.rule_call_range.end, Position::new(4, 5));
+    assert_eq!(target.range, target.rule_call_range);
+
+    // Elsewhere only a line continuation may
+    let source = "cc_binary \\
+    (
+    name = \"my_target\",
+)";
+
+    let targets = parser.extract_targets(source).unwrap();
+    assert_eq!(targets.len(), 1);
+    let target = &targets[0];
+
+    assert_eq!(target.rule_type_range.start, Position::new(0, 0));
+    assert_eq!(target.rule_type_range.end, Position::new(0, 9));
+    assert_eq!(target.rule_call_range.end, Position::new(3, 1));
+}
+
+#[test]
+fn test_extract_targets_bytes() {
+    let parser = BazelParser::new().unwrap();
+    let source = "cc_library(\n    name = \"lib\",\n)\n";
+
+    let targets = parser.extract_targets_bytes(source.as_bytes()).unwrap();
+    assert_eq!(targets.len(), 1);
+    assert_eq!(targets[0].name, "lib");
+    assert_eq!(targets[0].rule_type, "cc_library");
+
+    let Err(err) = parser.extract_targets_bytes(b"cc_library(name = \"\xff\")\n") else {
+        panic!("invalid UTF-8 should be rejected");
+    };
+    assert!(err.to_string().contains("not valid UTF-8"), "{}", err);
+}
+
 #[test]
 fn test_rule_call_range_multiple_targets() {
     let parser = BazelParser::new().unwrap();
@@ -254,3 +308,922 @@ fn test_is_in_deps_attribute_multiple_items() {
     }; // Inside deps list
     assert!(parser.is_in_deps_attribute(source, &position).unwrap());
 }
+
+#[test]
+fn test_extract_globs_with_exclude() {
+    let parser = BazelParser::new().unwrap();
+    let source = r#"
+cc_library(
+    name = "lib",
+    srcs = glob(["*.cc"], exclude = ["*_test.cc"]),
+)
+"#;
+
+    let globs = parser.extract_globs(source).unwrap();
+    assert_eq!(globs.len(), 1);
+
+    let glob = &globs[0];
+    assert_eq!(glob.attribute.as_deref(), Some("srcs"));
+    assert_eq!(glob.include, vec!["*.cc"]);
+    assert_eq!(glob.exclude, vec!["*_test.cc"]);
+
+    let files = ["lib.cc", "lib_test.cc", "util.cc", "lib.h"];
+    assert_eq!(glob.sources(&files), vec!["lib.cc", "util.cc"]);
+}
+
+#[test]
+fn test_nested_call_target() {
+    let parser = BazelParser::new().unwrap();
+    let source = r#"
+my_wrapper(
+    cc_library(
+        name = "inner",
+        srcs = ["lib.cc"],
+    ),
+    tag = "wrapped",
+)
+"#;
+
+    let targets = parser.extract_targets(source).unwrap();
+    assert_eq!(targets.len(), 1);
+
+    let target = &targets[0];
+    assert_eq!(target.name, "inner");
+    assert_eq!(target.rule_type, "cc_library");
+    assert_eq!(target.rule_type_range.start.line, 2);
+    assert_eq!(target.rule_type_range.start.character, 4);
+    assert_eq!(target.range.start.line, 2);
+    assert_eq!(target.range.end.line, 5);
+}
+
+#[test]
+fn test_extract_loads() {
+    let parser = BazelParser::new().unwrap();
+    let source = r#"
+load(":defs.bzl", "my_rule", other = "other_rule")
+load("@rules_cc//cc:defs.bzl", "cc_library")
+"#;
+
+    let loads = parser.extract_loads(source).unwrap();
+    assert_eq!(loads.len(), 2);
+
+    assert_eq!(loads[0].module, ":defs.bzl");
+    assert_eq!(loads[0].symbols.len(), 2);
+    assert_eq!(loads[0].symbols[0].name, "my_rule");
+    assert_eq!(loads[0].symbols[1].name, "other");
+    assert_eq!(loads[0].symbols[1].original, "other_rule");
+
+    assert_eq!(loads[1].module, "@rules_cc//cc:defs.bzl");
+    assert_eq!(loads[1].symbols[0].name, "cc_library");
+}
+
+#[test]
+fn test_is_at_statement_level() {
+    let parser = BazelParser::new().unwrap();
+    let source = r#"load(":defs.bzl", "my_rule")
+
+my_
+cc_library(
+    name = "lib",
+)
+"#;
+
+    let top_level = Position {
+        line: 2,
+        character: 3,
+    };
+    assert!(parser.is_at_statement_level(source, &top_level).unwrap());
+
+    let inside_call = Position {
+        line: 4,
+        character: 8,
+    };
+    assert!(!parser.is_at_statement_level(source, &inside_call).unwrap());
+}
+
+#[test]
+fn test_is_in_deps_attribute_inside_select() {
+    let parser = BazelParser::new().unwrap();
+    let source = r#"
+cc_library(
+    name = "lib",
+    deps = select({
+        "//conditions:default": ["//path/to:target"],
+    }),
+)
+"#;
+    let position = Position {
+        line: 4,
+        character: 34,
+    }; // Inside the default branch list
+    assert!(parser.is_in_deps_attribute(source, &position).unwrap());
+
+    let position = Position {
+        line: 4,
+        character: 10,
+    }; // Inside the condition key
+    assert!(!parser.is_in_deps_attribute(source, &position).unwrap());
+}
+
+#[test]
+fn test_is_in_deps_attribute_list_plus_select() {
+    let parser = BazelParser::new().unwrap();
+    let source = r#"
+cc_library(
+    name = "lib",
+    deps = ["//base:base"] + select({
+        "//conditions:default": [
+            "//path/to:target",
+        ],
+    }),
+)
+"#;
+    let before_select = Position {
+        line: 3,
+        character: 15,
+    };
+    assert!(parser.is_in_deps_attribute(source, &before_select).unwrap());
+
+    let inside_branch = Position {
+        line: 5,
+        character: 14,
+    };
+    assert!(parser.is_in_deps_attribute(source, &inside_branch).unwrap());
+}
+
+#[test]
+fn test_get_enclosing_rule() {
+    let parser = BazelParser::new().unwrap();
+    let source = r#"
+cc_library(
+    name = "lib",
+    deps = [":base"],
+)
+
+cc_binary(
+    name = "bin",
+    deps = [":lib"],
+)
+"#;
+    let target = parser
+        .get_enclosing_rule(
+            source,
+            &Position {
+                line: 8,
+                character: 14,
+            },
+        )
+        .unwrap()
+        .unwrap();
+    assert_eq!(target.name, "bin");
+
+    let between = Position {
+        line: 5,
+        character: 0,
+    };
+    assert!(parser
+        .get_enclosing_rule(source, &between)
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn test_get_string_at() {
+    let parser = BazelParser::new().unwrap();
+    let source = r#"
+cc_library(
+    name = "lib",
+    deps = [":base"],
+)
+"#;
+    let name = parser
+        .get_string_at(
+            source,
+            &Position {
+                line: 2,
+                character: 13,
+            },
+        )
+        .unwrap()
+        .unwrap();
+    assert_eq!(name.value, "lib");
+    assert_eq!(name.attribute.as_deref(), Some("name"));
+
+    let dep = parser
+        .get_string_at(
+            source,
+            &Position {
+                line: 3,
+                character: 15,
+            },
+        )
+        .unwrap()
+        .unwrap();
+    assert_eq!(dep.value, ":base");
+    assert_eq!(dep.attribute.as_deref(), Some("deps"));
+}
+
+#[test]
+fn test_parse_build_file_typed_attributes() {
+    let parser = BazelParser::new().unwrap();
+    let source = r#"
+cc_library(
+    name = "lib",
+    srcs = ["a.cc", "b.cc"],
+    deps = select({
+        "//conditions:default": [":base"],
+    }),
+    linkstatic = True,
+)
+"#;
+    let targets = parser.parse_build_file(source).unwrap();
+    assert_eq!(targets.len(), 1);
+
+    let target = &targets[0];
+    assert_eq!(target.name, "lib");
+    assert_eq!(target.rule_type, "cc_library");
+
+    assert_eq!(
+        target.attributes["name"].value,
+        AttributeValue::String("lib".into())
+    );
+    assert_eq!(
+        target.attributes["srcs"].value,
+        AttributeValue::List(vec!["a.cc".into(), "b.cc".into()])
+    );
+    assert_eq!(target.attributes["srcs"].range.start.line, 3);
+    assert_eq!(
+        target.attributes["deps"].value,
+        AttributeValue::Select(vec![(
+            "//conditions:default".into(),
+            AttributeValue::List(vec![":base".into()])
+        )])
+    );
+    assert_eq!(
+        target.attributes["linkstatic"].value,
+        AttributeValue::Other("True".into())
+    );
+}
+
+#[test]
+fn test_parser_mode_from_file_name() {
+    assert_eq!(
+        BazelParserMode::from_file_name("/ws/tools/defs.bzl"),
+        BazelParserMode::Starlark
+    );
+    assert_eq!(
+        BazelParserMode::from_file_name("/ws/pkg/BUILD.bazel"),
+        BazelParserMode::Build
+    );
+    assert_eq!(
+        BazelParserMode::from_file_name("/ws/pkg/BUILD"),
+        BazelParserMode::Build
+    );
+}
+
+#[test]
+fn test_starlark_mode_ignores_rule_calls_in_function_bodies() {
+    let source = r#"
+def my_macro(name):
+    cc_library(
+        name = "template",
+    )
+
+cc_library(
+    name = "lib",
+)
+"#;
+    let parser = BazelParser::new_with_mode(BazelParserMode::Starlark).unwrap();
+    assert_eq!(parser.check_syntax(source).unwrap(), vec![]);
+    let targets = parser.extract_targets(source).unwrap();
+    assert_eq!(targets.len(), 1);
+    assert_eq!(targets[0].name, "lib");
+
+    let parser = BazelParser::new_with_mode(BazelParserMode::Build).unwrap();
+    let errors = parser.check_syntax(source).unwrap();
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0]
+        .message
+        .ends_with("functions can only be defined in .bzl files"));
+    assert_eq!(errors[0].range.start, Position::new(1, 0));
+}
+
+#[test]
+fn test_check_syntax_valid() {
+    let parser = BazelParser::new().unwrap();
+    let source = r#"
+cc_library(
+    name = "lib",
+    deps = [":other"],
+)
+"#;
+    assert_eq!(parser.check_syntax(source).unwrap(), vec![]);
+}
+
+#[test]
+fn test_check_syntax_errors() {
+    let parser = BazelParser::new().unwrap();
+
+    // An unclosed call is reported once, where the error starts
+    let source = "cc_library(\n    name = \"lib\",\n";
+    let errors = parser.check_syntax(source).unwrap();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].range.start, Position::new(0, 0));
+    assert!(errors[0]
+        .message
+        .starts_with("Syntax error at line 1, column 1"));
+
+    // Separate statements with errors are reported separately
+    let source = "cc_library(name = \"a\" \"b\")\ncc_test(name = \"t\" \"u\")\n";
+    let errors = parser.check_syntax(source).unwrap();
+    let lines: Vec<u32> = errors.iter().map(|error| error.range.start.line).collect();
+    assert_eq!(lines, vec![0, 1]);
+}
+
+#[test]
+fn test_is_in_attribute() {
+    let parser = BazelParser::new().unwrap();
+    let source = r#"
+cc_test(
+    name = "test",
+    tags = ["manual"] + select({
+        "//conditions:default": [],
+    }),
+    srcs = [],
+)
+"#;
+    let inside_tags = Position {
+        line: 3,
+        character: 13,
+    };
+    assert!(parser
+        .is_in_attribute(source, &inside_tags, "tags")
+        .unwrap());
+    assert!(!parser
+        .is_in_attribute(source, &inside_tags, "srcs")
+        .unwrap());
+
+    let inside_select = Position {
+        line: 4,
+        character: 33,
+    };
+    assert!(parser
+        .is_in_attribute(source, &inside_select, "tags")
+        .unwrap());
+
+    let before_list = Position {
+        line: 3,
+        character: 11,
+    };
+    assert!(!parser
+        .is_in_attribute(source, &before_list, "tags")
+        .unwrap());
+}
+
+#[test]
+fn test_attribute_at_value() {
+    let parser = BazelParser::new().unwrap();
+    let attribute_at = |source: &str, line, character| {
+        parser
+            .attribute_at_value(source, &Position { line, character })
+            .unwrap()
+    };
+
+    // Half-typed, so the parser has to recover the argument from an error
+    let source = "cc_test(\n    name = \"test\",\n    testonly = \n)\n";
+    assert_eq!(attribute_at(source, 2, 15), Some("testonly".to_string()));
+    assert_eq!(attribute_at(source, 1, 11), Some("name".to_string()));
+
+    let source = "cc_test(\n    stamp = -1,\n    linkstatic = Tr,\n    tags = [],\n)\n";
+    assert_eq!(attribute_at(source, 1, 14), Some("stamp".to_string()));
+    assert_eq!(attribute_at(source, 2, 19), Some("linkstatic".to_string()));
+    assert_eq!(attribute_at(source, 3, 12), None);
+
+    // Nothing after the cursor, so not even the call is closed
+    let source = "cc_test(\n    name = \"test\",\n    flaky = ";
+    assert_eq!(attribute_at(source, 2, 12), Some("flaky".to_string()));
+
+    // Comparisons and assignments aren't attributes
+    assert_eq!(attribute_at("x = \n", 0, 4), None);
+    assert_eq!(attribute_at("f(a == b)\n", 0, 7), None);
+}
+
+#[test]
+fn test_attribute_name_context() {
+    let parser = BazelParser::new().unwrap();
+    let context_at = |source: &str, line, character| {
+        parser
+            .attribute_name_context(source, &Position { line, character })
+            .unwrap()
+    };
+
+    let source = "cc_test(\n    name = \"test\",\n    \n)\n";
+    assert_eq!(
+        context_at(source, 2, 4),
+        Some(("cc_test".to_string(), vec!["name".to_string()]))
+    );
+
+    let source = "my_rule(\n    name = \"x\",\n    conf\n)\n";
+    assert_eq!(
+        context_at(source, 2, 8),
+        Some(("my_rule".to_string(), vec!["name".to_string()]))
+    );
+
+    assert_eq!(
+        context_at("cc_test()\n", 0, 8),
+        Some(("cc_test".to_string(), vec![]))
+    );
+
+    // Values, strings and lists aren't attribute names
+    let source = "cc_test(\n    name = \"test\",\n    deps = [\n        \n    ],\n)\n";
+    assert_eq!(context_at(source, 1, 12), None);
+    assert_eq!(context_at(source, 1, 13), None);
+    assert_eq!(context_at(source, 3, 8), None);
+}
+
+#[test]
+fn test_unsortable_deps() {
+    let parser = BazelParser::new().unwrap();
+    let source = r#"
+cc_library(
+    name = "concatenated",
+    deps = COMMON_DEPS + ["//extra"],
+)
+
+cc_library(
+    name = "selected",
+    deps = select({"//conditions:default": []}),
+)
+
+cc_library(
+    name = "sortable",
+    deps = ["//b", "//a"],
+)
+
+cc_library(
+    name = "selected_and_listed",
+    deps = ["//a"] + select({"//conditions:default": ["//b"]}),
+)
+
+cc_library(
+    name = "selected_variable",
+    deps = select({"//conditions:default": BASE_DEPS}),
+)
+"#;
+
+    let ranges = parser.unsortable_deps(source).unwrap();
+    let lines: Vec<u32> = ranges.iter().map(|range| range.start.line).collect();
+    assert_eq!(lines, vec![3, 23]);
+    assert_eq!(ranges[0].start.character, 11);
+}
+
+#[test]
+fn test_extract_defs() {
+    let parser = BazelParser::new_with_mode(BazelParserMode::Starlark).unwrap();
+    let source = r#"load(":utils.bzl", "helper")
+
+def my_macro(name):
+    def inner():
+        pass
+    native.cc_library(name = name)
+
+def _impl(ctx, **kwargs):
+    pass
+"#;
+    let defs = parser.extract_defs(source).unwrap();
+    let names: Vec<&str> = defs.iter().map(|def| def.name.as_str()).collect();
+    // `inner` is nested in `my_macro`, so it is left out
+    assert_eq!(names, ["my_macro", "_impl"]);
+    assert_eq!(
+        defs[0].name_range,
+        Range::new(Position::new(2, 4), Position::new(2, 12))
+    );
+    assert_eq!(
+        defs[1].name_range,
+        Range::new(Position::new(7, 4), Position::new(7, 9))
+    );
+    assert_eq!(defs[1].range.start, Position::new(7, 0));
+}
+
+#[test]
+fn test_extract_exported_files() {
+    let parser = BazelParser::new().unwrap();
+    let source = r#"
+exports_files(["a.txt", "b.txt"])
+
+exports_files(
+    srcs = ["c.txt"],
+    visibility = ["//visibility:public"],
+)
+
+filegroup(
+    name = "docs",
+    srcs = ["a.txt"],
+)
+"#;
+    let files = parser.extract_exported_files(source).unwrap();
+    let names: Vec<&str> = files.iter().map(|file| file.name.as_str()).collect();
+    assert_eq!(names, ["a.txt", "b.txt", "c.txt"]);
+    assert_eq!(
+        files[0].range.start,
+        Position {
+            line: 1,
+            character: 15
+        }
+    );
+}
+
+#[test]
+fn test_extract_package_group() {
+    let parser = BazelParser::new().unwrap();
+    let source = r#"
+package_group(
+    name = "friends",
+    packages = ["//app/...", "//tools"],
+)
+
+cc_library(
+    name = "lib",
+    visibility = [":friends"],
+)
+"#;
+    let targets = parser.extract_targets(source).unwrap();
+    let targets: Vec<(&str, &str)> = targets
+        .iter()
+        .map(|target| (target.rule_type.as_str(), target.name.as_str()))
+        .collect();
+    assert_eq!(
+        targets,
+        [("package_group", "friends"), ("cc_library", "lib")]
+    );
+
+    let parsed = parser.parse_build_file(source).unwrap();
+    assert_eq!(
+        parsed[0].attributes["packages"].value,
+        AttributeValue::List(vec!["//app/...".into(), "//tools".into()])
+    );
+}
+
+#[test]
+fn test_extract_function_calls() {
+    let parser = BazelParser::new_with_mode(BazelParserMode::Starlark).unwrap();
+    let source = r#"
+load(":defs.bzl", "helper")
+
+def my_macro(name):
+    helper(
+        name,  # the target name
+        srcs = glob(["*.cc"]),
+    )
+
+package(default_visibility = ["//visibility:public"])
+"#;
+    let calls = parser.extract_function_calls(source).unwrap();
+    let names: Vec<&str> = calls.iter().map(|call| call.name.as_str()).collect();
+    assert_eq!(names, ["load", "helper", "glob", "package"]);
+
+    assert_eq!(calls[1].args, ["name", "srcs = glob([\"*.cc\"])"]);
+    assert_eq!(calls[2].args, ["[\"*.cc\"]"]);
+    assert_eq!(
+        calls[1].range,
+        Range {
+            start: Position {
+                line: 4,
+                character: 4
+            },
+            end: Position {
+                line: 7,
+                character: 5
+            },
+        }
+    );
+}
+
+#[test]
+fn test_compact_single_line_rule_calls() {
+    let parser = BazelParser::new().unwrap();
+    let source = r#"cc_library(name="x",srcs=["a.cc"])
+py_library(name='y',deps=[":x"]);sh_binary(name="z")
+"#;
+
+    let targets = parser.extract_targets(source).unwrap();
+    let names: Vec<(&str, &str)> = targets
+        .iter()
+        .map(|target| (target.rule_type.as_str(), target.name.as_str()))
+        .collect();
+    assert_eq!(
+        names,
+        [("cc_library", "x"), ("py_library", "y"), ("sh_binary", "z")]
+    );
+
+    assert_eq!(
+        targets[0].rule_type_range,
+        Range {
+            start: Position {
+                line: 0,
+                character: 0
+            },
+            end: Position {
+                line: 0,
+                character: 10
+            },
+        }
+    );
+    assert_eq!(
+        targets[2].rule_type_range,
+        Range {
+            start: Position {
+                line: 1,
+                character: 33
+            },
+            end: Position {
+                line: 1,
+                character: 42
+            },
+        }
+    );
+
+    let parsed = parser.parse_build_file(source).unwrap();
+    assert_eq!(
+        parsed[0].attributes["srcs"].value,
+        AttributeValue::List(vec!["a.cc".into()])
+    );
+}
+
+#[test]
+fn test_parse_to_tree() {
+    let parser = BazelParser::new().unwrap();
+    let tree = parser
+        .parse_to_tree("cc_library(\n    name = \"lib\",\n)\n")
+        .unwrap();
+
+    let root = tree.root_node();
+    assert_eq!(root.kind(), "module");
+    assert!(!root.has_error());
+    assert_eq!(root.named_child(0).unwrap().kind(), "expression_statement");
+
+    // Syntax errors are left in the tree for the caller to inspect
+    let tree = parser.parse_to_tree("cc_library(\n").unwrap();
+    assert!(tree.root_node().has_error());
+}
+
+#[test]
+fn test_identical_targets_are_both_extracted() {
+    let parser = BazelParser::new().unwrap();
+    let source = r#"cc_library(
+    name = "lib",
+    srcs = ["lib.cc"],
+)
+
+cc_library(
+    name = "lib",
+    srcs = ["lib.cc"],
+)
+"#;
+
+    let targets = parser.extract_targets(source).unwrap();
+    assert_eq!(targets.len(), 2);
+    assert_eq!(targets[0].name, targets[1].name);
+    assert_eq!(targets[0].rule_call_range.start.line, 0);
+    assert_eq!(targets[1].rule_call_range.start.line, 5);
+
+    assert_eq!(parser.parse_build_file(source).unwrap().len(), 2);
+}
+
+#[test]
+fn test_rule_category() {
+    let parser = BazelParser::new().unwrap();
+    let source = r#"
+cc_test(name = "cc_test")
+py_test(name = "py_test")
+go_test(name = "go_test")
+cc_binary(name = "cc_binary")
+java_binary(name = "java_binary")
+sh_binary(name = "sh_binary")
+cc_library(name = "cc_library")
+py_library(name = "py_library")
+proto_library(name = "proto_library")
+filegroup(name = "filegroup")
+test_suite(name = "test_suite")
+binary_test_data(name = "binary_test_data")
+"#;
+
+    let targets = parser.extract_targets(source).unwrap();
+    let categories: Vec<(&str, RuleCategory)> = targets
+        .iter()
+        .map(|target| (target.name.as_str(), target.rule_category()))
+        .collect();
+    assert_eq!(
+        categories,
+        vec![
+            ("cc_test", RuleCategory::Test),
+            ("py_test", RuleCategory::Test),
+            ("go_test", RuleCategory::Test),
+            ("cc_binary", RuleCategory::Binary),
+            ("java_binary", RuleCategory::Binary),
+            ("sh_binary", RuleCategory::Binary),
+            ("cc_library", RuleCategory::Library),
+            ("py_library", RuleCategory::Library),
+            ("proto_library", RuleCategory::Library),
+            ("filegroup", RuleCategory::Other),
+            ("test_suite", RuleCategory::Other),
+            ("binary_test_data", RuleCategory::Other),
+        ]
+    );
+
+    let test = &targets[0];
+    assert!(test.is_test_rule() && !test.is_binary_rule() && !test.is_library_rule());
+    let binary = &targets[3];
+    assert!(binary.is_binary_rule() && !binary.is_test_rule() && !binary.is_library_rule());
+    let library = &targets[6];
+    assert!(library.is_library_rule() && !library.is_test_rule() && !library.is_binary_rule());
+}
+
+#[test]
+fn test_extract_targets_skips_calls_without_literal_name() {
+    let parser = BazelParser::new().unwrap();
+    let source = r#"
+cc_library(**kwargs)
+
+cc_library(name = name, **kwargs)
+
+cc_library(**{"name": "from_dict", "srcs": ["lib.cc"]})
+
+py_binary(*args, **kwargs)
+
+cc_test(
+    name = name + "_test",
+    **kwargs
+)
+"#;
+
+    let targets = parser.extract_targets(source).unwrap();
+    assert!(targets.is_empty());
+
+    // A literal name still declares the target, whatever else is forwarded
+    let targets = parser
+        .extract_targets("cc_library(name = \"lib\", **common_attrs)\n")
+        .unwrap();
+    assert_eq!(targets.len(), 1);
+    assert_eq!(targets[0].name, "lib");
+    assert_eq!(targets[0].rule_type, "cc_library");
+}
+
+#[test]
+fn test_generated_files() {
+    let parser = BazelParser::new().unwrap();
+    let source = r#"
+genrule(
+    name = "gen",
+    srcs = ["template.txt"],
+    outs = ["gen.cc", "gen.h"],
+    cmd = "generate $< $(OUTS)",
+)
+
+genrule(
+    name = "computed",
+    outs = [name + ".txt" for name in NAMES],
+    cmd = "touch $(OUTS)",
+)
+
+cc_library(
+    name = "lib",
+    outs = ["not_generated.cc"],
+)
+"#;
+
+    let files = parser.generated_files(source).unwrap();
+    assert_eq!(
+        files,
+        vec![
+            GeneratedFile {
+                path: "gen.cc".to_string(),
+                generator: "gen".to_string(),
+            },
+            GeneratedFile {
+                path: "gen.h".to_string(),
+                generator: "gen".to_string(),
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_count_deps() {
+    let parser = BazelParser::new().unwrap();
+    let ten_deps: Vec<String> = (0..10).map(|i| format!("\"//lib:dep{}\"", i)).collect();
+    let source = format!(
+        r#"
+cc_library(
+    name = "none",
+    deps = [],
+)
+
+cc_library(
+    name = "one",
+    deps = ["//lib:a"],
+)
+
+cc_library(
+    name = "ten",
+    deps = [{}],
+)
+
+cc_library(
+    name = "duplicates",
+    deps = [
+        "//lib:a",
+        ":b",
+        "//lib:a",
+    ],
+)
+
+cc_library(
+    name = "no_deps_attribute",
+)
+"#,
+        ten_deps.join(", ")
+    );
+
+    assert_eq!(parser.count_deps(&source, "none").unwrap(), 0);
+    assert_eq!(parser.count_deps(&source, "one").unwrap(), 1);
+    assert_eq!(parser.count_deps(&source, "ten").unwrap(), 10);
+    assert_eq!(parser.count_deps(&source, "duplicates").unwrap(), 2);
+    assert_eq!(parser.count_deps(&source, "no_deps_attribute").unwrap(), 0);
+    assert_eq!(parser.count_deps(&source, "missing").unwrap(), 0);
+}
+
+#[test]
+fn test_byte_order_mark_and_leading_whitespace() {
+    let parser = BazelParser::new().unwrap();
+    let source = "\u{feff}cc_library(name = \"a\", deps = [\n    \"//b\",\n    \"//a\",\n])\n";
+
+    assert!(parser.check_syntax(source).unwrap().is_empty());
+    let targets = parser.extract_targets(source).unwrap();
+    assert_eq!(targets[0].rule_type_range.start, Position::new(0, 1));
+    assert_eq!(targets[0].rule_type_range.end, Position::new(0, 11));
+
+    assert_eq!(
+        parser.sort_deps_in_text(source).unwrap(),
+        "\u{feff}cc_library(name = \"a\", deps = [\n        \"//a\",\n        \"//b\",\n    ])\n"
+    );
+
+    // Blank lines and indentation before the first statement don't shift anything either
+    let source = "\u{feff}\n  \ncc_library(\n    name = \"a\",\n    deps = [\n        \"//b\",\n        \"//a\",\n    ],\n)\n";
+    let edits = parser.compute_deps_sort_edits(source).unwrap();
+    assert_eq!(edits.len(), 1);
+    assert_eq!(
+        edits[0].0,
+        Range::new(Position::new(4, 4), Position::new(7, 5))
+    );
+    assert_eq!(
+        parser.sort_deps_in_text(source).unwrap(),
+        "\u{feff}\n  \ncc_library(\n    name = \"a\",\n    deps = [\n        \"//a\",\n        \"//b\",\n    ],\n)\n"
+    );
+}
+
+#[test]
+fn test_extract_dep_strings() {
+    let parser = BazelParser::new().unwrap();
+    let source = r#"cc_library(
+    name = "lib",
+    srcs = ["lib.cc"],
+    deps = [":a", "//b"] + select({
+        "//conditions:linux": ["//c"],
+        "//conditions:default": [],
+    }),
+)
+"#;
+
+    let deps = parser.extract_dep_strings(source).unwrap();
+    let values: Vec<&str> = deps.iter().map(|dep| dep.value.as_str()).collect();
+    assert_eq!(values, [":a", "//b", "//c"]);
+    assert_eq!(
+        deps[1].range,
+        Range::new(Position::new(3, 18), Position::new(3, 23))
+    );
+}
+
+#[test]
+fn test_is_in_deps_attribute_only_inside_select() {
+    let parser = BazelParser::new().unwrap();
+    let source = r#"
+cc_library(
+    name = "lib",
+    deps = ["//base:base"] + platform_deps(["//path/to:target"]),
+)
+"#;
+    let inside_other_call = Position {
+        line: 3,
+        character: 45,
+    };
+    assert!(!parser
+        .is_in_deps_attribute(source, &inside_other_call)
+        .unwrap());
+
+    let before_call = Position {
+        line: 3,
+        character: 15,
+    };
+    assert!(parser.is_in_deps_attribute(source, &before_call).unwrap());
+}
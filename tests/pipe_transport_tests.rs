@@ -0,0 +1,101 @@
+#![cfg(unix)]
+
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+
+fn start_server(args: &[&str]) -> Result<Child, anyhow::Error> {
+    Ok(Command::new(env!("CARGO_BIN_EXE_bazel-lsp"))
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?)
+}
+
+/// Sends `initialize` and checks that the server answers with its capabilities.
+async fn assert_initializes(stream: &mut UnixStream) -> Result<(), anyhow::Error> {
+    let initialize = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "initialize",
+        "params": { "capabilities": {}, "processId": null }
+    })
+    .to_string();
+    stream
+        .write_all(format!("Content-Length: {}\r\n\r\n{}", initialize.len(), initialize).as_bytes())
+        .await?;
+
+    let mut response = Vec::new();
+    let mut buffer = [0; 4096];
+    while !String::from_utf8_lossy(&response).contains("\"capabilities\"") {
+        let read = stream.read(&mut buffer).await?;
+        assert!(read > 0, "the server closed the connection");
+        response.extend_from_slice(&buffer[..read]);
+    }
+    Ok(())
+}
+
+fn socket_path(dir: &TempDir) -> String {
+    dir.path()
+        .join("bazel-lsp.sock")
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[tokio::test]
+async fn test_listens_on_a_new_socket() -> Result<(), anyhow::Error> {
+    let dir = TempDir::new()?;
+    let path = socket_path(&dir);
+
+    let mut server = start_server(&["--pipe", &path])?;
+    let mut listening = String::new();
+    BufReader::new(server.stderr.take().unwrap()).read_line(&mut listening)?;
+    assert_eq!(listening.trim(), format!("Listening on {}", path));
+
+    let mut stream = UnixStream::connect(&path).await?;
+    assert_initializes(&mut stream).await?;
+
+    server.kill()?;
+    server.wait()?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_connects_to_a_listening_socket() -> Result<(), anyhow::Error> {
+    // Like VS Code, which creates the socket and waits for the server to connect
+    let dir = TempDir::new()?;
+    let path = socket_path(&dir);
+    let listener = UnixListener::bind(&path)?;
+
+    let mut server = start_server(&[&format!("--pipe={}", path)])?;
+    let (mut stream, _) = listener.accept().await?;
+    assert_initializes(&mut stream).await?;
+
+    server.kill()?;
+    server.wait()?;
+    Ok(())
+}
+
+#[test]
+fn test_unusable_path_is_reported() -> Result<(), anyhow::Error> {
+    // A regular file is in the way of the socket
+    let dir = TempDir::new()?;
+    let path = socket_path(&dir);
+    std::fs::write(&path, "")?;
+
+    let output = Command::new(env!("CARGO_BIN_EXE_bazel-lsp"))
+        .args(["--pipe", &path])
+        .stdin(Stdio::null())
+        .output()?;
+
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.starts_with(&format!("Failed to listen on {}: ", path)),
+        "{}",
+        stderr
+    );
+    Ok(())
+}
@@ -0,0 +1,132 @@
+mod common;
+
+use bazel_lsp::target_trie::RuleInfo;
+use common::{initialize, read_response, send_message, setup_server, setup_server_with};
+use std::fs;
+use tempfile::TempDir;
+
+async fn resolve_label(
+    stdin: &mut common::ClientWriter,
+    stdout: &mut common::ClientReader,
+    label: &str,
+) -> Result<serde_json::Value, anyhow::Error> {
+    let resolve_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "bazel/resolveLabel",
+        "params": { "label": label }
+    });
+    send_message(stdin, resolve_params).await?;
+
+    let (response, _) = read_response(stdout, 2).await?;
+    Ok(response["result"].clone())
+}
+
+#[tokio::test]
+async fn test_resolve_indexed_label() -> Result<(), anyhow::Error> {
+    // Not a workspace, so only the seeded index knows about the target
+    let dir = TempDir::new()?;
+    let build_file = dir.path().join("BUILD.bazel");
+    fs::write(
+        &build_file,
+        "exports_files([\"data.txt\"])\n\ncc_library(\n    name = \"lib\",\n)\n",
+    )?;
+
+    let seeded_file = build_file.clone();
+    let (mut stdin, mut stdout) = setup_server_with(move |backend| {
+        let rule = RuleInfo {
+            name: "lib".into(),
+            full_build_path: "//pkg:lib".into(),
+            source_file: Some(seeded_file.clone()),
+            ..Default::default()
+        };
+        backend
+            .target_trie
+            .try_write()
+            .unwrap()
+            .insert_file_target(&seeded_file, "pkg:lib", rule);
+        backend
+    });
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({ "capabilities": {}, "processId": 1 }),
+    )
+    .await?;
+
+    let result = resolve_label(&mut stdin, &mut stdout, "//pkg:lib").await?;
+    assert_eq!(result["path"], build_file.to_str().unwrap());
+    assert_eq!(
+        result["range"],
+        serde_json::json!({
+            "start": { "line": 2, "character": 0 },
+            "end": { "line": 4, "character": 1 }
+        })
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_resolve_label_in_workspace() -> Result<(), anyhow::Error> {
+    let workspace = TempDir::new()?;
+    fs::write(workspace.path().join("WORKSPACE"), "")?;
+    fs::create_dir_all(workspace.path().join("lib"))?;
+    let build_file = workspace.path().join("lib/BUILD");
+    fs::write(&build_file, "exports_files([\"data.txt\"])\n")?;
+
+    let workspace_uri = url::Url::from_file_path(workspace.path()).unwrap();
+    let (mut stdin, mut stdout) = setup_server();
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "workspaceFolders": [{ "uri": workspace_uri, "name": "workspace" }],
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let result = resolve_label(&mut stdin, &mut stdout, "//lib:data.txt").await?;
+    assert_eq!(result["path"], build_file.to_str().unwrap());
+    assert_eq!(result["range"]["start"]["character"], 15);
+
+    let result = resolve_label(&mut stdin, &mut stdout, "//lib:missing").await?;
+    assert!(result.is_null());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_resolve_root_level_label() -> Result<(), anyhow::Error> {
+    let workspace = TempDir::new()?;
+    fs::write(workspace.path().join("WORKSPACE"), "")?;
+    let build_file = workspace.path().join("BUILD");
+    fs::write(
+        &build_file,
+        "exports_files([\"data.txt\"])\n\ncc_binary(\n    name = \"app\",\n)\n",
+    )?;
+
+    let workspace_uri = url::Url::from_file_path(workspace.path()).unwrap();
+    let (mut stdin, mut stdout) = setup_server();
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "workspaceFolders": [{ "uri": workspace_uri, "name": "workspace" }],
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let result = resolve_label(&mut stdin, &mut stdout, "//:app").await?;
+    assert_eq!(result["path"], build_file.to_str().unwrap());
+    assert_eq!(result["range"]["start"]["line"], 2);
+
+    let result = resolve_label(&mut stdin, &mut stdout, "//:data.txt").await?;
+    assert_eq!(result["path"], build_file.to_str().unwrap());
+
+    Ok(())
+}
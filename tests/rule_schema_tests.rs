@@ -0,0 +1,83 @@
+use bazel_lsp::rule_schema::{RuleAttribute, RuleSchema};
+use std::fs;
+use tempfile::TempDir;
+
+fn attribute_names(schema: &RuleSchema, rule: &str) -> Vec<String> {
+    schema
+        .attributes(rule)
+        .into_iter()
+        .map(|attribute| attribute.name)
+        .collect()
+}
+
+#[test]
+fn test_builtin_rule_attributes() {
+    let schema = RuleSchema::default();
+
+    let attributes = attribute_names(&schema, "cc_library");
+    assert_eq!(attributes[..3], ["srcs", "hdrs", "deps"]);
+    assert!(attributes.contains(&"visibility".to_string()));
+
+    // Unknown rules still accept the attributes every rule has
+    let attributes = attribute_names(&schema, "my_rule");
+    assert!(attributes.contains(&"name".to_string()));
+    assert!(!attributes.contains(&"srcs".to_string()));
+}
+
+#[test]
+fn test_merge_schema_file_over_builtin_rules() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("rules.json");
+    fs::write(
+        &path,
+        r#"{
+            "my_rule": {
+                "attributes": {
+                    "config": { "doc": "The config file to apply" },
+                    "mode": {}
+                }
+            },
+            "cc_library": {
+                "attributes": {
+                    "srcs": { "doc": "Sources to compile" }
+                }
+            }
+        }"#,
+    )
+    .unwrap();
+
+    let mut schema = RuleSchema::default();
+    schema.merge(RuleSchema::load(&path).unwrap());
+
+    let attributes = schema.attributes("my_rule");
+    assert_eq!(
+        attributes[..2],
+        [
+            RuleAttribute {
+                name: "config".to_string(),
+                doc: Some("The config file to apply".to_string()),
+            },
+            RuleAttribute {
+                name: "mode".to_string(),
+                doc: None,
+            },
+        ]
+    );
+
+    // Described attributes replace the built-in ones and the rest are kept
+    let attributes = schema.attributes("cc_library");
+    assert_eq!(attributes[0].doc.as_deref(), Some("Sources to compile"));
+    assert_eq!(attributes[1].name, "hdrs");
+}
+
+#[test]
+fn test_invalid_schema() {
+    assert!(RuleSchema::from_json(&serde_json::json!(["my_rule"])).is_err());
+    assert!(RuleSchema::from_json(&serde_json::json!({
+        "my_rule": { "attributes": ["config"] }
+    }))
+    .is_err());
+
+    let temp_dir = TempDir::new().unwrap();
+    assert!(RuleSchema::load(&temp_dir.path().join("missing.json")).is_err());
+}
@@ -1,4 +1,7 @@
-use bazel_lsp::parser::BazelParser;
+mod common;
+
+use bazel_lsp::parser::{BazelParser, LiteralKind};
+use tower_lsp::lsp_types::Position;
 
 #[test]
 fn test_semantic_tokens_targets() {
@@ -21,7 +24,8 @@ cc_binary(
     assert_eq!(first_target.rule_type, "cc_binary");
 
     // Check that the range has the correct length for "cc_binary"
-    let range_length = first_target.rule_type_range.end.character - first_target.rule_type_range.start.character;
+    let range_length =
+        first_target.rule_type_range.end.character - first_target.rule_type_range.start.character;
     assert_eq!(range_length, 9);
 }
 
@@ -98,3 +102,331 @@ cc_binary(
     assert_eq!(attributes.len(), 2, "Expected 2 attributes");
     assert_eq!(strings.len(), 2, "Expected 2 strings");
 }
+
+#[test]
+fn test_semantic_tokens_literals() {
+    let parser = BazelParser::default();
+    let text = r#"
+cc_test(
+    name = "hello_test",
+    linkstatic = True,
+    shard_count = 4,
+)
+"#;
+
+    let literals = parser.extract_literals(text).unwrap();
+    assert_eq!(literals.len(), 2, "Expected a boolean and a number");
+
+    let boolean = &literals[0];
+    assert_eq!(boolean.kind, LiteralKind::Boolean);
+    assert_eq!(
+        boolean.range.start,
+        Position {
+            line: 3,
+            character: 17
+        }
+    );
+    assert_eq!(
+        boolean.range.end,
+        Position {
+            line: 3,
+            character: 21
+        }
+    );
+
+    let number = &literals[1];
+    assert_eq!(number.kind, LiteralKind::Number);
+    assert_eq!(
+        number.range.start,
+        Position {
+            line: 4,
+            character: 18
+        }
+    );
+    assert_eq!(
+        number.range.end,
+        Position {
+            line: 4,
+            character: 19
+        }
+    );
+}
+
+/// Decodes relative semantic token data into absolute `(line, start)` positions.
+fn token_positions(data: &serde_json::Value) -> Vec<(u64, u64)> {
+    let data: Vec<u64> = data
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|value| value.as_u64().unwrap())
+        .collect();
+
+    let mut positions = Vec::new();
+    let (mut line, mut start) = (0, 0);
+    for token in data.chunks(5) {
+        if token[0] > 0 {
+            line += token[0];
+            start = token[1];
+        } else {
+            start += token[1];
+        }
+        positions.push((line, start));
+    }
+    positions
+}
+
+#[tokio::test]
+async fn test_semantic_tokens_range_request() -> Result<(), anyhow::Error> {
+    let text: String = (0..25)
+        .map(|i| {
+            format!(
+                "cc_library(\n    name = \"lib{i}\",\n    srcs = [\"lib{i}.cc\"],\n)\n",
+                i = i
+            )
+        })
+        .collect();
+    assert_eq!(text.lines().count(), 100);
+
+    let (mut stdin, mut stdout) = common::setup_server();
+    common::initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({ "capabilities": {}, "processId": 1 }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": "file:///test/BUILD",
+                "languageId": "starlark",
+                "version": 1,
+                "text": text
+            }
+        }
+    });
+    common::send_message(&mut stdin, did_open_params).await?;
+
+    let full_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "textDocument/semanticTokens/full",
+        "params": { "textDocument": { "uri": "file:///test/BUILD" } }
+    });
+    common::send_message(&mut stdin, full_params).await?;
+    let (response, _) = common::read_response(&mut stdout, 2).await?;
+    let full = token_positions(&response["result"]["data"]);
+
+    // Lines 10 through 20
+    let range_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 3,
+        "method": "textDocument/semanticTokens/range",
+        "params": {
+            "textDocument": { "uri": "file:///test/BUILD" },
+            "range": {
+                "start": { "line": 10, "character": 0 },
+                "end": { "line": 21, "character": 0 }
+            }
+        }
+    });
+    common::send_message(&mut stdin, range_params).await?;
+    let (response, _) = common::read_response(&mut stdout, 3).await?;
+    let ranged = token_positions(&response["result"]["data"]);
+
+    let expected: Vec<(u64, u64)> = full
+        .into_iter()
+        .filter(|(line, _)| (10..=20).contains(line))
+        .collect();
+    assert!(!expected.is_empty());
+    assert_eq!(ranged, expected);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_semantic_tokens_do_not_overlap() -> Result<(), anyhow::Error> {
+    let text = r#"function(
+    name = "function",
+    srcs = ["function.cc"],
+)
+"#;
+
+    let (mut stdin, mut stdout) = common::setup_server();
+    common::initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({ "capabilities": {}, "processId": 1 }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": "file:///test/BUILD",
+                "languageId": "starlark",
+                "version": 1,
+                "text": text
+            }
+        }
+    });
+    common::send_message(&mut stdin, did_open_params).await?;
+
+    let full_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "textDocument/semanticTokens/full",
+        "params": { "textDocument": { "uri": "file:///test/BUILD" } }
+    });
+    common::send_message(&mut stdin, full_params).await?;
+    let (response, _) = common::read_response(&mut stdout, 2).await?;
+
+    let data = &response["result"]["data"];
+    let positions = token_positions(data);
+    let tokens: Vec<(u64, u64, u64, u64)> = data
+        .as_array()
+        .unwrap()
+        .chunks(5)
+        .zip(&positions)
+        .map(|(token, (line, start))| {
+            (
+                *line,
+                *start,
+                token[2].as_u64().unwrap(),
+                token[3].as_u64().unwrap(),
+            )
+        })
+        .collect();
+
+    // (line, start, length, type) with types function = 0, property = 1, string = 2
+    assert_eq!(
+        tokens,
+        [
+            (0, 0, 8, 0),
+            (1, 4, 4, 1),
+            (1, 11, 10, 2),
+            (2, 4, 4, 1),
+            (2, 12, 13, 2),
+        ]
+    );
+    for pair in tokens.windows(2) {
+        let (line, start, length, _) = pair[0];
+        assert!(pair[1].0 > line || pair[1].1 >= start + length);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_semantic_tokens_full_token_types() -> Result<(), anyhow::Error> {
+    let text = r#"cc_binary(
+    name = "app",
+    deps = [":lib", "//base"],
+)
+"#;
+
+    let (mut stdin, mut stdout) = common::setup_server();
+    common::initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({ "capabilities": {}, "processId": 1 }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": "file:///test/BUILD",
+                "languageId": "starlark",
+                "version": 1,
+                "text": text
+            }
+        }
+    });
+    common::send_message(&mut stdin, did_open_params).await?;
+
+    let full_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "textDocument/semanticTokens/full",
+        "params": { "textDocument": { "uri": "file:///test/BUILD" } }
+    });
+    common::send_message(&mut stdin, full_params).await?;
+    let (response, _) = common::read_response(&mut stdout, 2).await?;
+
+    let data: Vec<u64> = response["result"]["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|value| value.as_u64().unwrap())
+        .collect();
+    let tokens: Vec<&[u64]> = data.chunks(5).collect();
+    assert!(!tokens.is_empty());
+    assert_eq!(tokens[0][3], 0, "the rule is a function: {:?}", tokens);
+    assert_eq!(tokens[1][3], 1, "`name` is a property: {:?}", tokens);
+    assert!(tokens.iter().any(|token| token[3] == 2));
+
+    // Each token is (delta line, delta start, length, type, modifiers), its start
+    // relative to the previous token's when on the same line
+    assert_eq!(
+        tokens,
+        [
+            [0, 0, 9, 0, 0],
+            [1, 4, 4, 1, 0],
+            [0, 7, 5, 2, 0],
+            [1, 4, 4, 1, 0],
+            [0, 8, 6, 2, 0],
+            [0, 8, 8, 2, 0],
+        ]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_semantic_tokens_highlight_def_names() -> Result<(), anyhow::Error> {
+    let text = "def my_macro(name):\n    native.cc_library(name = name)\n";
+
+    let (mut stdin, mut stdout) = common::setup_server();
+    common::initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({ "capabilities": {}, "processId": 1 }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": "file:///test/defs.bzl",
+                "languageId": "starlark",
+                "version": 1,
+                "text": text
+            }
+        }
+    });
+    common::send_message(&mut stdin, did_open_params).await?;
+
+    let full_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "textDocument/semanticTokens/full",
+        "params": { "textDocument": { "uri": "file:///test/defs.bzl" } }
+    });
+    common::send_message(&mut stdin, full_params).await?;
+    let (response, _) = common::read_response(&mut stdout, 2).await?;
+
+    let data = response["result"]["data"].as_array().unwrap();
+    // The name of the def, as a function
+    assert_eq!(data[..5], [0, 4, 8, 0, 0]);
+
+    Ok(())
+}
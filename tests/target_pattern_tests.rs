@@ -0,0 +1,97 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::{initialize, read_response, send_message, setup_server};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use tempfile::TempDir;
+
+// Records the arguments it was called with
+const MOCK_BAZEL: &str = r#"#!/bin/sh
+echo "$*" > "${0%/*}/args"
+"#;
+
+#[tokio::test]
+async fn test_build_passes_wildcard_and_negative_patterns() -> Result<(), anyhow::Error> {
+    let bin_dir = TempDir::new()?;
+    let mock_bazel = bin_dir.path().join("bazel");
+    fs::write(&mock_bazel, MOCK_BAZEL)?;
+    fs::set_permissions(&mock_bazel, fs::Permissions::from_mode(0o755))?;
+
+    let (mut stdin, mut stdout) = setup_server();
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "initializationOptions": {
+                "bazel_env": { "PATH": bin_dir.path() }
+            },
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let execute_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "workspace/executeCommand",
+        "params": {
+            "command": "bazel.build",
+            "arguments": [{ "target": "//foo/... -//foo/bar/..." }]
+        }
+    });
+    send_message(&mut stdin, execute_params).await?;
+    read_response(&mut stdout, 2).await?;
+
+    let args = fs::read_to_string(bin_dir.path().join("args"))?;
+    assert_eq!(
+        args.trim_end(),
+        "build --noshow_progress --noshow_loading_progress --color=no -- //foo/... -//foo/bar/..."
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_build_passes_config() -> Result<(), anyhow::Error> {
+    let bin_dir = TempDir::new()?;
+    let mock_bazel = bin_dir.path().join("bazel");
+    fs::write(&mock_bazel, MOCK_BAZEL)?;
+    fs::set_permissions(&mock_bazel, fs::Permissions::from_mode(0o755))?;
+
+    let (mut stdin, mut stdout) = setup_server();
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "initializationOptions": {
+                "bazel_env": { "PATH": bin_dir.path() }
+            },
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let execute_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "workspace/executeCommand",
+        "params": {
+            "command": "bazel.build",
+            "arguments": [{ "target": "//app:main", "config": "opt" }]
+        }
+    });
+    send_message(&mut stdin, execute_params).await?;
+    read_response(&mut stdout, 2).await?;
+
+    let args = fs::read_to_string(bin_dir.path().join("args"))?;
+    assert_eq!(
+        args.trim_end(),
+        "build --noshow_progress --noshow_loading_progress --color=no --config=opt //app:main"
+    );
+
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+#[tokio::test]
+async fn test_serves_a_tcp_connection() -> Result<(), anyhow::Error> {
+    // Port 0 lets the OS pick a free port, which the server reports on stderr
+    let mut server = Command::new(env!("CARGO_BIN_EXE_bazel-lsp"))
+        .args(["--tcp", "0"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut listening = String::new();
+    BufReader::new(server.stderr.take().unwrap()).read_line(&mut listening)?;
+    let address = listening
+        .trim()
+        .strip_prefix("Listening on ")
+        .expect("the server should report its address");
+
+    let mut stream = TcpStream::connect(address).await?;
+    let initialize = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "initialize",
+        "params": { "capabilities": {}, "processId": null }
+    })
+    .to_string();
+    stream
+        .write_all(format!("Content-Length: {}\r\n\r\n{}", initialize.len(), initialize).as_bytes())
+        .await?;
+
+    let mut response = Vec::new();
+    let mut buffer = [0; 4096];
+    while !String::from_utf8_lossy(&response).contains("\"capabilities\"") {
+        let read = stream.read(&mut buffer).await?;
+        assert!(read > 0, "the server closed the connection");
+        response.extend_from_slice(&buffer[..read]);
+    }
+    assert!(String::from_utf8_lossy(&response).starts_with("Content-Length: "));
+
+    server.kill()?;
+    server.wait()?;
+    Ok(())
+}
+
+#[test]
+fn test_busy_port_is_reported() -> Result<(), anyhow::Error> {
+    let taken = std::net::TcpListener::bind("127.0.0.1:0")?;
+    let port = taken.local_addr()?.port().to_string();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_bazel-lsp"))
+        .args(["--tcp", &port])
+        .stdin(Stdio::null())
+        .output()?;
+
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.starts_with(&format!("Failed to listen on port {}: ", port)),
+        "{}",
+        stderr
+    );
+    Ok(())
+}
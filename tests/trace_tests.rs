@@ -0,0 +1,128 @@
+mod common;
+
+use common::{initialize, read_response, send_message, setup_server};
+
+#[tokio::test]
+async fn test_verbose_trace_logs_completion_decisions() -> Result<(), anyhow::Error> {
+    let (mut stdin, mut stdout) = setup_server();
+
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "initializationOptions": { "trace": "verbose" },
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": "file:///test/BUILD",
+                "languageId": "starlark",
+                "version": 1,
+                "text": "cc_library(\n    name = \"lib\",\n    deps = [\":\"],\n)\n"
+            }
+        }
+    });
+    send_message(&mut stdin, did_open_params).await?;
+
+    let completion_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "textDocument/completion",
+        "params": {
+            "textDocument": { "uri": "file:///test/BUILD" },
+            "position": { "line": 2, "character": 14 }
+        }
+    });
+    send_message(&mut stdin, completion_params).await?;
+
+    let (_, notifications) = read_response(&mut stdout, 2).await?;
+    let traces: Vec<&str> = notifications
+        .iter()
+        .filter(|n| n["method"] == "window/logMessage" && n["params"]["type"] == 4)
+        .filter_map(|n| n["params"]["message"].as_str())
+        .collect();
+
+    assert!(
+        traces
+            .iter()
+            .any(|m| m.starts_with("Completion at 2:14: Some(")),
+        "No completion trace in {:?}",
+        traces
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_trace_off_by_default() -> Result<(), anyhow::Error> {
+    let (mut stdin, mut stdout) = setup_server();
+
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({ "capabilities": {}, "processId": 1 }),
+    )
+    .await?;
+
+    let completion_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "textDocument/completion",
+        "params": {
+            "textDocument": { "uri": "file:///test/BUILD" },
+            "position": { "line": 0, "character": 0 }
+        }
+    });
+    send_message(&mut stdin, completion_params).await?;
+
+    let (_, notifications) = read_response(&mut stdout, 2).await?;
+    assert!(!notifications
+        .iter()
+        .any(|n| n["method"] == "window/logMessage" && n["params"]["type"] == 4));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_set_trace_turns_on_trace_logging() -> Result<(), anyhow::Error> {
+    let (mut stdin, mut stdout) = setup_server();
+
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({ "capabilities": {}, "processId": 1 }),
+    )
+    .await?;
+
+    let set_trace_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "$/setTrace",
+        "params": { "value": "verbose" }
+    });
+    send_message(&mut stdin, set_trace_params).await?;
+
+    let completion_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "textDocument/completion",
+        "params": {
+            "textDocument": { "uri": "file:///test/BUILD" },
+            "position": { "line": 0, "character": 0 }
+        }
+    });
+    send_message(&mut stdin, completion_params).await?;
+
+    let (_, notifications) = read_response(&mut stdout, 2).await?;
+    assert!(notifications
+        .iter()
+        .any(|n| n["method"] == "window/logMessage" && n["params"]["type"] == 4));
+
+    Ok(())
+}
@@ -1,4 +1,6 @@
+use bazel_lsp::label::Label;
 use bazel_lsp::target_trie::{RuleInfo, TargetTrie};
+use std::path::PathBuf;
 
 #[test]
 fn test_trie_insert_and_search() {
@@ -6,6 +8,7 @@ fn test_trie_insert_and_search() {
     let rule = RuleInfo {
         name: "test_rule".to_string(),
         full_build_path: "//a/b:c".to_string(),
+        ..Default::default()
     };
     trie.insert_target("//a/b:c", rule);
 
@@ -20,14 +23,17 @@ fn test_trie_starts_with() {
     let rule1 = RuleInfo {
         name: "rule1".to_string(),
         full_build_path: "//a/b:c".to_string(),
+        ..Default::default()
     };
     let rule2 = RuleInfo {
         name: "rule2".to_string(),
         full_build_path: "//a/b:d".to_string(),
+        ..Default::default()
     };
     let rule3 = RuleInfo {
         name: "rule3".to_string(),
         full_build_path: "//a/c:e".to_string(),
+        ..Default::default()
     };
 
     trie.insert_target("//a/b:c", rule1);
@@ -50,6 +56,7 @@ fn test_trie_with_package_path() {
     let rule = RuleInfo {
         name: "main".to_string(),
         full_build_path: "//src:main".to_string(),
+        ..Default::default()
     };
     trie.insert_target("//src:main", rule);
 
@@ -57,3 +64,148 @@ fn test_trie_with_package_path() {
     assert_eq!(results.len(), 1);
     assert_eq!(results[0][0].name, "main");
 }
+
+#[test]
+fn test_trie_resolve_implicit_target_name() {
+    let mut trie = TargetTrie::new();
+    trie.insert_target("//a/b:b", RuleInfo::new("b".into(), "//a/b:b".into()));
+    trie.insert_target("//a/b:c", RuleInfo::new("c".into(), "//a/b:c".into()));
+
+    let rule = trie.resolve("//a/b").unwrap();
+    assert_eq!(rule.name, "b");
+    assert_eq!(rule.full_build_path, "//a/b:b");
+
+    assert_eq!(trie.resolve("//a/b:c").unwrap().name, "c");
+    assert!(trie.resolve("//a/c").is_none());
+}
+
+#[test]
+fn test_trie_package_is_not_rule_prefix() {
+    let mut trie = TargetTrie::new();
+    trie.insert_target(
+        "//a/b:target1",
+        RuleInfo::new("target1".into(), "//a/b:target1".into()),
+    );
+    trie.insert_target(
+        "//a/b:target2",
+        RuleInfo::new("target2".into(), "//a/b:target2".into()),
+    );
+
+    let results = trie.starts_with("//a/b");
+    assert_eq!(results.len(), 2);
+
+    let results = trie.starts_with("//a/bt");
+    assert_eq!(results.len(), 0);
+}
+
+#[test]
+fn test_trie_partial_segment_matches_package_and_rule() {
+    let mut trie = TargetTrie::new();
+    trie.insert_target(
+        "//a/bar:lib",
+        RuleInfo::new("lib".into(), "//a/bar:lib".into()),
+    );
+    trie.insert_target("//a:baz", RuleInfo::new("baz".into(), "//a:baz".into()));
+    trie.insert_target("//a:qux", RuleInfo::new("qux".into(), "//a:qux".into()));
+
+    let mut names: Vec<&str> = trie
+        .starts_with("//a/b")
+        .into_iter()
+        .flatten()
+        .map(|rule| rule.name.as_str())
+        .collect();
+    names.sort();
+    assert_eq!(names, vec!["baz", "lib"]);
+}
+
+#[test]
+fn test_trie_remove_file() {
+    let mut trie = TargetTrie::new();
+    let build_a = std::path::Path::new("/ws/a/BUILD");
+    let build_b = std::path::Path::new("/ws/b/BUILD");
+    trie.insert_file_target(
+        build_a,
+        "a:lib",
+        RuleInfo::new("lib".into(), "//a:lib".into()),
+    );
+    trie.insert_file_target(
+        build_b,
+        "b:lib",
+        RuleInfo::new("lib".into(), "//b:lib".into()),
+    );
+    assert_eq!(trie.file_count(), 2);
+
+    trie.remove_file(build_a);
+
+    assert_eq!(trie.file_count(), 1);
+    assert!(trie.resolve("//a:lib").is_none());
+    assert!(trie.resolve("//b:lib").is_some());
+    assert_eq!(trie.starts_with("").len(), 1);
+}
+
+#[test]
+fn test_rule_info_all_fields() {
+    let rule = RuleInfo {
+        name: "lib".to_string(),
+        full_build_path: "//a:lib".to_string(),
+        rule_type: "cc_library".to_string(),
+        visibility: vec!["//visibility:public".to_string()],
+        source_file: Some(PathBuf::from("/ws/a/BUILD")),
+        testonly: Some(true),
+        deps: vec!["//b:base".to_string()],
+    };
+
+    let mut trie = TargetTrie::new();
+    trie.insert_target("//a:lib", rule);
+
+    let resolved = trie.resolve("//a:lib").unwrap();
+    assert_eq!(resolved.name, "lib");
+    assert_eq!(resolved.full_build_path, "//a:lib");
+    assert_eq!(resolved.rule_type, "cc_library");
+    assert_eq!(resolved.visibility, ["//visibility:public"]);
+    assert_eq!(resolved.source_file, Some(PathBuf::from("/ws/a/BUILD")));
+    assert_eq!(resolved.testonly, Some(true));
+    assert_eq!(resolved.deps, ["//b:base"]);
+
+    let defaults = RuleInfo::new("lib".into(), "//a:lib".into());
+    assert!(defaults.rule_type.is_empty());
+    assert!(defaults.visibility.is_empty());
+    assert!(defaults.source_file.is_none());
+    assert!(defaults.testonly.is_none());
+    assert!(defaults.deps.is_empty());
+}
+
+#[test]
+fn test_trie_root_level_target() {
+    let mut trie = TargetTrie::new();
+    let label = Label::new("", "app").to_string();
+    trie.insert_file_target(
+        &PathBuf::from("/ws/BUILD"),
+        &label,
+        RuleInfo::new("app".into(), label.clone()),
+    );
+
+    assert_eq!(trie.resolve("//:app").unwrap().full_build_path, "//:app");
+    let results = trie.starts_with("//:a");
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0][0].full_build_path, "//:app");
+
+    trie.remove_file(&PathBuf::from("/ws/BUILD"));
+    assert!(trie.resolve("//:app").is_none());
+}
+
+#[test]
+fn test_trie_is_empty() {
+    let mut trie = TargetTrie::new();
+    assert!(trie.is_empty());
+
+    trie.insert_file_target(
+        &PathBuf::from("/ws/a/BUILD"),
+        "//a:lib",
+        RuleInfo::new("lib".into(), "//a:lib".into()),
+    );
+    assert!(!trie.is_empty());
+
+    trie.remove_file(&PathBuf::from("/ws/a/BUILD"));
+    assert!(trie.is_empty());
+}
@@ -0,0 +1,134 @@
+mod common;
+
+use common::{initialize, read_response, send_message, setup_server};
+use std::fs;
+use tempfile::TempDir;
+
+const MACROS_BZL: &str = r#"load("@rules_cc//cc:defs.bzl", "cc_library")
+
+def helper():
+    pass
+
+def my_custom_macro(name, **kwargs):
+    cc_library(name = name, **kwargs)
+"#;
+
+const BUILD: &str = r#"load("//tools:macros.bzl", "my_custom_macro", aliased = "helper")
+
+my_custom_macro(
+    name = "lib",
+)
+
+aliased()
+"#;
+
+async fn type_definition_at(line: u32, character: u32) -> Result<serde_json::Value, anyhow::Error> {
+    type_definition_with_index(line, character, false).await
+}
+
+/// With `delete_bzl`, the .bzl file is deleted once the workspace has been indexed,
+/// so the definition can only come from the macro index.
+async fn type_definition_with_index(
+    line: u32,
+    character: u32,
+    delete_bzl: bool,
+) -> Result<serde_json::Value, anyhow::Error> {
+    let workspace = TempDir::new()?;
+    fs::write(workspace.path().join("WORKSPACE"), "")?;
+    fs::create_dir_all(workspace.path().join("tools"))?;
+    fs::create_dir_all(workspace.path().join("pkg"))?;
+    fs::write(workspace.path().join("tools/macros.bzl"), MACROS_BZL)?;
+    fs::write(workspace.path().join("pkg/BUILD"), BUILD)?;
+
+    let workspace_uri = url::Url::from_file_path(workspace.path()).unwrap();
+    let build_uri = url::Url::from_file_path(workspace.path().join("pkg/BUILD")).unwrap();
+    let (mut stdin, mut stdout) = setup_server();
+
+    initialize(
+        &mut stdin,
+        &mut stdout,
+        serde_json::json!({
+            "capabilities": {},
+            "workspaceFolders": [{ "uri": workspace_uri, "name": "workspace" }],
+            "processId": 1
+        }),
+    )
+    .await?;
+
+    if delete_bzl {
+        fs::remove_file(workspace.path().join("tools/macros.bzl"))?;
+    }
+
+    let did_open_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {
+            "textDocument": {
+                "uri": build_uri,
+                "languageId": "starlark",
+                "version": 1,
+                "text": BUILD
+            }
+        }
+    });
+    send_message(&mut stdin, did_open_params).await?;
+
+    let type_definition_params = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "textDocument/typeDefinition",
+        "params": {
+            "textDocument": { "uri": build_uri },
+            "position": { "line": line, "character": character }
+        }
+    });
+    send_message(&mut stdin, type_definition_params).await?;
+
+    let (response, _) = read_response(&mut stdout, 2).await?;
+    Ok(response["result"].clone())
+}
+
+#[tokio::test]
+async fn test_type_definition_of_loaded_macro() -> Result<(), anyhow::Error> {
+    let location = type_definition_at(2, 5).await?;
+
+    assert!(location["uri"]
+        .as_str()
+        .unwrap()
+        .ends_with("/tools/macros.bzl"));
+    assert_eq!(location["range"]["start"]["line"], 5);
+    assert_eq!(location["range"]["start"]["character"], 4);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_type_definition_of_aliased_load() -> Result<(), anyhow::Error> {
+    let location = type_definition_at(6, 3).await?;
+
+    assert_eq!(location["range"]["start"]["line"], 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_type_definition_outside_call() -> Result<(), anyhow::Error> {
+    let location = type_definition_at(3, 6).await?;
+
+    assert!(location.is_null());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_type_definition_from_macro_index() -> Result<(), anyhow::Error> {
+    let location = type_definition_with_index(2, 5, true).await?;
+
+    assert!(location["uri"]
+        .as_str()
+        .unwrap()
+        .ends_with("/tools/macros.bzl"));
+    assert_eq!(location["range"]["start"]["line"], 5);
+
+    Ok(())
+}